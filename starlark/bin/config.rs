@@ -0,0 +1,294 @@
+/*
+ * Copyright 2019 The Starlark in Rust Authors.
+ * Copyright (c) Facebook, Inc. and its affiliates.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Which flavour of Starlark to use: the dialect, the library extensions, the prelude and
+//! where `load()` finds files. Settings can come from a JSON file passed with `--config`,
+//! e.g.
+//!
+//! ```json
+//! {
+//!     "dialect": "standard",
+//!     "enable": ["f_strings"],
+//!     "extensions": ["StructType", "Json"],
+//!     "prelude": ["prelude.bzl"],
+//!     "root": "."
+//! }
+//! ```
+//!
+//! Relative paths in the file are relative to the directory containing it.
+//! Any settings given on the command line take precedence.
+
+use anyhow::anyhow;
+use serde::Deserialize;
+use starlark::{environment::LibraryExtension, syntax::Dialect};
+use std::{
+    fs, iter,
+    path::{Path, PathBuf},
+};
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    /// Either `standard` or `extended`, defaults to `extended`.
+    pub dialect: Option<String>,
+    /// Dialect features to turn on, named after the [`Dialect`] fields without `enable_`,
+    /// e.g. `f_strings`.
+    #[serde(default)]
+    pub enable: Vec<String>,
+    /// Dialect features to turn off, applied after `enable`.
+    #[serde(default)]
+    pub disable: Vec<String>,
+    /// The [`LibraryExtension`]s to make available, by name (e.g. `Json`), or `all`.
+    /// Defaults to all of them for the extended dialect, and none for the standard dialect.
+    pub extensions: Option<Vec<String>>,
+    /// Files to evaluate in advance, whose public symbols are available everywhere.
+    #[serde(default)]
+    pub prelude: Vec<PathBuf>,
+    /// The directory `load()` paths are relative to, defaults to the current directory.
+    pub root: Option<PathBuf>,
+}
+
+impl Config {
+    pub fn load(file: &Path) -> anyhow::Result<Self> {
+        let src = fs::read_to_string(file)?;
+        let dir = file.parent().unwrap_or_else(|| Path::new(""));
+        Self::parse(&src, dir)
+            .map_err(|e| anyhow!("Invalid config file `{}`: {}", file.display(), e))
+    }
+
+    /// Parse the JSON of a config file, with relative paths taken relative to `dir`.
+    fn parse(src: &str, dir: &Path) -> serde_json::Result<Self> {
+        let mut res: Self = serde_json::from_str(src)?;
+        res.prelude = res.prelude.into_iter().map(|x| dir.join(x)).collect();
+        res.root = res.root.map(|x| dir.join(x));
+        Ok(res)
+    }
+
+    /// Apply the settings given on the command line, which take precedence over these.
+    /// Features to enable or disable, and prelude files, are added to the existing ones.
+    pub fn merge(&mut self, args: Config) {
+        if args.dialect.is_some() {
+            self.dialect = args.dialect;
+        }
+        self.enable.extend(args.enable);
+        self.disable.extend(args.disable);
+        if args.extensions.is_some() {
+            self.extensions = args.extensions;
+        }
+        self.prelude.extend(args.prelude);
+        if args.root.is_some() {
+            self.root = args.root;
+        }
+    }
+
+    fn is_standard(&self) -> anyhow::Result<bool> {
+        match self.dialect.as_deref() {
+            None | Some("extended") => Ok(false),
+            Some("standard") => Ok(true),
+            Some(x) => Err(anyhow!(
+                "Unknown dialect `{}`, expected `standard` or `extended`",
+                x
+            )),
+        }
+    }
+
+    pub fn dialect(&self) -> anyhow::Result<Dialect> {
+        let mut res = if self.is_standard()? {
+            Dialect::Standard
+        } else {
+            Dialect::Extended
+        };
+        for x in &self.enable {
+            *dialect_flag(&mut res, x)? = true;
+        }
+        for x in &self.disable {
+            *dialect_flag(&mut res, x)? = false;
+        }
+        Ok(res)
+    }
+
+    pub fn extensions(&self) -> anyhow::Result<Vec<LibraryExtension>> {
+        match &self.extensions {
+            None if self.is_standard()? => Ok(Vec::new()),
+            None => Ok(LibraryExtension::all().to_vec()),
+            Some(xs) => {
+                let mut res = Vec::new();
+                for x in xs {
+                    if x == "all" {
+                        res.extend(LibraryExtension::all());
+                    } else {
+                        res.push(library_extension(x)?);
+                    }
+                }
+                Ok(res)
+            }
+        }
+    }
+
+    pub fn root(&self) -> PathBuf {
+        self.root.clone().unwrap_or_default()
+    }
+}
+
+fn dialect_flag<'a>(dialect: &'a mut Dialect, name: &str) -> anyhow::Result<&'a mut bool> {
+    Ok(match name {
+        "def" => &mut dialect.enable_def,
+        "lambda" => &mut dialect.enable_lambda,
+        "load" => &mut dialect.enable_load,
+        "keyword_only_arguments" => &mut dialect.enable_keyword_only_arguments,
+        "positional_only_arguments" => &mut dialect.enable_positional_only_arguments,
+        "types" => &mut dialect.enable_types,
+        "tabs" => &mut dialect.enable_tabs,
+        "load_reexport" => &mut dialect.enable_load_reexport,
+        "top_level_stmt" => &mut dialect.enable_top_level_stmt,
+        "f_strings" => &mut dialect.enable_f_strings,
+        _ => return Err(anyhow!("Unknown dialect feature `{}`", name)),
+    })
+}
+
+fn library_extension(name: &str) -> anyhow::Result<LibraryExtension> {
+    // `Catch` isn't in `all`, since it changes the semantics of Starlark, but can be asked for
+    LibraryExtension::all()
+        .iter()
+        .copied()
+        .chain(iter::once(LibraryExtension::Catch))
+        .find(|x| format!("{:?}", x) == name)
+        .ok_or_else(|| anyhow!("Unknown library extension `{}`", name))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let config = Config::parse(
+            r#"{
+                "dialect": "standard",
+                "enable": ["f_strings", "lambda"],
+                "disable": ["lambda"],
+                "extensions": ["StructType", "Json"],
+                "prelude": ["prelude.bzl"],
+                "root": "src"
+            }"#,
+            Path::new("dir"),
+        )
+        .unwrap();
+        assert_eq!(config.prelude, vec![Path::new("dir/prelude.bzl")]);
+        assert_eq!(config.root(), Path::new("dir/src"));
+        let dialect = config.dialect().unwrap();
+        assert!(dialect.enable_f_strings);
+        assert!(!dialect.enable_lambda);
+        assert!(!dialect.enable_top_level_stmt);
+        assert_eq!(
+            config.extensions().unwrap(),
+            vec![LibraryExtension::StructType, LibraryExtension::Json]
+        );
+
+        assert!(Config::parse(r#"{"dialects": "standard"}"#, Path::new("")).is_err());
+        assert!(Config::parse(r#"{"enable": "types"}"#, Path::new("")).is_err());
+    }
+
+    #[test]
+    fn test_defaults() {
+        let config = Config::default();
+        assert_eq!(config.dialect().unwrap(), Dialect::Extended);
+        assert_eq!(config.extensions().unwrap(), LibraryExtension::all());
+        assert_eq!(config.root(), PathBuf::new());
+
+        let standard = Config {
+            dialect: Some("standard".to_owned()),
+            ..Config::default()
+        };
+        assert_eq!(standard.dialect().unwrap(), Dialect::Standard);
+        assert_eq!(standard.extensions().unwrap(), Vec::new());
+    }
+
+    #[test]
+    fn test_errors() {
+        let err = |config: Config| {
+            let e = match config.dialect() {
+                Err(e) => e,
+                Ok(_) => config.extensions().unwrap_err(),
+            };
+            e.to_string()
+        };
+        assert_eq!(
+            err(Config {
+                dialect: Some("python".to_owned()),
+                ..Config::default()
+            }),
+            "Unknown dialect `python`, expected `standard` or `extended`"
+        );
+        assert_eq!(
+            err(Config {
+                enable: vec!["enable_types".to_owned()],
+                ..Config::default()
+            }),
+            "Unknown dialect feature `enable_types`"
+        );
+        assert_eq!(
+            err(Config {
+                extensions: Some(vec!["json".to_owned()]),
+                ..Config::default()
+            }),
+            "Unknown library extension `json`"
+        );
+    }
+
+    #[test]
+    fn test_merge() {
+        let mut config = Config::parse(
+            r#"{
+                "dialect": "standard",
+                "enable": ["types"],
+                "extensions": ["Json"],
+                "prelude": ["a.bzl"],
+                "root": "."
+            }"#,
+            Path::new("dir"),
+        )
+        .unwrap();
+        // Features and prelude files are added, the extensions and root are replaced,
+        // and the dialect is kept since it isn't given
+        config.merge(Config {
+            enable: vec!["f_strings".to_owned()],
+            disable: vec!["types".to_owned()],
+            extensions: Some(vec!["all".to_owned(), "Catch".to_owned()]),
+            prelude: vec![PathBuf::from("b.bzl")],
+            root: Some(PathBuf::from("other")),
+            ..Config::default()
+        });
+        let dialect = config.dialect().unwrap();
+        assert!(!dialect.enable_top_level_stmt);
+        assert!(dialect.enable_f_strings);
+        assert!(!dialect.enable_types);
+        let mut extensions = LibraryExtension::all().to_vec();
+        extensions.push(LibraryExtension::Catch);
+        assert_eq!(config.extensions().unwrap(), extensions);
+        assert_eq!(
+            config.prelude,
+            vec![PathBuf::from("dir/a.bzl"), PathBuf::from("b.bzl")]
+        );
+        assert_eq!(config.root(), Path::new("other"));
+
+        // Settings missing from the command line are left alone
+        config.merge(Config::default());
+        assert_eq!(config.root(), Path::new("other"));
+        assert_eq!(config.extensions().unwrap(), extensions);
+    }
+}
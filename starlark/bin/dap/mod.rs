@@ -15,16 +15,15 @@
  * limitations under the License.
  */
 
-use crate::eval::{dialect, globals, Context};
+use crate::eval::Context;
 use debugserver_types::*;
 use gazebo::prelude::*;
 pub use library::*;
 use serde_json::{Map, Value};
 use starlark::{
     codemap::{FileSpan, Span},
-    environment::Module,
     eval::Evaluator,
-    syntax::{AstModule, Dialect},
+    syntax::AstModule,
 };
 use std::{
     collections::{HashMap, HashSet},
@@ -42,7 +41,7 @@ mod library;
 #[derive(Debug)]
 struct Backend {
     client: Client,
-    starlark: Context,
+    starlark: Arc<Context>,
 
     file: Mutex<Option<String>>,
 
@@ -92,13 +91,15 @@ impl Backend {
         let breakpoints = self.breakpoints.dupe();
         let disable_breakpoints = self.disable_breakpoints.dupe();
         let receiver = self.receiver.dupe();
+        let ctx = self.starlark.dupe();
 
         let go = move || -> anyhow::Result<String> {
             client.log(&format!("EVALUATION PREPARE: {}", path.display()));
-            let ast = AstModule::parse_file(&path, &dialect())?;
-            let module = Module::new();
-            let globals = globals();
-            let mut eval = Evaluator::new(&module, &globals);
+            let ast = AstModule::parse_file(&path, &ctx.dialect)?;
+            let module = ctx.new_module();
+            let mut loader = ctx.loader();
+            let mut eval = Evaluator::new(&module, &ctx.globals);
+            eval.set_loader(&mut loader);
             let fun = |span, eval: &mut Evaluator| {
                 let stop = if disable_breakpoints.load(Ordering::SeqCst) > 0 {
                     false
@@ -198,7 +199,7 @@ impl DebugServer for Backend {
                 breakpoints: Vec::new(),
             })
         } else {
-            match AstModule::parse_file(Path::new(&source), &dialect()) {
+            match AstModule::parse_file(Path::new(&source), &self.starlark.dialect) {
                 Err(_) => {
                     self.breakpoints.lock().unwrap().remove(&source);
                     Ok(SetBreakpointsResponseBody {
@@ -356,11 +357,12 @@ impl DebugServer for Backend {
 
     fn evaluate(&self, x: EvaluateArguments) -> anyhow::Result<EvaluateResponseBody> {
         let disable_breakpoints = self.disable_breakpoints.dupe();
+        let dialect = self.starlark.dialect.clone();
         self.with_ctx(box move |_, eval| {
             // We don't want to trigger breakpoints during an evaluate,
             // not least because we currently don't allow reenterant evaluate
             disable_breakpoints.fetch_add(1, Ordering::SeqCst);
-            let ast = AstModule::parse("interactive", x.expression.clone(), &Dialect::Extended);
+            let ast = AstModule::parse("interactive", x.expression.clone(), &dialect);
             let s = match ast.and_then(|ast| eval.eval_statements(ast)) {
                 Err(e) => format!("{:#}", e),
                 Ok(v) => v.to_string(),
@@ -382,7 +384,7 @@ pub fn server(starlark: Context) {
     let (sender, receiver) = channel();
     DapService::run(|client| Backend {
         client,
-        starlark,
+        starlark: Arc::new(starlark),
         breakpoints: Default::default(),
         disable_breakpoints: Default::default(),
         file: Default::default(),
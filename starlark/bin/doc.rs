@@ -0,0 +1,57 @@
+/*
+ * Copyright 2019 The Starlark in Rust Authors.
+ * Copyright (c) Facebook, Inc. and its affiliates.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Print reference documentation, for the globals when there are no files, otherwise
+//! for the exported functions of each file.
+
+use crate::eval::Context;
+use starlark::{
+    docs::{render_markdown, Doc, DocItem},
+    syntax::AstModule,
+};
+use std::path::PathBuf;
+
+pub fn run(ctx: &Context, files: Vec<PathBuf>, json: bool) -> anyhow::Result<()> {
+    if files.is_empty() {
+        let docs = ctx.globals.documentation();
+        if json {
+            println!("{}", serde_json::to_string_pretty(&docs)?);
+        } else {
+            print!("{}", render_markdown("Globals", &docs));
+        }
+        return Ok(());
+    }
+
+    let mut pages = Vec::with_capacity(files.len());
+    for file in files {
+        let module = AstModule::parse_file(&file, &ctx.dialect)?;
+        pages.push(Doc {
+            name: file.display().to_string(),
+            item: DocItem::Namespace(module.documentation()),
+        });
+    }
+    if json {
+        println!("{}", serde_json::to_string_pretty(&pages)?);
+    } else {
+        for page in pages {
+            if let DocItem::Namespace(docs) = &page.item {
+                print!("{}", render_markdown(&page.name, docs));
+            }
+        }
+    }
+    Ok(())
+}
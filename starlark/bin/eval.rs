@@ -15,12 +15,13 @@
  * limitations under the License.
  */
 
-use crate::types::Message;
+use crate::{config::Config, types::Message};
+use anyhow::anyhow;
 use gazebo::prelude::*;
 use itertools::Either;
 use starlark::{
-    environment::{FrozenModule, Globals, Module},
-    eval::Evaluator,
+    environment::{FrozenModule, Globals, LibraryExtension, Module},
+    eval::{Evaluator, FileLoader},
     syntax::{AstModule, Dialect},
 };
 use std::{
@@ -33,27 +34,43 @@ pub struct Context {
     pub check: bool,
     pub info: bool,
     pub run: bool,
+    pub dialect: Dialect,
+    pub extensions: Vec<LibraryExtension>,
+    pub globals: Globals,
+    /// The directory that `load()` paths are relative to.
+    pub root: PathBuf,
     pub prelude: Vec<FrozenModule>,
 }
 
 impl Context {
-    pub fn new(check: bool, info: bool, run: bool, prelude: &[PathBuf]) -> anyhow::Result<Self> {
-        let globals = globals();
-        let prelude = prelude.try_map(|x| {
+    pub fn new(
+        check: bool,
+        info: bool,
+        run: bool,
+        config: &Config,
+        prelude: &[PathBuf],
+    ) -> anyhow::Result<Self> {
+        let extensions = config.extensions()?;
+        let mut res = Self {
+            check,
+            info,
+            run,
+            dialect: config.dialect()?,
+            globals: Globals::extended_by(&extensions),
+            extensions,
+            root: config.root(),
+            prelude: Vec::new(),
+        };
+        res.prelude = prelude.try_map(|x| {
             let env = Module::new();
-
-            let mut eval = Evaluator::new(&env, &globals);
-            let module = AstModule::parse_file(x, &dialect())?;
+            let mut loader = res.loader();
+            let mut eval = Evaluator::new(&env, &res.globals);
+            eval.set_loader(&mut loader);
+            let module = AstModule::parse_file(x, &res.dialect)?;
             eval.eval_module(module)?;
             env.freeze()
         })?;
-
-        Ok(Self {
-            check,
-            info,
-            run,
-            prelude,
-        })
+        Ok(res)
     }
 
     fn go(&self, file: &str, ast: AstModule) -> impl Iterator<Item = Message> {
@@ -86,7 +103,7 @@ impl Context {
         let file = "expression";
         Self::err(
             file,
-            AstModule::parse(file, content, &dialect()).map(|module| self.go(file, module)),
+            AstModule::parse(file, content, &self.dialect).map(|module| self.go(file, module)),
         )
     }
 
@@ -107,18 +124,61 @@ impl Context {
     ) -> impl Iterator<Item = Message> {
         Self::err(
             filename,
-            AstModule::parse(filename, content, &dialect()).map(|module| self.go(filename, module)),
+            AstModule::parse(filename, content, &self.dialect)
+                .map(|module| self.go(filename, module)),
         )
     }
 
-    fn run(&self, file: &str, ast: AstModule) -> impl Iterator<Item = Message> {
+    /// A [`FileLoader`] which resolves `load()` relative to the root.
+    pub fn loader(&self) -> ContextLoader {
+        ContextLoader {
+            ctx: self,
+            loading: Vec::new(),
+        }
+    }
+
+    /// Create a new module, with the prelude already imported.
+    pub fn new_module(&self) -> Module {
         let env = Module::new();
         for p in &self.prelude {
             env.import_public_symbols(p)
         }
-        let globals = globals();
-        let mut eval = Evaluator::new(&env, &globals);
-        Self::err(file, eval.eval_module(ast).map(|_| iter::empty()))
+        env
+    }
+
+    /// Evaluate `content` in `env`, which is kept alive between calls, so later inputs
+    /// can see the variables defined by earlier ones. Prints the result if it isn't `None`.
+    pub fn interactive(&self, env: &Module, content: String) -> impl Iterator<Item = Message> {
+        let file = "interactive";
+        let mut loader = self.loader();
+        let mut eval = Evaluator::new(env, &self.globals);
+        eval.set_loader(&mut loader);
+        let res = AstModule::parse(file, content, &self.dialect)
+            .and_then(|ast| eval.eval_module(ast))
+            .map(|v| {
+                if !v.is_none() {
+                    println!("{}", v.to_repr());
+                }
+                iter::empty()
+            });
+        eval.take_warnings()
+            .into_iter()
+            .map(move |x| Message::from_warning(file, x))
+            .chain(Self::err(file, res))
+    }
+
+    fn run(&self, file: &str, ast: AstModule) -> impl Iterator<Item = Message> {
+        let env = self.new_module();
+        let mut loader = self.loader();
+        let mut eval = Evaluator::new(&env, &self.globals);
+        eval.set_loader(&mut loader);
+        let res = eval.eval_module(ast);
+        // Report the warnings even if evaluation failed, since they may explain the failure
+        let path = file.to_owned();
+        eval.take_warnings()
+            .into_iter()
+            .map(move |x| Message::from_warning(&path, x))
+            .chain(Self::err(file, res.map(|_| iter::empty())))
     }
 
     fn info(&self, module: &AstModule) {
@@ -130,7 +190,9 @@ impl Context {
     }
 
     fn check(&self, module: &AstModule) -> impl Iterator<Item = Message> {
-        let mut globals = Vec::new();
+        // With a prelude we assume we know all the globals, so can check for undefined variables
+        let global_names = self.globals.names();
+        let mut globals: Vec<&str> = global_names.iter().map(|x| x.as_str()).collect();
         for x in &self.prelude {
             globals.extend(x.names());
         }
@@ -144,10 +206,31 @@ impl Context {
     }
 }
 
-pub fn globals() -> Globals {
-    Globals::extended()
+/// Resolves `load()` paths as files relative to the [`Context`] root,
+/// ignoring any leading `//`.
+pub struct ContextLoader<'a> {
+    ctx: &'a Context,
+    /// The files currently being loaded, so we can spot cycles.
+    loading: Vec<PathBuf>,
 }
 
-pub fn dialect() -> Dialect {
-    Dialect::Extended
+impl FileLoader for ContextLoader<'_> {
+    fn load(&mut self, path: &str) -> anyhow::Result<FrozenModule> {
+        let path = self.ctx.root.join(path.trim_start_match("//"));
+        if self.loading.contains(&path) {
+            return Err(anyhow!("Cycle in `load()` of `{}`", path.display()));
+        }
+        let ast = AstModule::parse_file(&path, &self.ctx.dialect)?;
+        let env = self.ctx.new_module();
+        let ctx = self.ctx;
+        self.loading.push(path);
+        let res = {
+            let mut eval = Evaluator::new(&env, &ctx.globals);
+            eval.set_loader(self);
+            eval.eval_module(ast).map(|_| ())
+        };
+        self.loading.pop();
+        res?;
+        env.freeze()
+    }
 }
@@ -21,22 +21,32 @@ use crate::{
     eval::Context,
     types::{Message as StarlarkMessage, Severity},
 };
-use lsp_server::{Connection, Message, Notification};
+use lsp_server::{Connection, Message, Notification, Request, RequestId, Response};
 use lsp_types::{
     notification::{
         DidChangeTextDocument, DidCloseTextDocument, DidOpenTextDocument, LogMessage,
         PublishDiagnostics,
     },
+    request::{HoverRequest, SignatureHelpRequest},
     Diagnostic, DiagnosticSeverity, DidChangeTextDocumentParams, DidCloseTextDocumentParams,
-    DidOpenTextDocumentParams, InitializeParams, LogMessageParams, MessageType, NumberOrString,
-    Position, PublishDiagnosticsParams, Range, ServerCapabilities, TextDocumentSyncCapability,
-    TextDocumentSyncKind, Url,
+    DidOpenTextDocumentParams, Documentation, Hover, HoverContents, HoverParams,
+    HoverProviderCapability, InitializeParams, LogMessageParams, MarkupContent, MarkupKind,
+    MessageType, NumberOrString, ParameterInformation, ParameterLabel, Position,
+    PublishDiagnosticsParams, Range, ServerCapabilities, SignatureHelp, SignatureHelpOptions,
+    SignatureHelpParams, SignatureInformation, TextDocumentSyncCapability, TextDocumentSyncKind,
+    Url,
 };
 use serde::de::DeserializeOwned;
+use starlark::docs::{Doc, DocItem, FunctionDocs, ParamDocs, ParamKind};
+use std::{cell::RefCell, collections::HashMap};
 
 struct Backend {
     connection: Connection,
     starlark: Context,
+    /// The documentation of the global functions, by the name they are called with.
+    docs: HashMap<String, FunctionDocs>,
+    /// The text of the open documents.
+    documents: RefCell<HashMap<Url, String>>,
 }
 
 fn to_severity(x: Severity) -> DiagnosticSeverity {
@@ -59,7 +69,10 @@ fn to_diagnostic(x: StarlarkMessage) -> Diagnostic {
     Diagnostic::new(
         range,
         Some(to_severity(x.severity)),
-        Some(NumberOrString::String(x.name)),
+        Some(NumberOrString::String(match x.kind {
+            Some(kind) => kind.code().to_owned(),
+            None => x.name,
+        })),
         None,
         x.description,
         None,
@@ -72,6 +85,11 @@ impl Backend {
     fn server_capabilities() -> ServerCapabilities {
         ServerCapabilities {
             text_document_sync: Some(TextDocumentSyncCapability::Kind(TextDocumentSyncKind::Full)),
+            hover_provider: Some(HoverProviderCapability::Simple(true)),
+            signature_help_provider: Some(SignatureHelpOptions {
+                trigger_characters: Some(vec!["(".to_owned(), ",".to_owned()]),
+                ..SignatureHelpOptions::default()
+            }),
             ..ServerCapabilities::default()
         }
     }
@@ -79,9 +97,10 @@ impl Backend {
     fn validate(&self, uri: Url, version: Option<i64>, text: String) {
         let diags = self
             .starlark
-            .file_with_contents(&uri.to_string(), text)
+            .file_with_contents(&uri.to_string(), text.clone())
             .map(to_diagnostic)
             .collect();
+        self.documents.borrow_mut().insert(uri.clone(), text);
         self.publish_diagnostics(uri, diags, version)
     }
 
@@ -104,8 +123,81 @@ impl Backend {
     }
 
     fn did_close(&self, params: DidCloseTextDocumentParams) {
+        self.documents
+            .borrow_mut()
+            .remove(&params.text_document.uri);
         self.publish_diagnostics(params.text_document.uri, Vec::new(), None)
     }
+
+    /// The signature and doc comment of the global function under the cursor. Doesn't
+    /// notice if a local variable shadows the global.
+    fn hover(&self, params: HoverParams) -> Option<Hover> {
+        let params = params.text_document_position_params;
+        let documents = self.documents.borrow();
+        let text = documents.get(&params.text_document.uri)?;
+        let name = name_at(text, to_offset(text, params.position))?;
+        let docs = self.docs.get(name)?;
+        let mut value = format!("```python\ndef {}\n```", docs.signature(name));
+        if let Some(docs) = &docs.docs {
+            value.push_str("\n\n");
+            value.push_str(docs);
+        }
+        Some(Hover {
+            contents: HoverContents::Markup(MarkupContent {
+                kind: MarkupKind::Markdown,
+                value,
+            }),
+            range: None,
+        })
+    }
+
+    /// The signature of the global function whose call the cursor is in, with the
+    /// argument under the cursor picked out.
+    fn signature_help(&self, params: SignatureHelpParams) -> Option<SignatureHelp> {
+        let params = params.text_document_position_params;
+        let documents = self.documents.borrow();
+        let text = documents.get(&params.text_document.uri)?;
+        let (name, mut active) = call_at(text, to_offset(text, params.position))?;
+        let docs = self.docs.get(name)?;
+
+        // Any arguments past a `*args` all go to it
+        if let Some(i) = docs.params.iter().position(|x| x.kind == ParamKind::Args) {
+            active = active.min(i as u32);
+        }
+        let docstring = docs.docstring();
+        let label = docs.signature(name);
+        let parameters = docs
+            .params
+            .iter()
+            .zip(param_offsets(&label, &docs.params))
+            .map(|(p, offsets)| ParameterInformation {
+                label: match offsets {
+                    Some(x) => ParameterLabel::LabelOffsets(x),
+                    None => ParameterLabel::Simple(p.name.clone()),
+                },
+                documentation: docstring.as_ref().and_then(|x| {
+                    // The docstring may write `*args` for the parameter `args`
+                    let (_, desc) = x
+                        .args
+                        .iter()
+                        .find(|(x, _)| x.trim_start_matches('*') == p.name)?;
+                    Some(Documentation::String(desc.clone()))
+                }),
+            })
+            .collect();
+        Some(SignatureHelp {
+            signatures: vec![SignatureInformation {
+                label,
+                documentation: docstring
+                    .filter(|x| !x.summary.is_empty())
+                    .map(|x| Documentation::String(x.summary)),
+                parameters: Some(parameters),
+                active_parameter: None,
+            }],
+            active_signature: Some(0),
+            active_parameter: Some(active),
+        })
+    }
 }
 
 /// The library style pieces
@@ -117,6 +209,10 @@ impl Backend {
             .unwrap()
     }
 
+    fn send_response(&self, x: Response) {
+        self.connection.sender.send(Message::Response(x)).unwrap()
+    }
+
     fn log_message(&self, typ: MessageType, message: &str) {
         self.send_notification(new_notification::<LogMessage>(LogMessageParams {
             typ,
@@ -138,6 +234,14 @@ impl Backend {
                     if self.connection.handle_shutdown(&req)? {
                         return Ok(());
                     }
+                    if let Some(params) = as_request::<HoverRequest>(&req) {
+                        self.send_response(new_response::<HoverRequest>(req.id, self.hover(params)))
+                    } else if let Some(params) = as_request::<SignatureHelpRequest>(&req) {
+                        self.send_response(new_response::<SignatureHelpRequest>(
+                            req.id,
+                            self.signature_help(params),
+                        ))
+                    }
                     // Currently don't handle any other requests
                 }
                 Message::Notification(x) => {
@@ -167,9 +271,13 @@ pub fn server(starlark: Context) -> anyhow::Result<()> {
     let server_capabilities = serde_json::to_value(&Backend::server_capabilities()).unwrap();
     let initialization_params = connection.initialize(server_capabilities)?;
     let initialization_params = serde_json::from_value(initialization_params).unwrap();
+    let mut docs = HashMap::new();
+    function_docs("", starlark.globals.documentation(), &mut docs);
     Backend {
         connection,
         starlark,
+        docs,
+        documents: RefCell::new(HashMap::new()),
     }
     .main_loop(initialization_params)?;
     io_threads.join()?;
@@ -178,6 +286,131 @@ pub fn server(starlark: Context) -> anyhow::Result<()> {
     Ok(())
 }
 
+/// Add the documentation of every function in `docs`, by the name it is called with, e.g.
+/// `json.encode` for the `encode` member of the `json` struct. Methods of types are skipped,
+/// since we can't tell the type of what they are called on.
+fn function_docs(prefix: &str, docs: Vec<Doc>, res: &mut HashMap<String, FunctionDocs>) {
+    for doc in docs {
+        let name = format!("{}{}", prefix, doc.name);
+        match doc.item {
+            DocItem::Function(x) => {
+                res.insert(name, x);
+            }
+            DocItem::Namespace(members) => function_docs(&format!("{}.", name), members, res),
+            DocItem::Type(_) | DocItem::Value(_) => {}
+        }
+    }
+}
+
+/// The byte offset of an LSP position, which counts UTF-16 code units along the line.
+fn to_offset(text: &str, pos: Position) -> usize {
+    let start: usize = text
+        .split_inclusive('\n')
+        .take(pos.line as usize)
+        .map(str::len)
+        .sum();
+    let mut units = 0;
+    for (i, c) in text[start..].char_indices() {
+        if units >= pos.character as usize || c == '\n' {
+            return start + i;
+        }
+        units += c.len_utf16();
+    }
+    text.len()
+}
+
+fn is_name_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+/// The dotted name ending at `end`, e.g. `json.encode`.
+fn name_before(text: &str, end: usize) -> Option<&str> {
+    let start = text[..end]
+        .rfind(|c| !is_name_char(c) && c != '.')
+        .map_or(0, |i| i + 1);
+    let name = text[start..end].trim_start_matches('.');
+    if name.is_empty() {
+        None
+    } else {
+        Some(name)
+    }
+}
+
+/// The dotted name that `offset` is in, up to the end of the name it points at, so
+/// pointing at `json` in `json.encode` gives just `json`.
+fn name_at(text: &str, offset: usize) -> Option<&str> {
+    let end = text[offset..]
+        .find(|c| !is_name_char(c))
+        .map_or(text.len(), |i| offset + i);
+    name_before(text, end)
+}
+
+/// The name of the function whose call `offset` is in, and which argument it is in,
+/// counting from 0. Brackets and commas in strings and comments aren't skipped, so can
+/// confuse it.
+fn call_at(text: &str, offset: usize) -> Option<(&str, u32)> {
+    let mut depth = 0;
+    let mut commas = 0;
+    for (i, c) in text[..offset].char_indices().rev() {
+        match c {
+            ')' | ']' | '}' => depth += 1,
+            '(' if depth == 0 => {
+                return Some((name_before(text, text[..i].trim_end().len())?, commas));
+            }
+            '[' | '{' if depth == 0 => return None,
+            '(' | '[' | '{' => depth -= 1,
+            ',' if depth == 0 => commas += 1,
+            _ => {}
+        }
+    }
+    None
+}
+
+/// Where each parameter name is in a signature, as UTF-16 offsets. Each name is the first
+/// after the previous one which follows a `(`, `, ` or `*`, so isn't part of a type hint.
+fn param_offsets(label: &str, params: &[ParamDocs]) -> Vec<Option<[u32; 2]>> {
+    let utf16 = |i: usize| label[..i].encode_utf16().count() as u32;
+    let mut from = label.find('(').map_or(0, |i| i + 1);
+    let mut res = Vec::with_capacity(params.len());
+    for p in params {
+        let start = label[from..]
+            .match_indices(&p.name)
+            .map(|(i, _)| from + i)
+            .find(|&i| {
+                let before = &label[..i];
+                let after = label[i + p.name.len()..].chars().next();
+                (before.ends_with('(') || before.ends_with(", ") || before.ends_with('*'))
+                    && !after.map_or(false, is_name_char)
+            });
+        res.push(start.map(|start| {
+            from = start + p.name.len();
+            [utf16(start), utf16(from)]
+        }));
+    }
+    res
+}
+
+fn as_request<T>(x: &Request) -> Option<T::Params>
+where
+    T: lsp_types::request::Request,
+    T::Params: DeserializeOwned,
+{
+    if x.method == T::METHOD {
+        let params = serde_json::from_value(x.params.clone())
+            .unwrap_or_else(|err| panic!("Invalid request\nMethod: {}\n error: {}", x.method, err));
+        Some(params)
+    } else {
+        None
+    }
+}
+
+fn new_response<T>(id: RequestId, result: T::Result) -> Response
+where
+    T: lsp_types::request::Request,
+{
+    Response::new_ok(id, result)
+}
+
 fn as_notification<T>(x: &Notification) -> Option<T::Params>
 where
     T: lsp_types::notification::Notification,
@@ -205,3 +438,66 @@ where
         params: serde_json::to_value(&params).unwrap(),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use gazebo::prelude::*;
+
+    #[test]
+    fn test_to_offset() {
+        let text = "x = 1\ny = \"\u{1F600}\" + z\n";
+        assert_eq!(to_offset(text, Position::new(0, 4)), 4);
+        assert_eq!(to_offset(text, Position::new(1, 0)), 6);
+        // The emoji is two UTF-16 code units, but four bytes
+        assert_eq!(&text[to_offset(text, Position::new(1, 11))..], "z\n");
+        assert_eq!(to_offset(text, Position::new(0, 100)), 5);
+        assert_eq!(to_offset(text, Position::new(5, 0)), text.len());
+    }
+
+    #[test]
+    fn test_name_at() {
+        let text = "x = json.encode(y)";
+        assert_eq!(name_at(text, 0), Some("x"));
+        assert_eq!(name_at(text, 5), Some("json"));
+        assert_eq!(name_at(text, 10), Some("json.encode"));
+        assert_eq!(name_at(text, 15), Some("json.encode"));
+        assert_eq!(name_at(text, 2), None);
+        assert_eq!(name_at("'x'.join", 5), Some("join"));
+    }
+
+    #[test]
+    fn test_call_at() {
+        let text = "f(1, g(2, 3), [4, 5], ";
+        assert_eq!(call_at(text, 2), Some(("f", 0)));
+        assert_eq!(call_at(text, 8), Some(("g", 0)));
+        assert_eq!(call_at(text, 11), Some(("g", 1)));
+        assert_eq!(call_at(text, 17), None);
+        assert_eq!(call_at(text, text.len()), Some(("f", 3)));
+        assert_eq!(call_at("json.encode (", 13), Some(("json.encode", 0)));
+        assert_eq!(call_at("(1, ", 4), None);
+    }
+
+    #[test]
+    fn test_param_offsets() {
+        let param = |name: &str, kind| ParamDocs {
+            name: name.to_owned(),
+            kind,
+            positional_only: false,
+            keyword_only: false,
+            typ: None,
+        };
+        let params = [
+            param("a", ParamKind::Required),
+            param("int", ParamKind::Required),
+            param("args", ParamKind::Args),
+        ];
+        let label = "fa(a: int, int: string = \"a\", *args)";
+        let offsets = param_offsets(label, &params);
+        assert_eq!(
+            offsets.map(|x| &label[x.unwrap()[0] as usize..x.unwrap()[1] as usize]),
+            ["a", "int", "args"]
+        );
+        assert_eq!(offsets[1], Some([11, 14]));
+    }
+}
@@ -25,19 +25,32 @@
 // Disagree these are good hints
 #![allow(clippy::type_complexity)]
 
-use crate::types::{LintMessage, Message, Severity};
+use crate::{
+    config::Config,
+    types::{LintMessage, Message, Severity},
+};
 use anyhow::anyhow;
 use eval::Context;
 use gazebo::prelude::*;
 use itertools::Either;
-use rustyline::{error::ReadlineError, Editor};
-use std::{ffi::OsStr, fmt, fmt::Display, fs, path::PathBuf, sync::Arc};
+use std::{
+    ffi::OsStr,
+    fmt,
+    fmt::Display,
+    fs, mem,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
 use structopt::{clap::AppSettings, StructOpt};
 use walkdir::WalkDir;
 
+mod config;
 mod dap;
+mod doc;
 mod eval;
 mod lsp;
+mod repl;
+mod test;
 mod types;
 
 #[derive(Debug, StructOpt)]
@@ -64,6 +77,25 @@ pub struct Args {
     #[structopt(long = "check", help = "Run checks and lints.")]
     check: bool,
 
+    #[structopt(
+        long = "test",
+        help = "Run the `test_*` functions in the given files as unit tests."
+    )]
+    test: bool,
+
+    #[structopt(
+        long = "junit",
+        name = "JUNIT",
+        help = "Write a JUnit XML report of the test results to this file (requires --test)."
+    )]
+    junit: Option<PathBuf>,
+
+    #[structopt(
+        long = "doc",
+        help = "Print reference documentation for the globals, or the exported functions of the given files, as Markdown (or JSON with --json)."
+    )]
+    doc: bool,
+
     #[structopt(long = "info", help = "Show information about the code.")]
     info: bool,
 
@@ -86,6 +118,46 @@ pub struct Args {
     #[structopt(long = "prelude", help = "Files to load in advance.")]
     prelude: Vec<PathBuf>,
 
+    #[structopt(
+        long = "config",
+        name = "CONFIG",
+        help = "JSON file with the dialect, library extensions, prelude and root to use."
+    )]
+    config: Option<PathBuf>,
+
+    #[structopt(
+        long = "dialect",
+        help = "The Starlark dialect, either `standard` or `extended` (the default)."
+    )]
+    dialect: Option<String>,
+
+    #[structopt(
+        long = "enable",
+        number_of_values = 1,
+        use_delimiter = true,
+        help = "Dialect features to turn on, e.g. `f_strings`."
+    )]
+    enable: Vec<String>,
+
+    #[structopt(
+        long = "disable",
+        number_of_values = 1,
+        use_delimiter = true,
+        help = "Dialect features to turn off, e.g. `top_level_stmt`."
+    )]
+    disable: Vec<String>,
+
+    #[structopt(
+        long = "library-extension",
+        number_of_values = 1,
+        use_delimiter = true,
+        help = "Library extensions to make available, e.g. `Json`, or `all`. Defaults to all of them for the extended dialect, and none for the standard dialect."
+    )]
+    library_extensions: Vec<String>,
+
+    #[structopt(long = "root", help = "Directory that `load()` paths are relative to.")]
+    root: Option<PathBuf>,
+
     #[structopt(
         long = "expression",
         short = "e",
@@ -183,21 +255,27 @@ fn drain(xs: impl Iterator<Item = Message>, json: bool, stats: &mut Stats) {
     }
 }
 
-fn interactive(ctx: &Context) -> anyhow::Result<()> {
-    let mut rl = Editor::<()>::new();
-    loop {
-        let readline = rl.readline("$> ");
-        match readline {
-            Ok(line) => {
-                rl.add_history_entry(line.as_str());
-                let mut stats = Stats::default();
-                drain(ctx.expression(line), false, &mut stats);
-            }
-            // User pressed EOF - disconnected terminal, or similar
-            Err(ReadlineError::Interrupted) | Err(ReadlineError::Eof) => return Ok(()),
-            Err(err) => return Err(err.into()),
+fn run_tests(
+    ctx: &Context,
+    files: impl Iterator<Item = PathBuf>,
+    junit: Option<&Path>,
+) -> anyhow::Result<()> {
+    let mut results = Vec::new();
+    for file in files {
+        for x in test::run_file(ctx, &file) {
+            println!("{}", x);
+            results.push(x);
         }
     }
+    if let Some(junit) = junit {
+        fs::write(junit, test::junit_xml(&results))?;
+    }
+    let summary = test::TestSummary::new(&results);
+    println!("{}", summary);
+    if summary.failed > 0 {
+        return Err(anyhow!("Failed with {} failing tests", summary.failed));
+    }
+    Ok(())
 }
 
 fn main() -> anyhow::Result<()> {
@@ -209,13 +287,47 @@ fn main() -> anyhow::Result<()> {
         .as_ref()
         .map_or("bzl", |x| x.as_str())
         .trim_start_match('.');
+    let mut config = match &args.config {
+        None => Config::default(),
+        Some(file) => Config::load(file)?,
+    };
+    config.merge(Config {
+        dialect: args.dialect,
+        enable: args.enable,
+        disable: args.disable,
+        extensions: if args.library_extensions.is_empty() {
+            None
+        } else {
+            Some(args.library_extensions)
+        },
+        prelude: args.prelude,
+        root: args.root,
+    });
+    let prelude = expand_dirs(ext, mem::take(&mut config.prelude)).collect::<Vec<_>>();
     let mut ctx = Context::new(
         args.check,
         args.info,
         !args.check && !args.info,
-        &expand_dirs(ext, args.prelude).collect::<Vec<_>>(),
+        &config,
+        &prelude,
     )?;
 
+    if args.test {
+        return run_tests(
+            &ctx,
+            expand_dirs(ext, expand_args(args.files)?),
+            args.junit.as_deref(),
+        );
+    }
+
+    if args.doc {
+        return doc::run(
+            &ctx,
+            expand_dirs(ext, expand_args(args.files)?).collect(),
+            args.json,
+        );
+    }
+
     let mut stats = Stats::default();
     for _ in 0..args.repeat {
         for e in args.evaluate.clone() {
@@ -230,7 +342,7 @@ fn main() -> anyhow::Result<()> {
     }
 
     if args.interactive {
-        interactive(&ctx)?;
+        repl::interactive(&ctx)?;
     }
 
     if args.lsp {
@@ -0,0 +1,172 @@
+/*
+ * Copyright 2019 The Starlark in Rust Authors.
+ * Copyright (c) Facebook, Inc. and its affiliates.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! An interactive REPL. All the inputs are evaluated in the same [`Module`](starlark::environment::Module),
+//! so later inputs can use the variables and functions defined by earlier ones.
+//!
+//! Input is buffered until it forms a complete statement, so a `def` or a bracketed
+//! expression can be typed over several lines. A compound statement (one whose first line
+//! ends with `:`) is only finished by a blank line, as in Python.
+
+use crate::eval::Context;
+use rustyline::{
+    completion::Completer, error::ReadlineError, highlight::Highlighter, hint::Hinter,
+    validate::Validator, Editor, Helper,
+};
+use starlark::{
+    codemap::Pos,
+    errors::Diagnostic,
+    syntax::{AstModule, Dialect},
+};
+
+/// Tab-completes identifiers from the globals and the variables defined so far.
+struct ReplHelper {
+    names: Vec<String>,
+}
+
+impl Completer for ReplHelper {
+    type Candidate = String;
+
+    fn complete(
+        &self,
+        line: &str,
+        pos: usize,
+        _ctx: &rustyline::Context<'_>,
+    ) -> rustyline::Result<(usize, Vec<String>)> {
+        let start = line[..pos]
+            .char_indices()
+            .rev()
+            .take_while(|(_, c)| c.is_alphanumeric() || *c == '_')
+            .last()
+            .map_or(pos, |(i, _)| i);
+        // Attributes depend on the value, which we don't know until we run the code
+        if line[..start].ends_with('.') {
+            return Ok((start, Vec::new()));
+        }
+        let prefix = &line[start..pos];
+        let mut res: Vec<String> = self
+            .names
+            .iter()
+            .filter(|x| x.starts_with(prefix))
+            .cloned()
+            .collect();
+        res.sort();
+        res.dedup();
+        Ok((start, res))
+    }
+}
+
+impl Hinter for ReplHelper {
+    type Hint = String;
+}
+
+impl Highlighter for ReplHelper {}
+
+impl Validator for ReplHelper {}
+
+impl Helper for ReplHelper {}
+
+/// Is `input` (which ends with a newline) ready to evaluate, or should we read more lines.
+/// Parse errors which happen at the very end of the input are taken as a sign that the
+/// input isn't finished yet, while anything else is reported straight away.
+fn is_complete(input: &str, dialect: &Dialect) -> bool {
+    // A blank line always ends the input, so the user can get out of a broken statement
+    if input.ends_with("\n\n") {
+        return true;
+    }
+    match AstModule::parse("interactive", input.to_owned(), dialect) {
+        Ok(_) => !input.lines().next().unwrap_or("").trim_end().ends_with(':'),
+        Err(e) => match e.downcast_ref::<Diagnostic>() {
+            Some(Diagnostic {
+                span: Some(span), ..
+            }) => {
+                // An unfinished triple-quoted string may carry on over several lines
+                let text = span.file.source_span(span.span);
+                span.span.begin() < Pos::new(input.trim_end().len() as u32)
+                    && !text.contains("\"\"\"")
+                    && !text.contains("'''")
+            }
+            _ => true,
+        },
+    }
+}
+
+pub fn interactive(ctx: &Context) -> anyhow::Result<()> {
+    let env = ctx.new_module();
+    let globals = ctx.globals.names();
+    let mut rl = Editor::new();
+    rl.set_helper(Some(ReplHelper {
+        names: globals.clone(),
+    }));
+    let mut input = String::new();
+    loop {
+        let prompt = if input.is_empty() { "$> " } else { "... " };
+        match rl.readline(prompt) {
+            Ok(line) => {
+                // When reading from a pipe, rather than a terminal, we get the newline too
+                let line = line.trim_end_matches(&['\n', '\r'][..]);
+                if input.is_empty() && line.trim().is_empty() {
+                    continue;
+                }
+                rl.add_history_entry(line);
+                input.push_str(line);
+                input.push('\n');
+                if !is_complete(&input, &ctx.dialect) {
+                    continue;
+                }
+                for x in ctx.interactive(&env, std::mem::take(&mut input)) {
+                    println!("{}", x);
+                }
+                if let Some(helper) = rl.helper_mut() {
+                    helper.names = globals.clone();
+                    helper.names.extend(env.variable_names());
+                }
+            }
+            // Interrupting a partial input throws it away, otherwise we exit
+            Err(ReadlineError::Interrupted) if !input.is_empty() => input.clear(),
+            // User pressed EOF - disconnected terminal, or similar
+            Err(ReadlineError::Interrupted) | Err(ReadlineError::Eof) => return Ok(()),
+            Err(err) => return Err(err.into()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_complete() {
+        let complete = |x: &str| is_complete(x, &Dialect::Extended);
+        assert!(complete("x = 1\n"));
+        assert!(!complete("1 +\n"));
+        // Errors before the end are reported straight away
+        assert!(complete("x = )\n"));
+        assert!(!complete("f(1,\n"));
+        assert!(!complete("x = [1,\n2,\n"));
+        assert!(complete("x = [1,\n2]\n"));
+        assert!(!complete("\"\"\"doc\n"));
+        assert!(complete("\"\"\"doc\n\"\"\"\n"));
+        // Compound statements need a blank line to finish
+        assert!(!complete("def f():\n"));
+        assert!(!complete("def f():\n    return 1\n"));
+        assert!(complete("def f():\n    return 1\n\n"));
+        assert!(!complete("if True:\n    x = 1\nelse:\n"));
+        // A blank line gives up on a broken input, so the error is shown
+        assert!(complete("f(1,\n\n"));
+    }
+}
@@ -0,0 +1,426 @@
+/*
+ * Copyright 2019 The Starlark in Rust Authors.
+ * Copyright (c) Facebook, Inc. and its affiliates.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Run the `test_*` functions defined in Starlark files, in the style of a unit test runner.
+//!
+//! Each test is run in a fresh [`Module`], so a test that mutates module state (or fails half
+//! way through) can't influence any other test. Tests have access to an `assert` struct with
+//! `eq`, `ne`, `contains`, `true` and `fails`.
+
+use crate::eval::Context;
+use anyhow::anyhow;
+use regex::Regex;
+use starlark::{
+    environment::{Globals, GlobalsBuilder, Module},
+    errors::Diagnostic,
+    eval::{Evaluator, FileLoader},
+    syntax::AstModule,
+    values::{function::FUNCTION_TYPE, none::NoneType, Value},
+};
+use starlark_module::starlark_module;
+use std::{
+    fmt::{self, Display, Write},
+    fs,
+    path::Path,
+    time::{Duration, Instant},
+};
+
+/// The `assert` struct available to tests.
+#[starlark_module]
+fn assert_members(builder: &mut GlobalsBuilder) {
+    fn eq(a: Value, b: Value) -> NoneType {
+        if !a.equals(b)? {
+            Err(anyhow!(
+                "assert.eq: expected {}, got {}",
+                a.to_repr(),
+                b.to_repr()
+            ))
+        } else {
+            Ok(NoneType)
+        }
+    }
+
+    fn ne(a: Value, b: Value) -> NoneType {
+        if a.equals(b)? {
+            Err(anyhow!("assert.ne: but {} == {}", a.to_repr(), b.to_repr()))
+        } else {
+            Ok(NoneType)
+        }
+    }
+
+    fn contains(xs: Value, x: Value) -> NoneType {
+        if !xs.is_in(x)? {
+            Err(anyhow!(
+                "assert.contains: expected {} to be in {}",
+                x.to_repr(),
+                xs.to_repr()
+            ))
+        } else {
+            Ok(NoneType)
+        }
+    }
+
+    fn r#true(x: Value, msg @ "": &str) -> NoneType {
+        if !x.to_bool() {
+            if msg.is_empty() {
+                Err(anyhow!("assert.true: got {}", x.to_repr()))
+            } else {
+                Err(anyhow!("assert.true: {}", msg))
+            }
+        } else {
+            Ok(NoneType)
+        }
+    }
+
+    /// Call `f` with no arguments, which must fail with an error matching the regular expression `pattern`.
+    fn fails(f: Value, pattern: &str) -> NoneType {
+        let regex = Regex::new(pattern)?;
+        match f.invoke_pos(None, &[], eval) {
+            Ok(_) => Err(anyhow!("assert.fails: didn't fail")),
+            Err(e) => {
+                let message = error_message(&e);
+                if regex.is_match(&message) {
+                    Ok(NoneType)
+                } else {
+                    Err(anyhow!(
+                        "assert.fails: error `{}` does not match `{}`",
+                        message,
+                        pattern
+                    ))
+                }
+            }
+        }
+    }
+}
+
+/// The underlying message of an error, without the location or call stack.
+fn error_message(e: &anyhow::Error) -> String {
+    let inner = e.downcast_ref::<Diagnostic>().map_or(e, |d| &d.message);
+    format!("{:#}", inner)
+}
+
+fn test_globals(ctx: &Context) -> Globals {
+    GlobalsBuilder::extended_by(&ctx.extensions)
+        .with_struct("assert", assert_members)
+        .build()
+}
+
+/// The outcome of running a single test.
+pub struct TestResult {
+    pub file: String,
+    pub name: String,
+    pub time: Duration,
+    /// [`None`] if the test passed, otherwise the error it failed with.
+    pub failure: Option<anyhow::Error>,
+}
+
+impl Display for TestResult {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.failure {
+            None => write!(f, "PASS: {}::{}", self.file, self.name),
+            Some(e) => write!(f, "FAIL: {}::{}\n{}", self.file, self.name, e),
+        }
+    }
+}
+
+/// Evaluate a parsed file in a fresh module, so we can either find the tests, or run one
+/// of them.
+fn eval_file<'v, 'a>(
+    ctx: &Context,
+    globals: &'a Globals,
+    loader: &'a mut dyn FileLoader,
+    ast: AstModule,
+    env: &'v Module,
+) -> anyhow::Result<Evaluator<'v, 'a>> {
+    for p in &ctx.prelude {
+        env.import_public_symbols(p)
+    }
+    let mut eval = Evaluator::new(env, globals);
+    eval.set_loader(loader);
+    eval.eval_module(ast)?;
+    Ok(eval)
+}
+
+/// Find the names of all the `test_*` functions in a file.
+fn discover(
+    ctx: &Context,
+    globals: &Globals,
+    file: &str,
+    content: &str,
+) -> anyhow::Result<Vec<String>> {
+    let ast = AstModule::parse(file, content.to_owned(), &ctx.dialect)?;
+    // Collect the candidates before the AST is consumed by evaluation
+    let mut names = ast
+        .exported_symbols()
+        .into_iter()
+        .map(|(_, name)| name.to_owned())
+        .filter(|name| name.starts_with("test_"))
+        .collect::<Vec<_>>();
+    let env = Module::new();
+    let mut loader = ctx.loader();
+    eval_file(ctx, globals, &mut loader, ast, &env)?;
+    names.retain(|name| env.get(name).map(|x| x.get_type()) == Some(FUNCTION_TYPE));
+    Ok(names)
+}
+
+fn run_test(
+    ctx: &Context,
+    globals: &Globals,
+    file: &str,
+    content: &str,
+    name: &str,
+) -> anyhow::Result<()> {
+    let ast = AstModule::parse(file, content.to_owned(), &ctx.dialect)?;
+    let env = Module::new();
+    let mut loader = ctx.loader();
+    let mut eval = eval_file(ctx, globals, &mut loader, ast, &env)?;
+    let f = env
+        .get(name)
+        .ok_or_else(|| anyhow!("Test function `{}` not found", name))?;
+    eval.eval_function(f, &[], &[])?;
+    Ok(())
+}
+
+/// Run all the tests in a file. If the file itself fails to load, that is reported
+/// as a single failing test named `<module>`.
+pub fn run_file(ctx: &Context, file: &Path) -> Vec<TestResult> {
+    let filename = file.to_string_lossy().into_owned();
+    let globals = test_globals(ctx);
+    let start = Instant::now();
+    let tests = fs::read_to_string(file)
+        .map_err(anyhow::Error::from)
+        .and_then(|content| Ok((discover(ctx, &globals, &filename, &content)?, content)));
+    let (tests, content) = match tests {
+        Ok(x) => x,
+        Err(e) => {
+            return vec![TestResult {
+                file: filename,
+                name: "<module>".to_owned(),
+                time: start.elapsed(),
+                failure: Some(e),
+            }];
+        }
+    };
+
+    let mut res = Vec::with_capacity(tests.len());
+    for name in tests {
+        let start = Instant::now();
+        let failure = run_test(ctx, &globals, &filename, &content, &name).err();
+        res.push(TestResult {
+            file: filename.clone(),
+            name,
+            time: start.elapsed(),
+            failure,
+        });
+    }
+    res
+}
+
+/// The number of tests which passed and failed, printed at the end of a test run.
+pub struct TestSummary {
+    pub passed: usize,
+    pub failed: usize,
+}
+
+impl TestSummary {
+    pub fn new(results: &[TestResult]) -> Self {
+        let failed = results.iter().filter(|x| x.failure.is_some()).count();
+        Self {
+            passed: results.len() - failed,
+            failed,
+        }
+    }
+}
+
+impl Display for TestSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} tests, {} passed, {} failed",
+            self.passed + self.failed,
+            self.passed,
+            self.failed
+        )
+    }
+}
+
+fn xml_escape(x: &str) -> String {
+    let mut res = String::with_capacity(x.len());
+    for c in x.chars() {
+        match c {
+            '&' => res.push_str("&amp;"),
+            '<' => res.push_str("&lt;"),
+            '>' => res.push_str("&gt;"),
+            '"' => res.push_str("&quot;"),
+            '\'' => res.push_str("&apos;"),
+            c => res.push(c),
+        }
+    }
+    res
+}
+
+/// Produce a JUnit XML report, with one `testsuite` per file.
+pub fn junit_xml(results: &[TestResult]) -> String {
+    // Group by file, preserving the order in which the files were run
+    let mut files: Vec<(&str, Vec<&TestResult>)> = Vec::new();
+    for x in results {
+        match files.last_mut() {
+            Some((file, xs)) if *file == x.file => xs.push(x),
+            _ => files.push((&x.file, vec![x])),
+        }
+    }
+
+    let summary = TestSummary::new(results);
+    let mut res = String::new();
+    // Writing to a String can't fail, so unwrap is safe
+    writeln!(res, r#"<?xml version="1.0" encoding="UTF-8"?>"#).unwrap();
+    writeln!(
+        res,
+        r#"<testsuites tests="{}" failures="{}">"#,
+        summary.passed + summary.failed,
+        summary.failed
+    )
+    .unwrap();
+    for (file, xs) in files {
+        let failures = xs.iter().filter(|x| x.failure.is_some()).count();
+        let time: Duration = xs.iter().map(|x| x.time).sum();
+        writeln!(
+            res,
+            r#"  <testsuite name="{}" tests="{}" failures="{}" time="{:.3}">"#,
+            xml_escape(file),
+            xs.len(),
+            failures,
+            time.as_secs_f64()
+        )
+        .unwrap();
+        for x in xs {
+            write!(
+                res,
+                r#"    <testcase name="{}" classname="{}" time="{:.3}""#,
+                xml_escape(&x.name),
+                xml_escape(file),
+                x.time.as_secs_f64()
+            )
+            .unwrap();
+            match &x.failure {
+                None => writeln!(res, "/>").unwrap(),
+                Some(e) => {
+                    writeln!(res, ">").unwrap();
+                    writeln!(
+                        res,
+                        r#"      <failure message="{}">{}</failure>"#,
+                        xml_escape(&error_message(e)),
+                        xml_escape(&e.to_string())
+                    )
+                    .unwrap();
+                    writeln!(res, "    </testcase>").unwrap();
+                }
+            }
+        }
+        writeln!(res, "  </testsuite>").unwrap();
+    }
+    writeln!(res, "</testsuites>").unwrap();
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+
+    fn context() -> Context {
+        Context::new(false, false, true, &Config::default(), &[]).unwrap()
+    }
+
+    const TESTS: &str = r#"
+def test_pass():
+    assert.fails(lambda: fail("oops"), "oo+ps")
+
+def test_wrong_error():
+    assert.fails(lambda: fail("oops"), "^nope$")
+
+def test_no_error():
+    assert.fails(lambda: None, "oops")
+
+def helper():
+    pass
+
+test_value = 1
+"#;
+
+    #[test]
+    fn test_discover() {
+        let ctx = context();
+        let globals = test_globals(&ctx);
+        assert_eq!(
+            discover(&ctx, &globals, "x.star", TESTS).unwrap(),
+            ["test_pass", "test_wrong_error", "test_no_error"]
+        );
+    }
+
+    #[test]
+    fn test_assert_fails() {
+        let ctx = context();
+        let globals = test_globals(&ctx);
+        let run = |name| {
+            run_test(&ctx, &globals, "x.star", TESTS, name)
+                .err()
+                .map(|e| error_message(&e))
+        };
+        assert_eq!(run("test_pass"), None);
+        assert_eq!(
+            run("test_wrong_error").unwrap(),
+            "assert.fails: error `fail(): oops` does not match `^nope$`"
+        );
+        assert_eq!(run("test_no_error").unwrap(), "assert.fails: didn't fail");
+    }
+
+    #[test]
+    fn test_junit_xml() {
+        let result = |file: &str, name: &str, failure: Option<&str>| TestResult {
+            file: file.to_owned(),
+            name: name.to_owned(),
+            time: Duration::from_millis(1500),
+            failure: failure.map(|x| anyhow!("{}", x.to_owned())),
+        };
+        let results = [
+            result("a.star", "test_one", None),
+            result("a.star", "test_two", Some("expected <1> & \"2\"")),
+            result("b.star", "test_three", None),
+        ];
+        assert_eq!(
+            junit_xml(&results),
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+<testsuites tests="3" failures="1">
+  <testsuite name="a.star" tests="2" failures="1" time="3.000">
+    <testcase name="test_one" classname="a.star" time="1.500"/>
+    <testcase name="test_two" classname="a.star" time="1.500">
+      <failure message="expected &lt;1&gt; &amp; &quot;2&quot;">expected &lt;1&gt; &amp; &quot;2&quot;</failure>
+    </testcase>
+  </testsuite>
+  <testsuite name="b.star" tests="1" failures="0" time="1.500">
+    <testcase name="test_three" classname="b.star" time="1.500"/>
+  </testsuite>
+</testsuites>
+"#
+        );
+        assert_eq!(
+            TestSummary::new(&results).to_string(),
+            "3 tests, 2 passed, 1 failed"
+        );
+    }
+}
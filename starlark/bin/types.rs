@@ -19,7 +19,7 @@ use gazebo::prelude::*;
 use serde::Serialize;
 use starlark::{
     codemap::ResolvedSpan,
-    errors::{Diagnostic, Lint},
+    errors::{Diagnostic, ErrorKind, Lint, Warning},
 };
 use std::fmt::{self, Display};
 
@@ -55,6 +55,8 @@ pub struct Message {
     pub description: String,
     /// The text referred to by span
     pub original: Option<String>,
+    /// The kind of error, if this message is an error
+    pub kind: Option<ErrorKind>,
 }
 
 impl Display for Message {
@@ -73,6 +75,7 @@ impl Message {
             Some(Diagnostic {
                 message,
                 span: Some(span),
+                kind,
                 ..
             }) => {
                 let original = span.file.source_span(span.span).to_owned();
@@ -84,6 +87,7 @@ impl Message {
                     name: "error".to_owned(),
                     description: format!("{:#}", message),
                     original: Some(original),
+                    kind: Some(*kind),
                 }
             }
             _ => Self {
@@ -93,6 +97,30 @@ impl Message {
                 name: "error".to_owned(),
                 description: format!("{:#}", x),
                 original: None,
+                kind: Some(ErrorKind::of(&x)),
+            },
+        }
+    }
+
+    pub fn from_warning(file: &str, x: Warning) -> Self {
+        match x.span {
+            Some(span) => Self {
+                path: span.file.filename().to_owned(),
+                span: Some(span.resolve_span()),
+                severity: Severity::Warning,
+                name: "warning".to_owned(),
+                description: x.message,
+                original: Some(span.file.source_span(span.span).to_owned()),
+                kind: None,
+            },
+            None => Self {
+                path: file.to_owned(),
+                span: None,
+                severity: Severity::Warning,
+                name: "warning".to_owned(),
+                description: x.message,
+                original: None,
+                kind: None,
             },
         }
     }
@@ -110,6 +138,7 @@ impl Message {
             name: x.short_name,
             description: x.problem,
             original: Some(x.original),
+            kind: None,
         }
     }
 }
@@ -127,6 +156,8 @@ pub struct LintMessage {
     description: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     original: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error_code: Option<&'static str>,
 }
 
 impl LintMessage {
@@ -140,6 +171,7 @@ impl LintMessage {
             name: x.name,
             description: Some(x.description),
             original: x.original,
+            error_code: x.kind.map(ErrorKind::code),
         }
     }
 }
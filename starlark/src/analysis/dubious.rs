@@ -47,6 +47,7 @@ fn duplicate_dictionary_key(module: &AstModule, res: &mut Vec<LintT<Dubious>>) {
     enum Key<'a> {
         Int(i32),
         String(&'a str),
+        Bytes(&'a [u8]),
         Identifier(&'a str),
     }
 
@@ -55,6 +56,7 @@ fn duplicate_dictionary_key(module: &AstModule, res: &mut Vec<LintT<Dubious>>) {
             Expr::Literal(x) => match &*x {
                 AstLiteral::IntLiteral(x) => Some((Key::Int(x.node), x.span)),
                 AstLiteral::StringLiteral(x) => Some((Key::String(&x.node), x.span)),
+                AstLiteral::BytesLiteral(x) => Some((Key::Bytes(&x.node), x.span)),
             },
             Expr::Identifier(x) => Some((Key::Identifier(&x.node), x.span)),
             _ => None,
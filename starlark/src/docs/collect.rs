@@ -0,0 +1,161 @@
+/*
+ * Copyright 2019 The Starlark in Rust Authors.
+ * Copyright (c) Facebook, Inc. and its affiliates.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Gathering the documentation for [`Globals`] and the exported functions of an
+//! [`AstModule`].
+
+use crate::{
+    collections::SmallMap,
+    docs::{Doc, DocItem, FunctionDocs, ParamDocs, ParamKind},
+    environment::Globals,
+    syntax::{
+        ast::{AstExpr, AstLiteral, AstParameter, AstStmt, Expr, Parameter, Stmt},
+        AstModule,
+    },
+    values::{bytes::Bytes, dict::Dict, structs::Struct, Heap, Value},
+};
+use std::collections::HashMap;
+
+impl Globals {
+    /// The documentation for everything in these globals, sorted by name, followed by
+    /// the methods of the builtin types which have them, e.g. `string.split`.
+    pub fn documentation(&self) -> Vec<Doc> {
+        let mut res = self
+            .iter()
+            .map(|(name, value)| value_doc(name, value.to_value()))
+            .collect::<Vec<_>>();
+        res.sort_by(|a, b| a.name.cmp(&b.name));
+
+        let heap = Heap::new();
+        let samples = [
+            heap.alloc(""),
+            heap.alloc(Vec::<Value>::new()),
+            heap.alloc(Dict::new(SmallMap::new())),
+            heap.alloc(Bytes::new(Vec::new())),
+            heap.alloc(Struct::new(SmallMap::new())),
+        ];
+        for sample in samples {
+            if let Some(methods) = sample.get_aref().get_methods() {
+                let mut methods = methods
+                    .iter()
+                    .map(|(name, value)| value_doc(name, value.to_value()))
+                    .collect::<Vec<_>>();
+                methods.sort_by(|a, b| a.name.cmp(&b.name));
+                res.push(Doc {
+                    name: sample.get_type().to_owned(),
+                    item: DocItem::Type(methods),
+                });
+            }
+        }
+        res
+    }
+}
+
+fn value_doc(name: &str, value: Value) -> Doc {
+    let item = if let Some(docs) = value.documentation() {
+        DocItem::Function(docs)
+    } else if let Some(x) = Struct::from_value(value) {
+        DocItem::Namespace(x.fields.iter().map(|(k, v)| value_doc(k, *v)).collect())
+    } else {
+        DocItem::Value(value.get_type().to_owned())
+    };
+    Doc {
+        name: name.to_owned(),
+        item,
+    }
+}
+
+impl AstModule {
+    /// The documentation for the exported functions defined with `def`, in the order they
+    /// were defined. Other exported symbols aren't known until the module is evaluated, so
+    /// are skipped.
+    pub fn documentation(&self) -> Vec<Doc> {
+        let mut defs = HashMap::new();
+        self.statement.visit_stmt(|x| {
+            if let Stmt::Def(name, params, return_type, body) = &**x {
+                defs.entry(name.node.as_str())
+                    .or_insert_with(|| def_docs(params, return_type.as_deref(), body));
+            }
+        });
+        self.exported_symbols()
+            .into_iter()
+            .filter_map(|(_, name)| {
+                Some(Doc {
+                    name: name.to_owned(),
+                    item: DocItem::Function(defs.remove(name)?),
+                })
+            })
+            .collect()
+    }
+}
+
+fn def_docs(
+    params: &[AstParameter],
+    return_type: Option<&AstExpr>,
+    body: &AstStmt,
+) -> FunctionDocs {
+    let mut res: Vec<ParamDocs> = Vec::new();
+    let mut keyword_only = false;
+    for param in params {
+        let (name, typ, kind) = match &param.node {
+            Parameter::Normal(name, typ) => (name, typ, ParamKind::Required),
+            Parameter::WithDefaultValue(name, typ, default) => {
+                (name, typ, ParamKind::Defaulted(default.node.to_string()))
+            }
+            Parameter::Args(name, typ) => {
+                keyword_only = true;
+                (name, typ, ParamKind::Args)
+            }
+            Parameter::KwArgs(name, typ) => (name, typ, ParamKind::Kwargs),
+            Parameter::Slash => {
+                res.iter_mut().for_each(|x| x.positional_only = true);
+                continue;
+            }
+            Parameter::NoArgs => {
+                keyword_only = true;
+                continue;
+            }
+        };
+        res.push(ParamDocs {
+            name: name.node.clone(),
+            keyword_only: keyword_only && !matches!(kind, ParamKind::Args | ParamKind::Kwargs),
+            kind,
+            positional_only: false,
+            typ: typ.as_ref().map(|x| x.node.to_string()),
+        });
+    }
+    FunctionDocs {
+        docs: docstring(body),
+        params: res,
+        return_type: return_type.map(|x| x.node.to_string()),
+    }
+}
+
+/// A string literal as the first statement of a function body.
+fn docstring(body: &AstStmt) -> Option<String> {
+    let first = match &body.node {
+        Stmt::Statements(xs) => xs.first()?,
+        _ => body,
+    };
+    match &first.node {
+        Stmt::Expression(AstExpr {
+            node: Expr::Literal(AstLiteral::StringLiteral(x)),
+            ..
+        }) => Some(x.node.clone()),
+        _ => None,
+    }
+}
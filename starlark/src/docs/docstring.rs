@@ -0,0 +1,220 @@
+/*
+ * Copyright 2019 The Starlark in Rust Authors.
+ * Copyright (c) Facebook, Inc. and its affiliates.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Splitting docstrings into their summary, `Args:` and `Returns:` sections.
+
+use serde::Serialize;
+
+/// A docstring, split up into its parts, in the style of
+/// [Google's Python docstrings](https://google.github.io/styleguide/pyguide.html#383-functions-and-methods).
+///
+/// ```
+/// use starlark::docs::DocString;
+///
+/// let doc = DocString::parse(r#"Build a library.
+///
+///     Args:
+///       name: The name of the target.
+///       srcs: The files to compile,
+///         relative to this package.
+///
+///     Returns:
+///       A provider.
+///     "#);
+/// assert_eq!(doc.summary, "Build a library.");
+/// assert_eq!(doc.args[1], ("srcs".to_owned(), "The files to compile, relative to this package.".to_owned()));
+/// assert_eq!(doc.returns.as_deref(), Some("A provider."));
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct DocString {
+    /// The first paragraph.
+    pub summary: String,
+    /// Everything else, apart from the `Args:` and `Returns:` sections.
+    pub details: Option<String>,
+    /// The description of each parameter in the `Args:` section, in order.
+    pub args: Vec<(String, String)>,
+    /// The `Returns:` section.
+    pub returns: Option<String>,
+}
+
+enum Section {
+    Details,
+    Args,
+    Returns,
+}
+
+impl DocString {
+    /// Split up a docstring. Never fails, a docstring without any sections is all summary
+    /// and details.
+    pub fn parse(docs: &str) -> Self {
+        let lines = dedent(docs);
+        let mut lines = lines.iter().map(String::as_str).peekable();
+
+        let mut summary = Vec::new();
+        while let Some(line) = lines.next_if(|x| !x.trim().is_empty()) {
+            summary.push(line.trim());
+        }
+
+        let mut details = Vec::new();
+        let mut args: Vec<(String, String)> = Vec::new();
+        let mut returns = Vec::new();
+        let mut section = Section::Details;
+        let mut args_indent = None;
+        for line in lines {
+            let indented = line.starts_with(char::is_whitespace);
+            match line.trim() {
+                "Args:" | "Arguments:" if !indented => {
+                    section = Section::Args;
+                    args_indent = None;
+                }
+                "Returns:" | "Return:" if !indented => section = Section::Returns,
+                // Blank lines only matter outside the sections
+                "" => {
+                    if let Section::Details = section {
+                        details.push("")
+                    }
+                }
+                text if !indented => {
+                    section = Section::Details;
+                    details.push(text);
+                }
+                text => match section {
+                    Section::Details => details.push(line),
+                    Section::Returns => returns.push(text),
+                    Section::Args => match parse_arg(text) {
+                        // Lines indented more than the first argument continue the previous one
+                        Some((name, desc))
+                            if indent(line) <= *args_indent.get_or_insert(indent(line)) =>
+                        {
+                            args.push((name.to_owned(), desc.to_owned()))
+                        }
+                        _ => match args.last_mut() {
+                            Some((_, desc)) => {
+                                if !desc.is_empty() {
+                                    desc.push(' ');
+                                }
+                                desc.push_str(text);
+                            }
+                            None => details.push(line),
+                        },
+                    },
+                },
+            }
+        }
+
+        let details = details.join("\n").trim_matches('\n').to_owned();
+        Self {
+            summary: summary.join(" "),
+            details: if details.is_empty() {
+                None
+            } else {
+                Some(details)
+            },
+            args,
+            returns: if returns.is_empty() {
+                None
+            } else {
+                Some(returns.join(" "))
+            },
+        }
+    }
+}
+
+/// Remove the indentation the lines after the first have in common, since docstrings
+/// are usually indented to match the code, apart from the first line.
+fn dedent(docs: &str) -> Vec<String> {
+    let mut lines = docs.trim().lines();
+    let first = lines.next().unwrap_or_default().to_owned();
+    let rest = lines.collect::<Vec<_>>();
+    let common = rest
+        .iter()
+        .filter(|x| !x.trim().is_empty())
+        .map(|x| indent(x))
+        .min()
+        .unwrap_or_default();
+    let mut res = vec![first];
+    res.extend(
+        rest.iter()
+            .map(|x| x.get(common..).unwrap_or_default().trim_end().to_owned()),
+    );
+    res
+}
+
+fn indent(line: &str) -> usize {
+    line.len() - line.trim_start().len()
+}
+
+/// A line `name: description` from an `Args:` section, where the name may be `*args`,
+/// or followed by a type, e.g. `name (str)`.
+fn parse_arg(line: &str) -> Option<(&str, &str)> {
+    let (name, desc) = line.split_once(':')?;
+    let name = name.split_whitespace().next()?;
+    let ident = name.trim_start_matches('*');
+    if !ident.is_empty() && ident.chars().all(|c| c.is_alphanumeric() || c == '_') {
+        Some((name, desc.trim()))
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_docstring() {
+        let doc = DocString::parse(
+            r#"Make a rule.
+
+    Some more words
+    about rules.
+
+    Args:
+      name: The name.
+      *deps (list): What it needs,
+        in order.
+      **kwargs: Passed on.
+
+    More words after the arguments.
+
+    Returns:
+      A rule, which
+      can be used.
+    "#,
+        );
+        assert_eq!(
+            doc,
+            DocString {
+                summary: "Make a rule.".to_owned(),
+                details: Some(
+                    "Some more words\nabout rules.\n\nMore words after the arguments.".to_owned()
+                ),
+                args: vec![
+                    ("name".to_owned(), "The name.".to_owned()),
+                    ("*deps".to_owned(), "What it needs, in order.".to_owned()),
+                    ("**kwargs".to_owned(), "Passed on.".to_owned()),
+                ],
+                returns: Some("A rule, which can be used.".to_owned()),
+            }
+        );
+
+        let doc = DocString::parse("Just a summary\nover two lines.");
+        assert_eq!(doc.summary, "Just a summary over two lines.");
+        assert_eq!(doc.details, None);
+        assert!(doc.args.is_empty());
+    }
+}
@@ -0,0 +1,128 @@
+/*
+ * Copyright 2019 The Starlark in Rust Authors.
+ * Copyright (c) Facebook, Inc. and its affiliates.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Writing [`Doc`]s out as Markdown.
+
+use crate::docs::{Doc, DocItem, FunctionDocs};
+use std::{collections::HashSet, fmt::Write};
+
+/// Write out documentation as a Markdown page, with a section for each item, where
+/// namespaces and types have a nested section for each of their members. Wherever a
+/// type hint mentions a documented type, e.g. the `string` in `string | NoneType`, it
+/// links to the section for that type.
+pub fn render_markdown(title: &str, docs: &[Doc]) -> String {
+    let types = docs
+        .iter()
+        .filter(|x| matches!(x.item, DocItem::Type(_)))
+        .map(|x| x.name.as_str())
+        .collect();
+    let mut out = format!("# {}\n", title);
+    for doc in docs {
+        Render { types: &types }.doc(&mut out, 2, "", doc);
+    }
+    out
+}
+
+struct Render<'a> {
+    /// The names of the documented types, which have a section to link to.
+    types: &'a HashSet<&'a str>,
+}
+
+impl Render<'_> {
+    fn doc(&self, out: &mut String, level: usize, prefix: &str, doc: &Doc) {
+        let name = format!("{}{}", prefix, doc.name);
+        writeln!(out, "\n{} {}", "#".repeat(level.min(6)), name).unwrap();
+        match &doc.item {
+            DocItem::Function(docs) => self.function(out, &name, docs),
+            DocItem::Value(typ) => writeln!(out, "\nA value of type {}.", self.typ(typ)).unwrap(),
+            DocItem::Namespace(members) | DocItem::Type(members) => {
+                let prefix = format!("{}.", name);
+                for x in members {
+                    self.doc(out, level + 1, &prefix, x);
+                }
+            }
+        }
+    }
+
+    fn function(&self, out: &mut String, name: &str, docs: &FunctionDocs) {
+        writeln!(out, "\n```python\ndef {}\n```", docs.signature(name)).unwrap();
+        let docstring = docs.docstring();
+        if let Some(docstring) = &docstring {
+            if !docstring.summary.is_empty() {
+                writeln!(out, "\n{}", docstring.summary).unwrap();
+            }
+            if let Some(details) = &docstring.details {
+                writeln!(out, "\n{}", details).unwrap();
+            }
+        }
+        let args = docstring.as_ref().map_or(&[][..], |x| &x.args[..]);
+
+        let mut params = Vec::new();
+        for p in &docs.params {
+            let mut line = format!("* `{}`", p.name);
+            if let Some(typ) = &p.typ {
+                write!(line, " ({})", self.typ(typ)).unwrap();
+            }
+            // The docstring may write `*args` for the parameter `args`
+            if let Some((_, desc)) = args
+                .iter()
+                .find(|(x, _)| x.trim_start_matches('*') == p.name)
+            {
+                write!(line, ": {}", desc).unwrap();
+            }
+            params.push(line);
+        }
+        if !params.is_empty() {
+            writeln!(out, "\n**Parameters:**\n\n{}", params.join("\n")).unwrap();
+        }
+
+        let returns = docstring.as_ref().and_then(|x| x.returns.as_deref());
+        match (&docs.return_type, returns) {
+            (Some(typ), Some(desc)) => writeln!(out, "\n**Returns:** {}: {}", self.typ(typ), desc),
+            (Some(typ), None) => writeln!(out, "\n**Returns:** {}", self.typ(typ)),
+            (None, Some(desc)) => writeln!(out, "\n**Returns:** {}", desc),
+            (None, None) => Ok(()),
+        }
+        .unwrap();
+    }
+
+    /// A type hint, with every name of a documented type linked to its section, and
+    /// anything else escaped so it isn't taken for Markdown.
+    fn typ(&self, typ: &str) -> String {
+        let mut res = String::new();
+        let mut chars = typ.chars().peekable();
+        while let Some(c) = chars.next() {
+            if c.is_alphanumeric() || c == '_' {
+                let mut word = c.to_string();
+                while let Some(c) = chars.next_if(|c| c.is_alphanumeric() || *c == '_') {
+                    word.push(c);
+                }
+                if self.types.contains(word.as_str()) {
+                    write!(res, "[{}](#{})", word, word).unwrap();
+                } else {
+                    res.push_str(&word);
+                }
+            } else {
+                if matches!(c, '[' | ']' | '*' | '<' | '\\' | '`') {
+                    res.push('\\');
+                }
+                res.push(c);
+            }
+        }
+        res
+    }
+}
@@ -0,0 +1,316 @@
+/*
+ * Copyright 2019 The Starlark in Rust Authors.
+ * Copyright (c) Facebook, Inc. and its affiliates.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Documentation for functions, available at runtime from their [`Value`](crate::values::Value)
+//! using [`documentation`](crate::values::Value::documentation), and reference
+//! documentation for whole [`Globals`](crate::environment::Globals) and modules, as
+//! Markdown or JSON.
+//!
+//! Functions defined with [`#[starlark_module]`](macro@crate::starlark_module) get their
+//! doc comment and a signature, with type hints worked out from the Rust types of the
+//! parameters and result, written as the names [`type`](crate::values::Value::get_type)
+//! gives at runtime, e.g. `&str` becomes `string` and `Vec<i32>` becomes `list`.
+//! Functions defined with `def` get their docstring, which [`DocString`] splits into its
+//! summary, `Args:` and `Returns:` sections.
+
+use serde::{Serialize, Serializer};
+
+mod collect;
+mod docstring;
+mod markdown;
+
+pub use docstring::DocString;
+pub use markdown::render_markdown;
+
+/// The documentation for something with a name, e.g. a function or a type.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Doc {
+    pub name: String,
+    pub item: DocItem,
+}
+
+/// What a [`Doc`] documents.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DocItem {
+    /// A function.
+    Function(FunctionDocs),
+    /// A collection of values, e.g. the `json` struct, or a file.
+    Namespace(Vec<Doc>),
+    /// A type, with its methods, e.g. `string`.
+    Type(Vec<Doc>),
+    /// Any other value, with its type, e.g. `True` is a `bool`.
+    Value(String),
+}
+
+/// The documentation for a function.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct FunctionDocs {
+    /// The doc comment, if there was one. Written out split up into a [`DocString`].
+    #[serde(serialize_with = "serialize_docstring")]
+    pub docs: Option<String>,
+    /// The parameters, in order.
+    pub params: Vec<ParamDocs>,
+    /// The type of the result, [`None`] if it could be anything.
+    pub return_type: Option<String>,
+}
+
+/// The documentation for a parameter of a function.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ParamDocs {
+    pub name: String,
+    pub kind: ParamKind,
+    /// Can only be passed by position, not by name.
+    pub positional_only: bool,
+    /// Can only be passed by name, because it comes after a bare `*`.
+    pub keyword_only: bool,
+    /// The type of the parameter, [`None`] if it could be anything.
+    pub typ: Option<String>,
+}
+
+/// How a parameter is passed.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ParamKind {
+    /// Must be passed.
+    Required,
+    /// May be omitted, in which case the function sees `None`.
+    Optional,
+    /// May be omitted, in which case it has this default, written as Starlark.
+    Defaulted(String),
+    /// Collects any extra positional arguments, i.e. `*args`.
+    Args,
+    /// Collects any extra named arguments, i.e. `**kwargs`.
+    Kwargs,
+}
+
+impl FunctionDocs {
+    /// The signature of a function with this documentation, with types, e.g.
+    /// `hasattr(x, name: string, /) -> bool`.
+    pub fn signature(&self, name: &str) -> String {
+        let mut res = format!("{}(", name);
+        let positional_only = self.params.iter().take_while(|x| x.positional_only).count();
+        let mut star = false;
+        for (i, p) in self.params.iter().enumerate() {
+            if i != 0 {
+                res.push_str(", ");
+            }
+            if p.keyword_only && !star {
+                res.push_str("*, ");
+            }
+            match p.kind {
+                ParamKind::Args => {
+                    res.push('*');
+                    star = true;
+                }
+                ParamKind::Kwargs => res.push_str("**"),
+                _ => {}
+            }
+            res.push_str(&p.name);
+            if let Some(typ) = &p.typ {
+                res.push_str(": ");
+                res.push_str(typ);
+            }
+            match &p.kind {
+                ParamKind::Optional => res.push_str(" = None"),
+                ParamKind::Defaulted(x) => {
+                    res.push_str(" = ");
+                    res.push_str(x);
+                }
+                _ => {}
+            }
+            star |= p.keyword_only;
+            if i + 1 == positional_only {
+                res.push_str(", /");
+            }
+        }
+        res.push(')');
+        if let Some(typ) = &self.return_type {
+            res.push_str(" -> ");
+            res.push_str(typ);
+        }
+        res
+    }
+
+    /// The doc comment, split up into its sections.
+    pub fn docstring(&self) -> Option<DocString> {
+        self.docs.as_deref().map(DocString::parse)
+    }
+
+    /// The type of a named parameter, if it has one.
+    pub(crate) fn param_type(&self, name: &str) -> Option<&str> {
+        self.params
+            .iter()
+            .find(|x| x.name == name)
+            .and_then(|x| x.typ.as_deref())
+    }
+}
+
+fn serialize_docstring<S: Serializer>(docs: &Option<String>, s: S) -> Result<S::Ok, S::Error> {
+    docs.as_deref().map(DocString::parse).serialize(s)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        self as starlark,
+        assert::Assert,
+        collections::SmallMap,
+        environment::{Globals, GlobalsBuilder},
+        syntax::{AstModule, Dialect},
+        values::{none::NoneType, Value},
+    };
+    use gazebo::prelude::*;
+    use starlark_module::starlark_module;
+
+    #[starlark_module]
+    fn docs_module(builder: &mut GlobalsBuilder) {
+        /// Repeat a string.
+        ///
+        /// Joins the copies with `sep`.
+        fn repeat(ref text: &str, count @ 2: i32, sep: Option<&str>) -> String {
+            Ok(vec![text; count as usize].join(sep.unwrap_or_default()))
+        }
+
+        fn log(args: Vec<Value>, kwargs: SmallMap<&str, Value>) -> NoneType {
+            let _ = (args, kwargs);
+            Ok(NoneType)
+        }
+    }
+
+    #[test]
+    fn test_native_docs() {
+        let mut a = Assert::new();
+        a.globals_add(docs_module);
+        let repeat = a.pass("repeat");
+        let docs = repeat.value().documentation().unwrap();
+        assert_eq!(
+            docs.docs.as_deref(),
+            Some("Repeat a string.\n\nJoins the copies with `sep`.")
+        );
+        assert_eq!(
+            docs.signature("repeat"),
+            "repeat(text: string, /, count: int = 2, sep: string = None) -> string"
+        );
+
+        let log = a.pass("log");
+        let docs = log.value().documentation().unwrap();
+        assert_eq!(docs.docs, None);
+        assert_eq!(
+            docs.signature("log"),
+            "log(*args: list, **kwargs: dict) -> NoneType"
+        );
+
+        // Bound methods have the documentation of their method
+        let docs = a.pass("'x'.join").value().documentation().unwrap();
+        assert_eq!(docs.return_type.as_deref(), Some("string"));
+        assert_eq!(a.pass("def f(): pass\nf").value().documentation(), None);
+    }
+
+    #[test]
+    fn test_native_type_error() {
+        let mut a = Assert::new();
+        a.globals_add(docs_module);
+        a.fail(
+            "repeat('x', count = 'y')",
+            "Type of parameter `count` doesn't match, expected `int`",
+        );
+    }
+
+    #[test]
+    fn test_module_docs() {
+        let module = AstModule::parse(
+            "lib.bzl",
+            r#"
+def library(name, srcs: [str.type] = [], *, deps = None, **kwargs) -> "provider":
+    """Build a library.
+
+    Args:
+      name: The name of the target.
+      **kwargs: Passed on.
+    """
+    pass
+
+def _private(x):
+    pass
+
+constant = 1
+
+def undocumented(a, /, *args):
+    pass
+"#
+            .to_owned(),
+            &Dialect::Extended,
+        )
+        .unwrap();
+        let docs = module.documentation();
+        assert_eq!(docs.map(|x| x.name.as_str()), ["library", "undocumented"]);
+
+        let library = match &docs[0].item {
+            DocItem::Function(x) => x,
+            x => panic!("Expected a function, got {:?}", x),
+        };
+        assert_eq!(
+            library.signature("library"),
+            r#"library(name, srcs: [str.type] = [], *, deps = None, **kwargs) -> "provider""#
+        );
+        let docstring = library.docstring().unwrap();
+        assert_eq!(docstring.summary, "Build a library.");
+        assert_eq!(docstring.args[1].0, "**kwargs");
+
+        let undocumented = match &docs[1].item {
+            DocItem::Function(x) => x,
+            x => panic!("Expected a function, got {:?}", x),
+        };
+        assert_eq!(undocumented.docs, None);
+        assert_eq!(
+            undocumented.signature("undocumented"),
+            "undocumented(a, /, *args)"
+        );
+    }
+
+    #[test]
+    fn test_markdown() {
+        let globals = GlobalsBuilder::standard().with(docs_module).build();
+        let docs = globals.documentation();
+        let markdown = render_markdown("Globals", &docs);
+        assert!(markdown.starts_with("# Globals\n"));
+        assert!(markdown.contains(
+            "\n## repeat\n\n```python\ndef repeat(text: string, /, count: int = 2, sep: string = None) -> string\n```\n\nRepeat a string.\n"
+        ));
+        // Types of parameters link to the methods of that type
+        assert!(markdown.contains("* `text` ([string](#string))\n"));
+        assert!(markdown.contains("\n## string\n\n### string."));
+        assert!(markdown.contains("\n### string.split\n"));
+        assert!(markdown.contains("\n## True\n\nA value of type bool.\n"));
+        assert!(!markdown.contains("# starlark::assert"));
+
+        let json = serde_json::to_value(&docs).unwrap();
+        let repeat = json
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|x| x["name"] == "repeat")
+            .unwrap();
+        assert_eq!(
+            repeat["item"]["function"]["docs"]["summary"],
+            "Repeat a string."
+        );
+        assert_eq!(Globals::new().documentation().len(), 5);
+    }
+}
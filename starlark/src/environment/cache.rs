@@ -0,0 +1,1548 @@
+/*
+ * Copyright 2019 The Starlark in Rust Authors.
+ * Copyright (c) Facebook, Inc. and its affiliates.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Saving a [`FrozenModule`] as bytes, so another process can load it without
+//! evaluating the module again.
+//!
+//! We save the values of the variables of the module, and everything they point to,
+//! along with the compiled code of its functions, so loading needs neither to parse
+//! nor to compile the module. The code refers to the variables of the module by slot,
+//! so we save which slot each has, and to its constants, which we save with the other
+//! values, and make again on the frozen heap of the new module.
+//!
+//! The code, and the spans in it, are only valid for the same source, dialect and
+//! globals, and the same modules loaded by it, so the bytes begin with a hash of them,
+//! which lets a stale cache be spotted and thrown away.
+
+use crate::{
+    codemap::{CodeMap, Pos, Span},
+    collections::SmallMap,
+    environment::{slots::ModuleSlotId, FrozenModule, Globals, Module},
+    eval::{DefCompiled, FrozenDef},
+    syntax::Dialect,
+    values::{
+        bytes::Bytes,
+        dict::{Dict, FrozenDict},
+        enumeration::{EnumType, FrozenEnumType, FrozenEnumValue},
+        list::{FrozenList, List},
+        range::Range,
+        record::{
+            Field, FieldGen, FrozenField, FrozenRecord, FrozenRecordType, Record, RecordType,
+        },
+        structs::{FrozenStruct, Struct},
+        tuple::{FrozenTuple, Tuple},
+        typing::TypeCompiled,
+        view::{FrozenListView, ListView},
+        FrozenHeap, FrozenValue, Heap, SimpleValue, Value, ValueRef,
+    },
+};
+use gazebo::prelude::*;
+use std::{
+    collections::HashMap,
+    convert::TryInto,
+    hash::{Hash, Hasher},
+    mem,
+    num::NonZeroI32,
+    ptr,
+};
+use thiserror::Error;
+
+/// The start of every cached module.
+const MAGIC: &[u8] = b"starlark";
+
+/// Changed whenever the format changes, so older caches are treated as stale.
+const VERSION: u32 = 2;
+
+#[derive(Debug, Error)]
+enum CacheError {
+    #[error("Can't save a value of type `{0}` in a cached module")]
+    Unsupported(String),
+    #[error("Can't save function `{0}` in a cached module, as it comes from a module which wasn't loaded")]
+    ForeignFunction(String),
+    #[error("Can't save a constant of type `{0}` in the code of a cached module")]
+    UnsupportedConstant(String),
+    #[error("Cached module is corrupt")]
+    Corrupt,
+    #[error("Cached module refers to global `{0}`, which doesn't exist")]
+    MissingGlobal(String),
+    #[error("Cached module has a value of type `{0}`, which isn't registered")]
+    UnregisteredNative(String),
+}
+
+/// A native value which can be saved in a cached module, once registered with
+/// [`ModuleCache::register`].
+pub trait SerializeValue: SimpleValue + Sized {
+    /// Turn the value into bytes.
+    fn serialize(&self) -> Vec<u8>;
+
+    /// Turn the bytes from [`serialize`](SerializeValue::serialize) back into the value.
+    fn deserialize(bytes: &[u8]) -> anyhow::Result<Self>;
+}
+
+impl SerializeValue for Range {
+    fn serialize(&self) -> Vec<u8> {
+        let mut res = Vec::with_capacity(12);
+        for x in [self.start, self.stop, self.step.get()] {
+            res.extend(x.to_le_bytes());
+        }
+        res
+    }
+
+    fn deserialize(bytes: &[u8]) -> anyhow::Result<Self> {
+        let mut r = Reader(bytes);
+        let start = r.u32()? as i32;
+        let stop = r.u32()? as i32;
+        let step = NonZeroI32::new(r.u32()? as i32).ok_or(CacheError::Corrupt)?;
+        r.end()?;
+        Ok(Range::new(start, stop, step))
+    }
+}
+
+impl SerializeValue for Bytes {
+    fn serialize(&self) -> Vec<u8> {
+        self.as_bytes().to_vec()
+    }
+
+    fn deserialize(bytes: &[u8]) -> anyhow::Result<Self> {
+        Ok(Bytes::new(bytes.to_vec()))
+    }
+}
+
+/// A type registered with [`ModuleCache::register`].
+struct Native {
+    name: &'static str,
+    save: fn(FrozenValue) -> Option<Vec<u8>>,
+    load: for<'v> fn(&[u8], &'v Heap) -> anyhow::Result<Value<'v>>,
+    /// Load the value as a constant in compiled code.
+    load_frozen: fn(&[u8], &FrozenHeap) -> anyhow::Result<FrozenValue>,
+}
+
+fn save_native<T: SerializeValue>(x: FrozenValue) -> Option<Vec<u8>> {
+    x.downcast_frozen_ref::<T>().map(|x| x.serialize())
+}
+
+fn load_native<'v, T: SerializeValue>(bytes: &[u8], heap: &'v Heap) -> anyhow::Result<Value<'v>> {
+    Ok(heap.alloc_simple(T::deserialize(bytes)?))
+}
+
+fn load_native_frozen<T: SerializeValue>(
+    bytes: &[u8],
+    heap: &FrozenHeap,
+) -> anyhow::Result<FrozenValue> {
+    Ok(heap.alloc_simple(T::deserialize(bytes)?))
+}
+
+struct CachedLoad<'a> {
+    name: String,
+    module: &'a FrozenModule,
+    hash: u64,
+}
+
+/// Everything a cached module depends on, which must be the same when it is loaded
+/// as when it was saved, with [`FrozenModule::serialize`] and
+/// [`FrozenModule::deserialize`].
+///
+/// Changes to the source, the dialect, the names of the globals, or the hashes of the
+/// loaded modules all change the [`hash`](ModuleCache::hash), so make the cache stale.
+/// Changes to what the native functions do don't, so a tool should keep its caches
+/// apart from those of other versions of itself.
+pub struct ModuleCache<'a> {
+    filename: &'a str,
+    source: &'a str,
+    dialect: &'a Dialect,
+    globals: &'a Globals,
+    loads: Vec<CachedLoad<'a>>,
+    natives: Vec<Native>,
+}
+
+impl<'a> ModuleCache<'a> {
+    /// The module in `filename`, with the code `source`, which is parsed with `dialect`
+    /// and run with `globals`.
+    pub fn new(
+        filename: &'a str,
+        source: &'a str,
+        dialect: &'a Dialect,
+        globals: &'a Globals,
+    ) -> Self {
+        let mut res = Self {
+            filename,
+            source,
+            dialect,
+            globals,
+            loads: Vec::new(),
+            natives: Vec::new(),
+        };
+        res.register::<Range>();
+        res.register::<Bytes>();
+        res
+    }
+
+    /// Add a module which the code loads as `load(name, ...)`, whose own cache has the
+    /// hash `hash`. Loads must be added in the same order when saving and loading.
+    pub fn add_load(&mut self, name: &str, module: &'a FrozenModule, hash: u64) {
+        self.loads.push(CachedLoad {
+            name: name.to_owned(),
+            module,
+            hash,
+        })
+    }
+
+    /// Allow values of type `T` in the module.
+    pub fn register<T: SerializeValue>(&mut self) {
+        self.natives.push(Native {
+            name: std::any::type_name::<T>(),
+            save: save_native::<T>,
+            load: load_native::<T>,
+            load_frozen: load_native_frozen::<T>,
+        })
+    }
+
+    /// A hash of everything the cached module depends on, which is stable across
+    /// processes, so modules which load this one can include it in their own hash.
+    pub fn hash(&self) -> u64 {
+        let mut h = StableHasher::new();
+        VERSION.hash(&mut h);
+        env!("CARGO_PKG_VERSION").hash(&mut h);
+        self.filename.hash(&mut h);
+        self.source.hash(&mut h);
+        self.dialect.hash(&mut h);
+        self.globals.names().hash(&mut h);
+        for x in &self.loads {
+            x.name.hash(&mut h);
+            x.hash.hash(&mut h);
+        }
+        h.finish()
+    }
+
+    /// The value of an [`Entry::Global`].
+    fn global(&self, path: &str) -> anyhow::Result<FrozenValue> {
+        let mut parts = path.splitn(2, '.');
+        let mut res = self.globals.get_frozen(parts.next().unwrap());
+        if let Some(field) = parts.next() {
+            res = res
+                .and_then(|x| x.downcast_frozen_ref::<FrozenStruct>())
+                .and_then(|x| x.fields.get(field).copied());
+        }
+        Ok(res.ok_or_else(|| CacheError::MissingGlobal(path.to_owned()))?)
+    }
+
+    /// The value of an [`Entry::Load`].
+    fn loaded(&self, load: u32, name: &str) -> anyhow::Result<FrozenValue> {
+        let load = self.loads.get(load as usize).ok_or(CacheError::Corrupt)?;
+        let data = load.module.data();
+        let res = data.names.get_name(name).and_then(|x| data.get_slot(x));
+        Ok(res.ok_or(CacheError::Corrupt)?)
+    }
+
+    fn native(&self, name: &str) -> anyhow::Result<&Native> {
+        let res = self.natives.iter().find(|x| x.name == name);
+        Ok(res.ok_or_else(|| CacheError::UnregisteredNative(name.to_owned()))?)
+    }
+}
+
+/// FNV-1a, which unlike the hasher in `std` is sure to give the same hash in every
+/// process, and with every version of Rust.
+struct StableHasher(u64);
+
+impl StableHasher {
+    fn new() -> Self {
+        Self(0xcbf29ce484222325)
+    }
+}
+
+impl Hasher for StableHasher {
+    fn finish(&self) -> u64 {
+        self.0
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for x in bytes {
+            self.0 = (self.0 ^ *x as u64).wrapping_mul(0x100000001b3);
+        }
+    }
+}
+
+/// A saved value, which refers to the values inside it by their index.
+#[derive(Clone)]
+enum Entry {
+    None,
+    Bool(bool),
+    Int(i32),
+    Str(String),
+    List(Vec<u32>),
+    Tuple(Vec<u32>),
+    /// A list view, with its width, whether it is of tuples, and its values.
+    ListView(u32, bool, Vec<u32>),
+    Dict(Vec<(u32, u32)>),
+    Struct(Vec<(String, u32)>),
+    EnumType(Option<String>, Vec<u32>),
+    EnumValue(u32, u32),
+    Field(u32, Option<u32>),
+    RecordType(Option<String>, Vec<(String, u32, Option<u32>)>),
+    Record(u32, Vec<u32>),
+    /// A function, whose definition has the given index in the code.
+    Def {
+        code: u32,
+        defaults: Vec<u32>,
+        types: Vec<(u32, u32)>,
+        return_type: Option<u32>,
+        captured: Vec<Option<u32>>,
+    },
+    /// A global, or a field of a global struct, as `a.b`.
+    Global(String),
+    /// A variable of the module loaded by the load with this index.
+    Load(u32, String),
+    Native(String, Vec<u8>),
+}
+
+struct Writer(Vec<u8>);
+
+impl Writer {
+    fn u8(&mut self, x: u8) {
+        self.0.push(x)
+    }
+
+    fn u32(&mut self, x: u32) {
+        self.0.extend(x.to_le_bytes())
+    }
+
+    fn bytes(&mut self, x: &[u8]) {
+        self.u32(x.len() as u32);
+        self.0.extend(x)
+    }
+
+    fn str(&mut self, x: &str) {
+        self.bytes(x.as_bytes())
+    }
+
+    fn list<T>(&mut self, xs: &[T], mut f: impl FnMut(&mut Self, &T)) {
+        self.u32(xs.len() as u32);
+        for x in xs {
+            f(self, x)
+        }
+    }
+
+    fn option<T>(&mut self, x: &Option<T>, f: impl FnOnce(&mut Self, &T)) {
+        match x {
+            None => self.u8(0),
+            Some(x) => {
+                self.u8(1);
+                f(self, x)
+            }
+        }
+    }
+
+    fn ids(&mut self, xs: &[u32]) {
+        self.list(xs, |w, x| w.u32(*x))
+    }
+
+    fn id(&mut self, x: &Option<u32>) {
+        self.option(x, |w, x| w.u32(*x))
+    }
+}
+
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> anyhow::Result<&'a [u8]> {
+        if self.0.len() < n {
+            return Err(CacheError::Corrupt.into());
+        }
+        let (res, rest) = self.0.split_at(n);
+        self.0 = rest;
+        Ok(res)
+    }
+
+    fn end(&self) -> anyhow::Result<()> {
+        if self.0.is_empty() {
+            Ok(())
+        } else {
+            Err(CacheError::Corrupt.into())
+        }
+    }
+
+    fn u8(&mut self) -> anyhow::Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u32(&mut self) -> anyhow::Result<u32> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn u64(&mut self) -> anyhow::Result<u64> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    fn bytes(&mut self) -> anyhow::Result<&'a [u8]> {
+        let n = self.u32()?;
+        self.take(n as usize)
+    }
+
+    fn string(&mut self) -> anyhow::Result<String> {
+        match std::str::from_utf8(self.bytes()?) {
+            Ok(x) => Ok(x.to_owned()),
+            Err(_) => Err(CacheError::Corrupt.into()),
+        }
+    }
+
+    fn list<T>(
+        &mut self,
+        mut f: impl FnMut(&mut Self) -> anyhow::Result<T>,
+    ) -> anyhow::Result<Vec<T>> {
+        let n = self.u32()?;
+        // Don't trust the length to reserve space, as the bytes may be corrupt
+        let mut res = Vec::new();
+        for _ in 0..n {
+            res.push(f(self)?);
+        }
+        Ok(res)
+    }
+
+    fn option<T>(
+        &mut self,
+        f: impl FnOnce(&mut Self) -> anyhow::Result<T>,
+    ) -> anyhow::Result<Option<T>> {
+        match self.u8()? {
+            0 => Ok(None),
+            1 => Ok(Some(f(self)?)),
+            _ => Err(CacheError::Corrupt.into()),
+        }
+    }
+
+    fn ids(&mut self) -> anyhow::Result<Vec<u32>> {
+        self.list(Self::u32)
+    }
+
+    fn id(&mut self) -> anyhow::Result<Option<u32>> {
+        self.option(Self::u32)
+    }
+}
+
+/// Writes the compiled code of a function, saving the constants it uses as entries,
+/// and the functions defined inside it as code of their own.
+pub(crate) struct CodeWriter<'s, 'a, 'c> {
+    saver: &'s mut Saver<'a, 'c>,
+    w: Writer,
+}
+
+impl CodeWriter<'_, '_, '_> {
+    pub(crate) fn u8(&mut self, x: u8) {
+        self.w.u8(x)
+    }
+
+    pub(crate) fn u32(&mut self, x: u32) {
+        self.w.u32(x)
+    }
+
+    pub(crate) fn bool(&mut self, x: bool) {
+        self.w.u8(x as u8)
+    }
+
+    pub(crate) fn char(&mut self, x: Option<char>) {
+        self.w.option(&x, |w, x| w.u32(*x as u32))
+    }
+
+    pub(crate) fn str(&mut self, x: &str) {
+        self.w.str(x)
+    }
+
+    pub(crate) fn span(&mut self, x: Span) {
+        self.w.u32(x.begin().get());
+        self.w.u32(x.end().get())
+    }
+
+    /// A constant, which must be one [`CodeReader::value`] can make on a frozen heap.
+    pub(crate) fn value(&mut self, x: FrozenValue) -> anyhow::Result<()> {
+        self.saver.check_constant(x)?;
+        let id = self.saver.add(x)?;
+        self.w.u32(id);
+        Ok(())
+    }
+
+    /// A function defined inside the code.
+    pub(crate) fn def(&mut self, x: &DefCompiled) -> anyhow::Result<()> {
+        let id = self.saver.add_code(x)?;
+        self.w.u32(id);
+        Ok(())
+    }
+
+    pub(crate) fn list<T>(
+        &mut self,
+        xs: &[T],
+        mut f: impl FnMut(&mut Self, &T) -> anyhow::Result<()>,
+    ) -> anyhow::Result<()> {
+        self.w.u32(xs.len() as u32);
+        for x in xs {
+            f(self, x)?;
+        }
+        Ok(())
+    }
+
+    pub(crate) fn option<T>(
+        &mut self,
+        x: &Option<T>,
+        f: impl FnOnce(&mut Self, &T) -> anyhow::Result<()>,
+    ) -> anyhow::Result<()> {
+        match x {
+            None => {
+                self.w.u8(0);
+                Ok(())
+            }
+            Some(x) => {
+                self.w.u8(1);
+                f(self, x)
+            }
+        }
+    }
+}
+
+/// Reads code written by [`CodeWriter`].
+pub(crate) struct CodeReader<'r, 'x> {
+    r: Reader<'x>,
+    loader: &'r mut CodeLoader<'x>,
+}
+
+impl CodeReader<'_, '_> {
+    /// The error for code which doesn't make sense.
+    pub(crate) fn corrupt() -> anyhow::Error {
+        CacheError::Corrupt.into()
+    }
+
+    /// The heap for any values the code needs.
+    pub(crate) fn heap(&self) -> &FrozenHeap {
+        self.loader.heap
+    }
+
+    pub(crate) fn u8(&mut self) -> anyhow::Result<u8> {
+        self.r.u8()
+    }
+
+    pub(crate) fn u32(&mut self) -> anyhow::Result<u32> {
+        self.r.u32()
+    }
+
+    pub(crate) fn bool(&mut self) -> anyhow::Result<bool> {
+        match self.r.u8()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(Self::corrupt()),
+        }
+    }
+
+    pub(crate) fn char(&mut self) -> anyhow::Result<Option<char>> {
+        self.r
+            .option(|r| std::char::from_u32(r.u32()?).ok_or_else(Self::corrupt))
+    }
+
+    pub(crate) fn string(&mut self) -> anyhow::Result<String> {
+        self.r.string()
+    }
+
+    pub(crate) fn span(&mut self) -> anyhow::Result<Span> {
+        let begin = self.r.u32()?;
+        let end = self.r.u32()?;
+        if begin > end {
+            return Err(Self::corrupt());
+        }
+        Ok(Span::new(Pos::new(begin), Pos::new(end)))
+    }
+
+    pub(crate) fn value(&mut self) -> anyhow::Result<FrozenValue> {
+        let id = self.r.u32()?;
+        self.loader.constant(id)
+    }
+
+    pub(crate) fn def(&mut self) -> anyhow::Result<DefCompiled> {
+        let id = self.r.u32()?;
+        self.loader.def(id)
+    }
+
+    pub(crate) fn list<T>(
+        &mut self,
+        mut f: impl FnMut(&mut Self) -> anyhow::Result<T>,
+    ) -> anyhow::Result<Vec<T>> {
+        let n = self.r.u32()?;
+        // Don't trust the length to reserve space, as the bytes may be corrupt
+        let mut res = Vec::new();
+        for _ in 0..n {
+            res.push(f(self)?);
+        }
+        Ok(res)
+    }
+
+    pub(crate) fn option<T>(
+        &mut self,
+        f: impl FnOnce(&mut Self) -> anyhow::Result<T>,
+    ) -> anyhow::Result<Option<T>> {
+        match self.r.u8()? {
+            0 => Ok(None),
+            1 => Ok(Some(f(self)?)),
+            _ => Err(Self::corrupt()),
+        }
+    }
+}
+
+impl Entry {
+    fn write(&self, w: &mut Writer) {
+        match self {
+            Self::None => w.u8(0),
+            Self::Bool(x) => {
+                w.u8(1);
+                w.u8(*x as u8)
+            }
+            Self::Int(x) => {
+                w.u8(2);
+                w.u32(*x as u32)
+            }
+            Self::Str(x) => {
+                w.u8(3);
+                w.str(x)
+            }
+            Self::List(xs) => {
+                w.u8(4);
+                w.ids(xs)
+            }
+            Self::Tuple(xs) => {
+                w.u8(5);
+                w.ids(xs)
+            }
+            Self::Dict(xs) => {
+                w.u8(6);
+                w.list(xs, |w, (k, v)| {
+                    w.u32(*k);
+                    w.u32(*v)
+                })
+            }
+            Self::Struct(xs) => {
+                w.u8(7);
+                w.list(xs, |w, (k, v)| {
+                    w.str(k);
+                    w.u32(*v)
+                })
+            }
+            Self::EnumType(name, xs) => {
+                w.u8(8);
+                w.option(name, |w, x| w.str(x));
+                w.ids(xs)
+            }
+            Self::EnumValue(typ, index) => {
+                w.u8(9);
+                w.u32(*typ);
+                w.u32(*index)
+            }
+            Self::Field(typ, default) => {
+                w.u8(10);
+                w.u32(*typ);
+                w.id(default)
+            }
+            Self::RecordType(name, fields) => {
+                w.u8(11);
+                w.option(name, |w, x| w.str(x));
+                w.list(fields, |w, (name, typ, default)| {
+                    w.str(name);
+                    w.u32(*typ);
+                    w.id(default)
+                })
+            }
+            Self::Record(typ, xs) => {
+                w.u8(12);
+                w.u32(*typ);
+                w.ids(xs)
+            }
+            Self::Def {
+                code,
+                defaults,
+                types,
+                return_type,
+                captured,
+            } => {
+                w.u8(13);
+                w.u32(*code);
+                w.ids(defaults);
+                w.list(types, |w, (i, t)| {
+                    w.u32(*i);
+                    w.u32(*t)
+                });
+                w.id(return_type);
+                w.list(captured, |w, x| w.id(x))
+            }
+            Self::Global(x) => {
+                w.u8(14);
+                w.str(x)
+            }
+            Self::Load(i, x) => {
+                w.u8(15);
+                w.u32(*i);
+                w.str(x)
+            }
+            Self::Native(name, x) => {
+                w.u8(16);
+                w.str(name);
+                w.bytes(x)
+            }
+            Self::ListView(width, tuples, xs) => {
+                w.u8(17);
+                w.u32(*width);
+                w.u8(*tuples as u8);
+                w.ids(xs)
+            }
+        }
+    }
+
+    fn read(r: &mut Reader) -> anyhow::Result<Self> {
+        Ok(match r.u8()? {
+            0 => Self::None,
+            1 => Self::Bool(r.u8()? != 0),
+            2 => Self::Int(r.u32()? as i32),
+            3 => Self::Str(r.string()?),
+            4 => Self::List(r.ids()?),
+            5 => Self::Tuple(r.ids()?),
+            6 => Self::Dict(r.list(|r| Ok((r.u32()?, r.u32()?)))?),
+            7 => Self::Struct(r.list(|r| Ok((r.string()?, r.u32()?)))?),
+            8 => Self::EnumType(r.option(Reader::string)?, r.ids()?),
+            9 => Self::EnumValue(r.u32()?, r.u32()?),
+            10 => Self::Field(r.u32()?, r.id()?),
+            11 => Self::RecordType(
+                r.option(Reader::string)?,
+                r.list(|r| Ok((r.string()?, r.u32()?, r.id()?)))?,
+            ),
+            12 => Self::Record(r.u32()?, r.ids()?),
+            13 => Self::Def {
+                code: r.u32()?,
+                defaults: r.ids()?,
+                types: r.list(|r| Ok((r.u32()?, r.u32()?)))?,
+                return_type: r.id()?,
+                captured: r.list(Reader::id)?,
+            },
+            14 => Self::Global(r.string()?),
+            15 => Self::Load(r.u32()?, r.string()?),
+            16 => Self::Native(r.string()?, r.bytes()?.to_vec()),
+            17 => Self::ListView(r.u32()?, r.u8()? != 0, r.ids()?),
+            _ => return Err(CacheError::Corrupt.into()),
+        })
+    }
+}
+
+/// Turns the values of a module into entries.
+struct Saver<'a, 'c> {
+    cache: &'c ModuleCache<'a>,
+    module: &'c FrozenModule,
+    /// Values which are globals, or come from loaded modules, by pointer.
+    known: HashMap<usize, Entry>,
+    /// The index of each value we have seen, by pointer.
+    ids: HashMap<usize, u32>,
+    entries: Vec<Entry>,
+    /// The index of each function definition we have seen, by [`DefCompiled::id`].
+    code_ids: HashMap<usize, u32>,
+    /// The code of each function definition, written by [`DefCompiled::save`].
+    code: Vec<Vec<u8>>,
+}
+
+impl<'a, 'c> Saver<'a, 'c> {
+    fn new(cache: &'c ModuleCache<'a>, module: &'c FrozenModule) -> Self {
+        let mut known = HashMap::new();
+        // Ints and the like have no identity, so shouldn't be taken for the global
+        let mut add = |x: FrozenValue, entry: Entry| {
+            if x.unpack_int().is_none() && x.unpack_bool().is_none() && !x.is_none() {
+                known.entry(x.to_value().ptr_value()).or_insert(entry);
+            }
+        };
+        for (name, x) in cache.globals.iter() {
+            add(x, Entry::Global(name.to_owned()));
+            if let Some(s) = x.downcast_frozen_ref::<FrozenStruct>() {
+                for (field, x) in &s.fields {
+                    add(*x, Entry::Global(format!("{}.{}", name, field)));
+                }
+            }
+        }
+        for (i, load) in cache.loads.iter().enumerate() {
+            let data = load.module.data();
+            for (name, slot) in data.names.symbols() {
+                if let Some(x) = data.get_slot(*slot) {
+                    add(x, Entry::Load(i as u32, name.clone()));
+                }
+            }
+        }
+        Self {
+            cache,
+            module,
+            known,
+            ids: HashMap::new(),
+            entries: Vec::new(),
+            code_ids: HashMap::new(),
+            code: Vec::new(),
+        }
+    }
+
+    fn add_code(&mut self, def: &DefCompiled) -> anyhow::Result<u32> {
+        if let Some(id) = self.code_ids.get(&def.id()) {
+            return Ok(*id);
+        }
+        let id = self.code.len() as u32;
+        self.code.push(Vec::new());
+        self.code_ids.insert(def.id(), id);
+        let mut w = CodeWriter {
+            saver: self,
+            w: Writer(Vec::new()),
+        };
+        def.save(&mut w)?;
+        self.code[id as usize] = w.w.0;
+        Ok(id)
+    }
+
+    /// Check a constant in compiled code is one which [`CodeLoader::constant`] can make.
+    fn check_constant(&self, x: FrozenValue) -> anyhow::Result<()> {
+        if self.known.contains_key(&x.to_value().ptr_value())
+            || x.is_none()
+            || x.unpack_bool().is_some()
+            || x.unpack_int().is_some()
+            || x.unpack_str().is_some()
+            || self.cache.natives.iter().any(|n| (n.save)(x).is_some())
+        {
+            Ok(())
+        } else if let Some(x) = x.downcast_frozen_ref::<FrozenTuple>() {
+            x.content.iter().try_for_each(|x| self.check_constant(*x))
+        } else {
+            Err(CacheError::UnsupportedConstant(x.to_value().get_type().to_owned()).into())
+        }
+    }
+
+    fn add(&mut self, x: FrozenValue) -> anyhow::Result<u32> {
+        let key = x.to_value().ptr_value();
+        if let Some(id) = self.ids.get(&key) {
+            return Ok(*id);
+        }
+        // Take the index before looking inside, so cycles find it
+        let id = self.entries.len() as u32;
+        self.entries.push(Entry::None);
+        self.ids.insert(key, id);
+        let entry = match self.known.get(&key) {
+            Some(entry) => entry.clone(),
+            None => self.entry(x)?,
+        };
+        self.entries[id as usize] = entry;
+        Ok(id)
+    }
+
+    fn add_all<'x>(
+        &mut self,
+        xs: impl IntoIterator<Item = &'x FrozenValue>,
+    ) -> anyhow::Result<Vec<u32>> {
+        xs.into_iter().map(|x| self.add(*x)).collect()
+    }
+
+    fn add_opt(&mut self, x: Option<FrozenValue>) -> anyhow::Result<Option<u32>> {
+        x.map(|x| self.add(x)).transpose()
+    }
+
+    fn entry(&mut self, x: FrozenValue) -> anyhow::Result<Entry> {
+        Ok(if x.is_none() {
+            Entry::None
+        } else if let Some(x) = x.unpack_bool() {
+            Entry::Bool(x)
+        } else if let Some(x) = x.unpack_int() {
+            Entry::Int(x)
+        } else if let Some(x) = x.unpack_str() {
+            Entry::Str(x.to_owned())
+        } else if let Some(x) = x.downcast_frozen_ref::<FrozenList>() {
+            Entry::List(self.add_all(&x.content)?)
+        } else if let Some(x) = x.downcast_frozen_ref::<FrozenTuple>() {
+            Entry::Tuple(self.add_all(&x.content)?)
+        } else if let Some(x) = x.downcast_frozen_ref::<FrozenListView>() {
+            let (width, tuples) = x.shape();
+            Entry::ListView(width as u32, tuples, self.add_all(x.content())?)
+        } else if let Some(x) = x.downcast_frozen_ref::<FrozenDict>() {
+            let mut res = Vec::with_capacity(x.content.len());
+            for (k, v) in &x.content {
+                res.push((self.add(*k)?, self.add(*v)?));
+            }
+            Entry::Dict(res)
+        } else if let Some(x) = x.downcast_frozen_ref::<FrozenStruct>() {
+            let mut res = Vec::with_capacity(x.fields.len());
+            for (k, v) in &x.fields {
+                res.push((k.clone(), self.add(*v)?));
+            }
+            Entry::Struct(res)
+        } else if let Some(x) = x.downcast_frozen_ref::<FrozenEnumType>() {
+            Entry::EnumType(x.typ.clone(), self.add_all(x.elements.keys())?)
+        } else if let Some(x) = x.downcast_frozen_ref::<FrozenEnumValue>() {
+            Entry::EnumValue(self.add(x.typ)?, x.index as u32)
+        } else if let Some(x) = x.downcast_frozen_ref::<FrozenField>() {
+            Entry::Field(self.add(x.typ)?, self.add_opt(x.default)?)
+        } else if let Some(x) = x.downcast_frozen_ref::<FrozenRecordType>() {
+            let mut res = Vec::with_capacity(x.fields.len());
+            for (name, (field, _)) in &x.fields {
+                res.push((
+                    name.clone(),
+                    self.add(field.typ)?,
+                    self.add_opt(field.default)?,
+                ));
+            }
+            Entry::RecordType(x.typ.clone(), res)
+        } else if let Some(x) = x.downcast_frozen_ref::<FrozenRecord>() {
+            Entry::Record(self.add(x.typ)?, self.add_all(&x.values)?)
+        } else if let Some(def) = x.downcast_frozen_ref::<FrozenDef>() {
+            // The code refers to the variables of its module, so must be from this one
+            match def.module() {
+                Some(m) if ptr::eq(m.get(), self.module.data()) => {}
+                _ => return Err(CacheError::ForeignFunction(x.to_value().to_repr()).into()),
+            }
+            let mut types = Vec::new();
+            for (i, t) in def.parameter_types() {
+                types.push((i as u32, self.add(t)?));
+            }
+            let mut captured = Vec::new();
+            for x in def.captured() {
+                captured.push(self.add_opt(*x)?);
+            }
+            Entry::Def {
+                code: self.add_code(&def.compiled())?,
+                defaults: self.add_all(&def.defaults())?,
+                types,
+                return_type: self.add_opt(def.return_type())?,
+                captured,
+            }
+        } else {
+            match self
+                .cache
+                .natives
+                .iter()
+                .find_map(|n| Some((n.name, (n.save)(x)?)))
+            {
+                Some((name, bytes)) => Entry::Native(name.to_owned(), bytes),
+                None => {
+                    return Err(CacheError::Unsupported(x.to_value().get_type().to_owned()).into());
+                }
+            }
+        })
+    }
+}
+
+/// Turns the saved code back into function definitions, before [`Loader`] makes the
+/// values, whose entries we read for the constants in the code. Constants are made on
+/// the frozen heap, as the code may outlive the module's heap.
+struct CodeLoader<'x> {
+    cache: &'x ModuleCache<'x>,
+    entries: &'x [Entry],
+    heap: &'x FrozenHeap,
+    /// The value of each constant, once made.
+    constants: Vec<Option<FrozenValue>>,
+    /// Whether we have started making each constant, to spot a corrupt cycle.
+    constants_started: Vec<bool>,
+    code: Vec<&'x [u8]>,
+    /// Each definition, once made.
+    defs: Vec<Option<DefCompiled>>,
+    /// Whether we have started making each definition, to spot a corrupt cycle.
+    defs_started: Vec<bool>,
+}
+
+impl<'x> CodeLoader<'x> {
+    fn constant(&mut self, id: u32) -> anyhow::Result<FrozenValue> {
+        let i = id as usize;
+        if let Some(v) = self.constants.get(i).ok_or(CacheError::Corrupt)? {
+            return Ok(*v);
+        }
+        if mem::replace(&mut self.constants_started[i], true) {
+            return Err(CacheError::Corrupt.into());
+        }
+        let heap = self.heap;
+        let v = match &self.entries[i] {
+            Entry::None => FrozenValue::new_none(),
+            Entry::Bool(x) => FrozenValue::new_bool(*x),
+            Entry::Int(x) => FrozenValue::new_int(*x),
+            Entry::Str(x) => heap.alloc(x.as_str()),
+            Entry::Tuple(xs) => {
+                let mut content = Vec::with_capacity(xs.len());
+                for x in xs {
+                    content.push(self.constant(*x)?);
+                }
+                heap.alloc(FrozenTuple { content })
+            }
+            Entry::Global(path) => self.cache.global(path)?,
+            Entry::Load(load, name) => self.cache.loaded(*load, name)?,
+            Entry::Native(name, bytes) => (self.cache.native(name)?.load_frozen)(bytes, heap)?,
+            _ => return Err(CacheError::Corrupt.into()),
+        };
+        self.constants[i] = Some(v);
+        Ok(v)
+    }
+
+    fn def(&mut self, id: u32) -> anyhow::Result<DefCompiled> {
+        let i = id as usize;
+        if let Some(x) = self.defs.get(i).ok_or(CacheError::Corrupt)? {
+            return Ok(x.dupe());
+        }
+        // A function can't be defined inside itself
+        if mem::replace(&mut self.defs_started[i], true) {
+            return Err(CacheError::Corrupt.into());
+        }
+        let mut r = CodeReader {
+            r: Reader(self.code[i]),
+            loader: self,
+        };
+        let def = DefCompiled::load(&mut r)?;
+        r.r.end()?;
+        self.defs[i] = Some(def.dupe());
+        Ok(def)
+    }
+}
+
+/// The contents of a value which [`Loader`] fills in after making the value itself.
+enum Pending<'v> {
+    List(Value<'v>, Vec<u32>),
+    Dict(Value<'v>, Vec<(u32, u32)>),
+    /// The variables captured by a function.
+    Captured(Vec<(ValueRef<'v>, u32)>),
+}
+
+/// Turns entries back into values, in a new module.
+///
+/// Values can only point back to themselves through a list, a dict, or the variables
+/// captured by a function, e.g. `a = ([],); a[0].append(a)`. So we make those empty,
+/// and fill them in after everything else, by which time what they point to has been
+/// made, even if it contains them.
+struct Loader<'a, 'c, 'v> {
+    cache: &'c ModuleCache<'a>,
+    entries: Vec<Entry>,
+    /// The value of each entry, once made.
+    values: Vec<Option<Value<'v>>>,
+    /// Whether we have started making each entry, to spot a corrupt cycle.
+    started: Vec<bool>,
+    /// Values which still need filling in.
+    pending: Vec<Pending<'v>>,
+    /// The function definitions, by their index in the code.
+    defs: Vec<DefCompiled>,
+    codemap: CodeMap,
+    heap: &'v Heap,
+}
+
+impl<'a, 'c, 'v> Loader<'a, 'c, 'v> {
+    fn value(&mut self, id: u32) -> anyhow::Result<Value<'v>> {
+        let i = id as usize;
+        if let Some(v) = self.values.get(i).ok_or(CacheError::Corrupt)? {
+            return Ok(*v);
+        }
+        // Nothing we make straight away can contain itself, so this is a corrupt cycle
+        if mem::replace(&mut self.started[i], true) {
+            return Err(CacheError::Corrupt.into());
+        }
+        let heap = self.heap;
+        let v = match mem::replace(&mut self.entries[i], Entry::None) {
+            Entry::None => Value::new_none(),
+            Entry::Bool(x) => Value::new_bool(x),
+            Entry::Int(x) => Value::new_int(x),
+            Entry::Str(x) => heap.alloc(x.as_str()),
+            Entry::List(xs) => {
+                let v = heap.alloc(List::new(Vec::new()));
+                self.pending.push(Pending::List(v, xs));
+                v
+            }
+            Entry::Tuple(xs) => heap.alloc(Tuple::new(self.values(&xs)?)),
+            Entry::ListView(width, tuples, xs) => {
+                let xs = self.values(&xs)?;
+                if !tuples {
+                    heap.alloc(ListView::new(xs))
+                } else if width != 0 && xs.len() % width as usize == 0 {
+                    heap.alloc(ListView::new_tuples(width as usize, xs))
+                } else {
+                    return Err(CacheError::Corrupt.into());
+                }
+            }
+            Entry::Dict(xs) => {
+                let v = heap.alloc(Dict::new(SmallMap::new()));
+                self.pending.push(Pending::Dict(v, xs));
+                v
+            }
+            Entry::Struct(xs) => {
+                let mut fields = SmallMap::with_capacity(xs.len());
+                for (k, x) in xs {
+                    fields.insert(k, self.value(x)?);
+                }
+                heap.alloc(Struct::new(fields))
+            }
+            Entry::EnumType(name, xs) => {
+                let v = EnumType::new(self.values(&xs)?, heap)?;
+                EnumType::from_value_mut(v)?.unwrap().typ = name;
+                v
+            }
+            Entry::EnumValue(typ, index) => {
+                let typ = self.value(typ)?;
+                let typ = EnumType::from_value(typ).ok_or(CacheError::Corrupt)?;
+                let res = typ.elements.get_index(index as usize);
+                *res.ok_or(CacheError::Corrupt)?.1
+            }
+            Entry::Field(typ, default) => {
+                let typ = self.value(typ)?;
+                heap.alloc(Field::new(typ, self.value_opt(default)?))
+            }
+            Entry::RecordType(name, xs) => {
+                let mut fields = SmallMap::with_capacity(xs.len());
+                for (k, typ, default) in xs {
+                    let typ = self.value(typ)?;
+                    let field = FieldGen::new(typ, self.value_opt(default)?);
+                    fields.insert(k, (field, TypeCompiled::new(typ)?));
+                }
+                let mut res = RecordType::new(fields, heap);
+                res.typ = name;
+                heap.alloc(res)
+            }
+            Entry::Record(typ, xs) => heap.alloc(Record {
+                typ: self.value(typ)?,
+                values: self.values(&xs)?,
+            }),
+            Entry::Def {
+                code,
+                defaults,
+                types,
+                return_type,
+                captured,
+            } => {
+                let def = self
+                    .defs
+                    .get(code as usize)
+                    .ok_or(CacheError::Corrupt)?
+                    .dupe();
+                let defaults = self.values(&defaults)?;
+                let mut parameter_types = Vec::with_capacity(types.len());
+                for (i, t) in types {
+                    parameter_types.push((i as usize, self.value(t)?));
+                }
+                let return_type = self.value_opt(return_type)?;
+                let values = def
+                    .stack_values(&defaults, &parameter_types, return_type)
+                    .ok_or(CacheError::Corrupt)?;
+                // The function gets references to the captured variables, which we
+                // fill in later, as they may include the function itself
+                let refs = captured.map(|_| ValueRef::new_unassigned());
+                let v = def.alloc(
+                    &values,
+                    refs.map(|x| x.clone_reference(heap)),
+                    self.codemap.dupe(),
+                    heap,
+                )?;
+                let captured = refs
+                    .into_iter()
+                    .zip(captured)
+                    .filter_map(|(r, x)| Some((r, x?)))
+                    .collect();
+                self.pending.push(Pending::Captured(captured));
+                v
+            }
+            Entry::Global(path) => Value::new_frozen(self.cache.global(&path)?),
+            Entry::Load(load, name) => Value::new_frozen(self.cache.loaded(load, &name)?),
+            Entry::Native(name, bytes) => (self.cache.native(&name)?.load)(&bytes, heap)?,
+        };
+        self.values[i] = Some(v);
+        Ok(v)
+    }
+
+    /// Fill in the values made by [`value`](Loader::value) which are still empty,
+    /// which may make more values that need filling in.
+    fn fill_pending(&mut self) -> anyhow::Result<()> {
+        while let Some(x) = self.pending.pop() {
+            match x {
+                Pending::List(v, xs) => {
+                    let xs = self.values(&xs)?;
+                    List::from_value_mut(v)?.unwrap().content = xs;
+                }
+                Pending::Dict(v, xs) => {
+                    let mut content = SmallMap::with_capacity(xs.len());
+                    for (k, x) in xs {
+                        let k = self.value(k)?;
+                        content.insert_hashed(k.get_hashed()?, self.value(x)?);
+                    }
+                    Dict::from_value_mut(v)?.unwrap().content = content;
+                }
+                Pending::Captured(xs) => {
+                    for (r, x) in xs {
+                        r.set(self.value(x)?);
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn values(&mut self, xs: &[u32]) -> anyhow::Result<Vec<Value<'v>>> {
+        let mut res = Vec::with_capacity(xs.len());
+        for x in xs {
+            res.push(self.value(*x)?);
+        }
+        Ok(res)
+    }
+
+    fn value_opt(&mut self, x: Option<u32>) -> anyhow::Result<Option<Value<'v>>> {
+        x.map(|x| self.value(x)).transpose()
+    }
+}
+
+impl FrozenModule {
+    /// Save the module as bytes, which [`deserialize`](FrozenModule::deserialize) turns
+    /// back into the module, given the same `cache`. The module must have come from
+    /// evaluating the code in `cache` in a new [`Module`].
+    ///
+    /// Fails if a variable holds, or points to, a native value which isn't
+    /// [registered](ModuleCache::register), or a function from a module which isn't
+    /// among the loads, unless it is a global or the variable of a loaded module.
+    pub fn serialize(&self, cache: &ModuleCache) -> anyhow::Result<Vec<u8>> {
+        let mut saver = Saver::new(cache, self);
+        let data = self.data();
+        // The code refers to variables by slot, so we keep the slots of all of them,
+        // including those without a value. We go in order of name, but which slot each
+        // variable gets can change each time the code is compiled, so evaluating the
+        // same code again may not give the same bytes.
+        let mut names: Vec<_> = data.names.symbols().collect();
+        names.sort_by_key(|x| x.0);
+        let mut slots = Vec::with_capacity(names.len());
+        for (name, slot) in names {
+            let x = saver.add_opt(data.get_slot(*slot))?;
+            slots.push((name.as_str(), slot.index() as u32, x));
+        }
+
+        let mut w = Writer(MAGIC.to_vec());
+        w.u32(VERSION);
+        w.0.extend(cache.hash().to_le_bytes());
+        w.list(&saver.entries, |w, x| x.write(w));
+        w.list(&saver.code, |w, x| w.bytes(x));
+        w.list(&slots, |w, (name, slot, x)| {
+            w.str(name);
+            w.u32(*slot);
+            w.id(x)
+        });
+        Ok(w.0)
+    }
+
+    /// Load a module saved by [`serialize`](FrozenModule::serialize), or return
+    /// [`None`] if it is stale, as something in `cache` has changed since it was
+    /// saved. The code in `cache` isn't parsed, compiled or evaluated.
+    pub fn deserialize(bytes: &[u8], cache: &ModuleCache) -> anyhow::Result<Option<FrozenModule>> {
+        let mut r = Reader(bytes);
+        if r.take(MAGIC.len()).ok() != Some(MAGIC)
+            || r.u32().ok() != Some(VERSION)
+            || r.u64().ok() != Some(cache.hash())
+        {
+            return Ok(None);
+        }
+        let entries = r.list(Entry::read)?;
+        let code = r.list(Reader::bytes)?;
+        let mut slots = r.list(|r| Ok((r.string()?, r.u32()?, r.id()?)))?;
+        r.end()?;
+
+        let module = Module::new();
+        module.frozen_heap().add_reference(cache.globals.heap());
+        for x in &cache.loads {
+            module.frozen_heap().add_reference(x.module.frozen_heap());
+        }
+        // Give each variable the slot it had, which is what the code refers to
+        slots.sort_by_key(|x| x.1);
+        for (name, slot, _) in &slots {
+            if module.names().add_name(name) != ModuleSlotId::new(*slot as usize) {
+                return Err(CacheError::Corrupt.into());
+            }
+        }
+        module.slots().ensure_slots(slots.len());
+
+        let mut code_loader = CodeLoader {
+            cache,
+            entries: &entries,
+            heap: module.frozen_heap(),
+            constants: vec![None; entries.len()],
+            constants_started: vec![false; entries.len()],
+            defs: vec![None; code.len()],
+            defs_started: vec![false; code.len()],
+            code,
+        };
+        let mut defs = Vec::with_capacity(code_loader.code.len());
+        for i in 0..code_loader.code.len() {
+            defs.push(code_loader.def(i as u32)?);
+        }
+        mem::drop(code_loader);
+
+        let mut loader = Loader {
+            cache,
+            values: vec![None; entries.len()],
+            started: vec![false; entries.len()],
+            pending: Vec::new(),
+            entries,
+            defs,
+            codemap: CodeMap::new(cache.filename.to_owned(), cache.source.to_owned()),
+            heap: module.heap(),
+        };
+        for (_, slot, x) in slots {
+            if let Some(x) = x {
+                let x = loader.value(x)?;
+                module.slots().set_slot(ModuleSlotId::new(slot as usize), x);
+            }
+        }
+        loader.fill_pending()?;
+        mem::drop(loader);
+        Ok(Some(module.freeze()?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        assert::Assert,
+        environment::GlobalsBuilder,
+        eval::{Evaluator, ReturnFileLoader},
+        syntax::AstModule,
+    };
+
+    fn eval(
+        filename: &str,
+        source: &str,
+        globals: &Globals,
+        loads: &[(&str, &FrozenModule)],
+    ) -> FrozenModule {
+        let ast = AstModule::parse(filename, source.to_owned(), &Dialect::Extended).unwrap();
+        let module = Module::new();
+        let modules = loads.iter().copied().collect();
+        let mut loader = ReturnFileLoader { modules: &modules };
+        let mut eval = Evaluator::new(&module, globals);
+        eval.set_loader(&mut loader);
+        eval.eval_module(ast).unwrap();
+        mem::drop(eval);
+        module.freeze().unwrap()
+    }
+
+    /// Evaluate the code, save it, and load it again.
+    fn round_trip(source: &str) -> FrozenModule {
+        let globals = Globals::extended();
+        let module = eval("test.star", source, &globals, &[]);
+        let cache = ModuleCache::new("test.star", source, &Dialect::Extended, &globals);
+        let bytes = module.serialize(&cache).unwrap();
+        FrozenModule::deserialize(&bytes, &cache).unwrap().unwrap()
+    }
+
+    #[test]
+    fn test_cache_values() {
+        let module = round_trip(
+            r#"
+xs = [1, "a", None, True, (2, 3), {"k": [4]}, range(1, 10, 2), struct(x = 5)]
+xs.append(xs)
+same = (xs, xs)
+a = ([],)
+a[0].append(a)
+s = struct(l = [])
+s.l.append(s)
+d = {}
+r = record(d = field(dict.type))(d = d)
+d["r"] = r
+def f():
+    return t
+t = (f,)
+Colors = enum("red", "green")
+green = Colors("green")
+Point = record(x = int.type, y = field(int.type, 0))
+p = Point(x = 1)
+views = ({"a": 1}.items(), reversed([1, 2]))
+"#,
+        );
+        let mut a = Assert::new();
+        a.module_add("test.star", module);
+        a.pass(
+            r#"
+load("test.star", "xs", "same", "Colors", "green", "Point", "p", "a", "s", "d", "r", "f", "t", "views")
+assert_eq(xs[:7], [1, "a", None, True, (2, 3), {"k": [4]}, range(1, 10, 2)])
+assert_eq(xs[7].x, 5)
+assert_eq(xs[8][0], 1)
+assert_eq(same[0][8][1], "a")
+assert_eq(Colors.type, "Colors")
+assert_eq(green, Colors("green"))
+assert_eq(green.index, 1)
+assert_eq(Point.type, "Point")
+assert_eq(p, Point(x = 1, y = 0))
+assert_eq(a[0][0][0][0], a)
+assert_eq(s.l[0].l[0], s)
+assert_eq(d["r"].d["r"], r)
+assert_eq(f()[0]()[0], f)
+assert_eq(t[0](), t)
+assert_eq(views, ([("a", 1)], [2, 1]))
+assert_eq(type(views[0]), "list_view")
+"#,
+        );
+        a.fail(
+            "load('test.star', 'Point')\nPoint(x = 'no')",
+            "`no` of type `string` does not match",
+        );
+    }
+
+    #[test]
+    fn test_cache_functions() {
+        let module = round_trip(
+            r#"
+def fib(n):
+    return n if n < 2 else fib(n - 1) + fib(n - 2)
+def adder(x):
+    def add(y = 1):
+        return x + y
+    return add
+add3 = adder(3)
+def typed(x: int.type, y: str.type = "y") -> str.type:
+    return str(x) + y
+def counter():
+    def count(n):
+        return 0 if n == 0 else 1 + count(n - 1)
+    return count
+count = counter()
+square = lambda x: x * x
+"#,
+        );
+        let mut a = Assert::new();
+        a.module_add("test.star", module);
+        a.pass(
+            r#"
+load("test.star", "fib", "add3", "typed", "count", "square")
+assert_eq(fib(10), 55)
+assert_eq(add3(), 4)
+assert_eq(add3(5), 8)
+assert_eq(typed(1), "1y")
+assert_eq(count(4), 4)
+assert_eq(square(3), 9)
+"#,
+        );
+        a.fail(
+            "load('test.star', 'typed')\ntyped('x')",
+            "does not match the type annotation",
+        );
+    }
+
+    #[test]
+    fn test_cache_code() {
+        // Code using each kind of instruction, and constants, which only works if the
+        // compiled bodies come back as they were saved
+        let module = round_trip(
+            r#"
+def body(xs, d, **kwargs):
+    n = 0
+    for i, x in enumerate(xs, 1):
+        n += i * x
+    for k, v in d.items():
+        n += v
+    for a, b in zip(xs, reversed(xs)):
+        n -= a - b
+    squares = [x * x for x in xs if x % 2 == 1]
+    table = {k: len(k) for k in d.keys()}
+    first, _ = ("a", "b") if False else (xs[0], None)
+    s = f"{n}-{first!r:>3}-{'x':{kwargs['w']}}-{squares}-{table}"
+    return s, str(b"ab"), json.encode({"k": [1, (2, 3)], "t": (True, None)})
+def default(x, y = "y", *, z = (1, "z")):
+    return (x, y, z, x.upper().strip(), x[1:-1:2])
+def mutate():
+    xs = [1, 2]
+    xs.append(3)
+    d = {"a": 1}
+    d["b"] = 2
+    return xs, d
+"#,
+        );
+        let mut a = Assert::new();
+        a.module_add("test.star", module);
+        a.pass(
+            r#"
+load("test.star", "body", "default", "mutate")
+assert_eq(body([1, 2, 3], {"a": 10, "bc": 20}, w = 3), (
+    '44-  1-x  -[1, 9]-{"a": 1, "bc": 2}',
+    "ab",
+    '{"k":[1,[2,3]],"t":[true,null]}',
+))
+assert_eq(default(" abcde "), (" abcde ", "y", (1, "z"), "ABCDE", "ace"))
+assert_eq(mutate(), ([1, 2, 3], {"a": 1, "b": 2}))
+# The constant lists and dicts are new each call
+assert_eq(mutate(), ([1, 2, 3], {"a": 1, "b": 2}))
+"#,
+        );
+        a.fail(
+            "load('test.star', 'default')\ndefault(1)",
+            "Operation `.upper` not supported on type `int`",
+        );
+    }
+
+    #[test]
+    fn test_cache_constants() {
+        // An item of a global list is folded into the code, but isn't a global itself,
+        // so can't be saved
+        let globals = GlobalsBuilder::extended()
+            .with(|g| g.set("xs", vec![vec![1]]))
+            .build();
+        let source = "def f():\n    return xs[0]";
+        let module = eval("test.star", source, &globals, &[]);
+        let cache = ModuleCache::new("test.star", source, &Dialect::Extended, &globals);
+        let err = module.serialize(&cache).unwrap_err();
+        assert!(
+            err.to_string().contains("constant of type `list`"),
+            "{}",
+            err
+        );
+    }
+
+    #[test]
+    fn test_cache_loads() {
+        let globals = Globals::extended();
+        let lib_source = "def double(x):\n    return x * 2\nitems = [1, 2]";
+        let lib = eval("lib.star", lib_source, &globals, &[]);
+        let lib_cache = ModuleCache::new("lib.star", lib_source, &Dialect::Extended, &globals);
+
+        let source = "load('lib.star', 'double', 'items')\nf = double\nys = [items, len]";
+        let module = eval("test.star", source, &globals, &[("lib.star", &lib)]);
+        let mut cache = ModuleCache::new("test.star", source, &Dialect::Extended, &globals);
+        cache.add_load("lib.star", &lib, lib_cache.hash());
+        let bytes = module.serialize(&cache).unwrap();
+        let module = FrozenModule::deserialize(&bytes, &cache).unwrap().unwrap();
+        let f = module.get("f").unwrap();
+        assert!(f.value().ptr_eq(lib.get("double").unwrap().value()));
+        // Values from the loaded module, and globals, are the same values, not copies
+        let ys = module.get("ys").unwrap();
+        let ys = List::from_value(ys.value()).unwrap();
+        let items = lib.get("items").unwrap();
+        assert_eq!(ys.content[0].ptr_value(), items.value().ptr_value());
+        assert_eq!(
+            ys.content[1].ptr_value(),
+            globals.get("len").unwrap().ptr_value()
+        );
+
+        // Without the load, the function can't be saved
+        let cache = ModuleCache::new("test.star", source, &Dialect::Extended, &globals);
+        assert!(module.serialize(&cache).is_err());
+
+        // If the loaded module changes, the cache is stale
+        let lib_changed = "def double(x):\n    return x + x\nitems = [1, 2]";
+        let lib_cache = ModuleCache::new("lib.star", lib_changed, &Dialect::Extended, &globals);
+        let mut cache = ModuleCache::new("test.star", source, &Dialect::Extended, &globals);
+        cache.add_load("lib.star", &lib, lib_cache.hash());
+        assert!(FrozenModule::deserialize(&bytes, &cache).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_cache_stale() {
+        let globals = Globals::extended();
+        let source = "x = 1";
+        let module = eval("test.star", source, &globals, &[]);
+        let cache = ModuleCache::new("test.star", source, &Dialect::Extended, &globals);
+        let bytes = module.serialize(&cache).unwrap();
+        let changed = ModuleCache::new("test.star", "x = 2", &Dialect::Extended, &globals);
+        assert!(FrozenModule::deserialize(&bytes, &changed)
+            .unwrap()
+            .is_none());
+        assert!(FrozenModule::deserialize(b"rubbish", &cache)
+            .unwrap()
+            .is_none());
+        assert!(FrozenModule::deserialize(&bytes[..bytes.len() - 1], &cache).is_err());
+    }
+}
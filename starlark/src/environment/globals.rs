@@ -16,7 +16,7 @@
  */
 
 use crate::{
-    collections::SmallMap,
+    collections::{BorrowHashed, SmallMap},
     stdlib,
     values::{
         function::NativeAttribute, structs::FrozenStruct, AllocFrozenValue, FrozenHeap,
@@ -26,7 +26,7 @@ use crate::{
 use gazebo::prelude::*;
 use itertools::Itertools;
 use once_cell::sync::OnceCell;
-use std::{collections::HashMap, mem, sync::Arc};
+use std::{mem, sync::Arc};
 
 pub use crate::stdlib::LibraryExtension;
 
@@ -37,7 +37,8 @@ pub struct Globals(Arc<GlobalsData>);
 #[derive(Debug)]
 struct GlobalsData {
     heap: FrozenHeapRef,
-    variables: HashMap<String, FrozenValue>,
+    // A `SmallMap` so that names hashed in advance, e.g. by the compiler, can be looked up
+    variables: SmallMap<String, FrozenValue>,
 }
 
 /// Used to build a [`Globals`] value.
@@ -46,7 +47,7 @@ pub struct GlobalsBuilder {
     // The heap everything is allocated in
     heap: FrozenHeap,
     // Normal top-level variables, e.g. True/hash
-    variables: HashMap<String, FrozenValue>,
+    variables: SmallMap<String, FrozenValue>,
     // Set to Some when we are in a struct builder, otherwise None
     struct_fields: Option<SmallMap<String, FrozenValue>>,
 }
@@ -87,6 +88,11 @@ impl Globals {
         self.0.variables.get(name).copied()
     }
 
+    /// Like [`get_frozen`](Globals::get_frozen), but for a name whose hash is already known.
+    pub(crate) fn get_frozen_hashed(&self, name: BorrowHashed<str>) -> Option<FrozenValue> {
+        self.0.variables.get_hashed(name).copied()
+    }
+
     /// Get all the names defined in this environment.
     pub fn names(&self) -> Vec<String> {
         self.0.variables.keys().cloned().collect()
@@ -96,6 +102,12 @@ impl Globals {
         &self.0.heap
     }
 
+    /// All the variables, which, as with [`get_frozen`](Globals::get_frozen), are only
+    /// valid while the heap is.
+    pub(crate) fn iter(&self) -> impl Iterator<Item = (&str, FrozenValue)> {
+        self.0.variables.iter().map(|(k, v)| (k.as_str(), *v))
+    }
+
     /// Print information about the values in this object.
     pub fn describe(&self) -> String {
         self.0
@@ -111,7 +123,7 @@ impl GlobalsBuilder {
     pub fn new() -> Self {
         Self {
             heap: FrozenHeap::new(),
-            variables: HashMap::new(),
+            variables: SmallMap::new(),
             struct_fields: None,
         }
     }
@@ -22,11 +22,14 @@
 //! User executions store their values in a [`Module`], which have to be converted to a
 //! [`FrozenModule`] using [`freeze`](Module::freeze) before they can be `load()`'d as a dependency.
 
+mod cache;
 mod globals;
 mod modules;
 pub(crate) mod names;
 pub(crate) mod slots;
 
+pub(crate) use cache::{CodeReader, CodeWriter};
+pub use cache::{ModuleCache, SerializeValue};
 pub use globals::*;
 pub use modules::*;
 
@@ -113,6 +113,10 @@ impl FrozenModule {
     pub fn describe(&self) -> String {
         self.1.0.describe()
     }
+
+    pub(crate) fn data(&self) -> &FrozenModuleData {
+        &self.1.0
+    }
 }
 
 impl FrozenModuleData {
@@ -212,6 +216,16 @@ impl Module {
         self.slots().get_slot(slot)
     }
 
+    /// Get the names of all the variables which currently have a value in this module.
+    pub fn variable_names(&self) -> Vec<String> {
+        self.names
+            .all_names()
+            .into_iter()
+            .filter(|(_, slot)| self.slots().get_slot(*slot).is_some())
+            .map(|(name, _)| name)
+            .collect()
+    }
+
     /// Freeze the environment, all its value will become immutable afterwards.
     pub fn freeze(self) -> anyhow::Result<FrozenModule> {
         let Module {
@@ -285,3 +299,27 @@ where
     FrozenModule: Send + Sync,
 {
 }
+
+#[test]
+fn test_variable_names() {
+    use crate::{
+        environment::Globals,
+        eval::Evaluator,
+        syntax::{AstModule, Dialect},
+    };
+
+    let module = Module::new();
+    let globals = Globals::extended();
+    let mut eval = Evaluator::new(&module, &globals);
+    let ast = AstModule::parse(
+        "test.star",
+        "x = 1\ndef f():\n    pass\nif False:\n    y = 2\n".to_owned(),
+        &Dialect::Extended,
+    )
+    .unwrap();
+    eval.eval_module(ast).unwrap();
+    // `y` has a slot, but was never assigned
+    let mut names = module.variable_names();
+    names.sort();
+    assert_eq!(names, vec!["f".to_owned(), "x".to_owned()]);
+}
@@ -26,6 +26,10 @@ impl ModuleSlotId {
     pub fn new(index: usize) -> Self {
         Self(index)
     }
+
+    pub(crate) fn index(self) -> usize {
+        self.0
+    }
 }
 
 // Indexed slots of a module. May contain unassigned values as `None`.
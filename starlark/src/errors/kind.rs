@@ -0,0 +1,341 @@
+/*
+ * Copyright 2019 The Starlark in Rust Authors.
+ * Copyright (c) Facebook, Inc. and its affiliates.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! A classification of errors with stable codes, see [`ErrorKind`].
+
+use crate::{
+    environment::EnvironmentError,
+    errors::Diagnostic,
+    eval::{AssignError, EvalError, FunctionError},
+    stdlib::FailError,
+    syntax::{
+        lexer::LexemeError,
+        parser::ParseError,
+        validate::{ArgumentDefinitionOrderError, ArgumentUseOrderError, ValidateError},
+        DialectError,
+    },
+    values::{
+        enumeration::EnumError, interpolation::StringInterpolationError, typing::TypingError,
+        ControlError, ValueError,
+    },
+};
+use gazebo::prelude::*;
+use std::fmt::{self, Display};
+
+/// The kind of an error raised by Starlark, each with a stable code such as `E0300`.
+///
+/// The code of an error will not change between releases, so can be used by tools to filter
+/// and document errors, rather than matching on the error message. Codes are grouped:
+/// `E00xx` are general, `E01xx` are syntax errors, `E02xx` concern variables and modules,
+/// `E03xx` are errors in operations on values and `E04xx` are errors when calling functions.
+///
+/// Obtain the kind of an error with [`ErrorKind::of`] or [`Diagnostic::kind`].
+#[derive(Debug, Clone, Copy, Dupe, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum ErrorKind {
+    /// An error which doesn't have a more specific kind, e.g. one raised by a native function.
+    Other,
+    /// Raised by the `fail()` function.
+    Fail,
+    /// The source code could not be lexed or parsed.
+    Parse,
+    /// The source code uses a feature not allowed by the [`Dialect`](crate::syntax::Dialect).
+    Dialect,
+    /// A statement is not allowed where it occurs, e.g. `break` outside a loop.
+    InvalidStatement,
+    /// The parameters of a `def`, or the arguments of a call, are in an invalid order.
+    ArgumentOrder,
+    /// A variable was used which is not defined.
+    VariableNotFound,
+    /// A local variable was used before it was assigned.
+    VariableReferencedBeforeAssignment,
+    /// A `load()` tried to import a private (underscore prefixed) symbol.
+    PrivateSymbol,
+    /// A `load()` was used, but no [`FileLoader`](crate::eval::FileLoader) was available.
+    NoImportsAvailable,
+    /// An operation is not supported on values of the given type(s).
+    OperationNotSupported,
+    /// An attribute was accessed which doesn't exist.
+    MissingAttribute,
+    /// Division or modulo by zero.
+    DivisionByZero,
+    /// An integer operation overflowed.
+    IntegerOverflow,
+    /// A value passed to a function has the wrong type.
+    IncorrectParameterType,
+    /// An index is out of bounds.
+    IndexOutOfBound,
+    /// A key was not found in a dictionary.
+    KeyNotFound,
+    /// A value which isn't hashable was used as a key.
+    NotHashable,
+    /// An attempt to mutate a frozen value.
+    Immutable,
+    /// An attempt to mutate a value while it is being iterated over.
+    MutationDuringIteration,
+    /// A value didn't match its type annotation, or the type annotation was invalid.
+    TypeAnnotation,
+    /// A string interpolation had the wrong number of arguments.
+    StringInterpolation,
+    /// A dictionary literal repeated a key.
+    DuplicateDictionaryKey,
+    /// Assignment to a tuple or list unpacked the wrong number of values.
+    UnpackLength,
+    /// An invalid enum definition or element.
+    Enum,
+    /// A call was missing a required parameter.
+    MissingParameter,
+    /// A call had too many positional arguments.
+    ExtraPositionalParameters,
+    /// A call had named arguments which weren't parameters of the function.
+    ExtraNamedParameters,
+    /// A call passed a positional-only parameter, one before a `/`, by name.
+    PositionalOnlyPassedByName,
+    /// A call gave the same parameter more than once.
+    RepeatedParameter,
+    /// The values passed as `*args` or `**kwargs` were of the wrong type.
+    InvalidStarArguments,
+    /// Too many nested function calls.
+    TooManyRecursionLevels,
+    /// A function called itself, directly or indirectly, when recursion is disabled.
+    RecursiveCall,
+}
+
+impl Display for ErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.code())
+    }
+}
+
+impl ErrorKind {
+    /// A list of all error kinds.
+    pub fn all() -> &'static [Self] {
+        use ErrorKind::*;
+        &[
+            Other,
+            Fail,
+            Parse,
+            Dialect,
+            InvalidStatement,
+            ArgumentOrder,
+            VariableNotFound,
+            VariableReferencedBeforeAssignment,
+            PrivateSymbol,
+            NoImportsAvailable,
+            OperationNotSupported,
+            MissingAttribute,
+            DivisionByZero,
+            IntegerOverflow,
+            IncorrectParameterType,
+            IndexOutOfBound,
+            KeyNotFound,
+            NotHashable,
+            Immutable,
+            MutationDuringIteration,
+            TypeAnnotation,
+            StringInterpolation,
+            DuplicateDictionaryKey,
+            UnpackLength,
+            Enum,
+            MissingParameter,
+            ExtraPositionalParameters,
+            ExtraNamedParameters,
+            PositionalOnlyPassedByName,
+            RepeatedParameter,
+            InvalidStarArguments,
+            TooManyRecursionLevels,
+            RecursiveCall,
+        ]
+    }
+
+    /// The stable code for this kind of error, e.g. `E0300`.
+    pub fn code(self) -> &'static str {
+        use ErrorKind::*;
+        match self {
+            Other => "E0000",
+            Fail => "E0001",
+            Parse => "E0100",
+            Dialect => "E0101",
+            InvalidStatement => "E0102",
+            ArgumentOrder => "E0103",
+            VariableNotFound => "E0200",
+            VariableReferencedBeforeAssignment => "E0201",
+            PrivateSymbol => "E0202",
+            NoImportsAvailable => "E0203",
+            OperationNotSupported => "E0300",
+            MissingAttribute => "E0301",
+            DivisionByZero => "E0302",
+            IntegerOverflow => "E0303",
+            IncorrectParameterType => "E0304",
+            IndexOutOfBound => "E0305",
+            KeyNotFound => "E0306",
+            NotHashable => "E0307",
+            Immutable => "E0308",
+            MutationDuringIteration => "E0309",
+            TypeAnnotation => "E0310",
+            StringInterpolation => "E0311",
+            DuplicateDictionaryKey => "E0312",
+            UnpackLength => "E0313",
+            Enum => "E0314",
+            MissingParameter => "E0400",
+            ExtraPositionalParameters => "E0401",
+            ExtraNamedParameters => "E0402",
+            RepeatedParameter => "E0403",
+            InvalidStarArguments => "E0404",
+            TooManyRecursionLevels => "E0405",
+            RecursiveCall => "E0406",
+            PositionalOnlyPassedByName => "E0407",
+        }
+    }
+
+    /// Find the [`ErrorKind`] from a code, the inverse of [`code`](ErrorKind::code).
+    pub fn from_code(code: &str) -> Option<Self> {
+        Self::all().iter().copied().find(|x| x.code() == code)
+    }
+
+    /// Classify an error. If the error is a [`Diagnostic`], this is its
+    /// [`kind`](Diagnostic::kind), worked out from its underlying message when it was created.
+    /// Errors which Starlark doesn't know about are [`Other`](ErrorKind::Other).
+    pub fn of(err: &anyhow::Error) -> Self {
+        if let Some(d) = err.downcast_ref::<Diagnostic>() {
+            return d.kind;
+        }
+
+        use ErrorKind::*;
+        if err.is::<FailError>() {
+            Fail
+        } else if err.is::<ParseError>() || err.is::<LexemeError>() {
+            Parse
+        } else if err.is::<DialectError>() {
+            Dialect
+        } else if err.is::<ValidateError>() {
+            InvalidStatement
+        } else if err.is::<ArgumentDefinitionOrderError>() || err.is::<ArgumentUseOrderError>() {
+            ArgumentOrder
+        } else if let Some(e) = err.downcast_ref::<EnvironmentError>() {
+            match e {
+                EnvironmentError::VariableNotFound(_) => VariableNotFound,
+                EnvironmentError::LocalVariableReferencedBeforeAssignment(_) => {
+                    VariableReferencedBeforeAssignment
+                }
+                EnvironmentError::CannotImportPrivateSymbol(_) => PrivateSymbol,
+                EnvironmentError::NoImportsAvailable(_) => NoImportsAvailable,
+            }
+        } else if let Some(e) = err.downcast_ref::<ValueError>() {
+            match e {
+                ValueError::MissingAttribute { .. } => MissingAttribute,
+                ValueError::OperationNotSupported { .. }
+                | ValueError::OperationNotSupportedBinary { .. } => OperationNotSupported,
+                ValueError::DivisionByZero => DivisionByZero,
+                ValueError::IntegerOverflow => IntegerOverflow,
+                ValueError::IncorrectParameterType
+                | ValueError::IncorrectParameterTypeNamed(_)
+                | ValueError::IncorrectParameterTypeNamedExpected(..) => IncorrectParameterType,
+                ValueError::IndexOutOfBound(_) => IndexOutOfBound,
+                ValueError::KeyNotFound(_) => KeyNotFound,
+            }
+        } else if let Some(e) = err.downcast_ref::<ControlError>() {
+            match e {
+                ControlError::CannotMutateImmutableValue => Immutable,
+                ControlError::NotHashableValue(_) => NotHashable,
+                ControlError::TooManyRecursionLevel => TooManyRecursionLevels,
+                ControlError::RecursiveCall(_) => RecursiveCall,
+                ControlError::MutationDuringIteration => MutationDuringIteration,
+            }
+        } else if err.is::<TypingError>() {
+            TypeAnnotation
+        } else if err.is::<StringInterpolationError>() {
+            StringInterpolation
+        } else if let Some(e) = err.downcast_ref::<EvalError>() {
+            match e {
+                EvalError::DuplicateDictionaryKey(_) => DuplicateDictionaryKey,
+            }
+        } else if err.is::<AssignError>() {
+            UnpackLength
+        } else if err.is::<EnumError>() {
+            Enum
+        } else if let Some(e) = err.downcast_ref::<FunctionError>() {
+            match e {
+                FunctionError::MissingParameter { .. } => MissingParameter,
+                FunctionError::ExtraPositionalParameters { .. } => ExtraPositionalParameters,
+                FunctionError::ExtraNamedParameters { .. } => ExtraNamedParameters,
+                FunctionError::PositionalOnlyPassedByName { .. } => PositionalOnlyPassedByName,
+                FunctionError::RepeatedParameter { .. } => RepeatedParameter,
+                FunctionError::ArgsValueIsNotString
+                | FunctionError::ArgsArrayIsNotIterable
+                | FunctionError::KwArgsIsNotDict => InvalidStarArguments,
+            }
+        } else {
+            Other
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{assert, errors::ErrorKind, values::ControlError};
+    use std::collections::HashSet;
+
+    #[test]
+    fn test_codes_unique() {
+        let codes: HashSet<_> = ErrorKind::all().iter().map(|x| x.code()).collect();
+        assert_eq!(codes.len(), ErrorKind::all().len());
+        for x in ErrorKind::all() {
+            assert_eq!(ErrorKind::from_code(x.code()), Some(*x));
+        }
+    }
+
+    #[test]
+    fn test_kinds() {
+        fn kind(program: &str) -> ErrorKind {
+            ErrorKind::of(&assert::fails(program, &[]))
+        }
+
+        use ErrorKind::*;
+        assert_eq!(kind("fail('x')"), Fail);
+        assert_eq!(kind("1 +"), Parse);
+        assert_eq!(kind("x = 'unterminated"), Parse);
+        assert_eq!(kind("break"), InvalidStatement);
+        assert_eq!(kind("x"), VariableNotFound);
+        assert_eq!(
+            kind("def f():\n  y = x\n  x = 1\nf()"),
+            VariableReferencedBeforeAssignment
+        );
+        assert_eq!(kind("1 + 'a'"), OperationNotSupported);
+        assert_eq!(kind("[].foo"), MissingAttribute);
+        assert_eq!(kind("1 // 0"), DivisionByZero);
+        assert_eq!(kind("[1][3]"), IndexOutOfBound);
+        assert_eq!(kind("{}['x']"), KeyNotFound);
+        assert_eq!(kind("{[]: 1}"), NotHashable);
+        assert_eq!(kind("def f(x: int.type):\n  pass\nf('a')"), TypeAnnotation);
+        assert_eq!(kind("'%s %s' % (1,)"), StringInterpolation);
+        assert_eq!(kind("{1: 2, 1: 3}"), DuplicateDictionaryKey);
+        assert_eq!(kind("a, b = (1, 2, 3)"), UnpackLength);
+        assert_eq!(kind("def f(x):\n  pass\nf()"), MissingParameter);
+        assert_eq!(kind("def f():\n  pass\nf(1)"), ExtraPositionalParameters);
+        assert_eq!(kind("def f():\n  pass\nf(x = 1)"), ExtraNamedParameters);
+        assert_eq!(
+            kind("def f(x, y = 2, /):\n  pass\nf(1, y = 3)"),
+            PositionalOnlyPassedByName
+        );
+        assert_eq!(kind("getattr([], 'foo')"), MissingAttribute);
+        assert_eq!(kind("def f():\n  f()\nf()"), TooManyRecursionLevels);
+        // Recursion is only an error if the evaluator disables it
+        let err = ControlError::RecursiveCall("f -> f".to_owned()).into();
+        assert_eq!(ErrorKind::of(&err), RecursiveCall);
+    }
+}
@@ -23,11 +23,14 @@ use annotate_snippets::{
     display_list::{DisplayList, FormatOptions},
     snippet::{Annotation, AnnotationType, Slice, Snippet, SourceAnnotation},
 };
+pub use kind::ErrorKind;
 use std::{
     error::Error,
     fmt::{self, Display, Formatter},
 };
 
+mod kind;
+
 /// An error plus its origination location and call stack.
 ///
 /// The underlying [`message`](Diagnostic::message) is an [`anyhow::Error`].
@@ -43,10 +46,14 @@ pub struct Diagnostic {
 
     /// Call stack of what called what. Most recent frames are at the end.
     pub call_stack: Vec<Frame>,
+
+    /// The [`ErrorKind`] of the underlying [`message`](Diagnostic::message), worked out
+    /// when the [`Diagnostic`] is created.
+    pub kind: ErrorKind,
 }
 
 /// A frame of the call-stack.
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Frame {
     /// The name of the entry on the call-stack.
     pub name: String,
@@ -93,6 +100,7 @@ impl Diagnostic {
             }
             _ => {
                 let mut err = Self {
+                    kind: ErrorKind::of(&err),
                     message: err,
                     span: None,
                     call_stack: Vec::new(),
@@ -139,6 +147,34 @@ impl Display for Diagnostic {
     }
 }
 
+/// A warning raised during evaluation, usually by the `warn()` function.
+///
+/// Unlike a [`Diagnostic`], a [`Warning`] does not stop evaluation. Warnings are collected on the
+/// [`Evaluator`](crate::eval::Evaluator) and can be retrieved with
+/// [`take_warnings`](crate::eval::Evaluator::take_warnings).
+#[derive(Debug, Clone)]
+pub struct Warning {
+    /// The text of the warning.
+    pub message: String,
+
+    /// Location where the warning was raised.
+    pub span: Option<FileSpan>,
+
+    /// Call stack of what called what. Most recent frames are at the end.
+    pub call_stack: Vec<Frame>,
+}
+
+impl Display for Warning {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        CallStackFmt(&self.call_stack).fmt(f)?;
+        write!(f, "Warning: {}", self.message)?;
+        if let Some(span) = &self.span {
+            write!(f, " at {}", span)?;
+        }
+        Ok(())
+    }
+}
+
 struct CallStackFmt<'a>(&'a Vec<Frame>);
 
 impl Display for CallStackFmt<'_> {
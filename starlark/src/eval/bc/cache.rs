@@ -0,0 +1,103 @@
+/*
+ * Copyright 2019 The Starlark in Rust Authors.
+ * Copyright (c) Facebook, Inc. and its affiliates.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Inline caches, which let an instruction remember what it looked up last time.
+//!
+//! A method is found by name in the table returned by
+//! [`get_methods`](crate::values::StarlarkValue::get_methods), which is shared by all
+//! values of a type, and lives forever. So at a call site which keeps seeing values of
+//! the same type, e.g. `s.startswith(...)` in a loop over strings, we can remember the
+//! table and the method in it, and next time just compare the table pointer.
+
+use crate::{
+    collections::{BorrowHashed, Hashed},
+    environment::Globals,
+    values::{FrozenValue, Heap, Value},
+};
+use once_cell::sync::OnceCell;
+use std::ptr;
+
+/// The method called at a site like `x.name(...)`.
+pub(crate) struct MethodCompiled {
+    /// Hashed in advance, so looking it up in the table doesn't need to hash it.
+    name: Hashed<String>,
+    /// The first methods table `name` was found in, and the method found there.
+    /// Only ever set once, so a site which sees several types only caches the first.
+    cache: OnceCell<(&'static Globals, FrozenValue)>,
+}
+
+impl MethodCompiled {
+    pub(crate) fn new(name: String) -> Self {
+        Self {
+            name: Hashed::new(name),
+            cache: OnceCell::new(),
+        }
+    }
+
+    pub(crate) fn name(&self) -> &str {
+        self.name.key()
+    }
+
+    /// Look up the method on `x`, the same as `x.get_attr_error(name, heap)`, but
+    /// without searching for it when `x` has the same type as last time.
+    pub(crate) fn get<'v>(&self, x: Value<'v>, heap: &'v Heap) -> anyhow::Result<Value<'v>> {
+        if let Some(methods) = x.get_aref().get_methods() {
+            if let Some((cached, v)) = self.cache.get() {
+                if ptr::eq(*cached, methods) {
+                    return Ok(v.to_value());
+                }
+            }
+            let name = BorrowHashed::new_unchecked(self.name.hash(), self.name());
+            if let Some(v) = methods.get_frozen_hashed(name) {
+                // If another thread got here first, its entry is just as good
+                let _ = self.cache.set((methods, v));
+                return Ok(v.to_value());
+            }
+        }
+        // Not a method, so a field, which has to be asked for each time
+        Ok(x.get_attr_error(self.name(), heap)?.1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::assert::Assert;
+
+    #[test]
+    fn test_method_cache() {
+        // The same call site sees values of several types, with methods and fields
+        let a = Assert::new();
+        a.pass(
+            r#"
+def call(x):
+    return x.get(1)
+assert_eq([call(x) for x in [{1: "a"}, {}, {1: "b"}]], ["a", None, "b"])
+assert_eq(call(struct(get = lambda x: x + 1)), 2)
+assert_eq(call({1: 3}), 3)
+"#,
+        );
+        a.fail(
+            r#"
+def upper(x):
+    return x.upper()
+upper("a")
+upper([])
+"#,
+            "Operation `.upper` not supported on type `list`",
+        );
+    }
+}
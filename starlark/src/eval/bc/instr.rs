@@ -0,0 +1,626 @@
+/*
+ * Copyright 2019 The Starlark in Rust Authors.
+ * Copyright (c) Facebook, Inc. and its affiliates.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! The instructions of the bytecode.
+//!
+//! Every instruction pops its operands off the evaluation stack and pushes its result.
+//! Where an instruction has several operands, they are pushed in the order they are
+//! evaluated, so the last operand is on top of the stack.
+
+use crate::{
+    codemap::Span,
+    collections::{Hashed, SmallMap},
+    environment::{slots::ModuleSlotId, CodeReader, CodeWriter},
+    eval::{
+        bc::cache::MethodCompiled,
+        fragment::{
+            def::DefCompiled,
+            expr::{CallCompiled, FStringCompiled},
+            loops::LoopCompiled,
+            stmt::LoadCompiled,
+        },
+        runtime::slots::LocalSlotId,
+    },
+    values::{FrozenValue, ValueLike},
+};
+use gazebo::prelude::*;
+
+/// The index of an instruction within a [`Bc`], the target of a jump.
+#[derive(Clone, Copy, Dupe, Debug, PartialEq, Eq)]
+pub(crate) struct BcAddr(pub(crate) u32);
+
+/// The index of a string in [`Bc::strings`].
+#[derive(Clone, Copy, Dupe, Debug, PartialEq, Eq)]
+pub(crate) struct StrId(pub(crate) u32);
+
+/// A single instruction. Anything that doesn't fit in a couple of words is boxed,
+/// to keep the instructions small and the dispatch loop cache-friendly.
+// Boxing a Vec is a pointer, rather than the three words of a Vec
+#[allow(clippy::box_collection)]
+pub(crate) enum Instr {
+    // Stack manipulation
+    /// Push a constant.
+    Const(FrozenValue),
+    /// Discard the top of the stack.
+    Pop,
+    /// `[a]` to `[a, a]`.
+    Dup,
+    /// `[a, b]` to `[a, b, a, b]`.
+    Dup2,
+    /// `[a, b]` to `[b, a]`.
+    Swap,
+    /// `[a, b, c]` to `[c, a, b]`.
+    Rot3,
+
+    // Variables
+    /// Push a local variable, the string is its name for the error if it is unassigned.
+    LoadLocal(LocalSlotId, StrId),
+    LoadModule(ModuleSlotId),
+    StoreLocal(LocalSlotId),
+    StoreModule(ModuleSlotId),
+    /// Store a module variable, first naming the value after it, so that things like
+    /// records know what they are called.
+    StoreModuleExport(ModuleSlotId, StrId),
+
+    // Unary operators
+    Not,
+    Minus,
+    Plus,
+    BitNot,
+    /// `len(x)`, where `len` is known to be the builtin.
+    Len,
+    /// `type(x)`, where `type` is known to be the builtin.
+    Type,
+
+    // Binary operators, `[l, r]` to `[l op r]`
+    Add,
+    Sub,
+    Mul,
+    Percent,
+    FloorDiv,
+    BitAnd,
+    BitOr,
+    BitXor,
+    LeftShift,
+    RightShift,
+    Equal,
+    NotEqual,
+    Less,
+    Greater,
+    LessOrEqual,
+    GreaterOrEqual,
+    /// `[r, l]` to `[l in r]`, as the collection is evaluated first.
+    In,
+    NotIn,
+    /// `+=`, which mutates lists in place.
+    AddAssign,
+
+    // Control flow
+    Jump(BcAddr),
+    /// Pop the top of the stack, and jump if it is false.
+    JumpIfFalse(BcAddr),
+    /// Jump if the top of the stack is true, leaving it there, otherwise pop it.
+    JumpIfTrueOrPop(BcAddr),
+    /// Jump if the top of the stack is false, leaving it there, otherwise pop it.
+    JumpIfFalseOrPop(BcAddr),
+    /// Pop a value and start iterating over it.
+    IterStart,
+    /// Pop the arguments of a call like `enumerate(x)` and start iterating over what it
+    /// would return, without building it.
+    IterStartCall(Box<LoopCompiled>),
+    /// Push the next value of the innermost iteration and jump, or carry on if there
+    /// are none left. Loops are written with this test at the bottom.
+    IterNext(BcAddr),
+    /// Like [`IterNext`](Instr::IterNext), for a loop with two variables, so unpack the
+    /// next value and push its second item, then its first.
+    IterNextPair(BcAddr),
+    /// Finish the innermost iteration.
+    IterStop,
+    /// Pop the result and return from the code.
+    Return,
+    /// Marks the start of a statement, for profiling and debugging.
+    BeforeStmt,
+    /// Collect garbage if enough has been allocated, only valid at the top level of a module.
+    PossibleGc,
+
+    // Collections
+    /// Pop the given number of values into a tuple.
+    Tuple(u32),
+    List(u32),
+    /// A list whose elements are all constants.
+    ListConst(Box<Vec<FrozenValue>>),
+    /// Pop the given number of key/value pairs into a dictionary.
+    Dict(u32),
+    /// A dictionary whose keys and values are all constants.
+    DictConst(Box<SmallMap<FrozenValue, FrozenValue>>),
+    /// A dictionary with constant keys, whose values are popped.
+    DictConstKeys(Box<Vec<Hashed<FrozenValue>>>),
+    /// Start a list comprehension.
+    ComprListNew,
+    /// Pop a value onto the innermost list comprehension.
+    ComprListAppend,
+    /// Finish the innermost list comprehension, pushing the list.
+    ComprListEnd,
+    ComprDictNew,
+    /// Pop a key and value into the innermost dict comprehension.
+    ComprDictInsert,
+    ComprDictEnd,
+
+    // Attributes and indexing
+    /// `[x]` to `[x.attr]`, binding methods to `x`.
+    Dot(StrId),
+    /// `[x]` to `[x.attr]`, without binding methods, for `x.attr += ...`.
+    GetAttrRaw(StrId),
+    /// `[x, i]` to `[x[i]]`.
+    Index,
+    /// `[x, start, stop, stride]` to `[x[start:stop:stride]]`, where the parts that are
+    /// present are given by the [`SLICE_START`] etc flags.
+    Slice(u8),
+    /// Pop `[value, x]` and set `x.attr = value`.
+    SetAttr(StrId),
+    /// Pop `[value, x, i]` and set `x[i] = value`.
+    SetIndex,
+    /// Pop a value and push its items, which must be exactly the given number,
+    /// so that the first item ends up on top. The value stays frozen until
+    /// the matching [`UnpackEnd`](Instr::UnpackEnd), so the assignments can't mutate it.
+    Unpack(u32),
+    UnpackEnd,
+
+    // Everything else
+    /// Pop the function and its arguments, and push the result of calling it.
+    Call(Box<CallCompiled>),
+    /// `[x]` to `[x, x.attr]`, where the attribute is about to be called as a method.
+    Method(Box<MethodCompiled>),
+    /// Pop `[x, x.attr]` and the arguments, and push the result of the method call.
+    CallMethod(Box<CallCompiled>),
+    /// Pop the parameter types and defaults, and the return type, and push a new function.
+    Def(Box<DefCompiled>),
+    /// Pop the values of the f-string fields, and push the formatted string.
+    FString(Box<FStringCompiled>),
+    Load(Box<LoadCompiled>),
+}
+
+pub(crate) const SLICE_START: u8 = 1;
+pub(crate) const SLICE_STOP: u8 = 2;
+pub(crate) const SLICE_STRIDE: u8 = 4;
+
+impl Instr {
+    /// The number of values popped and pushed when the instruction falls through
+    /// to the next instruction.
+    pub(crate) fn stack_effect(&self) -> (u32, u32) {
+        match self {
+            Self::Const(_) => (0, 1),
+            Self::Pop => (1, 0),
+            Self::Dup => (1, 2),
+            Self::Dup2 => (2, 4),
+            Self::Swap => (2, 2),
+            Self::Rot3 => (3, 3),
+            Self::LoadLocal(..) | Self::LoadModule(_) => (0, 1),
+            Self::StoreLocal(_) | Self::StoreModule(_) | Self::StoreModuleExport(..) => (1, 0),
+            Self::Not | Self::Minus | Self::Plus | Self::BitNot | Self::Len | Self::Type => (1, 1),
+            Self::Add
+            | Self::Sub
+            | Self::Mul
+            | Self::Percent
+            | Self::FloorDiv
+            | Self::BitAnd
+            | Self::BitOr
+            | Self::BitXor
+            | Self::LeftShift
+            | Self::RightShift
+            | Self::Equal
+            | Self::NotEqual
+            | Self::Less
+            | Self::Greater
+            | Self::LessOrEqual
+            | Self::GreaterOrEqual
+            | Self::In
+            | Self::NotIn
+            | Self::AddAssign => (2, 1),
+            Self::Jump(_) => (0, 0),
+            Self::JumpIfFalse(_) | Self::JumpIfTrueOrPop(_) | Self::JumpIfFalseOrPop(_) => (1, 0),
+            Self::IterStart => (1, 0),
+            Self::IterStartCall(x) => (x.stack_len() as u32, 0),
+            Self::IterNext(_) | Self::IterNextPair(_) | Self::IterStop => (0, 0),
+            Self::Return => (1, 0),
+            Self::BeforeStmt | Self::PossibleGc => (0, 0),
+            Self::Tuple(n) | Self::List(n) => (*n, 1),
+            Self::ListConst(_) | Self::DictConst(_) => (0, 1),
+            Self::Dict(n) => (2 * n, 1),
+            Self::DictConstKeys(keys) => (keys.len() as u32, 1),
+            Self::ComprListNew | Self::ComprDictNew => (0, 0),
+            Self::ComprListAppend => (1, 0),
+            Self::ComprDictInsert => (2, 0),
+            Self::ComprListEnd | Self::ComprDictEnd => (0, 1),
+            Self::Dot(_) | Self::GetAttrRaw(_) => (1, 1),
+            Self::Index => (2, 1),
+            Self::Slice(flags) => (1 + flags.count_ones(), 1),
+            Self::SetAttr(_) => (2, 0),
+            Self::SetIndex => (3, 0),
+            Self::Unpack(n) => (1, *n),
+            Self::UnpackEnd => (0, 0),
+            Self::Call(call) => (1 + call.stack_len() as u32, 1),
+            Self::Method(_) => (1, 2),
+            Self::CallMethod(call) => (2 + call.stack_len() as u32, 1),
+            Self::Def(def) => (def.stack_len() as u32, 1),
+            Self::FString(x) => (x.stack_len() as u32, 1),
+            Self::Load(_) => (0, 0),
+        }
+    }
+
+    /// Write the instruction, for a cached module.
+    pub(crate) fn save(&self, w: &mut CodeWriter) -> anyhow::Result<()> {
+        match self {
+            Self::Const(x) => {
+                w.u8(0);
+                w.value(*x)?
+            }
+            Self::Pop => w.u8(1),
+            Self::Dup => w.u8(2),
+            Self::Dup2 => w.u8(3),
+            Self::Swap => w.u8(4),
+            Self::Rot3 => w.u8(5),
+            Self::LoadLocal(slot, name) => {
+                w.u8(6);
+                w.u32(slot.index() as u32);
+                w.u32(name.0)
+            }
+            Self::LoadModule(slot) => {
+                w.u8(7);
+                w.u32(slot.index() as u32)
+            }
+            Self::StoreLocal(slot) => {
+                w.u8(8);
+                w.u32(slot.index() as u32)
+            }
+            Self::StoreModule(slot) => {
+                w.u8(9);
+                w.u32(slot.index() as u32)
+            }
+            Self::StoreModuleExport(slot, name) => {
+                w.u8(10);
+                w.u32(slot.index() as u32);
+                w.u32(name.0)
+            }
+            Self::Not => w.u8(11),
+            Self::Minus => w.u8(12),
+            Self::Plus => w.u8(13),
+            Self::BitNot => w.u8(14),
+            Self::Len => w.u8(15),
+            Self::Type => w.u8(16),
+            Self::Add => w.u8(17),
+            Self::Sub => w.u8(18),
+            Self::Mul => w.u8(19),
+            Self::Percent => w.u8(20),
+            Self::FloorDiv => w.u8(21),
+            Self::BitAnd => w.u8(22),
+            Self::BitOr => w.u8(23),
+            Self::BitXor => w.u8(24),
+            Self::LeftShift => w.u8(25),
+            Self::RightShift => w.u8(26),
+            Self::Equal => w.u8(27),
+            Self::NotEqual => w.u8(28),
+            Self::Less => w.u8(29),
+            Self::Greater => w.u8(30),
+            Self::LessOrEqual => w.u8(31),
+            Self::GreaterOrEqual => w.u8(32),
+            Self::In => w.u8(33),
+            Self::NotIn => w.u8(34),
+            Self::AddAssign => w.u8(35),
+            Self::Jump(addr) => {
+                w.u8(36);
+                w.u32(addr.0)
+            }
+            Self::JumpIfFalse(addr) => {
+                w.u8(37);
+                w.u32(addr.0)
+            }
+            Self::JumpIfTrueOrPop(addr) => {
+                w.u8(38);
+                w.u32(addr.0)
+            }
+            Self::JumpIfFalseOrPop(addr) => {
+                w.u8(39);
+                w.u32(addr.0)
+            }
+            Self::IterStart => w.u8(40),
+            Self::IterStartCall(x) => {
+                w.u8(41);
+                x.save(w)?
+            }
+            Self::IterNext(addr) => {
+                w.u8(42);
+                w.u32(addr.0)
+            }
+            Self::IterNextPair(addr) => {
+                w.u8(43);
+                w.u32(addr.0)
+            }
+            Self::IterStop => w.u8(44),
+            Self::Return => w.u8(45),
+            Self::BeforeStmt => w.u8(46),
+            Self::PossibleGc => w.u8(47),
+            Self::Tuple(n) => {
+                w.u8(48);
+                w.u32(*n)
+            }
+            Self::List(n) => {
+                w.u8(49);
+                w.u32(*n)
+            }
+            Self::ListConst(xs) => {
+                w.u8(50);
+                w.list(xs, |w, x| w.value(*x))?
+            }
+            Self::Dict(n) => {
+                w.u8(51);
+                w.u32(*n)
+            }
+            Self::DictConst(xs) => {
+                w.u8(52);
+                let xs: Vec<_> = xs.iter().collect();
+                w.list(&xs, |w, (k, v)| {
+                    w.value(**k)?;
+                    w.value(**v)
+                })?
+            }
+            Self::DictConstKeys(xs) => {
+                w.u8(53);
+                w.list(xs, |w, x| w.value(*x.key()))?
+            }
+            Self::ComprListNew => w.u8(54),
+            Self::ComprListAppend => w.u8(55),
+            Self::ComprListEnd => w.u8(56),
+            Self::ComprDictNew => w.u8(57),
+            Self::ComprDictInsert => w.u8(58),
+            Self::ComprDictEnd => w.u8(59),
+            Self::Dot(name) => {
+                w.u8(60);
+                w.u32(name.0)
+            }
+            Self::GetAttrRaw(name) => {
+                w.u8(61);
+                w.u32(name.0)
+            }
+            Self::Index => w.u8(62),
+            Self::Slice(flags) => {
+                w.u8(63);
+                w.u8(*flags)
+            }
+            Self::SetAttr(name) => {
+                w.u8(64);
+                w.u32(name.0)
+            }
+            Self::SetIndex => w.u8(65),
+            Self::Unpack(n) => {
+                w.u8(66);
+                w.u32(*n)
+            }
+            Self::UnpackEnd => w.u8(67),
+            Self::Call(x) => {
+                w.u8(68);
+                x.save(w)?
+            }
+            Self::Method(x) => {
+                w.u8(69);
+                w.str(x.name())
+            }
+            Self::CallMethod(x) => {
+                w.u8(70);
+                x.save(w)?
+            }
+            Self::Def(x) => {
+                w.u8(71);
+                w.def(x)?
+            }
+            Self::FString(x) => {
+                w.u8(72);
+                x.save(w)?
+            }
+            Self::Load(x) => {
+                w.u8(73);
+                x.save(w)?
+            }
+        }
+        Ok(())
+    }
+
+    /// Read an instruction written by [`save`](Instr::save).
+    pub(crate) fn load(r: &mut CodeReader) -> anyhow::Result<Self> {
+        let local =
+            |r: &mut CodeReader| Ok::<_, anyhow::Error>(LocalSlotId::new(r.u32()? as usize));
+        let module =
+            |r: &mut CodeReader| Ok::<_, anyhow::Error>(ModuleSlotId::new(r.u32()? as usize));
+        Ok(match r.u8()? {
+            0 => Self::Const(r.value()?),
+            1 => Self::Pop,
+            2 => Self::Dup,
+            3 => Self::Dup2,
+            4 => Self::Swap,
+            5 => Self::Rot3,
+            6 => Self::LoadLocal(local(r)?, StrId(r.u32()?)),
+            7 => Self::LoadModule(module(r)?),
+            8 => Self::StoreLocal(local(r)?),
+            9 => Self::StoreModule(module(r)?),
+            10 => Self::StoreModuleExport(module(r)?, StrId(r.u32()?)),
+            11 => Self::Not,
+            12 => Self::Minus,
+            13 => Self::Plus,
+            14 => Self::BitNot,
+            15 => Self::Len,
+            16 => Self::Type,
+            17 => Self::Add,
+            18 => Self::Sub,
+            19 => Self::Mul,
+            20 => Self::Percent,
+            21 => Self::FloorDiv,
+            22 => Self::BitAnd,
+            23 => Self::BitOr,
+            24 => Self::BitXor,
+            25 => Self::LeftShift,
+            26 => Self::RightShift,
+            27 => Self::Equal,
+            28 => Self::NotEqual,
+            29 => Self::Less,
+            30 => Self::Greater,
+            31 => Self::LessOrEqual,
+            32 => Self::GreaterOrEqual,
+            33 => Self::In,
+            34 => Self::NotIn,
+            35 => Self::AddAssign,
+            36 => Self::Jump(BcAddr(r.u32()?)),
+            37 => Self::JumpIfFalse(BcAddr(r.u32()?)),
+            38 => Self::JumpIfTrueOrPop(BcAddr(r.u32()?)),
+            39 => Self::JumpIfFalseOrPop(BcAddr(r.u32()?)),
+            40 => Self::IterStart,
+            41 => Self::IterStartCall(box LoopCompiled::load(r)?),
+            42 => Self::IterNext(BcAddr(r.u32()?)),
+            43 => Self::IterNextPair(BcAddr(r.u32()?)),
+            44 => Self::IterStop,
+            45 => Self::Return,
+            46 => Self::BeforeStmt,
+            47 => Self::PossibleGc,
+            48 => Self::Tuple(r.u32()?),
+            49 => Self::List(r.u32()?),
+            50 => Self::ListConst(box r.list(CodeReader::value)?),
+            51 => Self::Dict(r.u32()?),
+            52 => {
+                let mut res = SmallMap::new();
+                for (k, v) in r.list(|r| Ok((r.value()?, r.value()?)))? {
+                    res.insert_hashed(k.get_hashed()?, v);
+                }
+                Self::DictConst(box res)
+            }
+            53 => Self::DictConstKeys(box r.list(|r| r.value()?.get_hashed())?),
+            54 => Self::ComprListNew,
+            55 => Self::ComprListAppend,
+            56 => Self::ComprListEnd,
+            57 => Self::ComprDictNew,
+            58 => Self::ComprDictInsert,
+            59 => Self::ComprDictEnd,
+            60 => Self::Dot(StrId(r.u32()?)),
+            61 => Self::GetAttrRaw(StrId(r.u32()?)),
+            62 => Self::Index,
+            63 => Self::Slice(r.u8()?),
+            64 => Self::SetAttr(StrId(r.u32()?)),
+            65 => Self::SetIndex,
+            66 => Self::Unpack(r.u32()?),
+            67 => Self::UnpackEnd,
+            68 => Self::Call(box CallCompiled::load(r)?),
+            69 => Self::Method(box MethodCompiled::new(r.string()?)),
+            70 => Self::CallMethod(box CallCompiled::load(r)?),
+            71 => Self::Def(box r.def()?),
+            72 => Self::FString(box FStringCompiled::load(r)?),
+            73 => Self::Load(box LoadCompiled::load(r)?),
+            _ => return Err(CodeReader::corrupt()),
+        })
+    }
+
+    /// The target of a jump instruction.
+    pub(crate) fn jump_target_mut(&mut self) -> Option<&mut BcAddr> {
+        match self {
+            Self::Jump(x)
+            | Self::JumpIfFalse(x)
+            | Self::JumpIfTrueOrPop(x)
+            | Self::JumpIfFalseOrPop(x)
+            | Self::IterNext(x)
+            | Self::IterNextPair(x) => Some(x),
+            _ => None,
+        }
+    }
+}
+
+/// Compiled code for a module or a function body.
+pub(crate) struct Bc {
+    pub(crate) instrs: Box<[Instr]>,
+    /// The span of each instruction, used for errors.
+    pub(crate) spans: Box<[Span]>,
+    pub(crate) strings: Box<[String]>,
+    /// The most values that are on the evaluation stack at once.
+    pub(crate) max_stack: usize,
+}
+
+impl Bc {
+    pub(crate) fn string(&self, x: StrId) -> &str {
+        &self.strings[x.0 as usize]
+    }
+
+    /// Write the code, for a cached module.
+    pub(crate) fn save(&self, w: &mut CodeWriter) -> anyhow::Result<()> {
+        w.list(&self.instrs, |w, x| x.save(w))?;
+        w.list(&self.spans, |w, x| {
+            w.span(*x);
+            Ok(())
+        })?;
+        w.list(&self.strings, |w, x| {
+            w.str(x);
+            Ok(())
+        })?;
+        w.u32(self.max_stack as u32);
+        Ok(())
+    }
+
+    /// Read code written by [`save`](Bc::save). The interpreter trusts the code, so
+    /// we check the jumps and strings stay inside it.
+    pub(crate) fn load(r: &mut CodeReader) -> anyhow::Result<Self> {
+        let mut instrs = r.list(Instr::load)?;
+        let spans = r.list(CodeReader::span)?;
+        let strings = r.list(CodeReader::string)?;
+        let max_stack = r.u32()? as usize;
+        let len = instrs.len() as u32;
+        let ok = spans.len() == instrs.len()
+            && instrs.iter_mut().all(|x| {
+                let jump = x.jump_target_mut().map_or(true, |x| x.0 < len);
+                let string = match x {
+                    Instr::LoadLocal(_, x)
+                    | Instr::StoreModuleExport(_, x)
+                    | Instr::Dot(x)
+                    | Instr::GetAttrRaw(x)
+                    | Instr::SetAttr(x) => (x.0 as usize) < strings.len(),
+                    _ => true,
+                };
+                jump && string
+            });
+        if !ok {
+            return Err(CodeReader::corrupt());
+        }
+        Ok(Self {
+            instrs: instrs.into_boxed_slice(),
+            spans: spans.into_boxed_slice(),
+            strings: strings.into_boxed_slice(),
+            max_stack,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::mem;
+
+    #[test]
+    fn test_instr_size() {
+        assert!(mem::size_of::<Instr>() <= 16);
+    }
+
+    #[test]
+    fn test_send_sync()
+    where
+        Bc: Send + Sync,
+    {
+    }
+}
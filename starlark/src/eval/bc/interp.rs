@@ -0,0 +1,632 @@
+/*
+ * Copyright 2019 The Starlark in Rust Authors.
+ * Copyright (c) Facebook, Inc. and its affiliates.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! The interpreter loop, which runs a [`Bc`].
+
+use crate::{
+    codemap::Span,
+    collections::{Hashed, SmallMap},
+    eval::{
+        bc::instr::{Bc, Instr, SLICE_START, SLICE_STOP, SLICE_STRIDE},
+        compiler::throw_error,
+        fragment::{expr::EvalError, stmt::AssignError},
+        runtime::{evaluator::Evaluator, gc::next_gc_gap},
+    },
+    values::{
+        dict::Dict,
+        fast_string,
+        function::{BoundMethod, NativeAttribute},
+        list::{FrozenList, List},
+        tuple::Tuple,
+        AttrType, ControlError, FrozenValue, Heap, RefIterable, StarlarkValue, Trace, Value,
+    },
+};
+use anyhow::anyhow;
+use gazebo::{cast, cell::ARef, coerce::coerce_ref};
+use std::{any::TypeId, cmp::Ordering, mem, mem::MaybeUninit, time::Instant, vec};
+
+/// The evaluation stack of a running [`Bc`]. The [`BcWriter`](crate::eval::bc::writer::BcWriter)
+/// works out how deep the stack gets, and that the instructions never pop an empty stack,
+/// so we don't check either at runtime.
+struct Stack<'v, 's> {
+    xs: &'s mut [MaybeUninit<Value<'v>>],
+    len: usize,
+}
+
+impl<'v, 's> Stack<'v, 's> {
+    #[inline(always)]
+    fn push(&mut self, x: Value<'v>) {
+        debug_assert!(self.len < self.xs.len());
+        unsafe { self.xs.get_unchecked_mut(self.len).write(x) };
+        self.len += 1;
+    }
+
+    #[inline(always)]
+    fn pop(&mut self) -> Value<'v> {
+        debug_assert!(self.len > 0);
+        self.len -= 1;
+        unsafe { self.xs.get_unchecked(self.len).assume_init_read() }
+    }
+
+    #[inline(always)]
+    fn top(&self) -> Value<'v> {
+        debug_assert!(self.len > 0);
+        unsafe { self.xs.get_unchecked(self.len - 1).assume_init_read() }
+    }
+
+    /// Pop the top `n` values, returning them with the first pushed first.
+    #[inline(always)]
+    fn pop_n(&mut self, n: usize) -> &[Value<'v>] {
+        debug_assert!(self.len >= n);
+        self.len -= n;
+        unsafe { MaybeUninit::slice_assume_init_ref(self.xs.get_unchecked(self.len..self.len + n)) }
+    }
+
+    fn reverse_top(&mut self, n: usize) {
+        self.xs[self.len - n..self.len].reverse()
+    }
+}
+
+/// An iteration in progress.
+pub(crate) enum LoopIter<'v> {
+    /// Over a value, which is kept frozen, so it can't be mutated while we go through it.
+    Value {
+        // Declared first, so it is dropped before the things it borrows from
+        iter: Box<dyn Iterator<Item = Value<'v>> + 'v>,
+        _iterable: Box<RefIterable<'v>>,
+        _freeze_for_iteration: ARef<'v, dyn StarlarkValue<'v>>,
+    },
+    /// Over items copied out in advance, see [`LoopCompiled`](crate::eval::fragment::loops::LoopCompiled).
+    Values(vec::IntoIter<Value<'v>>),
+    /// Over pairs copied out in advance, which only become tuples if a loop needs them to.
+    Pairs(vec::IntoIter<(Value<'v>, Value<'v>)>),
+}
+
+impl<'v> LoopIter<'v> {
+    pub(crate) fn new(x: Value<'v>, heap: &'v Heap) -> anyhow::Result<Self> {
+        let freeze_for_iteration = x.get_aref();
+        let iterable = box x.iterate(heap)?;
+        // Safe because the iterable is boxed, so won't move, and lives as long as the iterator
+        let iter = unsafe { cast::ptr_lifetime(&*iterable) }.iter();
+        Ok(Self::Value {
+            iter,
+            _iterable: iterable,
+            _freeze_for_iteration: freeze_for_iteration,
+        })
+    }
+
+    #[inline(always)]
+    fn next(&mut self, heap: &'v Heap) -> Option<Value<'v>> {
+        match self {
+            Self::Value { iter, .. } => iter.next(),
+            Self::Values(xs) => xs.next(),
+            Self::Pairs(xs) => xs.next().map(|x| heap.alloc(x)),
+        }
+    }
+
+    /// The next item, unpacked into two values, the same as [`Instr::Unpack`] would.
+    fn next_pair(&mut self, heap: &'v Heap) -> Option<anyhow::Result<(Value<'v>, Value<'v>)>> {
+        let x = match self {
+            Self::Pairs(xs) => return xs.next().map(Ok),
+            _ => self.next(heap)?,
+        };
+        Some(unpack_pair(x, heap))
+    }
+}
+
+#[inline(never)]
+fn unpack_pair<'v>(x: Value<'v>, heap: &'v Heap) -> anyhow::Result<(Value<'v>, Value<'v>)> {
+    let len = x.length()?;
+    if len != 2 {
+        return Err(AssignError::IncorrectNumberOfValueToUnpack(2, len).into());
+    }
+    let xs = x.iterate(heap)?;
+    let mut xs = xs.iter();
+    Ok((xs.next().unwrap(), xs.next().unwrap()))
+}
+
+// This function should be called before every meaningful statement.
+// The purposes are GC, profiling and debugging.
+#[inline(always)]
+fn before_stmt(bc: &Bc, ip: usize, eval: &mut Evaluator) {
+    // In all the high-performance use cases we don't have any `before_stmt` things set,
+    // so ensure the check gets inlined but the operation doesn't.
+    #[inline(never)]
+    fn have_stmt(span: Span, eval: &mut Evaluator) {
+        // The user could inject more before_stmt values during iteration (although that sounds like a bad plan!)
+        // so grab the values at the start, and add any additional at the end.
+        let fs = mem::take(&mut eval.before_stmt);
+        for f in &fs {
+            f(span, eval)
+        }
+        let added = mem::replace(&mut eval.before_stmt, fs);
+        for x in added {
+            eval.before_stmt.push(x)
+        }
+    }
+
+    // Almost always will be empty, especially in high-perf use cases
+    if !eval.before_stmt.is_empty() {
+        have_stmt(bc.spans[ip], eval)
+    }
+}
+
+// There are two requirements to perform a GC:
+//
+// 1. We can't be profiling, since profiling relies on the redundant heap
+//    entries. When profiling we set disable_gc.
+// 2. We must be able to access all roots.
+//
+// We track as many roots as possible, and eventually aim to track them all, but
+// for the moment we're only sure we have all roots when we are in the module
+// evaluation eval. There are three roots we don't yet know about:
+//
+// 1. The evaluation stacks of the code that is running, which aren't traced.
+// 2. When evaluating inside a native function, especially if that native
+//    function calls back to a non-native function, e.g. sort with a comparison
+//    function.
+// 3. When iterating we freeze the iteration variable, which means it
+//    can't be moved by a GC. A special type of root.
+//
+// The first issue can be solved by tracing the stacks. The second issue can be
+// solved by disabling GC while in such functions (it's probably rare). The third
+// issue could be solved by making the freeze for iteration a separate flag to the
+// RefCell, at the cost of an extra word in ValueMem. Or we could disable GC while iterating.
+//
+// For the moment we only GC when executing a statement at the root of the
+// module, which we know is safe with respect to all three conditions, as the
+// evaluation stack of the module is empty between statements.
+//
+// We also require that `extra_v` is None, since otherwise the user might have
+// additional values stashed somewhere.
+fn possible_gc(eval: &mut Evaluator) {
+    if !eval.disable_gc
+        && eval.heap().allocated_bytes() >= eval.next_gc_level
+        && eval.extra_v.is_none()
+    {
+        eval.ann("garbage_collection", |eval| {
+            // When we are at a module scope (as checked above) the eval contains
+            // references to all values, so walking covers everything and the unsafe
+            // is satisfied.
+            let before = eval.heap().allocated_bytes();
+            let start = Instant::now();
+            unsafe { eval.heap().garbage_collect(|tracer| eval.trace(tracer)) }
+            let after = eval.heap().allocated_bytes();
+            eval.gc_stats.record(before, after, start.elapsed());
+            eval.next_gc_level = after + next_gc_gap(before, after);
+        })
+    }
+}
+
+fn add<'v>(l: Value<'v>, r: Value<'v>, heap: &'v Heap) -> anyhow::Result<Value<'v>> {
+    // Addition of string is super common and pretty cheap, so have a special case for it.
+    if let Some(ls) = l.unpack_str() {
+        if let Some(rs) = r.unpack_str() {
+            if ls.is_empty() {
+                return Ok(r);
+            } else if rs.is_empty() {
+                return Ok(l);
+            } else {
+                return Ok(heap.alloc(fast_string::append(ls, rs)));
+            }
+        }
+    }
+
+    // Written using Value::add so that Rust Analyzer doesn't think it is an error.
+    Value::add(l, r, heap)
+}
+
+/// Implement lhs += rhs, which is special in Starlark, because lists are mutated,
+/// while all other types are not.
+fn add_assign<'v>(lhs: Value<'v>, rhs: Value<'v>, heap: &'v Heap) -> anyhow::Result<Value<'v>> {
+    // Addition of strings is super common, so have a special case
+    if let Some(ls) = lhs.unpack_str() {
+        if let Some(rs) = rhs.unpack_str() {
+            if ls.is_empty() {
+                return Ok(rhs);
+            } else if rs.is_empty() {
+                return Ok(lhs);
+            } else {
+                return Ok(heap.alloc(fast_string::append(ls, rs)));
+            }
+        }
+    }
+
+    // The Starlark spec says list += mutates, while nothing else does.
+    // When mutating, be careful if they alias, so we don't have `lhs`
+    // mutably borrowed when we iterate over `rhs`, as they might alias.
+    // We also have to deal with frozen lists, in case they are
+    // copy-on-write.
+
+    let lhs_aref = lhs.get_aref();
+    let lhs_ty = lhs_aref.as_dyn_any().static_type_of();
+
+    if lhs_ty == TypeId::of::<List>() || lhs_ty == TypeId::of::<FrozenList>() {
+        mem::drop(lhs_aref);
+        // If the value is None, that must mean its a FrozenList, thus turn it into an immutable error
+        let mut list = List::from_value_mut(lhs)?
+            .ok_or_else(|| anyhow!(ControlError::CannotMutateImmutableValue))?;
+        if lhs.ptr_eq(rhs) {
+            list.content.extend_from_within(..);
+        } else {
+            list.content.extend(rhs.iterate(heap)?.iter());
+        }
+        Ok(lhs)
+    } else if let Some(v) = rhs.get_aref().radd(lhs, heap) {
+        v
+    } else {
+        lhs_aref.add(rhs, heap)
+    }
+}
+
+// The instructions below are kept out of the interpreter loop, so the common
+// instructions don't pay for their size.
+
+#[inline(never)]
+fn dict<'v>(xs: &[Value<'v>], heap: &'v Heap) -> anyhow::Result<Value<'v>> {
+    let mut res = SmallMap::with_capacity(xs.len() / 2);
+    for kv in xs.chunks(2) {
+        let k = kv[0];
+        if res.insert_hashed(k.get_hashed()?, kv[1]).is_some() {
+            return Err(EvalError::DuplicateDictionaryKey(k.to_string()).into());
+        }
+    }
+    Ok(heap.alloc(Dict::new(res)))
+}
+
+#[inline(never)]
+fn dict_const_keys<'v>(
+    keys: &[Hashed<FrozenValue>],
+    xs: &[Value<'v>],
+    heap: &'v Heap,
+) -> anyhow::Result<Value<'v>> {
+    let mut res = SmallMap::with_capacity(keys.len());
+    for (k, v) in keys.iter().zip(xs) {
+        if res.insert_hashed(k.to_hashed_value(), *v).is_some() {
+            return Err(EvalError::DuplicateDictionaryKey(k.key().to_string()).into());
+        }
+    }
+    Ok(heap.alloc(Dict::new(res)))
+}
+
+#[inline(never)]
+fn dot<'v>(x: Value<'v>, attr: &str, eval: &mut Evaluator<'v, '_>) -> anyhow::Result<Value<'v>> {
+    let (attr_type, v) = x.get_attr_error(attr, eval.heap())?;
+    if attr_type == AttrType::Field {
+        Ok(v)
+    } else if let Some(v_attr) = v.downcast_ref::<NativeAttribute>() {
+        v_attr.call(x, eval)
+    } else {
+        // Insert self so the method see the object it is acting on
+        Ok(eval.heap().alloc(BoundMethod::new(x, v)))
+    }
+}
+
+impl Bc {
+    /// Run the code, returning the value it returns.
+    pub(crate) fn run<'v>(&self, eval: &mut Evaluator<'v, '_>) -> anyhow::Result<Value<'v>> {
+        eval.alloca_uninit(self.max_stack, |xs, eval| {
+            self.run_with_stack(Stack { xs, len: 0 }, eval)
+        })
+    }
+
+    // Passing the stack by value lets its length live in a register.
+    fn run_with_stack<'v>(
+        &self,
+        mut stack: Stack<'v, '_>,
+        eval: &mut Evaluator<'v, '_>,
+    ) -> anyhow::Result<Value<'v>> {
+        // Iterations and comprehensions in progress, innermost last
+        let mut iters: Vec<LoopIter<'v>> = Vec::new();
+        let mut lists: Vec<Vec<Value<'v>>> = Vec::new();
+        let mut dicts: Vec<SmallMap<Value<'v>, Value<'v>>> = Vec::new();
+        // Values being unpacked by a tuple assignment
+        let mut unpacking: Vec<ARef<'v, dyn StarlarkValue<'v>>> = Vec::new();
+
+        // The index of the next instruction, so the current one is at `ip - 1`
+        let mut ip = 0;
+
+        // Attach the span of the current instruction to any error
+        macro_rules! throw {
+            ($e:expr) => {
+                match $e {
+                    Ok(x) => x,
+                    Err(e) => return Err(throw_error(e, self.spans[ip - 1], eval)),
+                }
+            };
+        }
+
+        macro_rules! unop {
+            (| $x:ident | $e:expr) => {{
+                let $x = stack.pop();
+                let res = $e;
+                stack.push(res)
+            }};
+        }
+
+        macro_rules! binop {
+            (| $l:ident, $r:ident | $e:expr) => {{
+                let $r = stack.pop();
+                let $l = stack.pop();
+                let res = $e;
+                stack.push(res)
+            }};
+        }
+
+        loop {
+            // The writer always finishes with a `Return`, and never jumps past the end
+            let instr = unsafe { self.instrs.get_unchecked(ip) };
+            ip += 1;
+            match instr {
+                Instr::Const(x) => stack.push(x.to_value()),
+                Instr::Pop => {
+                    stack.pop();
+                }
+                Instr::Dup => stack.push(stack.top()),
+                Instr::Dup2 => {
+                    let b = stack.pop();
+                    let a = stack.pop();
+                    stack.push(a);
+                    stack.push(b);
+                    stack.push(a);
+                    stack.push(b);
+                }
+                Instr::Swap => {
+                    let b = stack.pop();
+                    let a = stack.pop();
+                    stack.push(b);
+                    stack.push(a);
+                }
+                Instr::Rot3 => {
+                    let c = stack.pop();
+                    let b = stack.pop();
+                    let a = stack.pop();
+                    stack.push(c);
+                    stack.push(a);
+                    stack.push(b);
+                }
+                Instr::LoadLocal(slot, name) => {
+                    stack.push(throw!(eval.get_slot_local(*slot, self.string(*name))))
+                }
+                Instr::LoadModule(slot) => stack.push(throw!(eval.get_slot_module(*slot))),
+                Instr::StoreLocal(slot) => {
+                    let x = stack.pop();
+                    eval.set_slot_local(*slot, x)
+                }
+                Instr::StoreModule(slot) => {
+                    let x = stack.pop();
+                    eval.set_slot_module(*slot, x)
+                }
+                Instr::StoreModuleExport(slot, name) => {
+                    let x = stack.pop();
+                    // Make sure that `ComplexValue`s get their name as soon as possible
+                    x.export_as(self.string(*name), eval);
+                    eval.set_slot_module(*slot, x)
+                }
+                Instr::Not => unop!(|x| Value::new_bool(!x.to_bool())),
+                Instr::Minus => unop!(|x| throw!(x.minus(eval.heap()))),
+                Instr::Plus => unop!(|x| throw!(x.plus(eval.heap()))),
+                Instr::BitNot => unop!(|x| Value::new_int(!throw!(x.to_int()))),
+                // Technically the length command _could_ call other functions,
+                // and we'd not get entries on the call stack, which would be bad.
+                // But `len()` is super common, and no one expects it to call other functions,
+                // so let's just ignore that corner case for additional perf.
+                Instr::Len => unop!(|x| Value::new_int(throw!(x.length()))),
+                Instr::Type => unop!(|x| x.get_aref().get_type_value().unpack().to_value()),
+                Instr::Add => binop!(|l, r| throw!(add(l, r, eval.heap()))),
+                Instr::Sub => binop!(|l, r| throw!(l.sub(r, eval.heap()))),
+                Instr::Mul => binop!(|l, r| throw!(l.mul(r, eval.heap()))),
+                Instr::Percent => binop!(|l, r| throw!(l.percent(r, eval.heap()))),
+                Instr::FloorDiv => binop!(|l, r| throw!(l.floor_div(r, eval.heap()))),
+                Instr::BitAnd => binop!(|l, r| throw!(l.bit_and(r))),
+                Instr::BitOr => binop!(|l, r| throw!(l.bit_or(r))),
+                Instr::BitXor => binop!(|l, r| throw!(l.bit_xor(r))),
+                Instr::LeftShift => binop!(|l, r| throw!(l.left_shift(r))),
+                Instr::RightShift => binop!(|l, r| throw!(l.right_shift(r))),
+                Instr::Equal => binop!(|l, r| Value::new_bool(throw!(l.equals(r)))),
+                Instr::NotEqual => binop!(|l, r| Value::new_bool(!throw!(l.equals(r)))),
+                Instr::Less => {
+                    binop!(|l, r| Value::new_bool(throw!(l.compare(r)) == Ordering::Less))
+                }
+                Instr::Greater => {
+                    binop!(|l, r| Value::new_bool(throw!(l.compare(r)) == Ordering::Greater))
+                }
+                Instr::LessOrEqual => {
+                    binop!(|l, r| Value::new_bool(throw!(l.compare(r)) != Ordering::Greater))
+                }
+                Instr::GreaterOrEqual => {
+                    binop!(|l, r| Value::new_bool(throw!(l.compare(r)) != Ordering::Less))
+                }
+                // The collection is evaluated first, so is underneath
+                Instr::In => binop!(|r, l| Value::new_bool(throw!(r.is_in(l)))),
+                Instr::NotIn => binop!(|r, l| Value::new_bool(!throw!(r.is_in(l)))),
+                Instr::AddAssign => binop!(|l, r| throw!(add_assign(l, r, eval.heap()))),
+                Instr::Jump(x) => ip = x.0 as usize,
+                Instr::JumpIfFalse(x) => {
+                    if !stack.pop().to_bool() {
+                        ip = x.0 as usize;
+                    }
+                }
+                Instr::JumpIfTrueOrPop(x) => {
+                    if stack.top().to_bool() {
+                        ip = x.0 as usize;
+                    } else {
+                        stack.pop();
+                    }
+                }
+                Instr::JumpIfFalseOrPop(x) => {
+                    if !stack.top().to_bool() {
+                        ip = x.0 as usize;
+                    } else {
+                        stack.pop();
+                    }
+                }
+                Instr::IterStart => {
+                    let x = stack.pop();
+                    iters.push(throw!(LoopIter::new(x, eval.heap())));
+                }
+                Instr::IterStartCall(call) => {
+                    let xs = stack.pop_n(call.stack_len());
+                    let res = call.start(xs, self.spans[ip - 1], eval);
+                    iters.push(throw!(res));
+                }
+                Instr::IterNext(x) => {
+                    if let Some(v) = iters.last_mut().unwrap().next(eval.heap()) {
+                        stack.push(v);
+                        ip = x.0 as usize;
+                    }
+                }
+                Instr::IterNextPair(x) => {
+                    if let Some(res) = iters.last_mut().unwrap().next_pair(eval.heap()) {
+                        let (v0, v1) = throw!(res);
+                        stack.push(v1);
+                        stack.push(v0);
+                        ip = x.0 as usize;
+                    }
+                }
+                Instr::IterStop => {
+                    iters.pop();
+                }
+                Instr::Return => return Ok(stack.pop()),
+                Instr::BeforeStmt => before_stmt(self, ip - 1, eval),
+                Instr::PossibleGc => possible_gc(eval),
+                Instr::Tuple(n) => {
+                    let xs = stack.pop_n(*n as usize).to_vec();
+                    stack.push(eval.heap().alloc(Tuple::new(xs)))
+                }
+                Instr::List(n) => {
+                    let xs = stack.pop_n(*n as usize).to_vec();
+                    stack.push(eval.heap().alloc(List::new(xs)))
+                }
+                Instr::ListConst(xs) => {
+                    let xs = coerce_ref(&**xs).clone();
+                    stack.push(eval.heap().alloc(List::new(xs)))
+                }
+                Instr::Dict(n) => {
+                    let xs = stack.pop_n(2 * *n as usize);
+                    let res = dict(xs, eval.heap());
+                    stack.push(throw!(res))
+                }
+                Instr::DictConst(xs) => {
+                    let xs = coerce_ref(&**xs).clone();
+                    stack.push(eval.heap().alloc(Dict::new(xs)))
+                }
+                Instr::DictConstKeys(keys) => {
+                    let xs = stack.pop_n(keys.len());
+                    let res = dict_const_keys(keys, xs, eval.heap());
+                    stack.push(throw!(res))
+                }
+                Instr::ComprListNew => lists.push(Vec::new()),
+                Instr::ComprListAppend => {
+                    let x = stack.pop();
+                    lists.last_mut().unwrap().push(x)
+                }
+                Instr::ComprListEnd => {
+                    let xs = lists.pop().unwrap();
+                    stack.push(eval.heap().alloc(List::new(xs)))
+                }
+                Instr::ComprDictNew => dicts.push(SmallMap::new()),
+                Instr::ComprDictInsert => {
+                    let v = stack.pop();
+                    let k = stack.pop();
+                    let k = throw!(k.get_hashed());
+                    dicts.last_mut().unwrap().insert_hashed(k, v);
+                }
+                Instr::ComprDictEnd => {
+                    let xs = dicts.pop().unwrap();
+                    stack.push(eval.heap().alloc(Dict::new(xs)))
+                }
+                Instr::Dot(attr) => unop!(|x| throw!(dot(x, self.string(*attr), eval))),
+                Instr::GetAttrRaw(attr) => {
+                    unop!(|x| throw!(x.get_attr_error(self.string(*attr), eval.heap())).1)
+                }
+                Instr::Index => binop!(|x, i| throw!(x.at(i, eval.heap()))),
+                Instr::Slice(flags) => {
+                    let mut pop_if = |flag| {
+                        if flags & flag != 0 {
+                            Some(stack.pop())
+                        } else {
+                            None
+                        }
+                    };
+                    let stride = pop_if(SLICE_STRIDE);
+                    let stop = pop_if(SLICE_STOP);
+                    let start = pop_if(SLICE_START);
+                    unop!(|x| throw!(x.slice(start, stop, stride, eval.heap())))
+                }
+                Instr::SetAttr(attr) => {
+                    let x = stack.pop();
+                    let v = stack.pop();
+                    throw!(x.set_attr(self.string(*attr), v))
+                }
+                Instr::SetIndex => {
+                    let i = stack.pop();
+                    let x = stack.pop();
+                    let v = stack.pop();
+                    throw!(x.set_at(i, v))
+                }
+                Instr::Unpack(n) => {
+                    let x = stack.pop();
+                    let len = throw!(x.length());
+                    if len != *n as i32 {
+                        throw!(Err(AssignError::IncorrectNumberOfValueToUnpack(
+                            *n as i32, len
+                        )
+                        .into()))
+                    }
+                    for v in &throw!(x.iterate(eval.heap())) {
+                        stack.push(v)
+                    }
+                    stack.reverse_top(*n as usize);
+                    unpacking.push(x.get_aref());
+                }
+                Instr::UnpackEnd => {
+                    unpacking.pop();
+                }
+                Instr::Call(call) => {
+                    let xs = stack.pop_n(1 + call.stack_len());
+                    let res = call.invoke(xs[0], None, &xs[1..], self.spans[ip - 1], eval);
+                    stack.push(throw!(res))
+                }
+                Instr::Method(method) => {
+                    let x = stack.pop();
+                    // We don't need to worry about whether it's an attribute, method or field
+                    // since those that don't want the `this` just ignore it
+                    let fun = throw!(method.get(x, eval.heap()));
+                    stack.push(x);
+                    stack.push(fun)
+                }
+                Instr::CallMethod(call) => {
+                    let xs = stack.pop_n(2 + call.stack_len());
+                    let res = call.invoke(xs[1], Some(xs[0]), &xs[2..], self.spans[ip - 1], eval);
+                    stack.push(throw!(res))
+                }
+                Instr::Def(def) => {
+                    let xs = stack.pop_n(def.stack_len());
+                    let res = def.eval(xs, eval);
+                    stack.push(throw!(res))
+                }
+                Instr::FString(x) => {
+                    let xs = stack.pop_n(x.stack_len());
+                    let res = x.eval(xs, eval);
+                    stack.push(throw!(res))
+                }
+                Instr::Load(load) => load.eval(eval)?,
+            }
+        }
+    }
+}
@@ -0,0 +1,28 @@
+/*
+ * Copyright 2019 The Starlark in Rust Authors.
+ * Copyright (c) Facebook, Inc. and its affiliates.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! The bytecode that the compiler produces and the interpreter runs.
+//!
+//! Each module, and each function body, compiles to a [`Bc`](instr::Bc): a flat list of
+//! [`Instr`](instr::Instr) which work on an evaluation stack. The stack for each call
+//! is allocated from the [`Evaluator`](crate::eval::Evaluator)'s alloca, and is sized
+//! in advance by the [`BcWriter`](writer::BcWriter).
+
+pub(crate) mod cache;
+pub(crate) mod instr;
+pub(crate) mod interp;
+pub(crate) mod writer;
@@ -0,0 +1,205 @@
+/*
+ * Copyright 2019 The Starlark in Rust Authors.
+ * Copyright (c) Facebook, Inc. and its affiliates.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Accumulate instructions into a [`Bc`].
+
+use crate::{
+    codemap::Span,
+    eval::bc::instr::{Bc, BcAddr, Instr, StrId},
+    values::FrozenValue,
+};
+
+/// The jumps within a loop we are in the middle of writing.
+struct LoopLabels {
+    body: BcAddr,
+    /// Set for a loop with two variables, which takes them with [`Instr::IterNextPair`],
+    /// to the span of the variables, for errors unpacking them.
+    pair: Option<Span>,
+    /// Jumps to the test at the bottom of the loop.
+    continues: Vec<BcAddr>,
+    breaks: Vec<BcAddr>,
+}
+
+#[derive(Default)]
+pub(crate) struct BcWriter {
+    instrs: Vec<Instr>,
+    spans: Vec<Span>,
+    strings: Vec<String>,
+    /// The number of values on the stack after the last instruction, if it fell through.
+    stack: u32,
+    max_stack: u32,
+    loops: Vec<LoopLabels>,
+}
+
+impl BcWriter {
+    pub(crate) fn new() -> Self {
+        Self::default()
+    }
+
+    /// The address of the next instruction to be written.
+    pub(crate) fn addr(&self) -> BcAddr {
+        BcAddr(self.instrs.len() as u32)
+    }
+
+    pub(crate) fn emit(&mut self, instr: Instr, span: Span) {
+        let (pop, push) = instr.stack_effect();
+        assert!(
+            self.stack >= pop,
+            "BcWriter::emit, internal error, stack underflow"
+        );
+        self.stack = self.stack - pop + push;
+        self.max_stack = self.max_stack.max(self.stack);
+        self.instrs.push(instr);
+        self.spans.push(span);
+    }
+
+    /// Emit a jump whose target isn't known yet, to be filled in with [`patch`](BcWriter::patch).
+    pub(crate) fn emit_forward(&mut self, jump: fn(BcAddr) -> Instr, span: Span) -> BcAddr {
+        let res = self.addr();
+        self.emit(jump(BcAddr(u32::MAX)), span);
+        res
+    }
+
+    /// Make the jump at `jump` go to the next instruction to be written.
+    pub(crate) fn patch(&mut self, jump: BcAddr) {
+        let target = self.addr();
+        *self.instrs[jump.0 as usize]
+            .jump_target_mut()
+            .expect("BcWriter::patch, internal error, not a jump") = target;
+    }
+
+    pub(crate) fn string(&mut self, x: String) -> StrId {
+        self.strings.push(x);
+        StrId(self.strings.len() as u32 - 1)
+    }
+
+    /// A string previously added with [`string`](BcWriter::string).
+    pub(crate) fn get_string(&self, x: StrId) -> &str {
+        &self.strings[x.0 as usize]
+    }
+
+    /// The values pushed by the code written since `start`, if all it does is push constants.
+    pub(crate) fn consts_since(&self, start: BcAddr) -> Option<Vec<FrozenValue>> {
+        self.instrs[start.0 as usize..]
+            .iter()
+            .map(|x| match x {
+                Instr::Const(v) => Some(*v),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Throw away the code written since `start`. Only valid for code that nothing jumps
+    /// into, such as the code for an expression which has just been written.
+    pub(crate) fn truncate(&mut self, start: BcAddr) {
+        for x in self.instrs.drain(start.0 as usize..).rev() {
+            let (pop, push) = x.stack_effect();
+            self.stack = self.stack + pop - push;
+        }
+        self.spans.truncate(start.0 as usize);
+    }
+
+    /// The number of values on the stack at this point.
+    pub(crate) fn stack(&self) -> u32 {
+        self.stack
+    }
+
+    /// Where code is only reached by a jump, reset the stack to what it is at the jump.
+    pub(crate) fn set_stack(&mut self, stack: u32) {
+        self.stack = stack;
+        self.max_stack = self.max_stack.max(stack);
+    }
+
+    /// Start a loop with `start`, either [`Instr::IterStart`] or [`Instr::IterStartCall`],
+    /// leaving the first item on the stack for the body, or if `pair` is set, the two
+    /// values unpacked from it. The test goes at the bottom of the loop, so the body is
+    /// entered by jumping to the test, and each iteration takes only one jump.
+    pub(crate) fn loop_start(
+        &mut self,
+        start: Instr,
+        pair: Option<Span>,
+        over_span: Span,
+        span: Span,
+    ) {
+        self.emit(start, over_span);
+        let to_test = self.emit_forward(Instr::Jump, span);
+        let body = self.addr();
+        // Pushed by the `IterNext` which jumps back here
+        self.set_stack(self.stack + if pair.is_some() { 2 } else { 1 });
+        self.loops.push(LoopLabels {
+            body,
+            pair,
+            continues: vec![to_test],
+            breaks: Vec::new(),
+        })
+    }
+
+    /// Finish the innermost loop, writing the test and the end of the iteration.
+    pub(crate) fn loop_end(&mut self, span: Span) {
+        let labels = self
+            .loops
+            .pop()
+            .expect("BcWriter::loop_end, internal error, not in a loop");
+        for x in labels.continues {
+            self.patch(x);
+        }
+        match labels.pair {
+            None => self.emit(Instr::IterNext(labels.body), span),
+            Some(pair) => self.emit(Instr::IterNextPair(labels.body), pair),
+        }
+        for x in labels.breaks {
+            self.patch(x);
+        }
+        self.emit(Instr::IterStop, span);
+    }
+
+    pub(crate) fn emit_break(&mut self, span: Span) {
+        let jump = self.emit_forward(Instr::Jump, span);
+        // Break outside a loop is a parse error
+        if let Some(labels) = self.loops.last_mut() {
+            labels.breaks.push(jump);
+        }
+    }
+
+    /// Emit a jump to the next iteration of the innermost loop, e.g. [`Instr::Jump`]
+    /// for `continue`, or [`Instr::JumpIfFalse`] for the `if` clause of a comprehension.
+    pub(crate) fn emit_continue(&mut self, jump: fn(BcAddr) -> Instr, span: Span) {
+        let jump = self.emit_forward(jump, span);
+        // Continue outside a loop is a parse error
+        if let Some(labels) = self.loops.last_mut() {
+            labels.continues.push(jump);
+        }
+    }
+
+    pub(crate) fn finish(self) -> Bc {
+        assert!(
+            self.loops.is_empty(),
+            "BcWriter::finish, internal error, unfinished loop"
+        );
+        // The interpreter relies on this to never run off the end of the code
+        assert!(
+            matches!(self.instrs.last(), Some(Instr::Return)),
+            "BcWriter::finish, internal error, code doesn't end with a return"
+        );
+        Bc {
+            instrs: self.instrs.into_boxed_slice(),
+            spans: self.spans.into_boxed_slice(),
+            strings: self.strings.into_boxed_slice(),
+            max_stack: self.max_stack as usize,
+        }
+    }
+}
@@ -0,0 +1,432 @@
+/*
+ * Copyright 2019 The Starlark in Rust Authors.
+ * Copyright (c) Facebook, Inc. and its affiliates.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Optimisations made while compiling: evaluating expressions whose operands are all
+//! constants, and inlining calls to trivial frozen functions. Dead branches are dropped
+//! where the conditions are compiled, using [`expr_known`](Compiler::expr_known).
+//!
+//! We only fold operations which can't have side effects, and only when they succeed.
+//! Anything which fails (e.g. `1 // 0`) is left to fail at runtime, with the usual error,
+//! and only if it is actually run.
+
+use crate::{
+    codemap::Span,
+    collections::SmallMap,
+    eval::{
+        bc::instr::{BcAddr, Instr},
+        compiler::Compiler,
+        fragment::def::{FrozenDef, InlineDef},
+    },
+    syntax::ast::{Argument, AstArgument, AstExpr},
+    values::{
+        tuple::{FrozenTuple, Tuple},
+        AttrType, FrozenValue, Heap, Value, ValueLike,
+    },
+};
+use gazebo::prelude::*;
+use std::cmp::Ordering;
+
+impl Compiler<'_> {
+    /// Emit `instr`, which takes its operands from the code written since `start`.
+    /// If that code does nothing but push constants, and we can work out what `instr`
+    /// would produce from them, emit that instead.
+    pub(crate) fn emit_fold(&mut self, start: BcAddr, instr: Instr, span: Span) {
+        if self.optimise {
+            if let Some(xs) = self.bc.consts_since(start) {
+                if let Some(res) = self.fold(&instr, &xs) {
+                    self.bc.truncate(start);
+                    self.bc.emit(res, span);
+                    return;
+                }
+            }
+        }
+        self.bc.emit(instr, span)
+    }
+
+    /// Compile an expression, and if its value is known at compile time, take the code
+    /// back out and return the value instead.
+    pub(crate) fn expr_known(&mut self, expr: AstExpr) -> Option<FrozenValue> {
+        let start = self.bc.addr();
+        self.expr(expr);
+        if !self.optimise {
+            return None;
+        }
+        match self.bc.consts_since(start)?.as_slice() {
+            [x] => {
+                let x = *x;
+                self.bc.truncate(start);
+                Some(x)
+            }
+            _ => None,
+        }
+    }
+
+    /// If `f` is a frozen `def` whose body just returns a constant or one of its parameters,
+    /// and `args` fill its parameters exactly, what the call can be replaced with.
+    pub(crate) fn inline(&self, f: FrozenValue, args: &[AstArgument]) -> Option<InlineDef> {
+        if !self.optimise
+            || !args
+                .iter()
+                .all(|x| matches!(x.node, Argument::Positional(_)))
+        {
+            return None;
+        }
+        f.downcast_frozen_ref::<FrozenDef>()?.inline(args.len())
+    }
+
+    /// Emit the code for an inlined call. The arguments are still evaluated, in order,
+    /// unless they are constants, in case they have side effects.
+    pub(crate) fn call_inline(&mut self, inline: InlineDef, args: Vec<AstArgument>, span: Span) {
+        let keep = match inline {
+            InlineDef::Param(i) => Some(i),
+            InlineDef::Const(_) => None,
+        };
+        for (i, x) in args.into_iter().enumerate() {
+            let x = match x.node {
+                Argument::Positional(x) => x,
+                _ => unreachable!("Only positional arguments are inlined"),
+            };
+            let x_span = x.span;
+            let start = self.bc.addr();
+            self.expr(x);
+            if keep != Some(i) {
+                // Constants have no side effects, so needn't be evaluated at all
+                if self.bc.consts_since(start).is_some() {
+                    self.bc.truncate(start);
+                } else {
+                    self.bc.emit(Instr::Pop, x_span);
+                }
+            }
+        }
+        if let InlineDef::Const(v) = inline {
+            self.bc.emit(Instr::Const(v), span);
+        }
+    }
+
+    /// The instruction which does the same as `instr` applied to `xs`.
+    fn fold(&mut self, instr: &Instr, xs: &[FrozenValue]) -> Option<Instr> {
+        match (instr, xs) {
+            // These make mutable values, so must produce a fresh one each time
+            (Instr::List(_), _) if !xs.is_empty() => Some(Instr::ListConst(box xs.to_vec())),
+            (Instr::Dict(_), _) if !xs.is_empty() => {
+                let mut res = SmallMap::with_capacity(xs.len() / 2);
+                for kv in xs.chunks(2) {
+                    res.insert_hashed(kv[0].get_hashed().ok()?, kv[1]);
+                }
+                // Duplicate keys are an error at runtime, so leave them to raise it
+                if res.len() * 2 != xs.len() {
+                    return None;
+                }
+                Some(Instr::DictConst(box res))
+            }
+            _ => self.fold_value(instr, xs).map(Instr::Const),
+        }
+    }
+
+    fn fold_value(&mut self, instr: &Instr, xs: &[FrozenValue]) -> Option<FrozenValue> {
+        let c = self.constants;
+        match (instr, xs) {
+            (Instr::Not, [x]) => Some(FrozenValue::new_bool(!x.to_value().to_bool())),
+            (Instr::Minus, [x]) => self.eval_frozen(|heap| x.to_value().minus(heap).ok()),
+            (Instr::Plus, [x]) => self.eval_frozen(|heap| x.to_value().plus(heap).ok()),
+            (Instr::BitNot, [x]) => Some(FrozenValue::new_int(!x.unpack_int()?)),
+            (Instr::Len, [x]) => Some(FrozenValue::new_int(x.to_value().length().ok()?)),
+            (Instr::Type, [x]) => Some(x.to_value().get_aref().get_type_value().unpack()),
+            (Instr::Tuple(_), _) => Some(self.heap.alloc(FrozenTuple {
+                content: xs.to_vec(),
+            })),
+            (Instr::Index, [x, i]) => {
+                self.eval_frozen(|heap| x.to_value().at(i.to_value(), heap).ok())
+            }
+            // Fields of frozen values are frozen too, which resolves names like `json.encode`.
+            // Methods need binding to the value, so are left until runtime.
+            (Instr::Dot(attr), [x]) => {
+                let attr = self.bc.get_string(*attr).to_owned();
+                self.eval_frozen(|heap| match x.to_value().get_attr_error(&attr, heap) {
+                    Ok((AttrType::Field, v)) => Some(v),
+                    _ => None,
+                })
+            }
+            (Instr::Call(call), [f, x]) if call.is_positional() => {
+                if *f == c.fn_str {
+                    self.eval_frozen(|heap| Some(heap.alloc(x.to_value().to_str())))
+                } else if *f == c.fn_repr {
+                    self.eval_frozen(|heap| Some(heap.alloc(x.to_value().to_repr())))
+                } else if *f == c.fn_bool {
+                    Some(FrozenValue::new_bool(x.to_value().to_bool()))
+                } else {
+                    None
+                }
+            }
+            (_, [l, r]) => self.fold_binop(instr, *l, *r),
+            _ => None,
+        }
+    }
+
+    fn fold_binop(&mut self, instr: &Instr, l: FrozenValue, r: FrozenValue) -> Option<FrozenValue> {
+        // Repeating a string or tuple might make something huge, which we'd rather not
+        // do in advance, for code that may never run
+        if matches!(instr, Instr::Mul) && (l.unpack_int().is_none() || r.unpack_int().is_none()) {
+            return None;
+        }
+        let (lv, rv) = (l.to_value(), r.to_value());
+        let cmp = |f: fn(Ordering) -> bool| Some(FrozenValue::new_bool(f(lv.compare(rv).ok()?)));
+        match instr {
+            Instr::Equal => Some(FrozenValue::new_bool(lv.equals(rv).ok()?)),
+            Instr::NotEqual => Some(FrozenValue::new_bool(!lv.equals(rv).ok()?)),
+            Instr::Less => cmp(|x| x == Ordering::Less),
+            Instr::Greater => cmp(|x| x == Ordering::Greater),
+            Instr::LessOrEqual => cmp(|x| x != Ordering::Greater),
+            Instr::GreaterOrEqual => cmp(|x| x != Ordering::Less),
+            // The collection is pushed first
+            Instr::In => Some(FrozenValue::new_bool(lv.is_in(rv).ok()?)),
+            Instr::NotIn => Some(FrozenValue::new_bool(!lv.is_in(rv).ok()?)),
+            Instr::Add => self.eval_frozen(|heap| l.to_value().add(r.to_value(), heap).ok()),
+            Instr::Sub => self.eval_frozen(|heap| l.to_value().sub(r.to_value(), heap).ok()),
+            Instr::Mul => self.eval_frozen(|heap| l.to_value().mul(r.to_value(), heap).ok()),
+            Instr::Percent => {
+                self.eval_frozen(|heap| l.to_value().percent(r.to_value(), heap).ok())
+            }
+            Instr::FloorDiv => {
+                self.eval_frozen(|heap| l.to_value().floor_div(r.to_value(), heap).ok())
+            }
+            Instr::BitAnd => self.eval_frozen(|_| l.to_value().bit_and(r.to_value()).ok()),
+            Instr::BitOr => self.eval_frozen(|_| l.to_value().bit_or(r.to_value()).ok()),
+            Instr::BitXor => self.eval_frozen(|_| l.to_value().bit_xor(r.to_value()).ok()),
+            Instr::LeftShift => self.eval_frozen(|_| l.to_value().left_shift(r.to_value()).ok()),
+            Instr::RightShift => self.eval_frozen(|_| l.to_value().right_shift(r.to_value()).ok()),
+            _ => None,
+        }
+    }
+
+    /// Run `f` on a scratch heap, keeping the result if it can be moved to the frozen heap.
+    fn eval_frozen(
+        &mut self,
+        f: impl for<'v> FnOnce(&'v Heap) -> Option<Value<'v>>,
+    ) -> Option<FrozenValue> {
+        let heap = Heap::new();
+        let res = f(&heap)?;
+        self.freeze_folded(res)
+    }
+
+    /// Copy a value to the frozen heap. We only do this for immutable values, for
+    /// which a copy can't be told apart from the original.
+    fn freeze_folded(&mut self, x: Value) -> Option<FrozenValue> {
+        if let Some(x) = x.unpack_frozen() {
+            Some(x)
+        } else if let Some(x) = x.unpack_str() {
+            Some(self.heap.alloc(x))
+        } else if let Some(x) = Tuple::from_value(x) {
+            let content = x.content.try_map(|x| self.freeze_folded(*x).ok_or(()));
+            Some(self.heap.alloc(FrozenTuple {
+                content: content.ok()?,
+            }))
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use crate::{
+        assert::Assert,
+        environment::{Globals, GlobalsBuilder, Module},
+        eval::{
+            bc::{
+                instr::{Bc, Instr},
+                writer::BcWriter,
+            },
+            compiler::{scope::Scope, Compiler, Constants},
+            Evaluator,
+        },
+        syntax::{AstModule, Dialect},
+        values::FrozenValue,
+    };
+    use gazebo::prelude::*;
+
+    pub(crate) fn compile(module: &Module, program: &str, globals: &Globals, optimise: bool) -> Bc {
+        let ast = AstModule::parse("test.star", program.to_owned(), &Dialect::Extended).unwrap();
+        let mut compiler = Compiler {
+            scope: Scope::enter_module(module.names(), &ast.statement),
+            heap: module.frozen_heap(),
+            globals,
+            errors: Vec::new(),
+            codemap: ast.codemap.dupe(),
+            constants: Constants::new(),
+            optimise,
+            bc: BcWriter::new(),
+        };
+        compiler.stmt(ast.statement, false);
+        if let Some(e) = compiler.errors.pop() {
+            panic!("{}", e);
+        }
+        compiler
+            .bc
+            .emit(Instr::Const(FrozenValue::new_none()), Default::default());
+        compiler.bc.emit(Instr::Return, Default::default());
+        compiler.bc.finish()
+    }
+
+    /// What the expression compiles to, if it compiles to a constant.
+    fn folded_with(expr: &str, globals: &Globals) -> Option<String> {
+        let module = Module::new();
+        let bc = compile(&module, &format!("y = 1\nx = {}", expr), globals, true);
+        // Skip the code for `y = 1`
+        match &bc.instrs[3..] {
+            [Instr::BeforeStmt, Instr::Const(v), Instr::StoreModuleExport(..), ..] => {
+                Some(v.to_value().to_repr())
+            }
+            _ => None,
+        }
+    }
+
+    fn folded(expr: &str) -> Option<String> {
+        folded_with(expr, &Globals::extended())
+    }
+
+    #[test]
+    fn test_fold_operators() {
+        assert_eq!(folded("'a' + 'b'"), Some("\"ab\"".to_owned()));
+        assert_eq!(folded("1 << 4"), Some("16".to_owned()));
+        assert_eq!(folded("-(1 + 2) * 3"), Some("-9".to_owned()));
+        assert_eq!(folded("not (1 < 2)"), Some("False".to_owned()));
+        assert_eq!(folded("2 in (1, 2)"), Some("True".to_owned()));
+        assert_eq!(folded("(1, 'x' + 'y')"), Some("(1, \"xy\")".to_owned()));
+        assert_eq!(folded("(1, 2, 3)[1]"), Some("2".to_owned()));
+        assert_eq!(folded("'%s!' % 'hi'"), Some("\"hi!\"".to_owned()));
+        assert_eq!(folded("True or y"), Some("True".to_owned()));
+    }
+
+    #[test]
+    fn test_fold_builtins() {
+        assert_eq!(folded("len((1, 2))"), Some("2".to_owned()));
+        assert_eq!(folded("type('x')"), Some("\"string\"".to_owned()));
+        assert_eq!(
+            folded("str(1) + repr('a')"),
+            Some("\"1\\\"a\\\"\"".to_owned())
+        );
+        assert_eq!(folded("bool(())"), Some("False".to_owned()));
+        assert!(folded("json.encode").is_some());
+    }
+
+    #[test]
+    fn test_fold_skipped() {
+        // Errors are left until runtime
+        assert_eq!(folded("1 // 0"), None);
+        assert_eq!(folded("len(1)"), None);
+        // Values which might be big or are mutable
+        assert_eq!(folded("'a' * 3"), None);
+        assert_eq!(folded("[1] + [2]"), None);
+        // Depends on a variable
+        assert_eq!(folded("y + 1"), None);
+        // Methods might not be pure
+        assert_eq!(folded("'a'.upper()"), None);
+        // Only when optimising
+        let module = Module::new();
+        let bc = compile(&module, "x = 1 + 2", &Globals::standard(), false);
+        assert!(matches!(bc.instrs[3], Instr::Add));
+    }
+
+    #[test]
+    fn test_dead_branches() {
+        let calls = |program| {
+            let module = Module::new();
+            let bc = compile(&module, program, &Globals::extended(), true);
+            bc.instrs
+                .iter()
+                .filter(|x| matches!(x, Instr::Call(_)))
+                .count()
+        };
+        assert_eq!(calls("if 1 > 2:\n  print(1)\nelse:\n  x = 1"), 0);
+        assert_eq!(calls("if 1 < 2:\n  print(1)\nelse:\n  x = 1"), 1);
+        assert_eq!(calls("x = [] if True else print(1)"), 0);
+        assert_eq!(calls("x = False and print(1)"), 0);
+        assert_eq!(calls("x = False or print(1)"), 1);
+    }
+
+    #[test]
+    fn test_dead_branches_still_checked() {
+        let a = Assert::new();
+        a.fail("if False:\n  undefined_variable", "not found");
+        a.fail("x = 1 if True else undefined_variable", "not found");
+        a.fail("x = True or undefined_variable", "not found");
+    }
+
+    #[test]
+    fn test_same_results() {
+        let program = r#"
+def f(x):
+    if x > 2 and "b" in "abc":
+        return "big" + "!"
+    return (1, 2)[1] << 3 if len((1,)) == 1 else None
+(f(3), f(1), json.encode([1] if not False else [2]), (1, 2) + (3,), str(-(2 - 5)))
+"#;
+        let expected = r#"("big!", 16, "[1]", (1, 2, 3), "3")"#;
+        for optimise in [true, false] {
+            let module = Module::new();
+            let globals = Globals::extended();
+            let mut eval = Evaluator::new(&module, &globals);
+            if !optimise {
+                eval.disable_optimisation();
+            }
+            let ast = AstModule::parse("test.star", program.to_owned(), &Dialect::Extended);
+            let res = eval.eval_module(ast.unwrap()).unwrap();
+            assert_eq!(res.to_repr(), expected);
+        }
+    }
+
+    fn add_defs(g: &mut GlobalsBuilder) {
+        let program = "
+def ident(x): return x
+def second(x, y): return y
+def five(x):
+    return 5
+def typed(x: \"int\"): return x
+";
+        let module = Module::new();
+        let globals = Globals::standard();
+        let mut eval = Evaluator::new(&module, &globals);
+        let ast = AstModule::parse("defs.star", program.to_owned(), &Dialect::Extended);
+        eval.eval_module(ast.unwrap()).unwrap();
+        let module = module.freeze().unwrap();
+        for x in ["ident", "second", "five", "typed"] {
+            g.set(x, module.get(x).unwrap());
+        }
+    }
+
+    #[test]
+    fn test_inline() {
+        let globals = GlobalsBuilder::standard().with(add_defs).build();
+        assert_eq!(folded_with("ident(3)", &globals), Some("3".to_owned()));
+        assert_eq!(
+            folded_with("second(1, 'x') + 'y'", &globals),
+            Some("\"xy\"".to_owned())
+        );
+        assert_eq!(folded_with("five(1)", &globals), Some("5".to_owned()));
+        // Not inlined if the parameters aren't filled positionally, or have types
+        assert_eq!(folded_with("ident(x = 3)", &globals), None);
+        assert_eq!(folded_with("typed(3)", &globals), None);
+
+        let mut a = Assert::new();
+        a.globals_add(add_defs);
+        a.pass("xs = []\nassert_eq(five(xs.append(1)), 5)\nassert_eq(xs, [1])");
+        a.pass("def f(y): return second(y, [y])\nassert_eq(f(1), [1])");
+        a.fail("ident(1, 2)", "extra positional");
+        a.fail("typed('x')", "type");
+    }
+}
@@ -15,100 +15,34 @@
  * limitations under the License.
  */
 
+pub(crate) mod fold;
 pub(crate) mod scope;
 
 use crate::{
     codemap::{CodeMap, Span},
     environment::Globals,
     errors::Diagnostic,
-    eval::{compiler::scope::Scope, Evaluator},
-    values::{FrozenHeap, FrozenValue, Value},
+    eval::{bc::writer::BcWriter, compiler::scope::Scope, Evaluator},
+    values::{FrozenHeap, FrozenValue},
 };
-use anyhow::anyhow;
 use gazebo::prelude::*;
 use once_cell::sync::Lazy;
-use std::fmt::Debug;
-
-pub(crate) type ExprCompiled = Box<
-    dyn for<'v> Fn(&mut Evaluator<'v, '_>) -> Result<Value<'v>, EvalException<'v>> + Send + Sync,
->;
-pub(crate) enum ExprCompiledValue {
-    Value(FrozenValue),
-    Compiled(ExprCompiled),
-}
-
-impl ExprCompiledValue {
-    pub fn as_value(&self) -> Option<FrozenValue> {
-        match self {
-            Self::Value(x) => Some(*x),
-            Self::Compiled(_) => None,
-        }
-    }
-
-    pub fn as_compiled(self) -> ExprCompiled {
-        match self {
-            Self::Value(x) => box move |_| Ok(x.to_value()),
-            Self::Compiled(x) => x,
-        }
-    }
-}
-
-pub(crate) type StmtCompiled =
-    Box<dyn for<'v> Fn(&mut Evaluator<'v, '_>) -> Result<(), EvalException<'v>> + Send + Sync>;
-
-#[derive(Debug)]
-pub(crate) enum EvalException<'v> {
-    // Flow control statement reached, impossible to escape with
-    // since we statically check for them
-    Break,
-    Continue,
-    Return(Value<'v>),
-    // Error bubbling up
-    Error(anyhow::Error),
-}
-
-impl<'v> From<anyhow::Error> for EvalException<'v> {
-    fn from(x: anyhow::Error) -> Self {
-        Self::Error(x)
-    }
-}
+use std::mem;
 
 // Make sure the error-path doesn't get inlined into the normal-path execution
 #[inline(never)]
-fn throw_error<'v, T>(
-    e: anyhow::Error,
-    span: Span,
-    eval: &Evaluator<'v, '_>,
-) -> Result<T, EvalException<'v>> {
-    let e = Diagnostic::modify(e, |d: &mut Diagnostic| {
+pub(crate) fn throw_error(e: anyhow::Error, span: Span, eval: &Evaluator) -> anyhow::Error {
+    Diagnostic::modify(e, |d: &mut Diagnostic| {
         d.set_span(span, eval.codemap.dupe());
         d.set_call_stack(|| eval.call_stack.to_diagnostic_frames());
-    });
-    Err(EvalException::Error(e))
+    })
 }
 
 /// Convert syntax error to spanned evaluation exception
-pub(crate) fn throw<'v, T>(
-    r: anyhow::Result<T>,
-    span: Span,
-    eval: &Evaluator<'v, '_>,
-) -> Result<T, EvalException<'v>> {
+pub(crate) fn throw<T>(r: anyhow::Result<T>, span: Span, eval: &Evaluator) -> anyhow::Result<T> {
     match r {
         Ok(v) => Ok(v),
-        Err(e) => throw_error(e, span, eval),
-    }
-}
-
-impl From<EvalException<'_>> for anyhow::Error {
-    fn from(x: EvalException) -> Self {
-        match x {
-            EvalException::Error(e) => e,
-            EvalException::Break => anyhow!("Break statement used outside of a loop"),
-            EvalException::Continue => anyhow!("Continue statement used outside of a loop"),
-            EvalException::Return(..) => {
-                anyhow!("Return statement used outside of a function call")
-            }
-        }
+        Err(e) => Err(throw_error(e, span, eval)),
     }
 }
 
@@ -119,12 +53,32 @@ pub(crate) struct Compiler<'a> {
     pub(crate) errors: Vec<anyhow::Error>,
     pub(crate) codemap: CodeMap,
     pub(crate) constants: Constants,
+    /// Should we evaluate constant expressions, remove dead branches and inline functions.
+    pub(crate) optimise: bool,
+    /// Where the code being compiled is written.
+    pub(crate) bc: BcWriter,
+}
+
+impl Compiler<'_> {
+    /// Compile code which can never run, and throw it away. We still compile it,
+    /// so that it reports the same compile errors as if it could run.
+    pub(crate) fn dead_code(&mut self, f: impl FnOnce(&mut Self)) {
+        let live = mem::take(&mut self.bc);
+        f(self);
+        self.bc = live;
+    }
 }
 
 #[derive(Clone, Copy, Dupe)]
 pub(crate) struct Constants {
     pub(crate) fn_len: FrozenValue,
     pub(crate) fn_type: FrozenValue,
+    pub(crate) fn_str: FrozenValue,
+    pub(crate) fn_repr: FrozenValue,
+    pub(crate) fn_bool: FrozenValue,
+    pub(crate) fn_enumerate: FrozenValue,
+    pub(crate) fn_reversed: FrozenValue,
+    pub(crate) fn_zip: FrozenValue,
 }
 
 impl Constants {
@@ -134,6 +88,12 @@ impl Constants {
             Constants {
                 fn_len: g.get_frozen("len").unwrap(),
                 fn_type: g.get_frozen("type").unwrap(),
+                fn_str: g.get_frozen("str").unwrap(),
+                fn_repr: g.get_frozen("repr").unwrap(),
+                fn_bool: g.get_frozen("bool").unwrap(),
+                fn_enumerate: g.get_frozen("enumerate").unwrap(),
+                fn_reversed: g.get_frozen("reversed").unwrap(),
+                fn_zip: g.get_frozen("zip").unwrap(),
             }
         });
         *Lazy::force(&RES)
@@ -16,7 +16,7 @@
  */
 
 use crate::{
-    environment::{names::MutableNames, slots::ModuleSlotId},
+    environment::{names::MutableNames, slots::ModuleSlotId, CodeReader, CodeWriter},
     eval::runtime::slots::LocalSlotId,
     syntax::ast::{AstAssign, AstStmt, Expr, Stmt, Visibility},
 };
@@ -101,6 +101,40 @@ impl ScopeNames {
     fn get_name(&self, name: &str) -> Option<LocalSlotId> {
         self.mp.get(name).copied()
     }
+
+    /// Write the names, for a cached module.
+    pub(crate) fn save(&self, w: &mut CodeWriter) {
+        w.u32(self.used as u32);
+        // Sorted, so the same names always give the same bytes
+        let mut mp: Vec<_> = self.mp.iter().collect();
+        mp.sort_by_key(|x| x.0);
+        w.u32(mp.len() as u32);
+        for (name, slot) in mp {
+            w.str(name);
+            w.u32(slot.index() as u32);
+        }
+        w.u32(self.parent.len() as u32);
+        for (parent, slot) in &self.parent {
+            w.u32(parent.index() as u32);
+            w.u32(slot.index() as u32);
+        }
+    }
+
+    /// Read names written by [`save`](ScopeNames::save).
+    pub(crate) fn load(r: &mut CodeReader) -> anyhow::Result<Self> {
+        let used = r.u32()? as usize;
+        let slot = |r: &mut CodeReader| match r.u32()? as usize {
+            x if x < used => Ok(LocalSlotId::new(x)),
+            _ => Err(CodeReader::corrupt()),
+        };
+        let mp = r.list(|r| Ok((r.string()?, slot(r)?)))?;
+        let parent = r.list(|r| Ok((LocalSlotId::new(r.u32()? as usize), slot(r)?)))?;
+        Ok(Self {
+            used,
+            mp: mp.into_iter().collect(),
+            parent,
+        })
+    }
 }
 
 pub(crate) enum Slot {
@@ -19,16 +19,9 @@
 
 use crate::{
     codemap::Span,
-    collections::SmallMap,
-    eval::{
-        compiler::{throw, Compiler, EvalException, ExprCompiled, ExprCompiledValue},
-        fragment::stmt::AssignCompiled,
-        runtime::evaluator::Evaluator,
-    },
+    eval::{bc::instr::Instr, compiler::Compiler},
     syntax::ast::{AstExpr, Clause, ForClause},
-    values::{dict::Dict, list::List, Value},
 };
-use std::mem;
 
 impl Compiler<'_> {
     pub fn list_comprehension(
@@ -36,12 +29,19 @@ impl Compiler<'_> {
         x: AstExpr,
         for_: ForClause,
         clauses: Vec<Clause>,
-    ) -> ExprCompiledValue {
-        self.scope.enter_compr();
-        let clauses = compile_clauses(for_, clauses, self);
-        let x = self.expr(x).as_compiled();
-        self.scope.exit_compr();
-        eval_list(x, clauses)
+        span: Span,
+    ) {
+        self.comprehension(
+            for_,
+            clauses,
+            span,
+            Instr::ComprListNew,
+            Instr::ComprListEnd,
+            |me| {
+                me.expr(x);
+                me.bc.emit(Instr::ComprListAppend, span);
+            },
+        )
     }
 
     pub fn dict_comprehension(
@@ -50,193 +50,71 @@ impl Compiler<'_> {
         v: AstExpr,
         for_: ForClause,
         clauses: Vec<Clause>,
-    ) -> ExprCompiledValue {
-        self.scope.enter_compr();
-        let clauses = compile_clauses(for_, clauses, self);
-        let k = self.expr(k).as_compiled();
-        let v = self.expr(v).as_compiled();
-        self.scope.exit_compr();
-        eval_dict(k, v, clauses)
+        span: Span,
+    ) {
+        self.comprehension(
+            for_,
+            clauses,
+            span,
+            Instr::ComprDictNew,
+            Instr::ComprDictEnd,
+            |me| {
+                me.expr(k);
+                me.expr(v);
+                me.bc.emit(Instr::ComprDictInsert, span);
+            },
+        )
     }
-}
 
-/// Peel the final if's from clauses, and return them (in the order they started), plus the next for you get to
-fn compile_ifs(
-    clauses: &mut Vec<Clause>,
-    compiler: &mut Compiler,
-) -> (Option<ForClause>, Vec<ExprCompiled>) {
-    let mut ifs = Vec::new();
-    while let Some(x) = clauses.pop() {
-        match x {
-            Clause::For(f) => {
-                ifs.reverse();
-                return (Some(f), ifs);
-            }
-            Clause::If(x) => {
-                ifs.push(compiler.expr(x).as_compiled());
-            }
-        }
-    }
-    ifs.reverse();
-    (None, ifs)
-}
+    /// Emit a comprehension as nested loops, where each `if` clause skips to the next
+    /// iteration of the innermost loop, and `add` puts one result in the accumulator.
+    fn comprehension(
+        &mut self,
+        for_: ForClause,
+        clauses: Vec<Clause>,
+        span: Span,
+        new: Instr,
+        end: Instr,
+        add: impl FnOnce(&mut Self),
+    ) {
+        self.scope.enter_compr();
 
-fn compile_clauses(
-    for_: ForClause,
-    mut clauses: Vec<Clause>,
-    compiler: &mut Compiler,
-) -> Vec<ClauseCompiled> {
-    // The first for.over is scoped before we enter the list comp
-    let over_span = for_.over.span;
-    let over = compiler.expr(for_.over);
+        // The first for.over is scoped before we enter the list comp
+        let over_span = for_.over.span;
+        let start = self.loop_over(for_.over);
+        self.bc.emit(new, span);
 
-    // Now everything else must be compiled with all the for variables in scope
-    compiler.scope.add_compr(&for_.var);
-    for x in &clauses {
-        if let Clause::For(x) = x {
-            compiler.scope.add_compr(&x.var);
-        }
-    }
-
-    // Now we want to group them into a `for`, followed by any number of `if`.
-    // The evaluator wants to use pop to consume them, so reverse the order.
-    let mut res = Vec::new();
-    loop {
-        let (next_for, ifs) = compile_ifs(&mut clauses, compiler);
-        match next_for {
-            None => {
-                res.push(ClauseCompiled {
-                    var: compiler.assign(for_.var),
-                    over: over.as_compiled(),
-                    over_span,
-                    ifs,
-                });
-                return res;
-            }
-            Some(f) => {
-                let over_span = f.over.span;
-                res.push(ClauseCompiled {
-                    over: compiler.expr(f.over).as_compiled(),
-                    var: compiler.assign(f.var),
-                    over_span,
-                    ifs,
-                });
+        // Now everything else must be compiled with all the for variables in scope
+        self.scope.add_compr(&for_.var);
+        for x in &clauses {
+            if let Clause::For(x) = x {
+                self.scope.add_compr(&x.var);
             }
         }
-    }
-}
-
-fn eval_list(x: ExprCompiled, clauses: Vec<ClauseCompiled>) -> ExprCompiledValue {
-    let clauses = eval_one_dimensional_comprehension_list(clauses, box move |me, eval| {
-        let x = x(eval)?;
-        me.push(x);
-        Ok(())
-    });
-
-    expr!("list_comp", |eval| {
-        let mut r = Vec::new();
-        clauses(&mut r, eval)?;
-        eval.heap().alloc(List::new(r))
-    })
-}
-
-fn eval_dict(k: ExprCompiled, v: ExprCompiled, clauses: Vec<ClauseCompiled>) -> ExprCompiledValue {
-    let clauses = eval_one_dimensional_comprehension_dict(clauses, box move |me, eval| {
-        let k = k(eval)?;
-        let v = v(eval)?;
-        me.insert_hashed(k.get_hashed()?, v);
-        Ok(())
-    });
-
-    expr!("dict_comp", |eval| {
-        let mut r = SmallMap::new();
-        clauses(&mut r, eval)?;
-        eval.heap().alloc(Dict::new(r))
-    })
-}
-
-struct ClauseCompiled {
-    var: AssignCompiled,
-    over: ExprCompiled,
-    over_span: Span,
-    ifs: Vec<ExprCompiled>,
-}
 
-// FIXME: These two expressions are identical, but I need higher-kinded
-// lifetimes to express it :(
-
-fn eval_one_dimensional_comprehension_dict(
-    mut clauses: Vec<ClauseCompiled>,
-    add: Box<
-        dyn for<'v> Fn(
-                &mut SmallMap<Value<'v>, Value<'v>>,
-                &mut Evaluator<'v, '_>,
-            ) -> Result<(), EvalException<'v>>
-            + Send
-            + Sync,
-    >,
-) -> Box<
-    dyn for<'v> Fn(
-            &mut SmallMap<Value<'v>, Value<'v>>,
-            &mut Evaluator<'v, '_>,
-        ) -> Result<(), EvalException<'v>>
-        + Send
-        + Sync,
-> {
-    if let Some(c) = clauses.pop() {
-        let rest = eval_one_dimensional_comprehension_dict(clauses, add);
-        box move |accumulator, eval| {
-            // println!("eval1 {:?} {:?}", ***e, clauses);
-            let iterable = (c.over)(eval)?;
-            let freeze_for_iteration = iterable.get_aref();
-            'f: for i in &throw(iterable.iterate(eval.heap()), c.over_span, eval)? {
-                (c.var)(i, eval)?;
-                for ifc in &c.ifs {
-                    if !ifc(eval)?.to_bool() {
-                        continue 'f;
-                    }
+        self.loop_start(start, over_span, for_.var, span);
+        let mut loops = 1;
+        for x in clauses {
+            match x {
+                Clause::For(x) => {
+                    let over_span = x.over.span;
+                    let start = self.loop_over(x.over);
+                    self.loop_start(start, over_span, x.var, span);
+                    loops += 1;
                 }
-                rest(accumulator, eval)?;
-            }
-            mem::drop(freeze_for_iteration);
-            Ok(())
-        }
-    } else {
-        add
-    }
-}
-
-fn eval_one_dimensional_comprehension_list(
-    mut clauses: Vec<ClauseCompiled>,
-    add: Box<
-        dyn for<'v> Fn(&mut Vec<Value<'v>>, &mut Evaluator<'v, '_>) -> Result<(), EvalException<'v>>
-            + Send
-            + Sync,
-    >,
-) -> Box<
-    dyn for<'v> Fn(&mut Vec<Value<'v>>, &mut Evaluator<'v, '_>) -> Result<(), EvalException<'v>>
-        + Send
-        + Sync,
-> {
-    if let Some(c) = clauses.pop() {
-        let rest = eval_one_dimensional_comprehension_list(clauses, add);
-        box move |accumulator, eval| {
-            // println!("eval1 {:?} {:?}", ***e, clauses);
-            let iterable = (c.over)(eval)?;
-            let freeze_for_iteration = iterable.get_aref();
-            'f: for i in &throw(iterable.iterate(eval.heap()), c.over_span, eval)? {
-                (c.var)(i, eval)?;
-                for ifc in &c.ifs {
-                    if !ifc(eval)?.to_bool() {
-                        continue 'f;
-                    }
+                Clause::If(x) => {
+                    let if_span = x.span;
+                    self.expr(x);
+                    self.bc.emit_continue(Instr::JumpIfFalse, if_span);
                 }
-                rest(accumulator, eval)?;
             }
-            mem::drop(freeze_for_iteration);
-            Ok(())
         }
-    } else {
-        add
+        add(self);
+        for _ in 0..loops {
+            self.bc.loop_end(span);
+        }
+
+        self.scope.exit_compr();
+        self.bc.emit(end, span);
     }
 }
@@ -19,12 +19,10 @@
 
 use crate::{
     codemap::{CodeMap, Span},
-    environment::FrozenModuleValue,
+    environment::{CodeReader, CodeWriter, FrozenModuleValue},
     eval::{
-        compiler::{
-            scope::ScopeNames, Compiler, EvalException, ExprCompiled, ExprCompiledValue,
-            StmtCompiled,
-        },
+        bc::instr::{Bc, Instr},
+        compiler::{scope::ScopeNames, Compiler},
         runtime::{
             evaluator::Evaluator,
             parameters::ParametersSpec,
@@ -34,17 +32,18 @@ use crate::{
     },
     syntax::ast::{AstExpr, AstParameter, AstStmt, Parameter},
     values::{
-        function::FUNCTION_TYPE, typing::TypeCompiled, ComplexValue, Freezer, FrozenValue,
+        function::FUNCTION_TYPE, typing::TypeCompiled, ComplexValue, Freezer, FrozenValue, Heap,
         SimpleValue, StarlarkValue, Trace, Tracer, Value, ValueLike, ValueRef,
     },
 };
 use derivative::Derivative;
 use gazebo::prelude::*;
-use std::sync::Arc;
+use std::{mem, sync::Arc};
 
 enum ParameterCompiled<T> {
     Normal(String, Option<T>),
     WithDefaultValue(String, Option<T>, T),
+    Slash,
     NoArgs,
     Args(String, Option<T>),
     KwArgs(String, Option<T>),
@@ -55,7 +54,7 @@ impl<T> ParameterCompiled<T> {
         match self {
             Self::Normal(x, _) => Some(x),
             Self::WithDefaultValue(x, _, _) => Some(x),
-            Self::NoArgs => None,
+            Self::Slash | Self::NoArgs => None,
             Self::Args(x, _) => Some(x),
             Self::KwArgs(x, _) => Some(x),
         }
@@ -65,109 +64,340 @@ impl<T> ParameterCompiled<T> {
         match self {
             Self::Normal(_, t) => t.as_ref(),
             Self::WithDefaultValue(_, t, _) => t.as_ref(),
-            Self::NoArgs => None,
+            Self::Slash | Self::NoArgs => None,
             Self::Args(_, t) => t.as_ref(),
             Self::KwArgs(_, t) => t.as_ref(),
         }
     }
 }
 
+/// What a call can be replaced with, for a function whose body does nothing but return
+/// a constant or one of its parameters.
+#[derive(Clone, Copy, Dupe, Debug)]
+pub(crate) enum InlineDef {
+    Const(FrozenValue),
+    Param(usize),
+}
+
+impl InlineDef {
+    /// Look for a body which is `return c` or `return x`, where `c` is a constant which
+    /// doesn't live on a heap and `x` is one of the first `params` locals, i.e. a parameter.
+    fn new(body: &Bc, params: usize) -> Option<Self> {
+        let mut instrs = body
+            .instrs
+            .iter()
+            .skip_while(|x| matches!(x, Instr::BeforeStmt));
+        let res = match instrs.next()? {
+            Instr::Const(v)
+                if v.is_none() || v.unpack_bool().is_some() || v.unpack_int().is_some() =>
+            {
+                InlineDef::Const(*v)
+            }
+            Instr::LoadLocal(slot, _) if slot.index() < params => InlineDef::Param(slot.index()),
+            _ => return None,
+        };
+        match instrs.next()? {
+            Instr::Return => Some(res),
+            _ => None,
+        }
+    }
+}
+
 #[derive(Derivative)]
 #[derivative(Debug)]
 struct DefInfo {
+    function_name: String,
+    // Where a type or default value is `Some(())`, the value is on the stack
+    #[derivative(Debug = "ignore")]
+    params: Vec<ParameterCompiled<()>>,
+    return_type: bool,
+    // Where the body is, which tells apart the definitions in a module
+    span: Span,
     scope_names: ScopeNames,
-    // The compiled expression for the body of this definition, to be run
+    // The compiled code for the body of this definition, to be run
     // after the parameters are evaluated.
     #[derivative(Debug = "ignore")]
-    body: StmtCompiled,
+    body: Bc,
+    inline: Option<InlineDef>,
+}
+
+/// How to create a function, whose parameter types, default values and return type
+/// are evaluated onto the stack in that order. Shared with the functions it creates.
+#[derive(Clone, Dupe)]
+pub(crate) struct DefCompiled {
+    info: Arc<DefInfo>,
+}
+
+impl DefCompiled {
+    /// The number of values the definition takes off the stack.
+    pub(crate) fn stack_len(&self) -> usize {
+        let defaults = self
+            .info
+            .params
+            .iter()
+            .filter(|x| matches!(x, ParameterCompiled::WithDefaultValue(..)))
+            .count();
+        let types = self.info.params.iter().filter(|x| x.ty().is_some()).count();
+        defaults + types + self.info.return_type as usize
+    }
+
+    pub(crate) fn eval<'v>(
+        &self,
+        values: &[Value<'v>],
+        eval: &mut Evaluator<'v, '_>,
+    ) -> anyhow::Result<Value<'v>> {
+        let captured = self
+            .info
+            .scope_names
+            .parent
+            .map(|(x, _)| eval.clone_slot_reference(*x, eval.heap()));
+        self.alloc(values, captured, eval.codemap.dupe(), eval.heap())
+    }
+
+    /// Allocate the function, given the values [`eval`](DefCompiled::eval) takes off
+    /// the stack, and the variables it captures from the enclosing function.
+    pub(crate) fn alloc<'v>(
+        &self,
+        values: &[Value<'v>],
+        captured: Vec<ValueRef<'v>>,
+        codemap: CodeMap,
+        heap: &'v Heap,
+    ) -> anyhow::Result<Value<'v>> {
+        let mut values = values.iter().copied();
+        let info = &*self.info;
+        let mut parameters =
+            ParametersSpecBuilder::with_capacity(info.function_name.clone(), info.params.len());
+        let mut parameter_types = Vec::new();
+
+        for (i, x) in info.params.iter().enumerate() {
+            if x.ty().is_some() {
+                let v = values.next().unwrap();
+                let name = x.name().unwrap_or("unknown").to_owned();
+                parameter_types.push((i, name, v, TypeCompiled::new(v)?));
+            }
+
+            match x {
+                ParameterCompiled::Normal(n, _) => parameters.required(n),
+                ParameterCompiled::WithDefaultValue(n, _, ()) => {
+                    parameters.defaulted(n, values.next().unwrap());
+                }
+                ParameterCompiled::Slash => parameters.end_positional_only(),
+                ParameterCompiled::NoArgs => parameters.no_args(),
+                ParameterCompiled::Args(_, _) => parameters.args(),
+                ParameterCompiled::KwArgs(_, _) => parameters.kwargs(),
+            }
+        }
+        let return_type = if info.return_type {
+            let v = values.next().unwrap();
+            Some((v, TypeCompiled::new(v)?))
+        } else {
+            None
+        };
+        Ok(heap.alloc(Def {
+            parameters: parameters.build(),
+            parameter_types,
+            return_type,
+            stmt: self.info.dupe(),
+            codemap,
+            captured,
+            module: None,
+        }))
+    }
+
+    /// Put the default values, types, and return type of a function back in the order
+    /// [`eval`](DefCompiled::eval) takes them off the stack. The types are indexed by
+    /// parameter, as in [`FrozenDef::parameter_types`].
+    /// Returns [`None`] if they don't fit the parameters of this definition.
+    pub(crate) fn stack_values<V: Copy>(
+        &self,
+        defaults: &[V],
+        types: &[(usize, V)],
+        return_type: Option<V>,
+    ) -> Option<Vec<V>> {
+        let mut defaults = defaults.iter();
+        let mut types = types.iter().peekable();
+        let mut res = Vec::with_capacity(self.stack_len());
+        for (i, x) in self.info.params.iter().enumerate() {
+            if x.ty().is_some() {
+                match types.next() {
+                    Some((j, t)) if *j == i => res.push(*t),
+                    _ => return None,
+                }
+            }
+            if let ParameterCompiled::WithDefaultValue(..) = x {
+                res.push(*defaults.next()?);
+            }
+        }
+        if types.peek().is_some() || defaults.next().is_some() {
+            return None;
+        }
+        match (self.info.return_type, return_type) {
+            (true, Some(t)) => res.push(t),
+            (false, None) => {}
+            _ => return None,
+        }
+        Some(res)
+    }
+
+    /// Identifies the definition, which is shared with the functions it creates.
+    pub(crate) fn id(&self) -> usize {
+        Arc::as_ptr(&self.info) as usize
+    }
+
+    /// Write the definition, for a cached module.
+    pub(crate) fn save(&self, w: &mut CodeWriter) -> anyhow::Result<()> {
+        let info = &*self.info;
+        w.str(&info.function_name);
+        w.list(&info.params, |w, x| {
+            let (tag, name) = match x {
+                ParameterCompiled::Normal(x, _) => (0, Some(x)),
+                ParameterCompiled::WithDefaultValue(x, _, ()) => (1, Some(x)),
+                ParameterCompiled::Slash => (2, None),
+                ParameterCompiled::NoArgs => (3, None),
+                ParameterCompiled::Args(x, _) => (4, Some(x)),
+                ParameterCompiled::KwArgs(x, _) => (5, Some(x)),
+            };
+            w.u8(tag);
+            if let Some(name) = name {
+                w.str(name);
+                w.bool(x.ty().is_some());
+            }
+            Ok(())
+        })?;
+        w.bool(info.return_type);
+        w.span(info.span);
+        info.scope_names.save(w);
+        info.body.save(w)?;
+        w.option(&info.inline, |w, x| match x {
+            InlineDef::Const(x) => {
+                w.u8(0);
+                w.value(*x)
+            }
+            InlineDef::Param(i) => {
+                w.u8(1);
+                w.u32(*i as u32);
+                Ok(())
+            }
+        })
+    }
+
+    /// Read a definition written by [`save`](DefCompiled::save).
+    pub(crate) fn load(r: &mut CodeReader) -> anyhow::Result<Self> {
+        let function_name = r.string()?;
+        let params = r.list(|r| {
+            let tag = r.u8()?;
+            let named = |r: &mut CodeReader| -> anyhow::Result<_> {
+                let name = r.string()?;
+                Ok((name, if r.bool()? { Some(()) } else { None }))
+            };
+            Ok(match tag {
+                0 => {
+                    let (x, t) = named(r)?;
+                    ParameterCompiled::Normal(x, t)
+                }
+                1 => {
+                    let (x, t) = named(r)?;
+                    ParameterCompiled::WithDefaultValue(x, t, ())
+                }
+                2 => ParameterCompiled::Slash,
+                3 => ParameterCompiled::NoArgs,
+                4 => {
+                    let (x, t) = named(r)?;
+                    ParameterCompiled::Args(x, t)
+                }
+                5 => {
+                    let (x, t) = named(r)?;
+                    ParameterCompiled::KwArgs(x, t)
+                }
+                _ => return Err(CodeReader::corrupt()),
+            })
+        })?;
+        let return_type = r.bool()?;
+        let span = r.span()?;
+        let scope_names = ScopeNames::load(r)?;
+        let body = Bc::load(r)?;
+        let inline = r.option(|r| match r.u8()? {
+            0 => Ok(InlineDef::Const(r.value()?)),
+            1 => Ok(InlineDef::Param(r.u32()? as usize)),
+            _ => Err(CodeReader::corrupt()),
+        })?;
+        Ok(Self {
+            info: Arc::new(DefInfo {
+                function_name,
+                params,
+                return_type,
+                span,
+                scope_names,
+                body,
+                inline,
+            }),
+        })
+    }
 }
 
 impl Compiler<'_> {
-    fn parameter(&mut self, x: AstParameter) -> ParameterCompiled<ExprCompiled> {
+    /// Emit code for the type and default value of a parameter.
+    fn parameter(&mut self, x: AstParameter) -> ParameterCompiled<()> {
+        let ty = |me: &mut Self, t: Option<Box<AstExpr>>| t.map(|t| me.expr(*t));
         match x.node {
-            Parameter::Normal(x, t) => ParameterCompiled::Normal(
-                x.node,
-                self.expr_opt(t).map(ExprCompiledValue::as_compiled),
-            ),
-            Parameter::WithDefaultValue(x, t, v) => ParameterCompiled::WithDefaultValue(
-                x.node,
-                self.expr_opt(t).map(ExprCompiledValue::as_compiled),
-                self.expr(*v).as_compiled(),
-            ),
+            Parameter::Normal(x, t) => ParameterCompiled::Normal(x.node, ty(self, t)),
+            Parameter::WithDefaultValue(x, t, v) => {
+                let t = ty(self, t);
+                self.expr(*v);
+                ParameterCompiled::WithDefaultValue(x.node, t, ())
+            }
+            Parameter::Slash => ParameterCompiled::Slash,
             Parameter::NoArgs => ParameterCompiled::NoArgs,
-            Parameter::Args(x, t) => ParameterCompiled::Args(
-                x.node,
-                self.expr_opt(t).map(ExprCompiledValue::as_compiled),
-            ),
-            Parameter::KwArgs(x, t) => ParameterCompiled::KwArgs(
-                x.node,
-                self.expr_opt(t).map(ExprCompiledValue::as_compiled),
-            ),
+            Parameter::Args(x, t) => ParameterCompiled::Args(x.node, ty(self, t)),
+            Parameter::KwArgs(x, t) => ParameterCompiled::KwArgs(x.node, ty(self, t)),
         }
     }
 
+    /// Emit code which pushes a new function.
     pub fn function(
         &mut self,
         name: &str,
         params: Vec<AstParameter>,
         return_type: Option<Box<AstExpr>>,
         suite: AstStmt,
-    ) -> ExprCompiledValue {
-        let file = self.codemap.file_span(suite.span);
+    ) {
+        let span = suite.span;
+        let file = self.codemap.file_span(span);
         let function_name = format!("{}.{}", file.file.filename(), name);
 
         // The parameters run in the scope of the parent, so compile them with the outer
         // scope
         let params = params.into_map(|x| self.parameter(x));
-        let return_type = self
-            .expr_opt(return_type)
-            .map(ExprCompiledValue::as_compiled);
+        let return_type = return_type.map(|x| self.expr(*x)).is_some();
 
+        // The body has its own stack, so gets written separately
         self.scope
             .enter_def(params.iter().flat_map(ParameterCompiled::name), &suite);
-        let body = self.stmt(suite, false);
+        let outer = mem::take(&mut self.bc);
+        self.stmt(suite, false);
+        // Falling off the end of a function returns None
+        self.bc.emit(Instr::Const(FrozenValue::new_none()), span);
+        self.bc.emit(Instr::Return, span);
+        let body = mem::replace(&mut self.bc, outer).finish();
         let scope_names = self.scope.exit_def();
 
-        let info = Arc::new(DefInfo { scope_names, body });
-
-        expr!("def", |eval| {
-            let mut parameters =
-                ParametersSpecBuilder::with_capacity(function_name.to_owned(), params.len());
-            let mut parameter_types = Vec::new();
-
-            for (i, x) in params.iter().enumerate() {
-                if let Some(t) = x.ty() {
-                    let v = t(eval)?;
-                    let name = x.name().unwrap_or("unknown").to_owned();
-                    parameter_types.push((i, name, v, TypeCompiled::new(v)?));
-                }
-
-                match x {
-                    ParameterCompiled::Normal(n, _) => parameters.required(n),
-                    ParameterCompiled::WithDefaultValue(n, _, v) => {
-                        parameters.defaulted(n, v(eval)?);
-                    }
-                    ParameterCompiled::NoArgs => parameters.no_args(),
-                    ParameterCompiled::Args(_, _) => parameters.args(),
-                    ParameterCompiled::KwArgs(_, _) => parameters.kwargs(),
-                }
-            }
-            let return_type = match &return_type {
-                None => None,
-                Some(v) => {
-                    let v = v(eval)?;
-                    Some((v, TypeCompiled::new(v)?))
-                }
-            };
-            Def::new(
-                parameters.build(),
-                parameter_types,
+        let inline = if self.optimise {
+            InlineDef::new(&body, params.iter().filter(|x| x.name().is_some()).count())
+        } else {
+            None
+        };
+        let def = DefCompiled {
+            info: Arc::new(DefInfo {
+                function_name,
+                params,
                 return_type,
-                info.dupe(),
-                eval.codemap.dupe(),
-                eval,
-            )
-        })
+                span,
+                scope_names,
+                body,
+                inline,
+            }),
+        };
+        self.bc.emit(Instr::Def(box def), span)
     }
 }
 
@@ -193,28 +423,48 @@ pub(crate) type FrozenDef = DefGen<FrozenValue, Option<FrozenValue>>;
 
 starlark_complex_values!(Def);
 
-impl<'v> Def<'v> {
-    fn new(
-        parameters: ParametersSpec<Value<'v>>,
-        parameter_types: Vec<(usize, String, Value<'v>, TypeCompiled)>,
-        return_type: Option<(Value<'v>, TypeCompiled)>,
-        stmt: Arc<DefInfo>,
-        codemap: CodeMap,
-        eval: &mut Evaluator<'v, '_>,
-    ) -> Value<'v> {
-        let captured = stmt
-            .scope_names
-            .parent
-            .map(|(x, _)| eval.clone_slot_reference(*x, eval.heap()));
-        eval.heap().alloc(Self {
-            parameters,
-            parameter_types,
-            return_type,
-            stmt,
-            codemap,
-            captured,
-            module: None,
-        })
+impl FrozenDef {
+    /// What a call with `n` positional arguments can be replaced with, if anything.
+    /// Type checks could fail, so we never inline functions with types.
+    pub(crate) fn inline(&self, n: usize) -> Option<InlineDef> {
+        if self.parameter_types.is_empty()
+            && self.return_type.is_none()
+            && self.parameters.fills_positionally(n)
+        {
+            self.stmt.inline
+        } else {
+            None
+        }
+    }
+
+    /// The definition which created the function.
+    pub(crate) fn compiled(&self) -> DefCompiled {
+        DefCompiled {
+            info: self.stmt.dupe(),
+        }
+    }
+
+    /// The module the function was defined in.
+    pub(crate) fn module(&self) -> Option<FrozenModuleValue> {
+        self.module
+    }
+
+    pub(crate) fn defaults(&self) -> Vec<FrozenValue> {
+        self.parameters.defaults().copied().collect()
+    }
+
+    /// The types of the parameters which have one, by the index of the parameter.
+    pub(crate) fn parameter_types(&self) -> Vec<(usize, FrozenValue)> {
+        self.parameter_types.map(|(i, _, t, _)| (*i, *t))
+    }
+
+    pub(crate) fn return_type(&self) -> Option<FrozenValue> {
+        self.return_type.as_ref().map(|x| x.0)
+    }
+
+    /// The variables captured from the enclosing function, [`None`] where unassigned.
+    pub(crate) fn captured(&self) -> &[Option<FrozenValue>] {
+        &self.captured
     }
 }
 
@@ -278,6 +528,7 @@ impl<'v> StarlarkValue<'v> for FrozenDef {
         eval: &mut Evaluator<'v, '_>,
     ) -> anyhow::Result<Value<'v>> {
         eval.ann("invoke_frozen_def", |eval| {
+            eval.call_stack.check_recursion(me)?;
             let slots = self.stmt.scope_names.used;
             let slots = self.parameters.promote().collect(slots, params, eval)?;
             eval.with_call_stack(me, location, |eval| {
@@ -302,6 +553,7 @@ impl<'v> StarlarkValue<'v> for Def<'v> {
         eval: &mut Evaluator<'v, '_>,
     ) -> anyhow::Result<Value<'v>> {
         eval.ann("invoke_def", |eval| {
+            eval.call_stack.check_recursion(me)?;
             let slots = self.stmt.scope_names.used;
             let slots = self.parameters.collect(slots, params, eval)?;
             eval.with_call_stack(me, location, |eval| {
@@ -360,14 +612,9 @@ impl<'v, V: ValueLike<'v>, RefV: AsValueRef<'v>> DefGen<V, RefV> {
         }
 
         let res =
-            eval.with_function_context(self.module, &self.codemap, |eval| (self.stmt.body)(eval));
+            eval.with_function_context(self.module, &self.codemap, |eval| self.stmt.body.run(eval));
         eval.local_variables.release(old_locals);
-
-        let ret = match res {
-            Err(EvalException::Return(ret)) => ret,
-            Err(e) => return Err(e.into()),
-            Ok(_) => Value::new_none(),
-        };
+        let ret = res?;
 
         if eval.check_types() {
             // Slightly ugly: by the time we check the return type, we no longer
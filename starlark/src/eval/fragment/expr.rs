@@ -19,86 +19,189 @@
 use crate::{
     codemap::{Span, Spanned},
     collections::{Hashed, SmallMap},
-    environment::EnvironmentError,
+    environment::{CodeReader, CodeWriter, EnvironmentError},
     errors::Diagnostic,
     eval::{
-        compiler::{scope::Slot, throw, Compiler, EvalException, ExprCompiled, ExprCompiledValue},
+        bc::{
+            cache::MethodCompiled,
+            instr::{Instr, SLICE_START, SLICE_STOP, SLICE_STRIDE},
+        },
+        compiler::{scope::Slot, throw, Compiler},
         runtime::evaluator::Evaluator,
         Parameters,
     },
     syntax::ast::{
-        Argument, AstArgument, AstAssign, AstExpr, AstLiteral, BinOp, Expr, Stmt, Visibility,
+        Argument, AstArgument, AstAssign, AstExpr, AstLiteral, BinOp, Expr, FStringPart, Stmt,
+        Visibility,
     },
     values::{
-        dict::Dict,
-        fast_string,
-        function::{BoundMethod, NativeAttribute},
-        list::List,
-        tuple::{FrozenTuple, Tuple},
-        AttrType, FrozenHeap, FrozenValue, Value, ValueLike,
+        bytes::Bytes,
+        interpolation::{FStringFormat, StringInterpolationError},
+        tuple::FrozenTuple,
+        FrozenHeap, FrozenValue, Value, ValueLike,
     },
 };
-use gazebo::{coerce::coerce_ref, prelude::*};
-use std::{cmp::Ordering, collections::HashMap, mem::MaybeUninit};
+use gazebo::prelude::*;
+use std::collections::HashMap;
 use thiserror::Error;
 
-#[derive(Debug, Clone, Error)]
-pub(crate) enum EvalError {
-    #[error("Dictionary key repeated for `{0}`")]
-    DuplicateDictionaryKey(String),
-}
+/// A compiled f-string, whose fields are evaluated onto the stack in order.
+pub(crate) struct FStringCompiled(Vec<FStringPartCompiled>);
 
-fn eval_compare(
-    span: Span,
-    l: ExprCompiledValue,
-    r: ExprCompiledValue,
-    cmp: fn(Ordering) -> bool,
-) -> ExprCompiledValue {
-    expr!("compare", l, r, |eval| {
-        Value::new_bool(cmp(throw(l.compare(r), span, eval)?))
-    })
+enum FStringPartCompiled {
+    Literal(String),
+    Field(FStringFormat, Span),
+    /// A field whose format spec contains fields of its own, which are evaluated onto the
+    /// stack just after the value, so the spec is only parsed once they are known.
+    NestedField(Option<char>, Vec<FStringPartCompiled>, Span),
 }
 
-fn eval_equals(
-    span: Span,
-    l: ExprCompiledValue,
-    r: ExprCompiledValue,
-    cmp: fn(bool) -> bool,
-) -> ExprCompiledValue {
-    expr!("equals", l, r, |eval| {
-        Value::new_bool(cmp(throw(l.equals(r), span, eval)?))
-    })
+impl FStringCompiled {
+    /// Compile the parts of an f-string, collecting the expressions of its fields in the
+    /// order their values are expected on the stack.
+    fn new(
+        parts: Vec<FStringPart>,
+        exprs: &mut Vec<AstExpr>,
+    ) -> Result<Vec<FStringPartCompiled>, StringInterpolationError> {
+        parts.into_try_map(|x| match x {
+            FStringPart::Literal(s) => Ok(FStringPartCompiled::Literal(s)),
+            FStringPart::Expr(x, conversion, spec) => {
+                let field_span = x.span;
+                exprs.push(x);
+                match spec {
+                    Some(spec) if spec.iter().any(|x| matches!(x, FStringPart::Expr(..))) => {
+                        Ok(FStringPartCompiled::NestedField(
+                            conversion,
+                            Self::new(spec, exprs)?,
+                            field_span,
+                        ))
+                    }
+                    _ => {
+                        let spec = spec.map(|spec| {
+                            spec.into_iter()
+                                .map(|x| match x {
+                                    FStringPart::Literal(s) => s,
+                                    FStringPart::Expr(..) => unreachable!(),
+                                })
+                                .collect::<String>()
+                        });
+                        let format = FStringFormat::new(conversion, spec.as_deref())?;
+                        Ok(FStringPartCompiled::Field(format, field_span))
+                    }
+                }
+            }
+        })
+    }
+
+    /// The number of values the f-string takes off the stack.
+    pub(crate) fn stack_len(&self) -> usize {
+        fn len(parts: &[FStringPartCompiled]) -> usize {
+            parts
+                .iter()
+                .map(|x| match x {
+                    FStringPartCompiled::Literal(_) => 0,
+                    FStringPartCompiled::Field(..) => 1,
+                    FStringPartCompiled::NestedField(_, spec, _) => 1 + len(spec),
+                })
+                .sum()
+        }
+        len(&self.0)
+    }
+
+    /// Write the f-string, for a cached module.
+    pub(crate) fn save(&self, w: &mut CodeWriter) -> anyhow::Result<()> {
+        fn save(parts: &[FStringPartCompiled], w: &mut CodeWriter) -> anyhow::Result<()> {
+            w.list(parts, |w, x| {
+                match x {
+                    FStringPartCompiled::Literal(s) => {
+                        w.u8(0);
+                        w.str(s)
+                    }
+                    FStringPartCompiled::Field(format, span) => {
+                        w.u8(1);
+                        w.char(format.conversion());
+                        w.str(format.spec());
+                        w.span(*span)
+                    }
+                    FStringPartCompiled::NestedField(conversion, spec, span) => {
+                        w.u8(2);
+                        w.char(*conversion);
+                        save(spec, w)?;
+                        w.span(*span)
+                    }
+                }
+                Ok(())
+            })
+        }
+        save(&self.0, w)
+    }
+
+    /// Read an f-string written by [`save`](FStringCompiled::save).
+    pub(crate) fn load(r: &mut CodeReader) -> anyhow::Result<Self> {
+        fn load(r: &mut CodeReader) -> anyhow::Result<Vec<FStringPartCompiled>> {
+            r.list(|r| match r.u8()? {
+                0 => Ok(FStringPartCompiled::Literal(r.string()?)),
+                1 => {
+                    let conversion = r.char()?;
+                    let spec = r.string()?;
+                    let spec = if spec.is_empty() { None } else { Some(spec) };
+                    let format = FStringFormat::new(conversion, spec.as_deref())
+                        .map_err(|_| CodeReader::corrupt())?;
+                    Ok(FStringPartCompiled::Field(format, r.span()?))
+                }
+                2 => Ok(FStringPartCompiled::NestedField(
+                    r.char()?,
+                    load(r)?,
+                    r.span()?,
+                )),
+                _ => Err(CodeReader::corrupt()),
+            })
+        }
+        Ok(Self(load(r)?))
+    }
+
+    pub(crate) fn eval<'v>(
+        &self,
+        fields: &[Value<'v>],
+        eval: &mut Evaluator<'v, '_>,
+    ) -> anyhow::Result<Value<'v>> {
+        let mut res = String::new();
+        Self::write(&self.0, &mut fields.iter(), &mut res, eval)?;
+        Ok(eval.heap().alloc(res))
+    }
+
+    fn write<'v>(
+        parts: &[FStringPartCompiled],
+        fields: &mut std::slice::Iter<Value<'v>>,
+        res: &mut String,
+        eval: &mut Evaluator<'v, '_>,
+    ) -> anyhow::Result<()> {
+        for x in parts {
+            match x {
+                FStringPartCompiled::Literal(s) => res.push_str(s),
+                FStringPartCompiled::Field(format, span) => {
+                    let v = *fields.next().unwrap();
+                    throw(format.format(v, res), *span, eval)?;
+                }
+                FStringPartCompiled::NestedField(conversion, spec_parts, span) => {
+                    let v = *fields.next().unwrap();
+                    let mut spec = String::new();
+                    Self::write(spec_parts, fields, &mut spec, eval)?;
+                    let formatted = FStringFormat::new(*conversion, Some(&spec))
+                        .map_err(anyhow::Error::from)
+                        .and_then(|format| format.format(v, res));
+                    throw(formatted, *span, eval)?;
+                }
+            }
+        }
+        Ok(())
+    }
 }
 
-fn eval_slice(
-    span: Span,
-    collection: ExprCompiledValue,
-    start: Option<ExprCompiledValue>,
-    stop: Option<ExprCompiledValue>,
-    stride: Option<ExprCompiledValue>,
-) -> ExprCompiledValue {
-    let start = start.map(ExprCompiledValue::as_compiled);
-    let stop = stop.map(ExprCompiledValue::as_compiled);
-    let stride = stride.map(ExprCompiledValue::as_compiled);
-    expr!("slice", collection, |eval| {
-        let start = match start {
-            Some(ref e) => Some(e(eval)?),
-            None => None,
-        };
-        let stop = match stop {
-            Some(ref e) => Some(e(eval)?),
-            None => None,
-        };
-        let stride = match stride {
-            Some(ref e) => Some(e(eval)?),
-            None => None,
-        };
-        throw(
-            collection.slice(start, stop, stride, eval.heap()),
-            span,
-            eval,
-        )?
-    })
+#[derive(Debug, Clone, Error)]
+pub(crate) enum EvalError {
+    #[error("Dictionary key repeated for `{0}`")]
+    DuplicateDictionaryKey(String),
 }
 
 impl AstLiteral {
@@ -106,6 +209,7 @@ impl AstLiteral {
         match self {
             AstLiteral::IntLiteral(i) => FrozenValue::new_int(i.node),
             AstLiteral::StringLiteral(x) => heap.alloc(x.node.as_str()),
+            AstLiteral::BytesLiteral(x) => heap.alloc(Bytes::new(x.node.clone())),
         }
     }
 }
@@ -168,187 +272,190 @@ impl Expr {
     }
 }
 
-#[derive(Default)]
-struct ArgsCompiled {
-    pos_named: Vec<ExprCompiled>,
+/// How the arguments of a call are laid out on the stack, above the function: the positional
+/// arguments, the named arguments, then `*args` and `**kwargs` if they are present.
+pub(crate) struct CallCompiled {
+    pos_named: usize,
     names: Vec<(String, Hashed<FrozenValue>)>,
-    args: Option<ExprCompiled>,
-    kwargs: Option<ExprCompiled>,
+    args: bool,
+    kwargs: bool,
 }
 
-impl ArgsCompiled {
-    fn is_one_pos(&self) -> bool {
-        self.pos_named.len() == 1
-            && self.names.is_empty()
-            && self.args.is_none()
-            && self.kwargs.is_none()
+impl CallCompiled {
+    /// The number of values the arguments take on the stack.
+    pub(crate) fn stack_len(&self) -> usize {
+        self.pos_named + self.args as usize + self.kwargs as usize
     }
-}
 
-// Helper that creates some specialised argument calls
-macro_rules! args {
-    ($args:ident, $e:expr) => {
-        if $args.names.is_empty()
-            && $args.args.is_none()
-            && $args.kwargs.is_none()
-            && $args.pos_named.len() <= 2
-        {
-            let mut args = $args;
-            match args.pos_named.pop() {
-                None => {
-                    let $args = Args0Compiled;
-                    $e
-                }
-                Some(a1) => match args.pos_named.pop() {
-                    None => {
-                        let $args = Args1Compiled(a1);
-                        $e
-                    }
-                    Some(a2) => {
-                        let $args = Args2Compiled(a2, a1);
-                        $e
-                    }
-                },
-            }
-        } else {
-            $e
-        }
-    };
-}
-
-struct Args0Compiled;
-
-struct Args1Compiled(ExprCompiled);
-
-struct Args2Compiled(ExprCompiled, ExprCompiled);
+    /// Are all the arguments positional.
+    pub(crate) fn is_positional(&self) -> bool {
+        self.names.is_empty() && !self.args && !self.kwargs
+    }
 
-impl Args0Compiled {
-    #[inline(always)]
-    fn with_params<'v, R>(
-        &self,
-        this: Option<Value<'v>>,
-        eval: &mut Evaluator<'v, '_>,
-        f: impl FnOnce(Parameters<'v, '_>, &mut Evaluator<'v, '_>) -> Result<R, EvalException<'v>>,
-    ) -> Result<R, EvalException<'v>> {
-        let params = Parameters {
-            this,
-            pos: &[],
-            named: &[],
-            names: &[],
-            args: None,
-            kwargs: None,
-        };
-        f(params, eval)
+    /// Write the arguments, for a cached module.
+    pub(crate) fn save(&self, w: &mut CodeWriter) -> anyhow::Result<()> {
+        w.u32(self.pos_named as u32);
+        w.list(&self.names, |w, (name, _)| {
+            w.str(name);
+            Ok(())
+        })?;
+        w.bool(self.args);
+        w.bool(self.kwargs);
+        Ok(())
     }
-}
 
-impl Args1Compiled {
-    #[inline(always)]
-    fn with_params<'v, R>(
-        &self,
-        this: Option<Value<'v>>,
-        eval: &mut Evaluator<'v, '_>,
-        f: impl FnOnce(Parameters<'v, '_>, &mut Evaluator<'v, '_>) -> Result<R, EvalException<'v>>,
-    ) -> Result<R, EvalException<'v>> {
-        let params = Parameters {
-            this,
-            pos: &[self.0(eval)?],
-            named: &[],
-            names: &[],
-            args: None,
-            kwargs: None,
-        };
-        f(params, eval)
+    /// Read arguments written by [`save`](CallCompiled::save).
+    pub(crate) fn load(r: &mut CodeReader) -> anyhow::Result<Self> {
+        let pos_named = r.u32()? as usize;
+        let names = r.list(|r| {
+            let name = r.string()?;
+            let name_value = r.heap().alloc(name.as_str()).get_hashed()?;
+            Ok((name, name_value))
+        })?;
+        if names.len() > pos_named {
+            return Err(CodeReader::corrupt());
+        }
+        Ok(Self {
+            pos_named,
+            names,
+            args: r.bool()?,
+            kwargs: r.bool()?,
+        })
     }
-}
 
-impl Args2Compiled {
-    #[inline(always)]
-    fn with_params<'v, R>(
+    pub(crate) fn invoke<'v>(
         &self,
+        fun: Value<'v>,
         this: Option<Value<'v>>,
+        xs: &[Value<'v>],
+        span: Span,
         eval: &mut Evaluator<'v, '_>,
-        f: impl FnOnce(Parameters<'v, '_>, &mut Evaluator<'v, '_>) -> Result<R, EvalException<'v>>,
-    ) -> Result<R, EvalException<'v>> {
+    ) -> anyhow::Result<Value<'v>> {
+        let (pos_named, mut rest) = xs.split_at(self.pos_named);
+        let (pos, named) = pos_named.split_at(self.pos_named - self.names.len());
+        let mut next_if = |present| {
+            if present {
+                let (x, xs) = rest.split_first().unwrap();
+                rest = xs;
+                Some(*x)
+            } else {
+                None
+            }
+        };
+        let args = next_if(self.args);
+        let kwargs = next_if(self.kwargs);
         let params = Parameters {
             this,
-            pos: &[self.0(eval)?, self.1(eval)?],
-            named: &[],
-            names: &[],
-            args: None,
-            kwargs: None,
+            pos,
+            named,
+            names: Parameters::promote_names(&self.names),
+            args,
+            kwargs,
         };
-        f(params, eval)
-    }
-}
-
-impl ArgsCompiled {
-    #[inline(always)]
-    fn with_params<'v, R>(
-        &self,
-        this: Option<Value<'v>>,
-        eval: &mut Evaluator<'v, '_>,
-        f: impl FnOnce(Parameters<'v, '_>, &mut Evaluator<'v, '_>) -> Result<R, EvalException<'v>>,
-    ) -> Result<R, EvalException<'v>> {
-        eval.alloca_uninit(self.pos_named.len(), |xs, eval| {
-            // because Value has no drop, we don't need to worry about failures before assume_init
-            for (x, arg) in xs.iter_mut().zip(&self.pos_named) {
-                x.write(arg(eval)?);
-            }
-            // because we allocated `pos_named` elements and filled them all, we can assume it is now init
-            let xs = unsafe { MaybeUninit::slice_assume_init_ref(xs) };
-
-            let args = match &self.args {
-                None => None,
-                Some(f) => Some(f(eval)?),
-            };
-            let kwargs = match &self.kwargs {
-                None => None,
-                Some(f) => Some(f(eval)?),
-            };
-            let (pos, named) = &xs.split_at(xs.len() - self.names.len());
-            let params = Parameters {
-                this,
-                pos,
-                named,
-                names: Parameters::promote_names(&self.names),
-                args,
-                kwargs,
-            };
-            f(params, eval)
-        })
+        fun.invoke(Some(span), params, eval)
     }
 }
 
 impl Compiler<'_> {
-    pub fn expr_opt(&mut self, expr: Option<Box<AstExpr>>) -> Option<ExprCompiledValue> {
-        expr.map(|v| self.expr(*v))
-    }
-
-    fn args(&mut self, args: Vec<AstArgument>) -> ArgsCompiled {
-        let mut res = ArgsCompiled::default();
+    /// Emit code to evaluate the arguments, in the order they are evaluated.
+    fn args(&mut self, args: Vec<AstArgument>) -> CallCompiled {
+        let mut pos_named = Vec::with_capacity(args.len());
+        let mut names = Vec::new();
+        let mut star_args = None;
+        let mut star_kwargs = None;
         for x in args {
             match x.node {
-                Argument::Positional(x) => res.pos_named.push(self.expr(x).as_compiled()),
+                Argument::Positional(x) => pos_named.push(x),
                 Argument::Named(name, value) => {
                     let name_value = self
                         .heap
                         .alloc(name.node.as_str())
                         .get_hashed()
                         .expect("String is Hashable");
-                    res.names.push((name.node, name_value));
-                    res.pos_named.push(self.expr(value).as_compiled());
+                    names.push((name.node, name_value));
+                    pos_named.push(value);
                 }
-                Argument::Args(x) => res.args = Some(self.expr(x).as_compiled()),
-                Argument::KwArgs(x) => res.kwargs = Some(self.expr(x).as_compiled()),
+                Argument::Args(x) => star_args = Some(x),
+                Argument::KwArgs(x) => star_kwargs = Some(x),
             }
         }
+        let res = CallCompiled {
+            pos_named: pos_named.len(),
+            names,
+            args: star_args.is_some(),
+            kwargs: star_kwargs.is_some(),
+        };
+        for x in pos_named.into_iter().chain(star_args).chain(star_kwargs) {
+            self.expr(x);
+        }
         res
     }
 
-    pub fn expr(&mut self, expr: AstExpr) -> ExprCompiledValue {
-        // println!("compile {}", expr.node);
+    /// Can the expression be evaluated at compile time, see [`const_value`](Compiler::const_value).
+    fn is_const(&mut self, expr: &AstExpr) -> bool {
+        match &expr.node {
+            Expr::Literal(_) => true,
+            Expr::Identifier(x) => {
+                self.scope.get_name(&x.node).is_none() && self.globals.get_frozen(&x.node).is_some()
+            }
+            Expr::Minus(x) => x.unpack_int_literal().and_then(i32::checked_neg).is_some(),
+            Expr::Plus(x) => x.unpack_int_literal().is_some(),
+            Expr::Op(l, op, r) => Expr::reduces_to_string(*op, l, r).is_some(),
+            Expr::Tuple(xs) => xs.iter().all(|x| self.is_const(x)),
+            Expr::FString(parts) => parts.iter().all(|x| matches!(x, FStringPart::Literal(_))),
+            _ => false,
+        }
+    }
+
+    /// The value of an expression which doesn't need any code to be run, such as a literal
+    /// or a global function, allocated on the frozen heap.
+    pub(crate) fn const_value(&mut self, expr: &AstExpr) -> Option<FrozenValue> {
+        match &expr.node {
+            Expr::Literal(x) => Some(x.compile(self.heap)),
+            Expr::Identifier(x) => match self.scope.get_name(&x.node) {
+                Some(_) => None,
+                None => self.globals.get_frozen(&x.node),
+            },
+            Expr::Minus(x) => x
+                .unpack_int_literal()
+                .and_then(i32::checked_neg)
+                .map(FrozenValue::new_int),
+            Expr::Plus(x) => x.unpack_int_literal().map(FrozenValue::new_int),
+            Expr::Op(l, op, r) => Expr::reduces_to_string(*op, l, r).map(|x| self.heap.alloc(x)),
+            // Check first, so we don't allocate the elements if the tuple isn't constant
+            Expr::Tuple(xs) if xs.iter().all(|x| self.is_const(x)) => {
+                let content = xs.map(|x| self.const_value(x).unwrap());
+                Some(self.heap.alloc(FrozenTuple { content }))
+            }
+            Expr::FString(parts) => {
+                let mut res = String::new();
+                for x in parts {
+                    match x {
+                        FStringPart::Literal(s) => res.push_str(s),
+                        FStringPart::Expr(..) => return None,
+                    }
+                }
+                Some(self.heap.alloc(res))
+            }
+            _ => None,
+        }
+    }
+
+    /// Report an error at compile time. Since compilation then fails, the code we
+    /// emit in its place is never run.
+    fn expr_error(&mut self, err: anyhow::Error, span: Span) {
+        self.errors
+            .push(Diagnostic::new(err, span, self.codemap.dupe()));
+        self.bc.emit(Instr::Const(FrozenValue::new_none()), span);
+    }
+
+    /// Emit code which pushes the value of the expression.
+    pub fn expr(&mut self, expr: AstExpr) {
         let span = expr.span;
+        if let Some(x) = self.const_value(&expr) {
+            self.bc.emit(Instr::Const(x), span);
+            return;
+        }
         match expr.node {
             Expr::Identifier(ident) => {
                 let name = ident.node;
@@ -357,41 +464,17 @@ impl Compiler<'_> {
                     Some(Slot::Local(slot)) => {
                         // We can't look up the local variabless in advance, because they are different each time
                         // we go through a new function call.
-                        expr!("local", |eval| throw(
-                            eval.get_slot_local(slot, &name),
-                            span,
-                            eval
-                        )?)
+                        let name = self.bc.string(name);
+                        self.bc.emit(Instr::LoadLocal(slot, name), span)
                     }
                     Some(Slot::Module(slot)) => {
                         // We can't look up the module variables in advance because the first time around they are
                         // mutables, but after freezing they point at a different set of frozen slots.
-                        expr!("module", |eval| throw(
-                            eval.get_slot_module(slot),
-                            span,
-                            eval
-                        )?)
+                        self.bc.emit(Instr::LoadModule(slot), span)
                     }
                     None => {
-                        // Must be a global, since we know all variables
-                        match self.globals.get_frozen(&name) {
-                            Some(v) => value!(v),
-                            None => {
-                                let name = name.to_owned();
-                                let codemap = self.codemap.dupe();
-                                let mk_err = move || {
-                                    Diagnostic::new(
-                                        EnvironmentError::VariableNotFound(name.clone()),
-                                        span,
-                                        codemap.dupe(),
-                                    )
-                                };
-                                self.errors.push(mk_err());
-                                ExprCompiledValue::Compiled(box move |_eval| {
-                                    Err(EvalException::Error(mk_err()))
-                                })
-                            }
-                        }
+                        // Must be a global, and we would have found it in `const_value`
+                        self.expr_error(EnvironmentError::VariableNotFound(name).into(), span)
                     }
                 }
             }
@@ -403,337 +486,247 @@ impl Compiler<'_> {
                 self.function("lambda", params, None, suite)
             }
             Expr::Tuple(exprs) => {
-                let xs = exprs.into_map(|x| self.expr(x));
-                if xs.iter().all(|x| x.as_value().is_some()) {
-                    let content = xs.map(|v| v.as_value().unwrap());
-                    let result = self.heap.alloc(FrozenTuple { content });
-                    value!(result)
-                } else {
-                    let xs = xs.into_map(|x| x.as_compiled());
-                    expr!("tuple", |eval| eval
-                        .heap()
-                        .alloc(Tuple::new(xs.try_map(|x| x(eval))?)))
+                let start = self.bc.addr();
+                let n = exprs.len() as u32;
+                for x in exprs {
+                    self.expr(x);
                 }
+                self.emit_fold(start, Instr::Tuple(n), span)
             }
             Expr::List(exprs) => {
-                let xs = exprs.into_map(|x| self.expr(x));
-                if xs.is_empty() {
-                    expr!("list_empty", |eval| eval.heap().alloc(List::default()))
-                } else if xs.iter().all(|x| x.as_value().is_some()) {
-                    let content = xs.map(|v| v.as_value().unwrap());
-                    expr!("list_static", |eval| {
-                        let content = coerce_ref(&content).clone();
-                        eval.heap().alloc(List::new(content))
-                    })
+                if !exprs.is_empty() && exprs.iter().all(|x| self.is_const(x)) {
+                    let content = exprs.map(|x| self.const_value(x).unwrap());
+                    self.bc.emit(Instr::ListConst(box content), span)
                 } else {
-                    let xs = xs.into_map(|x| x.as_compiled());
-                    expr!("list", |eval| eval
-                        .heap()
-                        .alloc(List::new(xs.try_map(|x| x(eval))?)))
+                    let start = self.bc.addr();
+                    let n = exprs.len() as u32;
+                    for x in exprs {
+                        self.expr(x);
+                    }
+                    self.emit_fold(start, Instr::List(n), span)
                 }
             }
             Expr::Dict(exprs) => {
-                let xs = exprs.into_map(|(k, v)| (self.expr(k), self.expr(v)));
-                if xs.is_empty() {
-                    return expr!("dict_empty", |eval| eval.heap().alloc(Dict::default()));
-                }
-                if xs.iter().all(|(k, _)| k.as_value().is_some()) {
-                    if xs.iter().all(|(_, v)| v.as_value().is_some()) {
-                        let mut res = SmallMap::new();
-                        for (k, v) in xs.iter() {
-                            res.insert_hashed(
-                                k.as_value()
-                                    .unwrap()
-                                    .get_hashed()
-                                    .expect("Dictionary literals are hashable"),
-                                v.as_value().unwrap(),
-                            );
+                if !exprs.is_empty() && exprs.iter().all(|(k, _)| self.is_const(k)) {
+                    let keys = exprs.map(|(k, _)| {
+                        self.const_value(k)
+                            .unwrap()
+                            .get_hashed()
+                            .expect("Dictionary literals are hashable")
+                    });
+                    if exprs.iter().all(|(_, v)| self.is_const(v)) {
+                        let mut res = SmallMap::with_capacity(keys.len());
+                        for (k, (_, v)) in keys.iter().zip(&exprs) {
+                            res.insert_hashed(*k, self.const_value(v).unwrap());
                         }
                         // If we lost some elements, then there are duplicates, so don't take the fast-literal
                         // path and go down the slow runtime path (which will raise the error).
                         // We have a lint that will likely fire on this issue (and others).
-                        if res.len() == xs.len() {
-                            return expr!("dict_static", |eval| {
-                                let res = coerce_ref(&res).clone();
-                                eval.heap().alloc(Dict::new(res))
-                            });
+                        if res.len() == exprs.len() {
+                            self.bc.emit(Instr::DictConst(box res), span);
+                            return;
                         }
-                    } else {
-                        // The keys are all constant, but the variables change.
-                        // At least we can pre-hash these values.
-                        let xs = xs.into_map(|(k, v)| {
-                            (
-                                k.as_value()
-                                    .unwrap()
-                                    .get_hashed()
-                                    .expect("Dictionary literals are hashable"),
-                                v.as_compiled(),
-                            )
-                        });
-                        return expr!("dict_static_key", |eval| {
-                            let mut r = SmallMap::with_capacity(xs.len());
-                            for (k, v) in &xs {
-                                if r.insert_hashed(k.to_hashed_value(), v(eval)?).is_some() {
-                                    throw(
-                                        Err(EvalError::DuplicateDictionaryKey(k.key().to_string())
-                                            .into()),
-                                        span,
-                                        eval,
-                                    )?;
-                                }
-                            }
-                            eval.heap().alloc(Dict::new(r))
-                        });
                     }
-                }
-
-                let xs = xs.into_map(|(k, v)| (k.as_compiled(), v.as_compiled()));
-                expr!("dict", |eval| {
-                    let mut r = SmallMap::with_capacity(xs.len());
-                    for (k, v) in &xs {
-                        let k = k(eval)?;
-                        if r.insert_hashed(k.get_hashed()?, v(eval)?).is_some() {
-                            throw(
-                                Err(EvalError::DuplicateDictionaryKey(k.to_string()).into()),
-                                span,
-                                eval,
-                            )?;
-                        }
+                    // The keys are all constant, but the variables change.
+                    // At least we can pre-hash these values.
+                    for (_, v) in exprs {
+                        self.expr(v);
                     }
-                    eval.heap().alloc(Dict::new(r))
-                })
+                    self.bc.emit(Instr::DictConstKeys(box keys), span);
+                } else {
+                    let start = self.bc.addr();
+                    let n = exprs.len() as u32;
+                    for (k, v) in exprs {
+                        self.expr(k);
+                        self.expr(v);
+                    }
+                    self.emit_fold(start, Instr::Dict(n), span)
+                }
             }
             Expr::If(box (cond, then_expr, else_expr)) => {
-                let cond = self.expr(cond);
-                let then_expr = self.expr(then_expr).as_compiled();
-                let else_expr = self.expr(else_expr).as_compiled();
-                expr!("if_expr", cond, |eval| {
-                    if cond.to_bool() {
-                        then_expr(eval)?
+                if let Some(cond) = self.expr_known(cond) {
+                    let (live, dead) = if cond.to_value().to_bool() {
+                        (then_expr, else_expr)
                     } else {
-                        else_expr(eval)?
-                    }
-                })
+                        (else_expr, then_expr)
+                    };
+                    self.expr(live);
+                    self.dead_code(|me| me.expr(dead));
+                    return;
+                }
+                let to_else = self.bc.emit_forward(Instr::JumpIfFalse, span);
+                let stack = self.bc.stack();
+                self.expr(then_expr);
+                let to_end = self.bc.emit_forward(Instr::Jump, span);
+                self.bc.patch(to_else);
+                self.bc.set_stack(stack);
+                self.expr(else_expr);
+                self.bc.patch(to_end);
             }
             Expr::Dot(left, right) => {
-                let left = self.expr(*left);
-                let s = right.node;
-                expr!("dot", left, |eval| {
-                    let (attr_type, v) = throw(left.get_attr_error(&s, eval.heap()), span, eval)?;
-                    if attr_type == AttrType::Field {
-                        v
-                    } else if let Some(v_attr) = v.downcast_ref::<NativeAttribute>() {
-                        throw(v_attr.call(left, eval), span, eval)?
-                    } else {
-                        // Insert self so the method see the object it is acting on
-                        eval.heap().alloc(BoundMethod::new(left, v))
-                    }
-                })
+                let start = self.bc.addr();
+                self.expr(*left);
+                let s = self.bc.string(right.node);
+                self.emit_fold(start, Instr::Dot(s), span)
             }
-            Expr::Call(left, args) => {
-                let mut args = self.args(args);
-                match left.node {
-                    Expr::Dot(box e, s) => {
-                        let e = self.expr(e);
-                        args!(
-                            args,
-                            expr!("call_method", e, |eval| {
-                                // We don't need to worry about whether it's an attribute, method or field
-                                // since those that don't want the `this` just ignore it
-                                let fun =
-                                    throw(e.get_attr_error(&s.node, eval.heap()), span, eval)?.1;
-                                args.with_params(Some(e), eval, |params, eval| {
-                                    throw(fun.invoke(Some(span), params, eval), span, eval)
-                                })?
-                            })
-                        )
-                    }
-                    _ => {
-                        let left = self.expr(*left);
-                        match left {
-                            ExprCompiledValue::Value(v)
-                                if self.constants.fn_type == v && args.is_one_pos() =>
-                            {
-                                let x = args.pos_named.pop().unwrap();
-                                expr!("type", |eval| {
-                                    x(eval)?.get_aref().get_type_value().unpack().to_value()
-                                })
-                            }
-                            ExprCompiledValue::Value(v)
-                                if self.constants.fn_len == v && args.is_one_pos() =>
-                            {
-                                let x = args.pos_named.pop().unwrap();
-                                // Technically the length command _could_ call other functions,
-                                // and we'd not get entries on the call stack, which would be bad.
-                                // But `len()` is super common, and no one expects it to call other functions,
-                                // so let's just ignore that corner case for additional perf.
-                                expr!("len", |eval| Value::new_int(x(eval)?.length()?))
+            Expr::Call(left, args) => match left.node {
+                Expr::Dot(box e, s) => {
+                    self.expr(e);
+                    self.bc
+                        .emit(Instr::Method(box MethodCompiled::new(s.node)), span);
+                    let call = self.args(args);
+                    self.bc.emit(Instr::CallMethod(box call), span)
+                }
+                _ => {
+                    let start = self.bc.addr();
+                    let one_pos =
+                        args.len() == 1 && matches!(args[0].node, Argument::Positional(_));
+                    match self.const_value(&left) {
+                        Some(v) if one_pos && self.constants.fn_type == v => {
+                            self.args(args);
+                            self.emit_fold(start, Instr::Type, span)
+                        }
+                        Some(v) if one_pos && self.constants.fn_len == v => {
+                            self.args(args);
+                            self.emit_fold(start, Instr::Len, span)
+                        }
+                        Some(v) => match self.inline(v, &args) {
+                            Some(inline) => self.call_inline(inline, args, span),
+                            None => {
+                                self.bc.emit(Instr::Const(v), left.span);
+                                let call = self.args(args);
+                                self.emit_fold(start, Instr::Call(box call), span)
                             }
-                            _ => args!(
-                                args,
-                                expr!("call", left, |eval| {
-                                    args.with_params(None, eval, |params, eval| {
-                                        throw(left.invoke(Some(span), params, eval), span, eval)
-                                    })?
-                                })
-                            ),
+                        },
+                        None => {
+                            self.expr(*left);
+                            let call = self.args(args);
+                            self.bc.emit(Instr::Call(box call), span)
                         }
                     }
                 }
-            }
+            },
             Expr::ArrayIndirection(box (array, index)) => {
-                let array = self.expr(array);
-                let index = self.expr(index);
-                expr!("index", array, index, |eval| {
-                    throw(array.at(index, eval.heap()), span, eval)?
-                })
+                let start = self.bc.addr();
+                self.expr(array);
+                self.expr(index);
+                self.emit_fold(start, Instr::Index, span)
             }
             Expr::Slice(collection, start, stop, stride) => {
-                let collection = self.expr(*collection);
-                let start = start.map(|x| self.expr(*x));
-                let stop = stop.map(|x| self.expr(*x));
-                let stride = stride.map(|x| self.expr(*x));
-                eval_slice(span, collection, start, stop, stride)
+                self.expr(*collection);
+                let mut flags = 0;
+                for (x, flag) in [
+                    (start, SLICE_START),
+                    (stop, SLICE_STOP),
+                    (stride, SLICE_STRIDE),
+                ] {
+                    if let Some(x) = x {
+                        self.expr(*x);
+                        flags |= flag;
+                    }
+                }
+                self.bc.emit(Instr::Slice(flags), span)
             }
-            Expr::Not(expr) => {
-                let expr = self.expr(*expr);
-                expr!("not", expr, |_eval| Value::new_bool(!expr.to_bool()))
+            Expr::Not(x) => {
+                let start = self.bc.addr();
+                self.expr(*x);
+                self.emit_fold(start, Instr::Not, span)
             }
-            Expr::Minus(expr) => match expr.unpack_int_literal().and_then(i32::checked_neg) {
-                None => {
-                    let expr = self.expr(*expr);
-                    expr!("minus", expr, |eval| throw(
-                        expr.minus(eval.heap()),
-                        span,
-                        eval
-                    )?)
-                }
-                Some(x) => {
-                    value!(FrozenValue::new_int(x))
-                }
-            },
-            Expr::Plus(expr) => match expr.unpack_int_literal() {
-                None => {
-                    let expr = self.expr(*expr);
-                    expr!("plus", expr, |eval| throw(
-                        expr.plus(eval.heap()),
-                        span,
-                        eval
-                    )?)
-                }
-                Some(x) => {
-                    value!(FrozenValue::new_int(x))
-                }
-            },
-            Expr::BitNot(expr) => {
-                let expr = self.expr(*expr);
-                expr!("bit_not", expr, |_eval| Value::new_int(!expr.to_int()?))
+            Expr::Minus(x) => {
+                let start = self.bc.addr();
+                self.expr(*x);
+                self.emit_fold(start, Instr::Minus, span)
+            }
+            Expr::Plus(x) => {
+                let start = self.bc.addr();
+                self.expr(*x);
+                self.emit_fold(start, Instr::Plus, span)
+            }
+            Expr::BitNot(x) => {
+                let start = self.bc.addr();
+                self.expr(*x);
+                self.emit_fold(start, Instr::BitNot, span)
             }
             Expr::Op(left, op, right) => {
-                if let Some(x) = Expr::reduces_to_string(op, &left, &right) {
-                    let val = self.heap.alloc(x);
-                    value!(val)
-                } else {
-                    let l = self.expr(*left);
-                    let r = self.expr(*right);
-                    match op {
-                        BinOp::Or => {
-                            let r = r.as_compiled();
-                            expr!("or", l, |eval| {
-                                if l.to_bool() { l } else { r(eval)? }
-                            })
-                        }
-                        BinOp::And => {
-                            let r = r.as_compiled();
-                            expr!("and", l, |eval| {
-                                if !l.to_bool() { l } else { r(eval)? }
-                            })
-                        }
-                        BinOp::Equal => eval_equals(span, l, r, |x| x),
-                        BinOp::NotEqual => eval_equals(span, l, r, |x| !x),
-                        BinOp::Less => eval_compare(span, l, r, |x| x == Ordering::Less),
-                        BinOp::Greater => eval_compare(span, l, r, |x| x == Ordering::Greater),
-                        BinOp::LessOrEqual => eval_compare(span, l, r, |x| x != Ordering::Greater),
-                        BinOp::GreaterOrEqual => eval_compare(span, l, r, |x| x != Ordering::Less),
-                        BinOp::In => expr!("in", r, l, |eval| {
-                            throw(r.is_in(l).map(Value::new_bool), span, eval)?
-                        }),
-                        BinOp::NotIn => expr!("not_in", r, l, |eval| {
-                            throw(r.is_in(l).map(|x| Value::new_bool(!x)), span, eval)?
-                        }),
-                        BinOp::Subtract => {
-                            expr!("subtract", l, r, |eval| throw(
-                                l.sub(r, eval.heap()),
-                                span,
-                                eval
-                            )?)
-                        }
-                        BinOp::Add => expr!("add", l, r, |eval| {
-                            // Addition of string is super common and pretty cheap, so have a special case for it.
-                            if let Some(ls) = l.unpack_str() {
-                                if let Some(rs) = r.unpack_str() {
-                                    if ls.is_empty() {
-                                        return Ok(r);
-                                    } else if rs.is_empty() {
-                                        return Ok(l);
-                                    } else {
-                                        return Ok(eval.heap().alloc(fast_string::append(ls, rs)));
-                                    }
-                                }
+                let start = self.bc.addr();
+                let instr = match op {
+                    BinOp::Or | BinOp::And => {
+                        let left_span = left.span;
+                        if let Some(l) = self.expr_known(*left) {
+                            // `or` gives the left operand if it is true, `and` if it is false
+                            if l.to_value().to_bool() == (op == BinOp::Or) {
+                                self.bc.emit(Instr::Const(l), left_span);
+                                self.dead_code(|me| me.expr(*right));
+                            } else {
+                                self.expr(*right);
                             }
-
-                            // Written using Value::add so that Rust Analyzer doesn't think it is an error.
-                            throw(Value::add(l, r, eval.heap()), span, eval)?
-                        }),
-                        BinOp::Multiply => {
-                            expr!("multiply", l, r, |eval| throw(
-                                l.mul(r, eval.heap()),
-                                span,
-                                eval
-                            )?)
-                        }
-                        BinOp::Percent => expr!("percent", l, r, |eval| {
-                            throw(l.percent(r, eval.heap()), span, eval)?
-                        }),
-                        BinOp::FloorDivide => expr!("floor_divide", l, r, |eval| {
-                            throw(l.floor_div(r, eval.heap()), span, eval)?
-                        }),
-                        BinOp::BitAnd => {
-                            expr!("bit_and", l, r, |eval| throw(l.bit_and(r), span, eval)?)
-                        }
-                        BinOp::BitOr => {
-                            expr!("bit_or", l, r, |eval| throw(l.bit_or(r), span, eval)?)
-                        }
-                        BinOp::BitXor => {
-                            expr!("bit_xor", l, r, |eval| throw(l.bit_xor(r), span, eval)?)
-                        }
-                        BinOp::LeftShift => {
-                            expr!("left_shift", l, r, |eval| throw(
-                                l.left_shift(r),
-                                span,
-                                eval
-                            )?)
-                        }
-                        BinOp::RightShift => {
-                            expr!("right_shift", l, r, |eval| throw(
-                                l.right_shift(r),
-                                span,
-                                eval
-                            )?)
+                            return;
                         }
+                        let jump = if op == BinOp::Or {
+                            Instr::JumpIfTrueOrPop
+                        } else {
+                            Instr::JumpIfFalseOrPop
+                        };
+                        let to_end = self.bc.emit_forward(jump, span);
+                        self.expr(*right);
+                        self.bc.patch(to_end);
+                        return;
                     }
-                }
+                    BinOp::In | BinOp::NotIn => {
+                        // The collection is evaluated first
+                        self.expr(*right);
+                        self.expr(*left);
+                        let instr = if op == BinOp::In {
+                            Instr::In
+                        } else {
+                            Instr::NotIn
+                        };
+                        self.emit_fold(start, instr, span);
+                        return;
+                    }
+                    BinOp::Equal => Instr::Equal,
+                    BinOp::NotEqual => Instr::NotEqual,
+                    BinOp::Less => Instr::Less,
+                    BinOp::Greater => Instr::Greater,
+                    BinOp::LessOrEqual => Instr::LessOrEqual,
+                    BinOp::GreaterOrEqual => Instr::GreaterOrEqual,
+                    BinOp::Subtract => Instr::Sub,
+                    BinOp::Add => Instr::Add,
+                    BinOp::Multiply => Instr::Mul,
+                    BinOp::Percent => Instr::Percent,
+                    BinOp::FloorDivide => Instr::FloorDiv,
+                    BinOp::BitAnd => Instr::BitAnd,
+                    BinOp::BitOr => Instr::BitOr,
+                    BinOp::BitXor => Instr::BitXor,
+                    BinOp::LeftShift => Instr::LeftShift,
+                    BinOp::RightShift => Instr::RightShift,
+                };
+                self.expr(*left);
+                self.expr(*right);
+                self.emit_fold(start, instr, span)
             }
             Expr::ListComprehension(x, box for_, clauses) => {
-                self.list_comprehension(*x, for_, clauses)
+                self.list_comprehension(*x, for_, clauses, span)
             }
             Expr::DictComprehension(box (k, v), box for_, clauses) => {
-                self.dict_comprehension(k, v, for_, clauses)
+                self.dict_comprehension(k, v, for_, clauses, span)
             }
-            Expr::Literal(x) => {
-                let val = x.compile(self.heap);
-                value!(val)
+            // Always constant, so dealt with above
+            Expr::Literal(_) => unreachable!(),
+            Expr::FString(parts) => self.f_string(span, parts),
+        }
+    }
+
+    fn f_string(&mut self, span: Span, parts: Vec<FStringPart>) {
+        let mut exprs = Vec::new();
+        match FStringCompiled::new(parts, &mut exprs) {
+            Ok(xs) => {
+                for x in exprs {
+                    self.expr(x);
+                }
+                self.bc.emit(Instr::FString(box FStringCompiled(xs)), span)
             }
+            Err(e) => self.expr_error(e.into(), span),
         }
     }
 }
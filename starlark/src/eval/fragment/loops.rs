@@ -0,0 +1,423 @@
+/*
+ * Copyright 2019 The Starlark in Rust Authors.
+ * Copyright (c) Facebook, Inc. and its affiliates.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Loops over calls like `enumerate(xs)` or `d.items()`.
+//!
+//! These calls return [list views](crate::values::view), which only make the tuple for
+//! an item when it is asked for. A loop over the call doesn't need the view either, so
+//! for these calls we take the same copy of the items the view would have held, and let
+//! a loop with two variables take each pair straight from the copy, without making the
+//! tuple for `for k, v in ...` to unpack again.
+
+use crate::{
+    codemap::{Span, Spanned},
+    environment::{CodeReader, CodeWriter},
+    eval::{
+        bc::{cache::MethodCompiled, instr::Instr, interp::LoopIter},
+        compiler::Compiler,
+        runtime::evaluator::Evaluator,
+        Parameters,
+    },
+    syntax::ast::{Argument, Assign, AstArgument, AstAssign, AstExpr, Expr},
+    values::{dict::Dict, FrozenValue, Heap, Value},
+};
+
+/// Which list a dict method returns.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum DictView {
+    Items,
+    Keys,
+    Values,
+}
+
+/// A call whose result a loop can go through without building it. The arguments of
+/// the call, or the value the method is called on, are on the stack.
+pub(crate) enum LoopCompiled {
+    /// `enumerate(x)`, or `enumerate(x, start)` if the flag is set.
+    Enumerate(FrozenValue, bool),
+    /// `reversed(x)`.
+    Reversed(FrozenValue),
+    /// `zip(x, y)`.
+    Zip(FrozenValue),
+    /// `x.items()`, `x.keys()` or `x.values()`, which are only dict methods if `x` is a dict.
+    Dict(DictView, MethodCompiled),
+}
+
+impl LoopCompiled {
+    pub(crate) fn stack_len(&self) -> usize {
+        match self {
+            Self::Enumerate(_, start) => 1 + *start as usize,
+            Self::Reversed(_) | Self::Dict(..) => 1,
+            Self::Zip(_) => 2,
+        }
+    }
+
+    /// Write the call, for a cached module.
+    pub(crate) fn save(&self, w: &mut CodeWriter) -> anyhow::Result<()> {
+        match self {
+            Self::Enumerate(f, start) => {
+                w.u8(0);
+                w.value(*f)?;
+                w.bool(*start)
+            }
+            Self::Reversed(f) => {
+                w.u8(1);
+                w.value(*f)?
+            }
+            Self::Zip(f) => {
+                w.u8(2);
+                w.value(*f)?
+            }
+            Self::Dict(view, method) => {
+                w.u8(3);
+                w.u8(*view as u8);
+                w.str(method.name())
+            }
+        }
+        Ok(())
+    }
+
+    /// Read a call written by [`save`](LoopCompiled::save).
+    pub(crate) fn load(r: &mut CodeReader) -> anyhow::Result<Self> {
+        Ok(match r.u8()? {
+            0 => Self::Enumerate(r.value()?, r.bool()?),
+            1 => Self::Reversed(r.value()?),
+            2 => Self::Zip(r.value()?),
+            3 => {
+                let view = match r.u8()? {
+                    0 => DictView::Items,
+                    1 => DictView::Keys,
+                    2 => DictView::Values,
+                    _ => return Err(CodeReader::corrupt()),
+                };
+                Self::Dict(view, MethodCompiled::new(r.string()?))
+            }
+            _ => return Err(CodeReader::corrupt()),
+        })
+    }
+
+    /// Whether the items are pairs, which a loop with two variables can unpack for free.
+    fn pairs(&self) -> bool {
+        matches!(
+            self,
+            Self::Enumerate(..) | Self::Zip(_) | Self::Dict(DictView::Items, _)
+        )
+    }
+
+    /// Start a loop over the result of the call, given what it left on the stack.
+    pub(crate) fn start<'v>(
+        &self,
+        xs: &[Value<'v>],
+        span: Span,
+        eval: &mut Evaluator<'v, '_>,
+    ) -> anyhow::Result<LoopIter<'v>> {
+        if let Some(res) = self.copy_items(xs, eval.heap()) {
+            return Ok(res);
+        }
+        // Anything unusual, including errors, is left to the call itself
+        let res = match self {
+            Self::Enumerate(f, _) | Self::Reversed(f) | Self::Zip(f) => {
+                f.to_value().invoke_pos(Some(span), xs, eval)?
+            }
+            Self::Dict(_, method) => {
+                let fun = method.get(xs[0], eval.heap())?;
+                let params = Parameters {
+                    this: Some(xs[0]),
+                    ..Parameters::default()
+                };
+                fun.invoke(Some(span), params, eval)?
+            }
+        };
+        LoopIter::new(res, eval.heap())
+    }
+
+    /// The items of the list the call would return, without building it.
+    fn copy_items<'v>(&self, xs: &[Value<'v>], heap: &'v Heap) -> Option<LoopIter<'v>> {
+        match self {
+            Self::Enumerate(_, has_start) => {
+                let start = if *has_start { xs[1].unpack_int()? } else { 0 };
+                let items = xs[0].iterate(heap).ok()?;
+                let items = items
+                    .iter()
+                    .enumerate()
+                    .map(|(k, v)| (Value::new_int(k as i32 + start), v));
+                Some(LoopIter::Pairs(items.collect::<Vec<_>>().into_iter()))
+            }
+            Self::Reversed(_) => {
+                let mut items: Vec<_> = xs[0].iterate(heap).ok()?.iter().collect();
+                items.reverse();
+                Some(LoopIter::Values(items.into_iter()))
+            }
+            Self::Zip(_) => {
+                let xs0 = xs[0].iterate(heap).ok()?;
+                let xs1 = xs[1].iterate(heap).ok()?;
+                let items = xs0.iter().zip(xs1.iter());
+                Some(LoopIter::Pairs(items.collect::<Vec<_>>().into_iter()))
+            }
+            Self::Dict(view, _) => {
+                let dict = Dict::from_value(xs[0])?;
+                Some(match view {
+                    DictView::Items => LoopIter::Pairs(dict.items().into_iter()),
+                    DictView::Keys => LoopIter::Values(dict.keys().into_iter()),
+                    DictView::Values => LoopIter::Values(dict.values().into_iter()),
+                })
+            }
+        }
+    }
+}
+
+impl Compiler<'_> {
+    /// Emit the code for what a `for` loops over, returning the instruction which
+    /// starts the loop.
+    pub(crate) fn loop_over(&mut self, over: AstExpr) -> Instr {
+        let loop_ = match &over.node {
+            Expr::Call(f, args) => self.loop_call(f, args),
+            _ => None,
+        };
+        match loop_ {
+            None => {
+                self.expr(over);
+                Instr::IterStart
+            }
+            Some(loop_) => {
+                match over.node {
+                    Expr::Call(box f, args) => match f.node {
+                        Expr::Dot(box e, _) => self.expr(e),
+                        _ => {
+                            for x in args {
+                                if let Argument::Positional(x) = x.node {
+                                    self.expr(x)
+                                }
+                            }
+                        }
+                    },
+                    _ => unreachable!(),
+                }
+                Instr::IterStartCall(box loop_)
+            }
+        }
+    }
+
+    /// Recognise the calls in [`LoopCompiled`], which only have positional arguments.
+    fn loop_call(&mut self, f: &AstExpr, args: &[AstArgument]) -> Option<LoopCompiled> {
+        if !args
+            .iter()
+            .all(|x| matches!(x.node, Argument::Positional(_)))
+        {
+            return None;
+        }
+        if let Expr::Dot(_, name) = &f.node {
+            let view = match name.node.as_str() {
+                "items" => DictView::Items,
+                "keys" => DictView::Keys,
+                "values" => DictView::Values,
+                _ => return None,
+            };
+            return if args.is_empty() {
+                Some(LoopCompiled::Dict(
+                    view,
+                    MethodCompiled::new(name.node.clone()),
+                ))
+            } else {
+                None
+            };
+        }
+        let f = self.const_value(f)?;
+        let c = &self.constants;
+        if f == c.fn_enumerate && (args.len() == 1 || args.len() == 2) {
+            Some(LoopCompiled::Enumerate(f, args.len() == 2))
+        } else if f == c.fn_reversed && args.len() == 1 {
+            Some(LoopCompiled::Reversed(f))
+        } else if f == c.fn_zip && args.len() == 2 {
+            Some(LoopCompiled::Zip(f))
+        } else {
+            None
+        }
+    }
+
+    /// Start the loop with `start`, from [`loop_over`](Compiler::loop_over), assigning
+    /// each item to `var` at the top of the body.
+    pub(crate) fn loop_start(&mut self, start: Instr, over_span: Span, var: AstAssign, span: Span) {
+        let pairs = matches!(&start, Instr::IterStartCall(x) if x.pairs());
+        match var.node {
+            Assign::Tuple(vars) if pairs && vars.len() == 2 => {
+                self.bc.loop_start(start, Some(var.span), over_span, span);
+                for x in vars {
+                    self.assign(x);
+                }
+            }
+            node => {
+                self.bc.loop_start(start, None, over_span, span);
+                self.assign(Spanned {
+                    span: var.span,
+                    node,
+                });
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        assert::Assert,
+        environment::{Globals, Module},
+        eval::{bc::instr::Instr, compiler::fold::tests::compile},
+    };
+
+    /// How many loops in the program go through a call without building its result,
+    /// and how many of those take two variables without making tuples.
+    fn loop_calls(program: &str) -> (usize, usize) {
+        let module = Module::new();
+        let bc = compile(&module, program, &Globals::extended(), false);
+        let count = |f: fn(&Instr) -> bool| bc.instrs.iter().filter(|x| f(x)).count();
+        (
+            count(|x| matches!(x, Instr::IterStartCall(_))),
+            count(|x| matches!(x, Instr::IterNextPair(_))),
+        )
+    }
+
+    #[test]
+    fn test_loop_calls_compiled() {
+        assert_eq!(loop_calls("for k, v in {}.items():\n    pass"), (1, 1));
+        assert_eq!(loop_calls("for x in {}.items():\n    pass"), (1, 0));
+        assert_eq!(loop_calls("for k, v in {}.values():\n    pass"), (1, 0));
+        assert_eq!(loop_calls("[i for i, x in enumerate([], 1)]"), (1, 1));
+        assert_eq!(
+            loop_calls("[x for x in reversed([]) for y in zip([], [])]"),
+            (2, 0)
+        );
+        assert_eq!(loop_calls("for x in {}.items(1):\n    pass"), (0, 0));
+        assert_eq!(loop_calls("for x in enumerate(x = []):\n    pass"), (0, 0));
+        assert_eq!(loop_calls("for x in zip([]):\n    pass"), (0, 0));
+        assert_eq!(
+            loop_calls("zip = enumerate\nfor x in zip([], []):\n    pass"),
+            (0, 0)
+        );
+    }
+
+    #[test]
+    fn test_loop_calls() {
+        let a = Assert::new();
+        a.pass(
+            r#"
+d = {"a": 1, "b": 2}
+def run():
+    res = []
+    for k, v in d.items():
+        res.append((k, v))
+    for x in d.items():
+        res.append(x)
+    for k in d.keys():
+        res.append(k)
+    for v in d.values():
+        res.append(v)
+    for i, x in enumerate("xy".elems()):
+        res.append((i, x))
+    for i, x in enumerate(["x"], 5):
+        res.append((i, x))
+    for x in reversed([1, 2, 3]):
+        res.append(x)
+    for x, y in zip([1, 2, 3], "ab".elems()):
+        res.append((x, y))
+    return res
+assert_eq(run(), [
+    ("a", 1), ("b", 2), ("a", 1), ("b", 2), "a", "b", 1, 2,
+    (0, "x"), (1, "y"), (5, "x"), 3, 2, 1, (1, "a"), (2, "b"),
+])
+assert_eq([k + str(v) for k, v in d.items()], ["a1", "b2"])
+assert_eq({v: k for k, v in d.items()}, {1: "a", 2: "b"})
+assert_eq([(i, j) for i, x in enumerate([3, 4]) for j in reversed(range(x - 2))], [(0, 0), (1, 1), (1, 0)])
+"#,
+        );
+    }
+
+    #[test]
+    fn test_loop_calls_mutate() {
+        // We loop over a copy, as we would over the list the call returns
+        Assert::new().pass(
+            r#"
+def run():
+    d = {1: 2}
+    for k, v in d.items():
+        d[k + 1] = v
+    xs = [1, 2]
+    for i, x in enumerate(xs):
+        xs.append(i)
+    return d, xs
+assert_eq(run(), ({1: 2, 2: 2}, [1, 2, 0, 1]))
+"#,
+        );
+    }
+
+    #[test]
+    fn test_loop_calls_return_views() {
+        // Outside a loop, the calls return views, which act like immutable lists
+        let a = Assert::new();
+        a.pass(
+            r#"
+d = {"a": 1}
+assert_eq(d.items() + {"b": 2}.items(), [("a", 1), ("b", 2)])
+assert_eq(d.keys(), ["a"])
+assert_eq([type(x) for x in [enumerate([]), zip([], []), reversed([]), d.values()]], ["list_view"] * 4)
+xs = list(enumerate(["x"]))
+xs.append(1)
+assert_eq(xs, [(0, "x"), 1])
+"#,
+        );
+        a.fail(
+            "d = {}
+d.items().append(1)",
+            "not supported on type `list_view`",
+        );
+    }
+
+    #[test]
+    fn test_loop_calls_not_builtin() {
+        let a = Assert::new();
+        a.pass(
+            r#"
+s = struct(items = lambda: [(1, 2)], keys = lambda: "ab".elems())
+def enumerate(x):
+    return [("not", x)]
+def run():
+    res = []
+    for k, v in s.items():
+        res.append((k, v))
+    for k in s.keys():
+        res.append(k)
+    for k, v in enumerate(1):
+        res.append((k, v))
+    return res
+assert_eq(run(), [(1, 2), "a", "b", ("not", 1)])
+"#,
+        );
+        a.fail(
+            "for x, y in enumerate([1], 'x'):\n    pass",
+            "Type of parameter",
+        );
+        a.fail("for x, y in zip(1, []):\n    pass", "not supported");
+        a.fail(
+            "for x in [].items():\n    pass",
+            "not supported on type `list`",
+        );
+        a.fail(
+            "s = struct(items = lambda: [(1, 2, 3)])\nfor k, v in s.items():\n    pass",
+            "Unpacked 3 values but expected 2",
+        );
+    }
+}
@@ -15,54 +15,8 @@
  * limitations under the License.
  */
 
-macro_rules! expr {
-    ($name:expr, |$eval:ident| $body:expr) => {{
-        #[allow(clippy::needless_question_mark)]
-        let res: ExprCompiled = box move |$eval| $eval.ann($name, |$eval| Ok($body));
-        ExprCompiledValue::Compiled(res)
-    }};
-    ($name:expr, $v1:ident, |$eval:ident| $body:expr) => {{
-        let $v1 = $v1.as_compiled();
-        #[allow(clippy::needless_question_mark)]
-        let res: ExprCompiled = box move |$eval| {
-            let $v1 = $v1($eval)?;
-            $eval.ann($name, |$eval| Ok($body))
-        };
-        ExprCompiledValue::Compiled(res)
-    }};
-    ($name:expr, $v1:ident, $v2:ident, |$eval:ident| $body:expr) => {{
-        let $v1 = $v1.as_compiled();
-        let $v2 = $v2.as_compiled();
-        #[allow(clippy::needless_question_mark)]
-        let res: ExprCompiled = box move |$eval| {
-            let $v1 = $v1($eval)?;
-            let $v2 = $v2($eval)?;
-            $eval.ann($name, |$eval| Ok($body))
-        };
-        ExprCompiledValue::Compiled(res)
-    }};
-}
-
-macro_rules! value {
-    ($v:expr) => {
-        ExprCompiledValue::Value($v)
-    };
-}
-
-macro_rules! stmt {
-    ($name:expr, $span:ident, |$eval:ident| $body:expr) => {{
-        box move |$eval| {
-            $eval.ann($name, |$eval| {
-                before_stmt($span, $eval);
-                $body;
-                #[allow(unreachable_code)]
-                Ok(())
-            })
-        }
-    }};
-}
-
 pub(crate) mod compr;
 pub(crate) mod def;
 pub(crate) mod expr;
+pub(crate) mod loops;
 pub(crate) mod stmt;
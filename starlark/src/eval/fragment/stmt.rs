@@ -24,158 +24,160 @@
 //! Bazel's BUILD file). The BUILD dialect does not allow `def` statements.
 use crate::{
     codemap::{Span, Spanned},
-    environment::EnvironmentError,
+    environment::{slots::ModuleSlotId, CodeReader, CodeWriter, EnvironmentError},
     eval::{
-        compiler::{scope::Slot, throw, Compiler, EvalException, ExprCompiledValue, StmtCompiled},
-        runtime::evaluator::{Evaluator, GC_THRESHOLD},
-    },
-    syntax::ast::{Assign, AssignOp, AstAssign, AstStmt, Expr, Stmt, Visibility},
-    values::{
-        fast_string,
-        list::{FrozenList, List},
-        ControlError, Heap, Trace, Value,
+        bc::instr::Instr,
+        compiler::{scope::Slot, throw, Compiler},
+        runtime::{evaluator::Evaluator, slots::LocalSlotId},
     },
+    syntax::ast::{Assign, AssignOp, AstAssign, AstExpr, AstStmt, Expr, Stmt, Visibility},
+    values::FrozenValue,
 };
-use anyhow::anyhow;
 use gazebo::prelude::*;
-use std::{any::TypeId, collections::HashMap, mem};
+use std::collections::HashMap;
 use thiserror::Error;
 
 #[derive(Debug, Error)]
-enum AssignError {
+pub(crate) enum AssignError {
     // Incorrect number of value to unpack (expected, got)
     #[error("Unpacked {1} values but expected {0}")]
     IncorrectNumberOfValueToUnpack(i32, i32),
 }
 
-pub(crate) type AssignCompiled = Box<
-    dyn for<'v> Fn(Value<'v>, &mut Evaluator<'v, '_>) -> Result<(), EvalException<'v>>
-        + Send
-        + Sync,
->;
+/// The `load()` of some symbols from another module.
+pub(crate) struct LoadCompiled {
+    name: String,
+    /// Where to put the symbol, its name in the other module, and the span for errors.
+    symbols: Vec<(Slot, String, Span)>,
+}
 
-fn eval_assign_list<'v>(
-    lvalues: &[AssignCompiled],
-    span: Span,
-    value: Value<'v>,
-    eval: &mut Evaluator<'v, '_>,
-) -> Result<(), EvalException<'v>> {
-    let l = lvalues.len() as i32;
-    let nvl = throw(value.length(), span, eval)?;
-    if nvl != l {
-        throw(
-            Err(AssignError::IncorrectNumberOfValueToUnpack(l, nvl).into()),
-            span,
-            eval,
-        )
-    } else {
-        let mut it1 = lvalues.iter();
-        // TODO: the span here should probably include the rvalue
-        let it2 = throw(value.iterate(eval.heap()), span, eval)?;
-        let mut it2 = it2.iter();
-        for _ in 0..l {
-            it1.next().unwrap()(it2.next().unwrap(), eval)?;
+impl LoadCompiled {
+    pub(crate) fn eval(&self, eval: &mut Evaluator) -> anyhow::Result<()> {
+        let loadenv = match eval.loader.as_mut() {
+            None => return Err(EnvironmentError::NoImportsAvailable(self.name.clone()).into()),
+            Some(load) => load.load(&self.name)?,
+        };
+        for (new_name, orig_name, span) in &self.symbols {
+            let value = throw(
+                eval.module_env.load_symbol(&loadenv, orig_name),
+                *span,
+                eval,
+            )?;
+            match new_name {
+                Slot::Local(slot) => eval.set_slot_local(*slot, value),
+                Slot::Module(slot) => eval.set_slot_module(*slot, value),
+            }
         }
         Ok(())
     }
+
+    /// Write the load, for a cached module.
+    pub(crate) fn save(&self, w: &mut CodeWriter) -> anyhow::Result<()> {
+        w.str(&self.name);
+        w.list(&self.symbols, |w, (slot, name, span)| {
+            match slot {
+                Slot::Module(x) => {
+                    w.u8(0);
+                    w.u32(x.index() as u32)
+                }
+                Slot::Local(x) => {
+                    w.u8(1);
+                    w.u32(x.index() as u32)
+                }
+            }
+            w.str(name);
+            w.span(*span);
+            Ok(())
+        })
+    }
+
+    /// Read a load written by [`save`](LoadCompiled::save).
+    pub(crate) fn load(r: &mut CodeReader) -> anyhow::Result<Self> {
+        let name = r.string()?;
+        let symbols = r.list(|r| {
+            let slot = match r.u8()? {
+                0 => Slot::Module(ModuleSlotId::new(r.u32()? as usize)),
+                1 => Slot::Local(LocalSlotId::new(r.u32()? as usize)),
+                _ => return Err(CodeReader::corrupt()),
+            };
+            Ok((slot, r.string()?, r.span()?))
+        })?;
+        Ok(Self { name, symbols })
+    }
 }
 
 impl Compiler<'_> {
-    pub fn assign(&mut self, expr: AstAssign) -> AssignCompiled {
+    /// Emit code which pops the value on top of the stack and assigns it.
+    pub fn assign(&mut self, expr: AstAssign) {
         let span = expr.span;
         match expr.node {
             Assign::Dot(e, s) => {
-                let e = self.expr(*e).as_compiled();
-                let s = s.node;
-                box move |value, eval| throw(e(eval)?.set_attr(&s, value), span, eval)
+                self.expr(*e);
+                let s = self.bc.string(s.node);
+                self.bc.emit(Instr::SetAttr(s), span)
             }
             Assign::ArrayIndirection(box (e, idx)) => {
-                let e = self.expr(e).as_compiled();
-                let idx = self.expr(idx).as_compiled();
-                box move |value, eval| throw(e(eval)?.set_at(idx(eval)?, value), span, eval)
+                self.expr(e);
+                self.expr(idx);
+                self.bc.emit(Instr::SetIndex, span)
             }
             Assign::Tuple(v) => {
-                let v = v.into_map(|x| self.assign(x));
-                box move |value, eval| eval_assign_list(&v, span, value, eval)
+                // TODO: the span here should probably include the rvalue
+                self.bc.emit(Instr::Unpack(v.len() as u32), span);
+                for x in v {
+                    self.assign(x);
+                }
+                self.bc.emit(Instr::UnpackEnd, span);
             }
             Assign::Identifier(ident) => match self.scope.get_name_or_panic(&ident.node) {
-                Slot::Local(slot) => box move |value, eval| {
-                    eval.set_slot_local(slot, value);
-                    Ok(())
-                },
-                Slot::Module(slot) => box move |value, eval| {
-                    // Make sure that `ComplexValue`s get their name as soon as possible
-                    value.export_as(&ident.node, eval);
-                    eval.set_slot_module(slot, value);
-                    Ok(())
-                },
+                Slot::Local(slot) => self.bc.emit(Instr::StoreLocal(slot), span),
+                Slot::Module(slot) => {
+                    let name = self.bc.string(ident.node);
+                    self.bc.emit(Instr::StoreModuleExport(slot, name), span)
+                }
             },
         }
     }
 
-    fn assign_modify(
-        &mut self,
-        span_stmt: Span,
-        lhs: AstAssign,
-        rhs: ExprCompiledValue,
-        op: for<'v> fn(Value<'v>, Value<'v>, &mut Evaluator<'v, '_>) -> anyhow::Result<Value<'v>>,
-    ) -> StmtCompiled {
+    /// Emit `lhs op= rhs`, where `op` is the binary operator instruction.
+    fn assign_modify(&mut self, span_stmt: Span, lhs: AstAssign, rhs: AstExpr, op: Instr) {
         let span_lhs = lhs.span;
         match lhs.node {
             Assign::Dot(e, s) => {
-                let e = self.expr(*e).as_compiled();
-                let s = s.node;
-                let rhs = rhs.as_compiled();
-                stmt!("assign_dot", span_stmt, |eval| {
-                    let e: Value = e(eval)?;
-                    let (_, v) = throw(e.get_attr_error(&s, eval.heap()), span_lhs, eval)?;
-                    let rhs = rhs(eval)?;
-                    throw(
-                        e.set_attr(&s, throw(op(v, rhs, eval), span_stmt, eval)?),
-                        span_stmt,
-                        eval,
-                    )?;
-                })
+                self.expr(*e);
+                let s = self.bc.string(s.node);
+                self.bc.emit(Instr::Dup, span_lhs);
+                self.bc.emit(Instr::GetAttrRaw(s), span_lhs);
+                self.expr(rhs);
+                self.bc.emit(op, span_stmt);
+                self.bc.emit(Instr::Swap, span_stmt);
+                self.bc.emit(Instr::SetAttr(s), span_stmt);
             }
             Assign::ArrayIndirection(box (e, idx)) => {
-                let e = self.expr(e).as_compiled();
-                let idx = self.expr(idx).as_compiled();
-                let rhs = rhs.as_compiled();
-                stmt!("assign_array", span_stmt, |eval| {
-                    let e: Value = e(eval)?;
-                    let idx = idx(eval)?;
-                    let v = throw(e.at(idx, eval.heap()), span_lhs, eval)?;
-                    let rhs = rhs(eval)?;
-                    throw(
-                        e.set_at(idx, throw(op(v, rhs, eval), span_stmt, eval)?),
-                        span_stmt,
-                        eval,
-                    )?;
-                })
+                self.expr(e);
+                self.expr(idx);
+                self.bc.emit(Instr::Dup2, span_lhs);
+                self.bc.emit(Instr::Index, span_lhs);
+                self.expr(rhs);
+                self.bc.emit(op, span_stmt);
+                self.bc.emit(Instr::Rot3, span_stmt);
+                self.bc.emit(Instr::SetIndex, span_stmt);
             }
-            Assign::Identifier(ident) => {
-                let name = ident.node;
-                match self.scope.get_name_or_panic(&name) {
-                    Slot::Local(slot) => {
-                        let rhs = rhs.as_compiled();
-                        stmt!("assign_local", span_stmt, |eval| {
-                            let v = throw(eval.get_slot_local(slot, &name), span_lhs, eval)?;
-                            let rhs = rhs(eval)?;
-                            let v = throw(op(v, rhs, eval), span_stmt, eval)?;
-                            eval.set_slot_local(slot, v);
-                        })
-                    }
-                    Slot::Module(slot) => {
-                        let rhs = rhs.as_compiled();
-                        stmt!("assign_module", span_stmt, |eval| {
-                            let v = throw(eval.get_slot_module(slot), span_lhs, eval)?;
-                            let rhs = rhs(eval)?;
-                            let v = throw(op(v, rhs, eval), span_stmt, eval)?;
-                            eval.set_slot_module(slot, v);
-                        })
-                    }
+            Assign::Identifier(ident) => match self.scope.get_name_or_panic(&ident.node) {
+                Slot::Local(slot) => {
+                    let name = self.bc.string(ident.node);
+                    self.bc.emit(Instr::LoadLocal(slot, name), span_lhs);
+                    self.expr(rhs);
+                    self.bc.emit(op, span_stmt);
+                    self.bc.emit(Instr::StoreLocal(slot), span_stmt);
                 }
-            }
+                Slot::Module(slot) => {
+                    self.bc.emit(Instr::LoadModule(slot), span_lhs);
+                    self.expr(rhs);
+                    self.bc.emit(op, span_stmt);
+                    self.bc.emit(Instr::StoreModule(slot), span_stmt);
+                }
+            },
             Assign::Tuple(_) => {
                 unreachable!("Assign modify validates that the LHS is never a tuple")
             }
@@ -183,120 +185,6 @@ impl Compiler<'_> {
     }
 }
 
-// This function should be called before every meaningful statement.
-// The purposes are GC, profiling and debugging.
-fn before_stmt(span: Span, eval: &mut Evaluator) {
-    // In all the high-performance use cases we don't have any `before_stmt` things set,
-    // so ensure the check gets inlined but the operation doesn't.
-    #[inline(never)]
-    fn have_stmt(span: Span, eval: &mut Evaluator) {
-        // The user could inject more before_stmt values during iteration (although that sounds like a bad plan!)
-        // so grab the values at the start, and add any additional at the end.
-        let fs = mem::take(&mut eval.before_stmt);
-        for f in &fs {
-            f(span, eval)
-        }
-        let added = mem::replace(&mut eval.before_stmt, fs);
-        for x in added {
-            eval.before_stmt.push(x)
-        }
-    }
-
-    // Almost always will be empty, especially in high-perf use cases
-    if !eval.before_stmt.is_empty() {
-        have_stmt(span, eval)
-    }
-}
-
-// There are two requirements to perform a GC:
-//
-// 1. We can't be profiling, since profiling relies on the redundant heap
-//    entries. When profiling we set disable_gc.
-// 2. We must be able to access all roots.
-//
-// We track as many roots as possible, and eventually aim to track them all, but
-// for the moment we're only sure we have all roots when we are in the module
-// evaluation eval. There are three roots we don't yet know about:
-//
-// 1. When evaluating an expression which has multiple subexpressions, e.g. List
-//    we can't GC during that, as we can't see the root of the first list.
-// 2. When evaluating inside a native function, especially if that native
-//    function calls back to a non-native function, e.g. sort with a comparison
-//    function.
-// 3. When iterating we freeze the iteration variable, which means it
-//    can't be moved by a GC. A special type of root.
-//
-// The first issue can be solved by moving to a bytecode interpreter and
-// evaluation stack. The second issue can be solved by disabling GC while in
-// such functions (it's probably rare). The third issue could be solved by
-// making the freeze for iteration a separate flag to the RefCell, at the cost
-// of an extra word in ValueMem. Or we could disable GC while iterating.
-//
-// For the moment we only GC when executing a statement at the root of the
-// module, which we know is safe with respect to all three conditions.
-//
-// We also require that `extra_v` is None, since otherwise the user might have
-// additional values stashed somewhere.
-fn possible_gc(eval: &mut Evaluator) {
-    if !eval.disable_gc
-        && eval.heap().allocated_bytes() >= eval.next_gc_level
-        && eval.extra_v.is_none()
-    {
-        eval.ann("garbage_collection", |eval| {
-            // When we are at a module scope (as checked above) the eval contains
-            // references to all values, so walking covers everything and the unsafe
-            // is satisfied.
-            unsafe {
-                eval.heap().garbage_collect(|tracer| eval.trace(tracer))
-            }
-            eval.next_gc_level = eval.heap().allocated_bytes() + GC_THRESHOLD;
-        })
-    }
-}
-
-/// Implement lhs += rhs, which is special in Starlark, because lists are mutated,
-/// while all other types are not.
-fn add_assign<'v>(lhs: Value<'v>, rhs: Value<'v>, heap: &'v Heap) -> anyhow::Result<Value<'v>> {
-    // Addition of strings is super common, so have a special case
-    if let Some(ls) = lhs.unpack_str() {
-        if let Some(rs) = rhs.unpack_str() {
-            if ls.is_empty() {
-                return Ok(rhs);
-            } else if rs.is_empty() {
-                return Ok(lhs);
-            } else {
-                return Ok(heap.alloc(fast_string::append(ls, rs)));
-            }
-        }
-    }
-
-    // The Starlark spec says list += mutates, while nothing else does.
-    // When mutating, be careful if they alias, so we don't have `lhs`
-    // mutably borrowed when we iterate over `rhs`, as they might alias.
-    // We also have to deal with frozen lists, in case they are
-    // copy-on-write.
-
-    let lhs_aref = lhs.get_aref();
-    let lhs_ty = lhs_aref.as_dyn_any().static_type_of();
-
-    if lhs_ty == TypeId::of::<List>() || lhs_ty == TypeId::of::<FrozenList>() {
-        mem::drop(lhs_aref);
-        // If the value is None, that must mean its a FrozenList, thus turn it into an immutable error
-        let mut list = List::from_value_mut(lhs)?
-            .ok_or_else(|| anyhow!(ControlError::CannotMutateImmutableValue))?;
-        if lhs.ptr_eq(rhs) {
-            list.content.extend_from_within(..);
-        } else {
-            list.content.extend(rhs.iterate(heap)?.iter());
-        }
-        Ok(lhs)
-    } else if let Some(v) = rhs.get_aref().radd(lhs, heap) {
-        v
-    } else {
-        lhs_aref.add(rhs, heap)
-    }
-}
-
 impl Stmt {
     // Collect all the variables that are defined in this scope
     pub(crate) fn collect_defines<'a>(
@@ -339,142 +227,109 @@ impl Stmt {
 }
 
 impl Compiler<'_> {
-    pub(crate) fn stmt(&mut self, stmt: AstStmt, allow_gc: bool) -> StmtCompiled {
-        let is_statements = matches!(&stmt.node, Stmt::Statements(_));
-        let res = self.stmt_direct(stmt, allow_gc);
-        // No point inserting a GC point around statements, since they will contain inner statements we can do
-        if allow_gc && !is_statements {
-            // We could do this more efficiently by fusing the possible_gc
-            // into the inner closure, but no real need - we insert allow_gc fairly rarely
-            box move |eval| {
-                possible_gc(eval);
-                res(eval)
+    /// Emit the code for a statement. If `allow_gc` is set, garbage collection may happen
+    /// between the statements, which is only safe at the top level of a module.
+    pub(crate) fn stmt(&mut self, stmt: AstStmt, allow_gc: bool) {
+        let span = stmt.span;
+        match stmt.node {
+            Stmt::Statements(stmts) => {
+                // No need to do before_stmt on these statements as they are
+                // not meaningful statements
+                for x in Stmt::flatten_statements(stmts) {
+                    self.stmt(x, allow_gc);
+                }
+            }
+            node => {
+                if allow_gc {
+                    self.bc.emit(Instr::PossibleGc, span);
+                }
+                self.bc.emit(Instr::BeforeStmt, span);
+                self.stmt_direct(Spanned { span, node }, allow_gc)
             }
-        } else {
-            res
         }
     }
 
-    fn stmt_direct(&mut self, stmt: AstStmt, allow_gc: bool) -> StmtCompiled {
+    fn stmt_direct(&mut self, stmt: AstStmt, allow_gc: bool) {
         let span = stmt.span;
         match stmt.node {
             Stmt::Def(name, params, return_type, suite) => {
-                let rhs = self
-                    .function(&name.node, params, return_type, *suite)
-                    .as_compiled();
-                let lhs = self.assign(Spanned {
+                self.function(&name.node, params, return_type, *suite);
+                self.assign(Spanned {
                     span: name.span,
                     node: Assign::Identifier(name),
                 });
-                stmt!("define_def", span, |eval| {
-                    lhs(rhs(eval)?, eval)?;
-                })
             }
             Stmt::For(var, box (over, body)) => {
                 let over_span = over.span;
-                let var = self.assign(var);
-                let over = self.expr(over).as_compiled();
-                let st = self.stmt(body, false);
-                stmt!("for", span, |eval| {
-                    let iterable = over(eval)?;
-                    let freeze_for_iteration = iterable.get_aref();
-                    for v in &throw(iterable.iterate(eval.heap()), over_span, eval)? {
-                        var(v, eval)?;
-                        match st(eval) {
-                            Err(EvalException::Break) => break,
-                            Err(EvalException::Continue) => {}
-                            Err(e) => return Err(e),
-                            _ => {}
-                        }
-                    }
-                    mem::drop(freeze_for_iteration);
-                })
+                let start = self.loop_over(over);
+                self.loop_start(start, over_span, var, span);
+                self.stmt(body, false);
+                self.bc.loop_end(span);
             }
             Stmt::Return(Some(e)) => {
-                let e = self.expr(e).as_compiled();
-                stmt!("return_value", span, |eval| {
-                    return Err(EvalException::Return(e(eval)?));
-                })
+                self.expr(e);
+                self.bc.emit(Instr::Return, span);
+            }
+            Stmt::Return(None) => {
+                self.bc.emit(Instr::Const(FrozenValue::new_none()), span);
+                self.bc.emit(Instr::Return, span);
             }
-            Stmt::Return(None) => stmt!("return", span, |eval| {
-                return Err(EvalException::Return(Value::new_none()));
-            }),
             Stmt::If(cond, box then_block) => {
-                let cond = self.expr(cond).as_compiled();
-                let then_block = self.stmt(then_block, allow_gc);
-                stmt!("if_then", span, |eval| if cond(eval)?.to_bool() {
-                    then_block(eval)?
-                })
+                if let Some(cond) = self.expr_known(cond) {
+                    if cond.to_value().to_bool() {
+                        self.stmt(then_block, allow_gc);
+                    } else {
+                        self.dead_code(|me| me.stmt(then_block, allow_gc));
+                    }
+                    return;
+                }
+                let to_end = self.bc.emit_forward(Instr::JumpIfFalse, span);
+                self.stmt(then_block, allow_gc);
+                self.bc.patch(to_end);
             }
             Stmt::IfElse(cond, box (then_block, else_block)) => {
-                let cond = self.expr(cond).as_compiled();
-                let then_block = self.stmt(then_block, allow_gc);
-                let else_block = self.stmt(else_block, allow_gc);
-                stmt!("if_then_else", span, |eval| if cond(eval)?.to_bool() {
-                    then_block(eval)?
-                } else {
-                    else_block(eval)?
-                })
-            }
-            Stmt::Statements(stmts) => {
-                // No need to do before_stmt on these statements as they are
-                // not meaningful statements
-                let stmts = Stmt::flatten_statements(stmts);
-                let mut stmts = stmts.into_map(|x| self.stmt(x, allow_gc));
-                match stmts.len() {
-                    0 => box move |_| Ok(()),
-                    1 => stmts.pop().unwrap(),
-                    _ => box move |eval| {
-                        for stmt in &stmts {
-                            stmt(eval)?;
-                        }
-                        Ok(())
-                    },
+                if let Some(cond) = self.expr_known(cond) {
+                    let (live, dead) = if cond.to_value().to_bool() {
+                        (then_block, else_block)
+                    } else {
+                        (else_block, then_block)
+                    };
+                    self.stmt(live, allow_gc);
+                    self.dead_code(|me| me.stmt(dead, allow_gc));
+                    return;
                 }
+                let to_else = self.bc.emit_forward(Instr::JumpIfFalse, span);
+                self.stmt(then_block, allow_gc);
+                let to_end = self.bc.emit_forward(Instr::Jump, span);
+                self.bc.patch(to_else);
+                self.stmt(else_block, allow_gc);
+                self.bc.patch(to_end);
             }
+            Stmt::Statements(_) => unreachable!("Dealt with by Compiler::stmt"),
             Stmt::Expression(e) => {
-                let e = self.expr(e).as_compiled();
-                stmt!("expression", span, |eval| {
-                    e(eval)?;
-                })
+                self.expr(e);
+                self.bc.emit(Instr::Pop, span);
             }
             Stmt::Assign(lhs, rhs) => {
-                let rhs = self.expr(*rhs).as_compiled();
-                let lhs = self.assign(lhs);
-                stmt!("assign", span, |eval| {
-                    lhs(rhs(eval)?, eval)?;
-                })
+                self.expr(*rhs);
+                self.assign(lhs);
             }
             Stmt::AssignModify(lhs, op, rhs) => {
-                let rhs = self.expr(*rhs);
-                match op {
-                    AssignOp::Add => self
-                        .assign_modify(span, lhs, rhs, |l, r, eval| add_assign(l, r, eval.heap())),
-                    AssignOp::Subtract => {
-                        self.assign_modify(span, lhs, rhs, |l, r, eval| l.sub(r, eval.heap()))
-                    }
-                    AssignOp::Multiply => {
-                        self.assign_modify(span, lhs, rhs, |l, r, eval| l.mul(r, eval.heap()))
-                    }
-                    AssignOp::FloorDivide => {
-                        self.assign_modify(span, lhs, rhs, |l, r, eval| l.floor_div(r, eval.heap()))
-                    }
-                    AssignOp::Percent => {
-                        self.assign_modify(span, lhs, rhs, |l, r, eval| l.percent(r, eval.heap()))
-                    }
-                    AssignOp::BitAnd => self.assign_modify(span, lhs, rhs, |l, r, _| l.bit_and(r)),
-                    AssignOp::BitOr => self.assign_modify(span, lhs, rhs, |l, r, _| l.bit_or(r)),
-                    AssignOp::BitXor => self.assign_modify(span, lhs, rhs, |l, r, _| l.bit_xor(r)),
-                    AssignOp::LeftShift => {
-                        self.assign_modify(span, lhs, rhs, |l, r, _| l.left_shift(r))
-                    }
-                    AssignOp::RightShift => {
-                        self.assign_modify(span, lhs, rhs, |l, r, _| l.right_shift(r))
-                    }
-                }
+                let op = match op {
+                    AssignOp::Add => Instr::AddAssign,
+                    AssignOp::Subtract => Instr::Sub,
+                    AssignOp::Multiply => Instr::Mul,
+                    AssignOp::FloorDivide => Instr::FloorDiv,
+                    AssignOp::Percent => Instr::Percent,
+                    AssignOp::BitAnd => Instr::BitAnd,
+                    AssignOp::BitOr => Instr::BitOr,
+                    AssignOp::BitXor => Instr::BitXor,
+                    AssignOp::LeftShift => Instr::LeftShift,
+                    AssignOp::RightShift => Instr::RightShift,
+                };
+                self.assign_modify(span, lhs, *rhs, op)
             }
             Stmt::Load(name, v, _) => {
-                let name = name.node;
                 let symbols = v.into_map(|(x, y)| {
                     (
                         self.scope.get_name_or_panic(&x.node),
@@ -482,31 +337,15 @@ impl Compiler<'_> {
                         x.span.merge(y.span),
                     )
                 });
-                stmt!("load", span, |eval| {
-                    let loadenv = match eval.loader.as_mut() {
-                        None => {
-                            return Err(EvalException::Error(
-                                EnvironmentError::NoImportsAvailable(name.to_owned()).into(),
-                            ));
-                        }
-                        Some(load) => load.load(&name).map_err(EvalException::Error)?,
-                    };
-                    for (new_name, orig_name, span) in &symbols {
-                        let value = throw(
-                            eval.module_env.load_symbol(&loadenv, orig_name),
-                            *span,
-                            eval,
-                        )?;
-                        match new_name {
-                            Slot::Local(slot) => eval.set_slot_local(*slot, value),
-                            Slot::Module(slot) => eval.set_slot_module(*slot, value),
-                        }
-                    }
-                })
+                let load = LoadCompiled {
+                    name: name.node,
+                    symbols,
+                };
+                self.bc.emit(Instr::Load(box load), span);
             }
-            Stmt::Pass => stmt!("pass", span, |eval| {}),
-            Stmt::Break => stmt!("break", span, |eval| return Err(EvalException::Break)),
-            Stmt::Continue => stmt!("continue", span, |eval| return Err(EvalException::Continue)),
+            Stmt::Pass => {}
+            Stmt::Break => self.bc.emit_break(span),
+            Stmt::Continue => self.bc.emit_continue(Instr::Jump, span),
         }
     }
 }
@@ -19,22 +19,37 @@
 //! [`eval_module`](Evaluator::eval_module).
 
 use crate::{
-    codemap::{Span, Spanned},
-    eval::compiler::{scope::Scope, Compiler, Constants, EvalException},
+    codemap::{CodeMap, Span, Spanned},
+    environment::{Globals, Module},
+    eval::{
+        bc::{
+            instr::{Bc, Instr},
+            writer::BcWriter,
+        },
+        compiler::{scope::Scope, Compiler, Constants},
+    },
     syntax::ast::{AstModule, AstStmt, Expr, Stmt},
-    values::Value,
+    values::{FrozenValue, Value},
 };
 use gazebo::{cast, prelude::*};
 use std::mem;
 
 pub(crate) use compiler::scope::ScopeNames;
-pub(crate) use fragment::def::{Def, FrozenDef};
+pub(crate) use fragment::{
+    def::{Def, DefCompiled, FrozenDef},
+    expr::EvalError,
+    stmt::AssignError,
+};
+pub(crate) use runtime::parameters::FunctionError;
 pub use runtime::{
     evaluator::Evaluator,
     file_loader::{FileLoader, ReturnFileLoader},
+    gc::GcStats,
     parameters::{Parameters, ParametersParser, ParametersSpec, ParametersSpecBuilder},
+    print_handler::{PrintHandler, StderrPrintHandler},
 };
 
+mod bc;
 mod compiler;
 mod fragment;
 mod runtime;
@@ -67,39 +82,63 @@ fn inject_return(x: &mut AstStmt) {
     }
 }
 
+/// Compile the code of a module, adding its variables to `module`, returning the
+/// compiled code and the number of local variables it needs.
+fn compile_module(
+    module: &Module,
+    mut statement: AstStmt,
+    codemap: &CodeMap,
+    globals: &Globals,
+    optimise: bool,
+) -> anyhow::Result<(Bc, usize)> {
+    inject_return(&mut statement);
+
+    let scope = Scope::enter_module(module.names(), &statement);
+
+    let span = statement.span;
+
+    let mut compiler = Compiler {
+        scope,
+        heap: module.frozen_heap(),
+        globals,
+        errors: Vec::new(),
+        codemap: codemap.dupe(),
+        constants: Constants::new(),
+        optimise,
+        bc: BcWriter::new(),
+    };
+    compiler.stmt(statement, true);
+    compiler
+        .bc
+        .emit(Instr::Const(FrozenValue::new_none()), span);
+    compiler.bc.emit(Instr::Return, span);
+
+    // We want to grab the first error only, with ownership, so drop all but the first
+    compiler.errors.truncate(1);
+    if let Some(e) = compiler.errors.pop() {
+        // Static errors, reported even if the branch is not hit
+        return Err(e);
+    }
+
+    let bc = compiler.bc.finish();
+    let (module_slots, local_slots) = compiler.scope.exit_module();
+    module.slots().ensure_slots(module_slots);
+    Ok((bc, local_slots))
+}
+
 impl<'v, 'a> Evaluator<'v, 'a> {
     /// Evaluate an [`AstModule`] with this [`Evaluator`], modifying the in-scope
     /// [`Module`](crate::environment::Module) as appropriate.
     pub fn eval_module(&mut self, ast: AstModule) -> anyhow::Result<Value<'v>> {
-        let AstModule {
-            codemap,
-            mut statement,
-        } = ast;
-        inject_return(&mut statement);
-
-        let scope = Scope::enter_module(self.module_env.names(), &statement);
-
+        let AstModule { codemap, statement } = ast;
         let span = statement.span;
-
-        let mut compiler = Compiler {
-            scope,
-            heap: self.module_env.frozen_heap(),
-            globals: self.globals,
-            errors: Vec::new(),
-            codemap: codemap.dupe(),
-            constants: Constants::new(),
-        };
-        let stmt = compiler.stmt(statement, true);
-
-        // We want to grab the first error only, with ownership, so drop all but the first
-        compiler.errors.truncate(1);
-        if let Some(e) = compiler.errors.pop() {
-            // Static errors, reported even if the branch is not hit
-            return Err(e);
-        }
-
-        let (module_slots, local_slots) = compiler.scope.exit_module();
-        self.module_env.slots().ensure_slots(module_slots);
+        let (bc, local_slots) = compile_module(
+            self.module_env,
+            statement,
+            &codemap,
+            self.globals,
+            !self.disable_optimisation,
+        )?;
         let new_locals = self.local_variables.reserve(local_slots);
         let old_locals = self.local_variables.utilise(new_locals);
 
@@ -118,7 +157,7 @@ impl<'v, 'a> Evaluator<'v, 'a> {
         }
 
         // Evaluation
-        let res = stmt(self);
+        let res = bc.run(self);
 
         // Clean up the world, putting everything back
         self.call_stack.pop();
@@ -129,11 +168,7 @@ impl<'v, 'a> Evaluator<'v, 'a> {
         self.local_variables.release(old_locals);
 
         // Return the result of evaluation
-        match res {
-            Ok(_) => Ok(Value::new_none()),
-            Err(EvalException::Return(x)) => Ok(x),
-            Err(e) => Err(e.into()),
-        }
+        res
     }
 
     /// Evaluate a function stored in a [`Value`], passing in `positional` and `named` arguments.
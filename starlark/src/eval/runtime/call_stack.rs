@@ -71,38 +71,32 @@ impl Debug for CheapFrame<'_> {
 /// Starlark call stack.
 #[derive(Debug)]
 pub(crate) struct CallStack<'v> {
-    count: usize,
-    stack: [CheapFrame<'v>; MAX_CALLSTACK_RECURSION],
+    stack: Vec<CheapFrame<'v>>,
+    /// The maximum number of frames, after which we fail with `TooManyRecursionLevel`.
+    max_size: usize,
+    /// Are `def` functions forbidden from calling themselves, directly or indirectly.
+    no_recursion: bool,
 }
 
 impl<'v> Default for CallStack<'v> {
     fn default() -> Self {
         Self {
-            count: 0,
-            stack: [CheapFrame {
-                function: Value::new_none(),
-                file: None,
-                span: Span::default(),
-            }; MAX_CALLSTACK_RECURSION],
+            stack: Vec::with_capacity(DEFAULT_MAX_CALLSTACK_SIZE),
+            max_size: DEFAULT_MAX_CALLSTACK_SIZE,
+            no_recursion: false,
         }
     }
 }
 
 // At 50 we see the C stack overflowing, so limit to 40 (which seems quite
 // low...)
-const MAX_CALLSTACK_RECURSION: usize = 40;
+const DEFAULT_MAX_CALLSTACK_SIZE: usize = 40;
 
 unsafe impl<'v> Trace<'v> for CallStack<'v> {
     fn trace(&mut self, tracer: &Tracer<'v>) {
-        for x in self.stack[0..self.count].iter_mut() {
+        for x in self.stack.iter_mut() {
             x.function.trace(tracer);
         }
-        // Not required, but since we are chosing not to walk those above
-        // the current stack depth, it's good practice to blank those values out
-        for x in self.stack[self.count..].iter_mut() {
-            x.function = Value::new_none();
-            x.file = None;
-        }
     }
 }
 
@@ -115,43 +109,71 @@ impl<'v> CallStack<'v> {
         span: Span,
         file: Option<&'v CodeMap>,
     ) -> anyhow::Result<()> {
-        if self.count >= MAX_CALLSTACK_RECURSION {
+        if self.stack.len() >= self.max_size {
             return Err(ControlError::TooManyRecursionLevel.into());
         }
-        self.stack[self.count] = CheapFrame {
+        self.stack.push(CheapFrame {
             function,
             file,
             span,
-        };
-        self.count += 1;
+        });
         Ok(())
     }
 
     /// Remove the top element from the stack. Called after `push`.
     pub(crate) fn pop(&mut self) {
-        debug_assert!(self.count >= 1);
-        // We could clear the elements, but don't need to bother
-        self.count -= 1;
+        debug_assert!(!self.stack.is_empty());
+        self.stack.pop();
+    }
+
+    pub(crate) fn set_max_size(&mut self, max_size: usize) {
+        self.max_size = max_size;
+    }
+
+    pub(crate) fn disable_recursion(&mut self) {
+        self.no_recursion = true;
+    }
+
+    /// If recursion is disabled, check that `function` is not already on the stack.
+    /// The error describes the cycle, from the earlier call of `function` to this one.
+    #[inline(always)]
+    pub(crate) fn check_recursion(&self, function: Value<'v>) -> anyhow::Result<()> {
+        #[inline(never)]
+        fn check<'v>(stack: &[CheapFrame<'v>], function: Value<'v>) -> anyhow::Result<()> {
+            match stack.iter().rposition(|x| x.function.ptr_eq(function)) {
+                None => Ok(()),
+                Some(i) => {
+                    let mut cycle = stack[i..].map(|x| x.function.to_repr());
+                    cycle.push(function.to_repr());
+                    Err(ControlError::RecursiveCall(cycle.join(" -> ")).into())
+                }
+            }
+        }
+
+        if self.no_recursion {
+            check(&self.stack, function)
+        } else {
+            Ok(())
+        }
     }
 
     /// The location at the top of the stack. May be `None` if
     /// either there the stack is empty, or the top of the stack lacks location
     /// information (e.g. called from Rust).
     pub fn top_location(&self) -> Option<FileSpan> {
-        if self.count == 0 {
-            None
-        } else {
-            self.stack[self.count - 1].location()
-        }
+        self.stack.last().and_then(CheapFrame::location)
     }
 
     pub fn to_diagnostic_frames(&self) -> Vec<Frame> {
         // The first entry is just the entire module, so skip it
-        self.stack[1..self.count].map(CheapFrame::to_frame)
+        self.stack
+            .get(1..)
+            .unwrap_or_default()
+            .map(CheapFrame::to_frame)
     }
 
     /// List the entries on the stack as values
     pub(crate) fn to_function_values(&self) -> Vec<Value<'v>> {
-        self.stack[1..self.count].map(|x| x.function)
+        self.stack.get(1..).unwrap_or_default().map(|x| x.function)
     }
 }
@@ -21,10 +21,12 @@ use crate::{
     environment::{
         slots::ModuleSlotId, EnvironmentError, FrozenModuleData, FrozenModuleValue, Globals, Module,
     },
-    errors::{Diagnostic, Frame},
+    errors::{Diagnostic, Frame, Warning},
     eval::{
         runtime::{
             call_stack::CallStack,
+            gc::{GcStats, GC_THRESHOLD},
+            print_handler::{PrintHandler, StderrPrintHandler},
             slots::{LocalSlotId, LocalSlots},
             stmt_profile::StmtProfile,
         },
@@ -48,9 +50,6 @@ enum EvaluatorError {
     StmtProfilingNotEnabled,
 }
 
-/// Number of bytes to allocate between GC's.
-pub(crate) const GC_THRESHOLD: usize = 100000;
-
 /// Holds everything about an ongoing evaluation (local variables, globals, module resolution etc).
 pub struct Evaluator<'v, 'a> {
     // The module that is being used for this evaluation
@@ -71,8 +70,16 @@ pub struct Evaluator<'v, 'a> {
     pub(crate) profiling: bool,
     // Is GC disabled for some reason
     pub(crate) disable_gc: bool,
+    // Should the compiler leave the code as written, rather than optimising it
+    pub(crate) disable_optimisation: bool,
     // Size of the heap when we should next perform a GC.
     pub(crate) next_gc_level: usize,
+    // What garbage collection has cost so far.
+    pub(crate) gc_stats: GcStats,
+    // Warnings raised so far, waiting to be collected by `take_warnings`.
+    pub(crate) warnings: Vec<Warning>,
+    // Where the output of `print` goes.
+    pub(crate) print_handler: &'a dyn PrintHandler,
     // Extra functions to run on each statement, usually empty
     pub(crate) before_stmt: Vec<&'a dyn Fn(Span, &mut Evaluator<'v, 'a>)>,
     // Used for line profiling
@@ -86,7 +93,6 @@ pub struct Evaluator<'v, 'a> {
     /// If this value is used, garbage collection is disabled.
     pub extra_v: Option<&'a dyn AnyLifetime<'v>>,
     // The Starlark-level call-stack of functions.
-    // Must go last because it's quite a big structure
     pub(crate) call_stack: CallStack<'v>,
 }
 
@@ -122,11 +128,15 @@ impl<'v, 'a> Evaluator<'v, 'a> {
             extra: None,
             extra_v: None,
             next_gc_level: GC_THRESHOLD,
+            gc_stats: GcStats::default(),
             disable_gc: false,
+            disable_optimisation: false,
             alloca: Alloca::new(),
             profiling: false,
             stmt_profile: StmtProfile::new(),
             before_stmt: Vec::new(),
+            print_handler: &StderrPrintHandler,
+            warnings: Vec::new(),
         }
     }
 
@@ -137,6 +147,34 @@ impl<'v, 'a> Evaluator<'v, 'a> {
         self.disable_gc = true;
     }
 
+    /// Statistics about the garbage collections so far, which happen between the
+    /// top-level statements of a module, once enough has been allocated since the last.
+    pub fn gc_stats(&self) -> &GcStats {
+        &self.gc_stats
+    }
+
+    /// Compile code as it is written, without evaluating constant expressions in advance,
+    /// removing branches that can't be taken or inlining simple functions. The results
+    /// are the same, but the code runs closer to the source, which can help when debugging.
+    /// Affects code evaluated from now onwards.
+    pub fn disable_optimisation(&mut self) {
+        self.disable_optimisation = true;
+    }
+
+    /// Forbid functions defined with `def` or `lambda` from calling themselves, either
+    /// directly or via other functions, as required by the Starlark standard.
+    /// Cannot be re-enabled.
+    pub fn disable_recursion(&mut self) {
+        self.call_stack.disable_recursion();
+    }
+
+    /// Set the maximum depth of nested function calls, after which evaluation fails.
+    /// The default is 40. Large values may cause the native stack to overflow,
+    /// in which case run the evaluator on a thread with a larger stack.
+    pub fn set_max_callstack_size(&mut self, max_size: usize) {
+        self.call_stack.set_max_size(max_size);
+    }
+
     /// Set the [`FileLoader`] used to resolve `load()` statements.
     /// A list of all load statements can be obtained through
     /// [`AstModule::loads`](crate::syntax::AstModule::loads).
@@ -144,6 +182,12 @@ impl<'v, 'a> Evaluator<'v, 'a> {
         self.loader = Some(loader);
     }
 
+    /// Set the [`PrintHandler`] used to deal with the output of `print`.
+    /// If not set, output is written to stderr.
+    pub fn set_print_handler(&mut self, handler: &'a dyn PrintHandler) {
+        self.print_handler = handler;
+    }
+
     /// Enable profiling, allowing [`Evaluator::write_profile`] to be used.
     /// Has the side effect of disabling garbage-collection.
     ///
@@ -203,6 +247,23 @@ impl<'v, 'a> Evaluator<'v, 'a> {
         self.call_stack.top_location()
     }
 
+    /// Record a [`Warning`], attributed to the location at the top of the call-stack.
+    /// This is the function used by `warn()`, but can also be called by your own native functions.
+    pub fn add_warning(&mut self, message: String) {
+        let warning = Warning {
+            message,
+            span: self.call_stack_top_location(),
+            call_stack: self.call_stack(),
+        };
+        self.warnings.push(warning);
+    }
+
+    /// Remove and return all the [`Warning`]s raised so far, in the order they were raised.
+    /// Usually called after [`eval_module`](Evaluator::eval_module).
+    pub fn take_warnings(&mut self) -> Vec<Warning> {
+        mem::take(&mut self.warnings)
+    }
+
     /// Called before every statement is run with the [`Span`] and a reference to the containing [`Evaluator`].
     /// A list of all possible statements can be obtained in advance by
     /// [`AstModule::stmt_locations`](crate::syntax::AstModule::stmt_locations).
@@ -0,0 +1,84 @@
+/*
+ * Copyright 2019 The Starlark in Rust Authors.
+ * Copyright (c) Facebook, Inc. and its affiliates.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! When to collect garbage, and what collecting has cost so far.
+//!
+//! A collection copies everything that survives it into a new heap, so its cost is
+//! the size of the live data. If we collected after a fixed number of bytes, a large
+//! long-lived value, e.g. a big dict built at the start of a module, would be copied
+//! again every time, however little garbage there was. Instead we wait until the
+//! heap has grown by at least as much as survived the last collection, so each byte
+//! allocated pays for at most a fixed amount of copying, and wait twice as long when
+//! most of the heap survived, as collecting then frees little.
+
+use std::time::Duration;
+
+/// The fewest bytes to allocate between collections.
+pub(crate) const GC_THRESHOLD: usize = 100000;
+
+/// How many bytes to allocate before the next collection, given the size of the heap
+/// before and after the last one.
+pub(crate) fn next_gc_gap(before: usize, after: usize) -> usize {
+    let gap = if after * 2 > before { after * 2 } else { after };
+    gap.max(GC_THRESHOLD)
+}
+
+/// Statistics about the garbage collections performed by an
+/// [`Evaluator`](crate::eval::Evaluator), as returned by
+/// [`gc_stats`](crate::eval::Evaluator::gc_stats).
+/// Sizes are those of the heap's own storage, not including what values point to
+/// outside it, such as the contents of lists.
+#[derive(Debug, Clone, Default, PartialEq)]
+#[non_exhaustive]
+pub struct GcStats {
+    /// The number of collections.
+    pub collections: usize,
+    /// Total bytes copied, which is the total surviving collections.
+    pub bytes_copied: usize,
+    /// Total bytes freed.
+    pub bytes_freed: usize,
+    /// Total time spent collecting.
+    pub pause: Duration,
+    /// The time taken by the longest collection.
+    pub max_pause: Duration,
+}
+
+impl GcStats {
+    /// Record a collection which shrank the heap from `before` bytes to `after`.
+    pub(crate) fn record(&mut self, before: usize, after: usize, pause: Duration) {
+        self.collections += 1;
+        self.bytes_copied += after;
+        self.bytes_freed += before.saturating_sub(after);
+        self.pause += pause;
+        self.max_pause = self.max_pause.max(pause);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_next_gc_gap() {
+        // Small heaps keep the minimum
+        assert_eq!(next_gc_gap(150000, 1000), GC_THRESHOLD);
+        // Mostly garbage, so wait for as much as survived
+        assert_eq!(next_gc_gap(1000000, 300000), 300000);
+        // Mostly live, so wait longer
+        assert_eq!(next_gc_gap(1000000, 800000), 1600000);
+    }
+}
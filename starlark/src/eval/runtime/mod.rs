@@ -18,6 +18,8 @@
 pub(crate) mod call_stack;
 pub(crate) mod evaluator;
 pub(crate) mod file_loader;
+pub(crate) mod gc;
 pub(crate) mod parameters;
+pub(crate) mod print_handler;
 pub(crate) mod slots;
 pub(crate) mod stmt_profile;
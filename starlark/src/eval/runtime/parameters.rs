@@ -22,7 +22,7 @@ use crate::{
     collections::{BorrowHashed, Hashed, SmallMap},
     eval::{runtime::slots::LocalSlotBase, Evaluator},
     values::{
-        dict::Dict, tuple::Tuple, Freezer, FrozenValue, Trace, Tracer, UnpackValue, Value,
+        dict::Dict, tuple::Tuple, Freeze, Freezer, FrozenValue, Trace, Tracer, UnpackValue, Value,
         ValueError,
     },
 };
@@ -41,6 +41,11 @@ pub(crate) enum FunctionError {
         names: Vec<String>,
         function: String,
     },
+    #[error("Found positional-only parameter(s) {} passed by name for call to {function}", .names.join(" "))]
+    PositionalOnlyPassedByName {
+        names: Vec<String>,
+        function: String,
+    },
     #[error("Parameter `{name}` occurs both explicitly and in **kwargs")]
     RepeatedParameter { name: String },
     #[error("The argument provided for *args is not an identifier")]
@@ -98,6 +103,9 @@ pub struct ParametersSpecRaw<V> {
     /// Number of arguments that can be filled positionally.
     /// Excludes *args/**kwargs, keyword arguments after *args
     positional: usize,
+    /// Number of arguments that can only be filled positionally, those before a `/`.
+    /// Native functions instead mark these arguments with a `$` prefix on the name.
+    positional_only: usize,
 
     /// Has the no_args been passed
     no_args: bool,
@@ -130,6 +138,7 @@ impl<V> ParametersSpecBuilder<V> {
             kinds: Vec::with_capacity(capacity),
             names: SmallMap::with_capacity(capacity),
             positional: 0,
+            positional_only: 0,
             no_args: false,
             args: None,
             kwargs: None,
@@ -185,6 +194,14 @@ impl<V> ParametersSpecBuilder<V> {
         self.0.args = Some(self.0.kinds.len() - 1);
     }
 
+    /// All the parameters added so far can _only_ be supplied by position,
+    /// corresponds to the Python parameter `/`.
+    /// If supplied by name, they are instead collected into `**kwargs`, or are an error.
+    pub fn end_positional_only(&mut self) {
+        assert!(self.0.args.is_none() && !self.0.no_args && self.0.kwargs.is_none());
+        self.0.positional_only = self.0.kinds.len();
+    }
+
     /// This function has no `*args` parameter, corresponds to the Python parameter `*`.
     /// After this call, any subsequent [`required`](ParametersSpecBuilder::required),
     /// [`optional`](ParametersSpecBuilder::optional) or [`defaulted`](ParametersSpecBuilder::defaulted)
@@ -218,6 +235,20 @@ impl<V> ParametersSpec<V> {
         collector
     }
 
+    /// The default values of the parameters which have one, in order.
+    pub(crate) fn defaults(&self) -> impl Iterator<Item = &V> {
+        self.0.kinds.iter().filter_map(|x| match x {
+            ParameterKind::Defaulted(v) => Some(v),
+            _ => None,
+        })
+    }
+
+    /// Do exactly `n` positional arguments fill every parameter, so that no defaults,
+    /// `*args` or `**kwargs` are involved.
+    pub(crate) fn fills_positionally(&self, n: usize) -> bool {
+        n == self.0.positional && n == self.0.kinds.len()
+    }
+
     /// Figure out the argument name at an index in kinds.
     /// Only called in the error path, so is not optimised.
     pub(crate) fn param_name_at(&self, index: usize) -> String {
@@ -255,6 +286,9 @@ impl<V> ParametersSpec<V> {
         for (i, typ) in self.0.kinds.iter().enumerate() {
             if i != 0 {
                 collector.push_str(", ");
+                if i == self.0.positional_only {
+                    collector.push_str("/, ");
+                }
             }
             match typ {
                 ParameterKind::Required => collector.push_str(next_name()),
@@ -266,6 +300,9 @@ impl<V> ParametersSpec<V> {
                 ParameterKind::KWargs => collector.push_str("**kwargs"),
             }
         }
+        if self.0.positional_only != 0 && self.0.positional_only == self.0.kinds.len() {
+            collector.push_str(", /");
+        }
         collector.push(')');
     }
 }
@@ -345,13 +382,13 @@ impl<'v> ParametersSpec<Value<'v>> {
                 // Safe to use new_unchecked because hash for the Value and str are the same
                 let name_hash = BorrowHashed::new_unchecked(name_value.hash(), name);
                 match self.0.names.get_hashed(name_hash) {
-                    None => {
-                        add_kwargs(&mut kwargs, *name_value, *v);
-                    }
-                    Some(i) => {
+                    Some(i) if *i >= self.0.positional_only => {
                         slots[*i].set_direct(*v);
                         lowest_name = cmp::min(lowest_name, *i);
                     }
+                    _ => {
+                        add_kwargs(&mut kwargs, *name_value, *v);
+                    }
                 }
             }
         }
@@ -391,13 +428,13 @@ impl<'v> ParametersSpec<Value<'v>> {
                             Some(s) => {
                                 let name_hash = BorrowHashed::new_unchecked(k.hash(), s);
                                 let repeat = match self.0.names.get_hashed(name_hash) {
-                                    None => add_kwargs(&mut kwargs, k, v),
-                                    Some(i) => {
+                                    Some(i) if *i >= self.0.positional_only => {
                                         let this_slot = &slots[*i];
                                         let repeat = this_slot.get_direct().is_some();
                                         this_slot.set_direct(v);
                                         repeat
                                     }
+                                    _ => add_kwargs(&mut kwargs, k, v),
                                 };
                                 if repeat {
                                     return Err(FunctionError::RepeatedParameter {
@@ -459,6 +496,20 @@ impl<'v> ParametersSpec<Value<'v>> {
             let kwargs = kwargs.take().unwrap_or_default();
             slots[kwargs_pos].set_direct(eval.heap().alloc_complex(*kwargs));
         } else if let Some(kwargs) = kwargs {
+            let positional_only: Vec<String> = kwargs
+                .keys()
+                .into_iter()
+                .filter_map(|x| x.unpack_str())
+                .filter(|x| matches!(self.0.names.get(*x), Some(i) if *i < self.0.positional_only))
+                .map(|x| x.to_owned())
+                .collect();
+            if !positional_only.is_empty() {
+                return Err(FunctionError::PositionalOnlyPassedByName {
+                    names: positional_only,
+                    function: self.signature(),
+                }
+                .into());
+            }
             return Err(FunctionError::ExtraNamedParameters {
                 names: kwargs.keys().map(|x| x.to_str()),
                 function: self.signature(),
@@ -492,6 +543,7 @@ impl<'v> ParametersSpec<Value<'v>> {
             kinds: self.0.kinds.try_map(|v| v.freeze(freezer))?,
             names: self.0.names,
             positional: self.0.positional,
+            positional_only: self.0.positional_only,
             no_args: self.0.no_args,
             args: self.0.args,
             kwargs: self.0.kwargs,
@@ -499,6 +551,14 @@ impl<'v> ParametersSpec<Value<'v>> {
     }
 }
 
+impl<'v> Freeze for ParametersSpec<Value<'v>> {
+    type Frozen = ParametersSpec<FrozenValue>;
+
+    fn freeze(self, freezer: &Freezer) -> anyhow::Result<ParametersSpec<FrozenValue>> {
+        ParametersSpec::<Value<'v>>::freeze(self, freezer)
+    }
+}
+
 /// Parse a series of parameters which were specified by [`ParametersSpec`].
 pub struct ParametersParser {
     base: LocalSlotBase,
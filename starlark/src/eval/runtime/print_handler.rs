@@ -0,0 +1,41 @@
+/*
+ * Copyright 2019 The Starlark in Rust Authors.
+ * Copyright (c) Facebook, Inc. and its affiliates.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Control where the output of the `print` function goes.
+
+use crate::codemap::FileSpan;
+
+/// A trait for deciding what to do with the output of the `print` function.
+/// Set on an [`Evaluator`](crate::eval::Evaluator) with
+/// [`set_print_handler`](crate::eval::Evaluator::set_print_handler).
+/// The default handler is [`StderrPrintHandler`].
+pub trait PrintHandler {
+    /// Called with the rendered message (the arguments to `print` joined with spaces)
+    /// and the location of the `print` call, if known. Returning an error will
+    /// cause the `print` call to fail.
+    fn println(&self, text: &str, location: Option<FileSpan>) -> anyhow::Result<()>;
+}
+
+/// [`PrintHandler`] that writes each message to stderr, on its own line.
+pub struct StderrPrintHandler;
+
+impl PrintHandler for StderrPrintHandler {
+    fn println(&self, text: &str, _location: Option<FileSpan>) -> anyhow::Result<()> {
+        eprintln!("{}", text);
+        Ok(())
+    }
+}
@@ -26,6 +26,10 @@ impl LocalSlotId {
     pub fn new(index: usize) -> Self {
         Self(index)
     }
+
+    pub(crate) fn index(self) -> usize {
+        self.0
+    }
 }
 
 #[derive(Clone, Copy, Dupe, Debug, PartialEq, Eq)]
@@ -24,11 +24,12 @@ use crate::{
     eval::Evaluator,
     syntax::{AstModule, Dialect},
     values::{
-        any::StarlarkAny, none::NoneType, ComplexValue, Freezer, Heap, OwnedFrozenValue,
-        SimpleValue, StarlarkValue, Trace, UnpackValue, Value, ValueLike,
+        any::StarlarkAny, none::NoneType, set_string_indexing, ComplexValue, Freeze, Freezer, Heap,
+        OwnedFrozenValue, SimpleValue, StarlarkAttrs, StarlarkValue, StringIndexing, Trace,
+        UnpackValue, Value, ValueLike,
     },
 };
-use gazebo::any::AnyLifetime;
+use gazebo::{any::AnyLifetime, prelude::*};
 use itertools::Itertools;
 use once_cell::sync::Lazy;
 use std::{
@@ -252,6 +253,45 @@ fn test_tabs_pass() {
     a.pass("def f():\n x\t=3");
 }
 
+#[test]
+fn test_f_strings() {
+    assert::pass(
+        r#"
+name = "starlark"
+version = 3
+d = {"a": [1, 2]}
+assert_eq(f"{name}-{version}", "starlark-3")
+assert_eq(f'{name!r}', '"starlark"')
+assert_eq(f"{version:>4}|{version:<4}|{version:04}", "   3|3   |0003")
+assert_eq(f"{name!r:>12}", '  "starlark"')
+assert_eq(f"{version * 2 + 1:x}", "7")
+assert_eq(f"{d['a'][1]} {d}", '2 {"a": [1, 2]}')
+assert_eq(f"{{}} {{{version}}}", "{} {3}")
+assert_eq(f"{ [x for x in range(3)] }", "[0, 1, 2]")
+assert_eq(f"{version != 3}", "False")
+assert_eq(f"a\tb{1}", "a	b1")
+assert_eq(rf"a\tb{1}", "a\\tb1")
+assert_eq(f"""{name}
+{version}""", "starlark\n3")
+assert_eq(f"plain", "plain")
+assert_eq(f"{version:>{len(name)}}", "       3")
+assert_eq(f"{name!r:{'*'}^{version * 4}.{version + 2}}", '***"star****')
+assert_eq(f"{version:{''}}", "3")
+def g(x):
+    return f"<{x}>"
+assert_eq(g(1), "<1>")
+"#,
+    );
+    assert::fail("f'{undefined}'", "Variable `undefined` not found");
+    assert::fail("f'{1:q}'", "Unknown format code 'q'");
+    assert::fail("f'{1:x<<}'", "at position");
+    assert::fail("f'{1:{\"q\"}}'", "Unknown format code 'q'");
+    assert::fail("f'{1:{x}}'", "Variable `x` not found");
+    let mut a = Assert::new();
+    a.dialect(&Dialect::Standard);
+    a.fail("f'{1}'", "f-strings are not allowed in this dialect");
+}
+
 #[test]
 fn test_lambda() {
     assert::is_true("(lambda x: x)(1) == 1");
@@ -518,6 +558,48 @@ def h(a=1, *, b=2):
     );
 }
 
+#[test]
+fn test_positional_only_arguments() {
+    fn f(x: &str) -> String {
+        format!(
+            "
+def f(a, b=2, /, c=3):
+    return [a, b, c]
+def g(a, /, **kwargs):
+    return [a, kwargs]
+{}",
+            x
+        )
+    }
+    assert::is_true(&f("f(1) == [1, 2, 3]"));
+    assert::is_true(&f("f(1, 4, 5) == [1, 4, 5]"));
+    assert::is_true(&f("f(1, c=5) == [1, 2, 5]"));
+    assert::is_true(&f("f(*[1, 4], **{'c': 5}) == [1, 4, 5]"));
+    assert::fail(&f("f(a=1)"), "Missing parameter `a`");
+    assert::fail(
+        &f("f(1, b=4)"),
+        "positional-only parameter(s) b passed by name",
+    );
+    assert::fail(
+        &f("f(1, **{'b': 4})"),
+        "positional-only parameter(s) b passed by name",
+    );
+    assert::fail(&f("f()"), "call to assert.bzl.f(a, b = ..., /, c = ...)");
+    assert::is_true(&f("g(1, a=2) == [1, {'a': 2}]"));
+    assert::is_true(&f("(lambda x, /: x)(3) == 3"));
+    assert::fail(&f("(lambda x, /: x)(x=3)"), "Missing parameter `x`");
+    assert::fail(&f("def bad(/, x):\n  pass"), "Positional-only marker");
+    assert::fail(&f("def bad(x, /, /):\n  pass"), "Positional-only marker");
+    assert::fail(&f("def bad(x, *, y, /):\n  pass"), "Positional-only marker");
+    assert::fail(&f("lambda *x, /: x"), "Positional-only marker");
+    let mut a = Assert::new();
+    a.dialect(&Dialect::Standard);
+    a.fail(
+        "def f(a, /):\n  pass",
+        "positional-only-arguments is not allowed",
+    );
+}
+
 #[test]
 fn test_compiled_literals() {
     assert::is_true(
@@ -1724,6 +1806,26 @@ def expensive(n):
     assert!(!a.pass(&code).unpack_bool().unwrap());
 }
 
+#[test]
+fn test_gc_stats() {
+    let module = Module::new();
+    let globals = Globals::standard();
+    let mut eval = Evaluator::new(&module, &globals);
+    assert_eq!(eval.gc_stats().collections, 0);
+    eval.trigger_gc();
+    let ast = AstModule::parse(
+        "a.star",
+        "x = [str(i) for i in range(1000)]\ny = x\n".to_owned(),
+        &Dialect::Standard,
+    )
+    .unwrap();
+    eval.eval_module(ast).unwrap();
+    // Only the first statement collects, as then the threshold goes back up
+    let stats = eval.gc_stats();
+    assert_eq!(stats.collections, 1);
+    assert!(stats.max_pause <= stats.pause);
+}
+
 #[test]
 fn test_label_assign() {
     // Test the a.b = c construct.
@@ -1797,6 +1899,67 @@ assert_eq(len(count), 1)
     );
 }
 
+#[test]
+fn test_derive_freeze_attrs() {
+    #[derive(Debug, Trace, Freeze, StarlarkAttrs)]
+    struct RuleGen<V> {
+        name: String,
+        deps: V,
+        #[starlark(skip)]
+        #[freeze(identity)]
+        hidden: i32,
+    }
+    starlark_complex_value!(Rule);
+
+    impl<'v, V: ValueLike<'v>> StarlarkValue<'v> for RuleGen<V>
+    where
+        Self: AnyLifetime<'v>,
+    {
+        starlark_type!("rule");
+        starlark_attrs!();
+    }
+
+    impl<'v> ComplexValue<'v> for Rule<'v> {
+        fn freeze(self: Box<Self>, freezer: &Freezer) -> anyhow::Result<Box<dyn SimpleValue>> {
+            Ok(box Freeze::freeze(*self, freezer)?)
+        }
+    }
+
+    #[starlark_module]
+    fn module(builder: &mut GlobalsBuilder) {
+        fn rule(name: &str, ref deps: Value) -> Rule<'v> {
+            Ok(RuleGen {
+                name: name.to_owned(),
+                deps,
+                hidden: 0,
+            })
+        }
+    }
+
+    let mut a = Assert::new();
+    a.globals_add(module);
+    a.module(
+        "rules",
+        "lib = rule('lib', [])\napp = rule('app', [lib, 'x'])",
+    );
+    a.pass(
+        r#"
+load("rules", "app")
+assert_eq(app.name, "app")
+assert_eq(app.deps[0].name, "lib")
+assert_eq(app.deps[1], "x")
+assert_eq(dir(app), ["deps", "name"])
+assert_eq(hasattr(app, "hidden"), False)
+assert_eq(hasattr(rule("local", []), "deps"), True)
+def mutate():
+    local = rule("local", [])
+    local.deps.append(app)
+    return local.deps
+assert_eq(mutate()[0].name, "app")
+"#,
+    );
+}
+
 #[test]
 fn test_joe() {
     // Based on discussions at https://github.com/facebookexperimental/starlark-rust/issues/22
@@ -1919,7 +2082,9 @@ fn test_go() {
         ],
     ));
     // Skip int.star, a lot of bit mask stuff, floats and int's outside our range
-    // Skip list.star, our strings disagree about whether they are lists of codepoints or lists of 1-char strings
+    // Skip list.star and string.star, which aren't mirrored here. They index strings by byte, so
+    // need StringIndexing::Bytes (see test_string_bytes), and any expected values holding part of
+    // a character can't be represented, since our strings are always UTF-8
     assert.conformance_except(
         &ignore_bad_lines(
             test_case!("misc.star"),
@@ -1936,12 +2101,190 @@ fn test_go() {
     // Skip paths.star, a path support library, not tests
     // Skip recursion.star, we don't support `while` loops, which is what this mostly tests
     // Skip set.star, we don't support set
-    // Skip string.star, our String's are fundamentally different
     assert.conformance(&ignore_bad_lines(
         test_case!("tuple.star"),
         &[
             "1000000 * 1000000", // Some tests check that you can't create too large tuples, but that's not principled, so we allow it
-            // But it takes approximately forever, so doing it is a bad idea.
+                                 // But it takes approximately forever, so doing it is a bad idea.
         ],
     ));
 }
+
+#[test]
+fn test_string_conformance() {
+    Assert::new().conformance(include_str!(concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/testcases/eval/string.star"
+    )));
+}
+
+#[test]
+fn test_string_bytes() {
+    set_string_indexing(StringIndexing::Bytes);
+    let a = Assert::new();
+    a.all_true(
+        r#"
+len("Hello, 世界!") == 14
+"Hello, 世界!"[0] == "H"
+"Hello, 世界!"[-1] == "!"
+"Hello, 世界!"[7:10] == "世"
+"Hello, 世界!"[10:] == "界!"
+"Hello, 世界!".find("界") == 10
+"Hello, 世界!".rindex("l") == 3
+"Hello, 世界!".count("l", 0, 3) == 1
+"abc"[::-1] == "cba"
+list("abc".elems()) == ["a", "b", "c"]
+list("é".elem_ords()) == [195, 169]
+list("é".codepoint_ords()) == [233]
+list("aé".codepoints()) == ["a", "é"]
+ord("é") == 233
+"#,
+    );
+    a.fail("'Hello, 世界!'[7]", "would split a character");
+    a.fail("'Hello, 世界!'[7:9]", "would split a character");
+    a.fail("'Hello, 世界!'.find('l', 8)", "would split a character");
+    a.fail("'é'[::-1]", "would split a character");
+    a.fail("'é'.elems()", "would split a character");
+    set_string_indexing(StringIndexing::CodePoints);
+    assert::eq("len('Hello, 世界!')", "10");
+}
+
+#[test]
+fn test_disable_recursion() {
+    fn run(program: &str, setup: impl FnOnce(&mut Evaluator)) -> anyhow::Result<()> {
+        let modu = Module::new();
+        let globals = Globals::extended();
+        let mut eval = Evaluator::new(&modu, &globals);
+        setup(&mut eval);
+        let ast = AstModule::parse("test.star", program.to_owned(), &Dialect::Extended)?;
+        eval.eval_module(ast).map(|_| ())
+    }
+
+    let recursive = "
+def yin(x):
+    if x:
+        yang(x - 1)
+def yang(x):
+    yin(x)
+yin(2)
+";
+    assert!(run(recursive, |_| {}).is_ok());
+    let err = run(recursive, |eval| eval.disable_recursion()).unwrap_err();
+    assert!(
+        err.to_string()
+            .contains("called recursively, which is not allowed: test.star.yin(x) -> test.star.yang(x) -> test.star.yin(x)"),
+        "{}",
+        err
+    );
+    // Functions may call other functions repeatedly, so long as they don't recur.
+    let repeated = "
+def f(x):
+    return x + 1
+def g():
+    return f(f(1)) + len([f(i) for i in range(3)])
+g()
+";
+    run(repeated, |eval| eval.disable_recursion()).unwrap();
+
+    let deep = "
+def f(x):
+    if x:
+        f(x - 1)
+f(30)
+";
+    assert!(run(deep, |_| {}).is_ok());
+    let err = run(deep, |eval| eval.set_max_callstack_size(20)).unwrap_err();
+    assert!(
+        err.to_string().contains("Too many recursion levels"),
+        "{}",
+        err
+    );
+
+    // Comparing nested values has its own limit, which applies to the whole thread.
+    let nested = "
+def nest(n):
+    x = []
+    for _ in range(n):
+        x = [x]
+    return x
+nest(50) == nest(50)
+";
+    assert!(run(nested, |_| {}).is_ok());
+    let err = std::thread::spawn(move || {
+        crate::values::set_max_comparison_depth(20);
+        run(nested, |_| {})
+    })
+    .join()
+    .unwrap()
+    .unwrap_err();
+    assert!(
+        err.to_string().contains("Too many recursion levels"),
+        "{}",
+        err
+    );
+}
+
+#[test]
+fn test_print_handler() -> anyhow::Result<()> {
+    use crate::{codemap::FileSpan, eval::PrintHandler};
+    use std::cell::RefCell;
+
+    struct Capture(RefCell<Vec<String>>);
+
+    impl PrintHandler for Capture {
+        fn println(&self, text: &str, location: Option<FileSpan>) -> anyhow::Result<()> {
+            let location = location.map_or_else(String::new, |x| x.to_string());
+            self.0.borrow_mut().push(format!("{}: {}", location, text));
+            Ok(())
+        }
+    }
+
+    let modu = Module::new();
+    let globals = Globals::extended();
+    let capture = Capture(RefCell::new(Vec::new()));
+    let mut eval = Evaluator::new(&modu, &globals);
+    eval.set_print_handler(&capture);
+    eval.eval_module(AstModule::parse(
+        "test.star",
+        "def f(x):\n    print('in', x)\nprint(1, 'two', [3])\nf(4)".to_owned(),
+        &Dialect::Extended,
+    )?)?;
+    assert_eq!(
+        *capture.0.borrow(),
+        vec![
+            "test.star:3:1-21: 1 two [3]".to_owned(),
+            "test.star:2:5-19: in 4".to_owned(),
+        ]
+    );
+    Ok(())
+}
+
+#[test]
+fn test_warnings() -> anyhow::Result<()> {
+    let modu = Module::new();
+    let globals = Globals::extended();
+    let mut eval = Evaluator::new(&modu, &globals);
+    eval.eval_module(AstModule::parse(
+        "test.star",
+        "def f():\n    warn('deprecated')\nf()\nwarn('top')".to_owned(),
+        &Dialect::Extended,
+    )?)?;
+    let warnings = eval.take_warnings();
+    assert_eq!(
+        warnings.map(|x| (
+            x.message.as_str(),
+            x.span.as_ref().unwrap().to_string(),
+            x.call_stack.map(|x| x.name.as_str()).join(",")
+        )),
+        vec![
+            (
+                "deprecated",
+                "test.star:2:5-23".to_owned(),
+                "test.star.f(),warn(msg)".to_owned()
+            ),
+            ("top", "test.star:4:1-12".to_owned(), "warn(msg)".to_owned()),
+        ]
+    );
+    assert!(eval.take_warnings().is_empty());
+    Ok(())
+}
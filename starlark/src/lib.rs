@@ -284,17 +284,19 @@
 //!
 //! Finally, we can define our own types in Rust which live in the Starlark heap.
 //! Such types are relatively complex, see the details at [`StarlarkValue`](values::StarlarkValue).
+//! Fields can be exposed as attributes with `#[derive(StarlarkAttrs)]`, and types containing
+//! other values can implement freezing with `#[derive(Freeze)]`.
 //!
 //! ```
 //! # fn run() -> anyhow::Result<()> {
 //! use starlark::environment::{Globals, Module};
 //! use starlark::eval::Evaluator;
 //! use starlark::syntax::{AstModule, Dialect};
-//! use starlark::values::{Heap, SimpleValue, StarlarkValue, Value, ValueError};
-//! use starlark::{starlark_type, starlark_simple_value};
+//! use starlark::values::{Heap, SimpleValue, StarlarkAttrs, StarlarkValue, Value, ValueError};
+//! use starlark::{starlark_attrs, starlark_type, starlark_simple_value};
 //!
-//! // Define complex numbers
-//! #[derive(Debug, PartialEq, Eq)]
+//! // Define complex numbers, with their parts as attributes
+//! #[derive(Debug, PartialEq, Eq, StarlarkAttrs)]
 //! struct Complex {
 //!     real: i32,
 //!     imaginary: i32,
@@ -303,6 +305,7 @@
 //!
 //! impl<'v> StarlarkValue<'v> for Complex {
 //!     starlark_type!("complex");
+//!     starlark_attrs!();
 //!
 //!     // How we display them
 //!     fn collect_repr(&self, collector: &mut String) {
@@ -323,7 +326,7 @@
 //!     }
 //! }
 //!
-//! let content = "str(a + b)";
+//! let content = "str(a + b) + ', real part ' + str(b.real)";
 //!
 //! let ast = AstModule::parse("complex.star", content.to_owned(), &Dialect::Standard)?;
 //! let globals = Globals::standard();
@@ -335,7 +338,7 @@
 //! module.set("b", b);
 //! let mut eval = Evaluator::new(&module, &globals);
 //! let res = eval.eval_module(ast)?;
-//! assert_eq!(res.unpack_str(), Some("5 + 10i"));
+//! assert_eq!(res.unpack_str(), Some("5 + 10i, real part 4"));
 //! # Ok(())
 //! # }
 //! # fn main(){ run().unwrap(); }
@@ -389,6 +392,7 @@ pub mod assert;
 pub mod codemap;
 pub mod collections;
 mod debug;
+pub mod docs;
 pub mod environment;
 pub mod errors;
 pub mod eval;
@@ -15,6 +15,35 @@
  * limitations under the License.
  */
 
+/// Define the [`get_attr`](crate::values::StarlarkValue::get_attr),
+/// [`has_attr`](crate::values::StarlarkValue::has_attr) and
+/// [`dir_attr`](crate::values::StarlarkValue::dir_attr) fields of
+/// [`StarlarkValue`](crate::values::StarlarkValue) from its
+/// [`StarlarkAttrs`](crate::values::StarlarkAttrs) implementation.
+#[macro_export]
+macro_rules! starlark_attrs {
+    () => {
+        fn get_attr(
+            &self,
+            attribute: &str,
+            heap: &'v $crate::values::Heap,
+        ) -> Option<$crate::values::Value<'v>> {
+            $crate::values::StarlarkAttrs::attr(self, attribute, heap)
+        }
+
+        fn has_attr(&self, attribute: &str) -> bool {
+            <Self as $crate::values::StarlarkAttrs<'v>>::ATTRS.contains(&attribute)
+        }
+
+        fn dir_attr(&self) -> Vec<String> {
+            <Self as $crate::values::StarlarkAttrs<'v>>::ATTRS
+                .iter()
+                .map(|x| (*x).to_owned())
+                .collect()
+        }
+    };
+}
+
 /// Define the [`get_type`](crate::values::StarlarkValue::get_type) and
 /// [`get_type_value`](crate::values::StarlarkValue::get_type_value) fields of
 /// [`StarlarkValue`](crate::values::StarlarkValue).
@@ -0,0 +1,39 @@
+/*
+ * Copyright 2018 The Starlark in Rust Authors.
+ * Copyright (c) Facebook, Inc. and its affiliates.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Methods for the `bytes` type.
+
+use crate::{self as starlark, environment::GlobalsBuilder, values::bytes::Bytes};
+use gazebo::cell::ARef;
+
+#[starlark_module]
+pub(crate) fn bytes_methods(builder: &mut GlobalsBuilder) {
+    /// [bytes.elems](
+    /// https://github.com/bazelbuild/starlark/blob/master/spec.md#bytes·elems
+    /// ): returns the bytes as a list of ints.
+    ///
+    /// `b.elems()` returns a list of the elements of `b`, each an int in the range 0-255.
+    ///
+    /// ```
+    /// # starlark::assert::all_true(r#"
+    /// b"AZ".elems() == [65, 90]
+    /// # "#);
+    /// ```
+    fn elems(this: ARef<Bytes>) -> Vec<i32> {
+        Ok(this.as_bytes().iter().map(|x| *x as i32).collect())
+    }
+}
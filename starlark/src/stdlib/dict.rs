@@ -20,7 +20,7 @@
 use crate as starlark;
 use crate::{
     environment::GlobalsBuilder,
-    values::{dict::Dict, list::List, none::NoneType, Value},
+    values::{dict::Dict, none::NoneType, view::ListView, Value},
 };
 use anyhow::anyhow;
 use gazebo::cell::ARef;
@@ -90,7 +90,8 @@ pub(crate) fn dict_methods(registry: &mut GlobalsBuilder) {
     ///
     /// `D.items()` returns a new list of key/value pairs, one per element in
     /// dictionary D, in the same order as they would be returned by a `for`
-    /// loop.
+    /// loop. The result is a `list_view`, which only makes the tuple
+    /// for a pair when it is asked for.
     ///
     /// Examples:
     ///
@@ -100,14 +101,13 @@ pub(crate) fn dict_methods(registry: &mut GlobalsBuilder) {
     /// x.items() == [("one", 1), ("two", 2)]
     /// # "#);
     /// ```
-    fn items(this: ARef<Dict>) -> List<'v> {
-        // We go straight to a List, not a Vec, so we can avoid one allocation
-        Ok(List::new(
-            this.content
-                .iter()
-                .map(|(k, v)| heap.alloc((*k, *v)))
-                .collect(),
-        ))
+    fn items(this: ARef<Dict>) -> ListView<'v> {
+        let mut res = Vec::with_capacity(this.content.len() * 2);
+        for (k, v) in this.iter() {
+            res.push(k);
+            res.push(v);
+        }
+        Ok(ListView::new_tuples(2, res))
     }
 
     /// [dict.keys](
@@ -125,8 +125,8 @@ pub(crate) fn dict_methods(registry: &mut GlobalsBuilder) {
     /// x.keys() == ["one", "two"]
     /// # "#);
     /// ```
-    fn keys(this: ARef<Dict>) -> List<'v> {
-        Ok(List::new(this.keys()))
+    fn keys(this: ARef<Dict>) -> ListView<'v> {
+        Ok(ListView::new(this.keys()))
     }
 
     /// [dict.pop](
@@ -354,8 +354,8 @@ pub(crate) fn dict_methods(registry: &mut GlobalsBuilder) {
     /// x.values() == [1, 2]
     /// # "#);
     /// ```
-    fn values(this: ARef<Dict>) -> List<'v> {
-        Ok(List::new(this.values()))
+    fn values(this: ARef<Dict>) -> ListView<'v> {
+        Ok(ListView::new(this.values()))
     }
 }
 
@@ -18,15 +18,17 @@
 use crate::{
     self as starlark,
     codemap::Span,
-    collections::Hashed,
+    collections::{Hashed, SmallMap},
     environment::GlobalsBuilder,
+    errors::Diagnostic,
     eval::{Evaluator, Parameters, ParametersSpec, ParametersSpecBuilder},
     values::{
-        dict::Dict, function::FUNCTION_TYPE, list::List, none::NoneType, tuple::Tuple,
-        ComplexValue, Freezer, SimpleValue, StarlarkValue, Trace, Tracer, Value, ValueLike,
+        dict::Dict, function::FUNCTION_TYPE, list::List, none::NoneType, structs::Struct,
+        tuple::Tuple, ComplexValue, Freeze, Freezer, SimpleValue, StarlarkValue, Trace, Value,
+        ValueLike,
     },
 };
-use gazebo::{any::AnyLifetime, cell::ARef, prelude::*};
+use gazebo::{any::AnyLifetime, cell::ARef};
 use itertools::Itertools;
 use std::collections::HashSet;
 
@@ -119,16 +121,43 @@ pub fn dedupe(builder: &mut GlobalsBuilder) {
 #[starlark_module]
 pub fn print(builder: &mut GlobalsBuilder) {
     fn print(args: Vec<Value>) -> NoneType {
-        // In practice most users should want to put the print somewhere else, but this does for now
-        eprintln!("{}", args.iter().join(" "));
+        let location = eval.call_stack_top_location();
+        eval.print_handler.println(&args.iter().join(" "), location)?;
         Ok(NoneType)
     }
 }
 
 #[starlark_module]
-pub fn json(builder: &mut GlobalsBuilder) {
-    fn json(ref x: Value) -> String {
-        x.to_json()
+pub fn warn(builder: &mut GlobalsBuilder) {
+    fn warn(ref msg: &str) -> NoneType {
+        eval.add_warning(msg.to_owned());
+        Ok(NoneType)
+    }
+}
+
+#[starlark_module]
+pub fn catch(builder: &mut GlobalsBuilder) {
+    /// Call `f(*args)`. If the call succeeds, returns `struct(ok = True, value = result, error = None)`.
+    /// If the call fails, returns `struct(ok = False, value = None, error = message)`.
+    ///
+    /// Any functions on the call-stack are removed as the error propagates out of `f`,
+    /// so the call-stack after `catch` returns is the same as it was before.
+    fn catch(ref f: Value, args: Vec<Value>) -> Struct<'v> {
+        let (ok, value, error) = match f.invoke_pos(None, &args, eval) {
+            Ok(v) => (true, v, Value::new_none()),
+            Err(e) => {
+                let message = match e.downcast_ref::<Diagnostic>() {
+                    Some(d) => format!("{:#}", d.message),
+                    None => format!("{:#}", e),
+                };
+                (false, Value::new_none(), heap.alloc(message))
+            }
+        };
+        let mut fields = SmallMap::with_capacity(3);
+        fields.insert("ok".to_owned(), Value::new_bool(ok));
+        fields.insert("value".to_owned(), value);
+        fields.insert("error".to_owned(), error);
+        Ok(Struct::new(fields))
     }
 }
 
@@ -139,7 +168,7 @@ pub fn abs(builder: &mut GlobalsBuilder) {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Trace, Freeze)]
 struct PartialGen<V> {
     func: V,
     pos: Vec<V>,
@@ -150,29 +179,9 @@ struct PartialGen<V> {
 
 starlark_complex_value!(Partial);
 
-unsafe impl<'v> Trace<'v> for Partial<'v> {
-    fn trace(&mut self, tracer: &Tracer<'v>) {
-        tracer.trace(&mut self.func);
-        self.pos.iter_mut().for_each(|x| tracer.trace(x));
-        self.named.iter_mut().for_each(|x| tracer.trace(x));
-        self.names
-            .iter_mut()
-            .for_each(|x| tracer.trace(x.1.key_mut()));
-        self.signature.trace(tracer);
-    }
-}
-
 impl<'v> ComplexValue<'v> for Partial<'v> {
     fn freeze(self: Box<Self>, freezer: &Freezer) -> anyhow::Result<Box<dyn SimpleValue>> {
-        Ok(box FrozenPartial {
-            func: self.func.freeze(freezer)?,
-            pos: self.pos.try_map(|x| x.freeze(freezer))?,
-            named: self.named.try_map(|x| x.freeze(freezer))?,
-            names: self
-                .names
-                .into_try_map(|(s, x)| Ok::<_, anyhow::Error>((s, x.freeze(freezer)?)))?,
-            signature: self.signature.freeze(freezer)?,
-        })
+        Ok(box Freeze::freeze(*self, freezer)?)
     }
 }
 
@@ -236,7 +245,8 @@ where
 
 #[cfg(test)]
 mod tests {
-    use crate::assert;
+    use crate::{assert, errors::Diagnostic};
+    use gazebo::prelude::*;
 
     #[test]
     fn test_filter() {
@@ -325,4 +335,48 @@ assert_eq(dedupe([a,b,a]), [a,b])
 "#,
         );
     }
+
+    #[test]
+    fn test_catch() {
+        let mut a = assert::Assert::new();
+        a.globals_add(super::catch);
+        a.pass(
+            r#"
+def div(x, y):
+    return x // y
+
+r = catch(div, 6, 3)
+assert_eq((r.ok, r.value, r.error), (True, 2, None))
+r = catch(div, 6, 0)
+assert_eq((r.ok, r.value, r.error), (False, None, "Cannot divide by zero"))
+r = catch(fail, "oops")
+assert_eq((r.ok, r.value, r.error), (False, None, "fail(): oops"))
+assert_eq(catch(lambda: None).ok, True)
+"#,
+        );
+        // Not included in the extended globals, since it changes the meaning of errors
+        assert::fail("catch(len, [])", "Variable `catch` not found");
+    }
+
+    #[test]
+    fn test_catch_call_stack() {
+        let mut a = assert::Assert::new();
+        a.globals_add(super::catch);
+        let err = a.fail(
+            r#"
+def inner():
+    fail("inner")
+def outer():
+    catch(inner)
+    fail("outer")
+outer()
+"#,
+            "outer",
+        );
+        let err = err.downcast_ref::<Diagnostic>().unwrap();
+        assert_eq!(
+            err.call_stack.map(|x| x.name.as_str()),
+            vec!["assert.bzl.outer()", "fail(msg)"]
+        );
+    }
 }
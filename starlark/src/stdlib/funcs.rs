@@ -24,6 +24,7 @@ use crate::{
     environment::GlobalsBuilder,
     values::{
         bool::BOOL_TYPE,
+        bytes::Bytes,
         dict::Dict,
         function::{BoundMethod, NativeAttribute},
         int::INT_TYPE,
@@ -32,12 +33,19 @@ use crate::{
         range::Range,
         string::STRING_TYPE,
         tuple::Tuple,
+        view::ListView,
         AttrType, Heap, Value, ValueError,
     },
 };
 use anyhow::anyhow;
 use gazebo::prelude::*;
 use std::{cmp::Ordering, num::NonZeroI32};
+use thiserror::Error;
+
+/// The error raised by `fail()`.
+#[derive(Debug, Error)]
+#[error("fail(): {0}")]
+pub(crate) struct FailError(String);
 
 fn unpack_pair<'v>(it: Value<'v>, heap: &'v Heap) -> anyhow::Result<(Value<'v>, Value<'v>)> {
     match it.iterate(heap) {
@@ -74,7 +82,7 @@ pub(crate) fn global_functions(builder: &mut GlobalsBuilder) {
     /// # "#, "this is an error");
     /// ```
     fn fail(ref msg: Value) -> NoneType {
-        Err(anyhow!("fail(): {}", msg))
+        Err(FailError(msg.to_string()).into())
     }
 
     /// [any](
@@ -160,6 +168,38 @@ pub(crate) fn global_functions(builder: &mut GlobalsBuilder) {
         Ok(x.to_bool())
     }
 
+    /// [bytes](
+    /// https://github.com/bazelbuild/starlark/blob/master/spec.md#bytes
+    /// ): converts a value to bytes.
+    ///
+    /// `bytes(x)` accepts bytes, which are returned unchanged, a string,
+    /// which is encoded as UTF-8, or an iterable of ints in the range 0-255.
+    ///
+    /// ```
+    /// # starlark::assert::all_true(r#"
+    /// bytes("abc") == b"abc"
+    /// bytes(b"abc") == b"abc"
+    /// bytes([97, 98, 99]) == b"abc"
+    /// # "#);
+    /// ```
+    #[starlark_type(Bytes::TYPE)]
+    fn bytes(ref x: Value) -> Value<'v> {
+        if Bytes::from_value(x).is_some() {
+            Ok(x)
+        } else if let Some(s) = x.unpack_str() {
+            Ok(heap.alloc(Bytes::new(s.as_bytes().to_vec())))
+        } else {
+            let mut res = Vec::new();
+            for v in &x.iterate(heap)? {
+                match v.to_int()? {
+                    b @ 0..=255 => res.push(b as u8),
+                    b => return Err(anyhow!("bytes: value {} is out of range 0-255", b)),
+                }
+            }
+            Ok(heap.alloc(Bytes::new(res)))
+        }
+    }
+
     /// [chr](
     /// https://github.com/google/skylark/blob/a0e5de7e63b47e716cca7226662a4c95d47bf873/doc/spec.md#bool
     /// ): returns a string encoding a codepoint.
@@ -269,7 +309,8 @@ pub(crate) fn global_functions(builder: &mut GlobalsBuilder) {
     ///
     /// `enumerate(x)` returns a list of `(index, value)` pairs, each containing
     /// successive values of the iterable sequence and the index of the
-    /// value within the sequence.
+    /// value within the sequence. The result is a `list_view`, which
+    /// only makes the tuple for a pair when it is asked for.
     ///
     /// The optional second parameter, `start`, specifies an integer value to
     /// add to each index.
@@ -282,14 +323,14 @@ pub(crate) fn global_functions(builder: &mut GlobalsBuilder) {
     /// enumerate(["one", "two"], 1) == [(1, "one"), (2, "two")]
     /// # "#);
     /// ```
-    fn enumerate(ref it: Value, start @ 0: i32) -> List<'v> {
-        let v = it
-            .iterate(heap)?
-            .iter()
-            .enumerate()
-            .map(|(k, v)| heap.alloc((k as i32 + start, v)))
-            .collect();
-        Ok(List::new(v))
+    fn enumerate(ref it: Value, start @ 0: i32) -> ListView<'v> {
+        let it = it.iterate(heap)?;
+        let mut res = Vec::new();
+        for (k, v) in it.iter().enumerate() {
+            res.push(Value::new_int(k as i32 + start));
+            res.push(v);
+        }
+        Ok(ListView::new_tuples(2, res))
     }
 
     /// [getattr](
@@ -321,7 +362,7 @@ pub(crate) fn global_functions(builder: &mut GlobalsBuilder) {
             }
             None => match default {
                 Some(x) => Ok(x),
-                None => ValueError::unsupported_owned(a.get_type(), &format!(".{}", attr), None),
+                None => ValueError::missing_attribute(a.get_type(), attr),
             },
         }
     }
@@ -653,6 +694,8 @@ pub(crate) fn global_functions(builder: &mut GlobalsBuilder) {
     /// Each invalid code within the string is treated as if it encodes the
     /// Unicode replacement character, U+FFFD.
     ///
+    /// If `s` is bytes of length 1, `ord` returns the value of the sole byte.
+    ///
     /// Example:
     ///
     /// ```
@@ -660,6 +703,7 @@ pub(crate) fn global_functions(builder: &mut GlobalsBuilder) {
     /// ord("A")                                == 65
     /// ord("Й")                                == 1049
     /// ord("😿")                               == 0x1F63F
+    /// ord(b"\xff")                            == 255
     /// # "#);
     /// ```
     fn ord(ref a: Value) -> i32 {
@@ -670,6 +714,10 @@ pub(crate) fn global_functions(builder: &mut GlobalsBuilder) {
                     return Ok(u32::from(c) as i32);
                 }
             }
+        } else if let Some(b) = Bytes::from_value(a) {
+            if let [x] = b.as_bytes() {
+                return Ok(*x as i32);
+            }
         }
         Err(anyhow!(
             "ord(): {} is not a single character string",
@@ -757,10 +805,10 @@ pub(crate) fn global_functions(builder: &mut GlobalsBuilder) {
     /// reversed({"one": 1, "two": 2}.keys())  == ["two", "one"]
     /// # "#);
     /// ```
-    fn reversed(ref a: Value) -> List<'v> {
+    fn reversed(ref a: Value) -> ListView<'v> {
         let mut v: Vec<Value> = a.iterate(heap)?.iter().collect();
         v.reverse();
-        Ok(List::new(v))
+        Ok(ListView::new(v))
     }
 
     /// [sorted](
@@ -828,6 +876,7 @@ pub(crate) fn global_functions(builder: &mut GlobalsBuilder) {
     /// ): formats its argument as a string.
     ///
     /// If x is a string, the result is x (without quotation).
+    /// If x is bytes, the result is x decoded as UTF-8.
     /// All other strings, such as elements of a list of strings, are
     /// double-quoted.
     ///
@@ -835,6 +884,7 @@ pub(crate) fn global_functions(builder: &mut GlobalsBuilder) {
     /// # starlark::assert::all_true(r#"
     /// str(1)                          == '1'
     /// str("x")                        == 'x'
+    /// str(b"x")                       == 'x'
     /// str([1, "x"])                   == "[1, \"x\"]"
     /// # "#);
     /// ```
@@ -904,24 +954,17 @@ pub(crate) fn global_functions(builder: &mut GlobalsBuilder) {
     /// zip(range(5), "abc".elems())    == [(0, "a"), (1, "b"), (2, "c")]
     /// # "#);
     /// ```
-    fn zip(args: Vec<Value>) -> List<'v> {
-        let mut v = Vec::new();
-        let mut first = true;
-        for arg in args {
-            let mut idx = 0;
-            for e in &arg.iterate(heap)? {
-                if first {
-                    v.push(heap.alloc((e,)));
-                    idx += 1;
-                } else if idx < v.len() {
-                    v[idx] = v[idx].add(heap.alloc((e,)), heap)?;
-                    idx += 1;
-                }
-            }
-            v.truncate(idx);
-            first = false;
+    fn zip(args: Vec<Value>) -> ListView<'v> {
+        let mut columns = Vec::with_capacity(args.len());
+        for arg in &args {
+            columns.push(arg.iterate(heap)?.iter().collect::<Vec<_>>());
+        }
+        let len = columns.iter().map(Vec::len).min().unwrap_or(0);
+        let mut res = Vec::with_capacity(len * columns.len());
+        for i in 0..len {
+            res.extend(columns.iter().map(|x| x[i]));
         }
-        Ok(List::new(v))
+        Ok(ListView::new_tuples(columns.len().max(1), res))
     }
 }
 
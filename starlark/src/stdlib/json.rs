@@ -0,0 +1,484 @@
+/*
+ * Copyright 2019 The Starlark in Rust Authors.
+ * Copyright (c) Facebook, Inc. and its affiliates.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! The `json` module, compatible with the `json` module of the
+//! [Go](https://pkg.go.dev/go.starlark.net/lib/json) and Java implementations of Starlark.
+
+use crate::{
+    self as starlark,
+    collections::SmallMap,
+    environment::GlobalsBuilder,
+    values::{
+        dict::Dict, enumeration::EnumValue, list::List, record::Record, structs::Struct,
+        tuple::Tuple, view::ListView, ControlError, Heap, Value, ValueLike,
+    },
+};
+use serde::de::{self, DeserializeSeed, Deserializer, IgnoredAny, MapAccess, SeqAccess, Visitor};
+use std::{convert::TryFrom, fmt};
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+enum JsonError {
+    #[error("json.encode: cannot encode value of type `{0}` as JSON, at {1}")]
+    Unsupported(String, String),
+    #[error("json.encode: cannot encode cyclic data structure, at {0}")]
+    Cycle(String),
+    #[error("json.encode: dictionary keys must be strings, got `{0}`, at {1}")]
+    KeyNotString(String, String),
+    #[error("json.decode: {0}")]
+    Decode(serde_json::Error),
+    #[error("json.indent: {0}")]
+    Indent(serde_json::Error),
+}
+
+/// Encodes Starlark values as compact JSON, keeping track of where we are so errors
+/// can say which element was the problem.
+struct Encoder<'v> {
+    out: String,
+    /// The containers we are currently inside, used to detect cycles.
+    parents: Vec<Value<'v>>,
+    /// The path to the current element, e.g. `["deps"]`, `[0]`, `.name`.
+    path: Vec<String>,
+}
+
+impl<'v> Encoder<'v> {
+    fn path(&self) -> String {
+        format!("x{}", self.path.concat())
+    }
+
+    fn string(&mut self, x: &str) {
+        // Encoding a string can't fail
+        self.out.push_str(&serde_json::to_string(x).unwrap())
+    }
+
+    fn nested(&mut self, path: String, x: Value<'v>) -> anyhow::Result<()> {
+        self.path.push(path);
+        self.value(x)?;
+        self.path.pop();
+        Ok(())
+    }
+
+    fn container(
+        &mut self,
+        x: Value<'v>,
+        f: impl FnOnce(&mut Self) -> anyhow::Result<()>,
+    ) -> anyhow::Result<()> {
+        if self.parents.iter().any(|p| p.ptr_eq(x)) {
+            return Err(JsonError::Cycle(self.path()).into());
+        }
+        self.parents.push(x);
+        f(self)?;
+        self.parents.pop();
+        Ok(())
+    }
+
+    fn value(&mut self, x: Value<'v>) -> anyhow::Result<()> {
+        if x.is_none() {
+            self.out.push_str("null");
+        } else if let Some(b) = x.unpack_bool() {
+            self.out.push_str(if b { "true" } else { "false" });
+        } else if let Some(i) = x.unpack_int() {
+            self.out.push_str(&i.to_string());
+        } else if let Some(s) = x.unpack_str() {
+            self.string(s);
+        } else if let Some(xs) = List::from_value(x) {
+            self.container(x, |me| me.sequence(&xs.content))?;
+        } else if let Some(xs) = Tuple::from_value(x) {
+            self.container(x, |me| me.sequence(&xs.content))?;
+        } else if let Some(xs) = ListView::from_value(x) {
+            self.container(x, |me| match xs.shape() {
+                (width, true) => {
+                    me.out.push('[');
+                    for (i, item) in xs.content().chunks(width).enumerate() {
+                        if i != 0 {
+                            me.out.push(',');
+                        }
+                        me.path.push(format!("[{}]", i));
+                        me.sequence(item)?;
+                        me.path.pop();
+                    }
+                    me.out.push(']');
+                    Ok(())
+                }
+                _ => me.sequence(xs.content()),
+            })?;
+        } else if let Some(d) = Dict::from_value(x) {
+            self.container(x, |me| {
+                me.out.push('{');
+                for (i, (k, v)) in d.iter().enumerate() {
+                    let key = match k.unpack_str() {
+                        Some(key) => key,
+                        None => {
+                            return Err(JsonError::KeyNotString(
+                                k.get_type().to_owned(),
+                                me.path(),
+                            )
+                            .into());
+                        }
+                    };
+                    if i != 0 {
+                        me.out.push(',');
+                    }
+                    me.string(key);
+                    me.out.push(':');
+                    me.nested(format!("[{}]", k.to_repr()), v)?;
+                }
+                me.out.push('}');
+                Ok(())
+            })?;
+        } else if let Some(s) = Struct::from_value(x) {
+            self.container(x, |me| {
+                me.fields(s.fields.iter().map(|(k, v)| (k.as_str(), *v)))
+            })?;
+        } else if let Some(r) = Record::from_value(x) {
+            let typ = r.get_record_type();
+            self.container(x, |me| {
+                me.fields(
+                    typ.fields
+                        .keys()
+                        .map(String::as_str)
+                        .zip(r.values.iter().copied()),
+                )
+            })?;
+        } else if let Some(e) = EnumValue::from_value(x) {
+            self.value(e.value)?;
+        } else {
+            // User-defined types may know how to produce JSON. If they contain a cycle,
+            // `to_json` fails with too many recursion levels, which we pass on.
+            match x.to_json() {
+                Ok(s) => self.out.push_str(&s),
+                Err(e) if e.is::<ControlError>() => return Err(e),
+                Err(_) => {
+                    return Err(JsonError::Unsupported(x.get_type().to_owned(), self.path()).into());
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn fields<'a>(
+        &mut self,
+        fields: impl Iterator<Item = (&'a str, Value<'v>)>,
+    ) -> anyhow::Result<()> {
+        self.out.push('{');
+        for (i, (k, v)) in fields.enumerate() {
+            if i != 0 {
+                self.out.push(',');
+            }
+            self.string(k);
+            self.out.push(':');
+            self.nested(format!(".{}", k), v)?;
+        }
+        self.out.push('}');
+        Ok(())
+    }
+
+    fn sequence(&mut self, xs: &[Value<'v>]) -> anyhow::Result<()> {
+        self.out.push('[');
+        for (i, x) in xs.iter().enumerate() {
+            if i != 0 {
+                self.out.push(',');
+            }
+            self.nested(format!("[{}]", i), *x)?;
+        }
+        self.out.push(']');
+        Ok(())
+    }
+}
+
+fn encode_value(x: Value) -> anyhow::Result<String> {
+    let mut encoder = Encoder {
+        out: String::new(),
+        parents: Vec::new(),
+        path: Vec::new(),
+    };
+    encoder.value(x)?;
+    Ok(encoder.out)
+}
+
+/// Reformat JSON text, putting each array element and object member on a new line,
+/// where each line starts with `prefix` followed by `indent` once per level of nesting.
+/// Empty arrays and objects are written as `[]` and `{}`.
+fn indent_text(x: &str, prefix: &str, indent: &str) -> anyhow::Result<String> {
+    // Check it is valid before we start, so we can assume it is well formed below
+    serde_json::from_str::<IgnoredAny>(x).map_err(JsonError::Indent)?;
+
+    let mut res = String::with_capacity(x.len());
+    let newline = |res: &mut String, depth: usize| {
+        res.push('\n');
+        res.push_str(prefix);
+        for _ in 0..depth {
+            res.push_str(indent);
+        }
+    };
+
+    let mut depth = 0;
+    // We have opened a container, but not yet written a newline, in case it is empty
+    let mut pending = false;
+    let mut in_string = false;
+    let mut escaped = false;
+    for c in x.chars() {
+        if in_string {
+            res.push(c);
+            if escaped {
+                escaped = false;
+            } else if c == '\\' {
+                escaped = true;
+            } else if c == '"' {
+                in_string = false;
+            }
+            continue;
+        }
+        if c.is_ascii_whitespace() {
+            continue;
+        }
+        if pending {
+            pending = false;
+            if c != ']' && c != '}' {
+                newline(&mut res, depth);
+            } else {
+                depth -= 1;
+                res.push(c);
+                continue;
+            }
+        }
+        match c {
+            '[' | '{' => {
+                res.push(c);
+                depth += 1;
+                pending = true;
+            }
+            ']' | '}' => {
+                depth -= 1;
+                newline(&mut res, depth);
+                res.push(c);
+            }
+            ',' => {
+                res.push(c);
+                newline(&mut res, depth);
+            }
+            ':' => res.push_str(": "),
+            '"' => {
+                res.push(c);
+                in_string = true;
+            }
+            _ => res.push(c),
+        }
+    }
+    Ok(res)
+}
+
+/// Deserialize JSON directly into Starlark values, preserving the order of object members.
+#[derive(Clone, Copy)]
+struct Decoder<'v>(&'v Heap);
+
+impl<'de, 'v> DeserializeSeed<'de> for Decoder<'v> {
+    type Value = Value<'v>;
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<Value<'v>, D::Error> {
+        deserializer.deserialize_any(self)
+    }
+}
+
+impl<'de, 'v> Visitor<'de> for Decoder<'v> {
+    type Value = Value<'v>;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a JSON value")
+    }
+
+    fn visit_unit<E>(self) -> Result<Value<'v>, E> {
+        Ok(Value::new_none())
+    }
+
+    fn visit_bool<E>(self, x: bool) -> Result<Value<'v>, E> {
+        Ok(Value::new_bool(x))
+    }
+
+    fn visit_i64<E: de::Error>(self, x: i64) -> Result<Value<'v>, E> {
+        match i32::try_from(x) {
+            Ok(x) => Ok(Value::new_int(x)),
+            Err(_) => Err(E::custom(format!("integer `{}` is too large", x))),
+        }
+    }
+
+    fn visit_u64<E: de::Error>(self, x: u64) -> Result<Value<'v>, E> {
+        match i32::try_from(x) {
+            Ok(x) => Ok(Value::new_int(x)),
+            Err(_) => Err(E::custom(format!("integer `{}` is too large", x))),
+        }
+    }
+
+    fn visit_f64<E: de::Error>(self, x: f64) -> Result<Value<'v>, E> {
+        Err(E::custom(format!(
+            "number `{}` is not supported, only integers are",
+            x
+        )))
+    }
+
+    fn visit_str<E>(self, x: &str) -> Result<Value<'v>, E> {
+        Ok(self.0.alloc(x))
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Value<'v>, A::Error> {
+        let mut res = Vec::with_capacity(seq.size_hint().unwrap_or_default());
+        while let Some(x) = seq.next_element_seed(self)? {
+            res.push(x);
+        }
+        Ok(self.0.alloc(res))
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Value<'v>, A::Error> {
+        let mut res = SmallMap::with_capacity(map.size_hint().unwrap_or_default());
+        while let Some(k) = map.next_key::<String>()? {
+            let k = self.0.alloc(k).get_hashed().map_err(de::Error::custom)?;
+            let v = map.next_value_seed(self)?;
+            res.insert_hashed(k, v);
+        }
+        Ok(self.0.alloc(Dict::new(res)))
+    }
+}
+
+#[starlark_module]
+fn json_members(builder: &mut GlobalsBuilder) {
+    /// Encode a value as compact JSON. Supports `None`, bools, ints, strings, lists, tuples,
+    /// dicts with string keys and structs, plus any other type with a JSON representation.
+    fn encode(ref x: Value) -> String {
+        encode_value(x)
+    }
+
+    /// Decode JSON text to a Starlark value. Objects become dicts (in the same order),
+    /// arrays become lists and `null` becomes `None`.
+    fn decode(ref x: &str) -> Value<'v> {
+        let mut deserializer = serde_json::Deserializer::from_str(x);
+        let res = Decoder(heap)
+            .deserialize(&mut deserializer)
+            .and_then(|v| deserializer.end().map(|_| v))
+            .map_err(JsonError::Decode)?;
+        Ok(res)
+    }
+
+    /// Reformat JSON text with one element per line, each line starting with `prefix`
+    /// followed by `indent` repeated once per level of nesting.
+    fn indent(ref x: &str, prefix @ "": &str, indent @ "\t": &str) -> String {
+        indent_text(x, prefix, indent)
+    }
+
+    /// Equivalent to `json.indent(json.encode(x), prefix, indent)`.
+    fn encode_indent(ref x: Value, prefix @ "": &str, indent @ "\t": &str) -> String {
+        indent_text(&encode_value(x)?, prefix, indent)
+    }
+}
+
+pub fn json(builder: &mut GlobalsBuilder) {
+    builder.struct_("json", json_members)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::assert;
+
+    #[test]
+    fn test_encode() {
+        assert::all_true(
+            r#"
+json.encode(None) == 'null'
+json.encode(True) == 'true'
+json.encode(-42) == '-42'
+json.encode("a\"b\n\u0001") == '"a\\"b\\n\\u0001"'
+json.encode([1, (2, 3), []]) == '[1,[2,3],[]]'
+json.encode({"b": 1, "a": [None]}) == '{"b":1,"a":[null]}'
+json.encode(struct(x = 1, y = struct(z = "w"))) == '{"x":1,"y":{"z":"w"}}'
+json.encode(record(a = field(int.type), b = field(list.type))(a = 1, b = [2])) == '{"a":1,"b":[2]}'
+json.encode(enum("x", "y")("y")) == '"y"'
+json.encode({"a": 1}.items()) == '[["a",1]]'
+json.encode(reversed([1, 2])) == '[2,1]'
+"#,
+        );
+    }
+
+    #[test]
+    fn test_encode_fail() {
+        assert::fail(
+            r#"
+def f(): pass
+json.encode({"a": [1, struct(b = f)]})
+"#,
+            "cannot encode value of type `function` as JSON, at x[\"a\"][1].b",
+        );
+        assert::fail(
+            "json.encode({'a': {1: 2}})",
+            "dictionary keys must be strings, got `int`, at x[\"a\"]",
+        );
+        assert::fail(
+            r#"
+xs = [1]
+xs.append({"k": xs})
+json.encode(xs)
+"#,
+            "cannot encode cyclic data structure, at x[1][\"k\"]",
+        );
+        assert::fail(
+            r#"
+xs = []
+r = record(xs = field(list.type))(xs = xs)
+xs.append(r)
+json.encode(struct(r = r))
+"#,
+            "cannot encode cyclic data structure, at x.r.xs[0]",
+        );
+        // The older `to_json` method fails rather than overflowing the stack
+        assert::fail(
+            r#"
+xs = []
+xs.append(xs)
+struct(xs = xs).to_json()
+"#,
+            "Too many recursion levels",
+        );
+    }
+
+    #[test]
+    fn test_decode() {
+        assert::all_true(
+            r#"
+json.decode('null') == None
+json.decode('[true, false, -7, "x\\u0041"]') == [True, False, -7, "xA"]
+json.decode('{"b": {"c": []}, "a": 1}') == {"b": {"c": []}, "a": 1}
+list(json.decode('{"b": 1, "a": 2, "c": 3}').keys()) == ["b", "a", "c"]
+json.decode(json.encode({"x": [1, "two", None]})) == {"x": [1, "two", None]}
+"#,
+        );
+        assert::fail("json.decode('[1,')", "json.decode: EOF while parsing");
+        assert::fail("json.decode('[1] 2')", "json.decode: trailing characters");
+        assert::fail("json.decode('1.5')", "only integers are");
+        assert::fail("json.decode('10000000000')", "too large");
+    }
+
+    #[test]
+    fn test_indent() {
+        assert::all_true(
+            r#"
+json.indent('{"a": [1, 2], "b": {}, "c": []}') == '{\n\t"a": [\n\t\t1,\n\t\t2\n\t],\n\t"b": {},\n\t"c": []\n}'
+json.indent('[1,"a, [b]"]', prefix = "> ", indent = "  ") == '[\n>   1,\n>   "a, [b]"\n> ]'
+json.indent('"x"') == '"x"'
+json.encode_indent({"k": ["\\\""]}, indent = " ") == '{\n "k": [\n  "\\\\\\""\n ]\n}'
+"#,
+        );
+        assert::fail("json.indent('[1')", "json.indent:");
+    }
+}
@@ -19,12 +19,16 @@
 //! dialect of Starlark
 
 use crate::environment::GlobalsBuilder;
+pub(crate) use funcs::FailError;
 
 mod breakpoint;
+pub(crate) mod bytes;
 pub(crate) mod dict;
 pub(crate) mod enumeration;
 mod extra;
 mod funcs;
+mod json;
+mod re;
 use gazebo::prelude::*;
 pub(crate) mod list;
 pub(crate) mod record;
@@ -42,7 +46,7 @@ pub(crate) fn standard_environment() -> GlobalsBuilder {
 }
 
 /// The extra library definitions available in this Starlark implementation, but not in the standard.
-#[derive(PartialEq, Eq, Copy, Clone, Dupe)]
+#[derive(PartialEq, Eq, Copy, Clone, Dupe, Debug)]
 pub enum LibraryExtension {
     /// Definitions to support the `struct` type, the `struct()` constructor.
     StructType,
@@ -63,24 +67,40 @@ pub enum LibraryExtension {
     /// Add a function `debug(x)` which shows the Rust [`Debug`](std::fmt::Debug) representation of a value.
     /// Useful when debugging, but the output should not be considered stable.
     Debug,
-    /// Add a function `print(x)` which prints to stderr.
+    /// Add a function `print(x)` which prints to stderr, or to the
+    /// [`PrintHandler`](crate::eval::PrintHandler) set on the [`Evaluator`](crate::eval::Evaluator).
     Print,
+    /// Add a function `warn(msg)` which records a warning on the [`Evaluator`](crate::eval::Evaluator),
+    /// to be collected with [`take_warnings`](crate::eval::Evaluator::take_warnings).
+    Warn,
     /// Add a function `breakpoint()` which will drop into a console-module evaluation prompt.
     Breakpoint,
-    /// Add a function `json()` which will generate JSON for a module.
+    /// Add a `json` module with functions `encode`, `decode`, `indent` and `encode_indent`,
+    /// compatible with the Go and Java implementations of Starlark.
     Json,
     /// Add a function `abs()` which will take the absolute value of an int.
     Abs,
-    // Make sure if you add anything new, you add it to `all` below.
+    /// Add a `re` module with regular expression functions `compile`, `match`, `search`,
+    /// `findall`, `sub` and `split`. All operations take linear time.
+    Regex,
+    /// Add a function `catch(f, *args)` which calls `f(*args)` and returns a struct with fields
+    /// `ok`, `value` and `error`, rather than failing if `f` fails.
+    /// Since this changes the semantics of errors in Starlark, it is not included in
+    /// [`all`](LibraryExtension::all), and must be requested explicitly.
+    Catch,
+    // Make sure if you add anything new, you add it to `all` below,
+    // unless it alters the semantics of standard Starlark.
 }
 
 impl LibraryExtension {
     /// A list of all extensions that will be updated as new methods are added.
+    /// Extensions which alter the semantics of standard Starlark (e.g. [`Catch`](LibraryExtension::Catch))
+    /// are excluded.
     pub fn all() -> &'static [Self] {
         use LibraryExtension::*;
         &[
-            StructType, RecordType, EnumType, Map, Filter, Partial, Dedupe, Debug, Print,
-            Breakpoint, Json, Abs,
+            StructType, RecordType, EnumType, Map, Filter, Partial, Dedupe, Debug, Print, Warn,
+            Breakpoint, Json, Abs, Regex,
         ]
     }
 
@@ -97,9 +117,12 @@ impl LibraryExtension {
             Dedupe => extra::dedupe(builder),
             Debug => extra::debug(builder),
             Print => extra::print(builder),
+            Warn => extra::warn(builder),
             Breakpoint => breakpoint::global(builder),
-            Json => extra::json(builder),
+            Json => json::json(builder),
             Abs => extra::abs(builder),
+            Regex => re::re(builder),
+            Catch => extra::catch(builder),
         }
     }
 }
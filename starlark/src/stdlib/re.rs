@@ -0,0 +1,305 @@
+/*
+ * Copyright 2019 The Starlark in Rust Authors.
+ * Copyright (c) Facebook, Inc. and its affiliates.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! The `re` module, providing regular expressions using the [`regex`] crate.
+//!
+//! The patterns use the [`regex` syntax](https://docs.rs/regex/latest/regex/#syntax), which is
+//! similar to Python, but without look-around or backreferences. In exchange, all operations
+//! take time linear in the size of the pattern and input, so a script can't hang the
+//! interpreter with a pathological pattern.
+//!
+//! Each function takes a pattern, which can either be a string or the result of `re.compile`.
+//! Compiled patterns are immutable, so can be stored in frozen modules and shared.
+//!
+//! ```
+//! # starlark::assert::is_true(r#"
+//! label = re.compile("^//([a-z/]*):([a-z_]+)$")
+//! label.match("//foo/bar:baz") == ["//foo/bar:baz", "foo/bar", "baz"]
+//! # "#);
+//! ```
+
+use crate::{
+    self as starlark,
+    environment::{Globals, GlobalsBuilder, GlobalsStatic},
+    values::{list::List, tuple::Tuple, ARef, Heap, StarlarkValue, Value, ValueError},
+};
+use once_cell::sync::Lazy;
+use regex::{Captures, Regex};
+use std::{collections::HashMap, sync::Mutex};
+
+/// A compiled regular expression, the result of `re.compile`.
+#[derive(Debug, Clone)]
+pub struct RegexValue(Regex);
+
+starlark_simple_value!(RegexValue);
+
+impl RegexValue {
+    /// The result of calling `type()` on a compiled regular expression.
+    pub const TYPE: &'static str = "regex";
+}
+
+impl<'v> StarlarkValue<'v> for RegexValue {
+    starlark_type!(RegexValue::TYPE);
+
+    fn collect_repr(&self, s: &mut String) {
+        s.push_str("re.compile(");
+        Box::<str>::from(self.0.as_str()).collect_repr(s);
+        s.push(')');
+    }
+
+    fn equals(&self, other: Value<'v>) -> anyhow::Result<bool> {
+        match RegexValue::from_value(other) {
+            Some(other) => Ok(self.0.as_str() == other.0.as_str()),
+            None => Ok(false),
+        }
+    }
+
+    fn get_methods(&self) -> Option<&'static Globals> {
+        static RES: GlobalsStatic = GlobalsStatic::new();
+        RES.methods(regex_methods)
+    }
+}
+
+/// Compile a pattern, reusing the previous result if we have seen the pattern recently.
+fn compile_cached(pattern: &str) -> anyhow::Result<Regex> {
+    // Most programs use a handful of patterns, so a small cache that is emptied when full is enough
+    const CACHE_SIZE: usize = 64;
+    static CACHE: Lazy<Mutex<HashMap<String, Regex>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+
+    if let Some(x) = CACHE.lock().unwrap().get(pattern) {
+        return Ok(x.clone());
+    }
+    let res = Regex::new(pattern)?;
+    let mut cache = CACHE.lock().unwrap();
+    if cache.len() >= CACHE_SIZE {
+        cache.clear();
+    }
+    cache.insert(pattern.to_owned(), res.clone());
+    Ok(res)
+}
+
+/// Accept either a string or a compiled pattern.
+fn to_regex(pattern: Value) -> anyhow::Result<Regex> {
+    if let Some(x) = RegexValue::from_value(pattern) {
+        Ok(x.0.clone())
+    } else if let Some(x) = pattern.unpack_str() {
+        compile_cached(x)
+    } else {
+        Err(ValueError::IncorrectParameterTypeNamed("pattern".to_owned()).into())
+    }
+}
+
+/// The whole match followed by each group, with `None` for groups that didn't match.
+fn groups<'v>(captures: &Captures, heap: &'v Heap) -> Value<'v> {
+    heap.alloc(
+        captures
+            .iter()
+            .map(|x| match x {
+                None => Value::new_none(),
+                Some(x) => heap.alloc(x.as_str()),
+            })
+            .collect::<Vec<_>>(),
+    )
+}
+
+fn re_match<'v>(regex: &Regex, s: &str, heap: &'v Heap) -> Value<'v> {
+    // Leftmost-first semantics means if there is any match starting at 0, that's the one we get
+    match regex.captures(s) {
+        Some(x) if x.get(0).unwrap().start() == 0 => groups(&x, heap),
+        _ => Value::new_none(),
+    }
+}
+
+fn re_search<'v>(regex: &Regex, s: &str, heap: &'v Heap) -> Value<'v> {
+    match regex.captures(s) {
+        Some(x) => groups(&x, heap),
+        None => Value::new_none(),
+    }
+}
+
+fn re_findall<'v>(regex: &Regex, s: &str, heap: &'v Heap) -> Value<'v> {
+    let group = |x: Option<regex::Match>| heap.alloc(x.map_or("", |x| x.as_str()));
+    let res = regex.captures_iter(s).map(|x| match x.len() {
+        1 => group(x.get(0)),
+        2 => group(x.get(1)),
+        n => heap.alloc(Tuple::new((1..n).map(|i| group(x.get(i))).collect())),
+    });
+    heap.alloc(List::new(res.collect()))
+}
+
+fn re_sub(regex: &Regex, repl: &str, s: &str, count: i32) -> String {
+    regex
+        .replacen(s, if count <= 0 { 0 } else { count as usize }, repl)
+        .into_owned()
+}
+
+fn re_split<'v>(regex: &Regex, s: &str, maxsplit: i32, heap: &'v Heap) -> Value<'v> {
+    let res: Vec<Value> = if maxsplit <= 0 {
+        regex.split(s).map(|x| heap.alloc(x)).collect()
+    } else {
+        regex
+            .splitn(s, maxsplit as usize + 1)
+            .map(|x| heap.alloc(x))
+            .collect()
+    };
+    heap.alloc(List::new(res))
+}
+
+#[starlark_module]
+fn re_members(builder: &mut GlobalsBuilder) {
+    /// Compile a pattern, so it can be used repeatedly and stored in frozen values.
+    /// Fails if the pattern is not a valid regular expression.
+    fn compile(ref pattern: &str) -> RegexValue {
+        Ok(RegexValue(compile_cached(pattern)?))
+    }
+
+    /// If the pattern matches at the start of `s`, returns a list of the whole match followed by
+    /// each group (`None` for groups which didn't participate), otherwise returns `None`.
+    fn r#match(ref pattern: Value, ref s: &str) -> Value<'v> {
+        Ok(re_match(&to_regex(pattern)?, s, heap))
+    }
+
+    /// Like `match`, but the pattern can match anywhere in `s`.
+    fn search(ref pattern: Value, ref s: &str) -> Value<'v> {
+        Ok(re_search(&to_regex(pattern)?, s, heap))
+    }
+
+    /// All non-overlapping matches of the pattern in `s`. If the pattern has no groups, returns
+    /// a list of the matched strings, with one group a list of that group, and with several
+    /// groups a list of tuples of the groups.
+    fn findall(ref pattern: Value, ref s: &str) -> Value<'v> {
+        Ok(re_findall(&to_regex(pattern)?, s, heap))
+    }
+
+    /// Replace the first `count` matches (all matches if `count` is `0`) of the pattern in `s`
+    /// with `repl`. Groups are referred to in `repl` as `$1` or `${name}`.
+    fn sub(ref pattern: Value, ref repl: &str, ref s: &str, count @ 0: i32) -> String {
+        Ok(re_sub(&to_regex(pattern)?, repl, s, count))
+    }
+
+    /// Split `s` by the matches of the pattern, at most `maxsplit` times if it is positive.
+    /// Unlike Python, groups in the pattern are not included in the result.
+    fn split(ref pattern: Value, ref s: &str, maxsplit @ 0: i32) -> Value<'v> {
+        Ok(re_split(&to_regex(pattern)?, s, maxsplit, heap))
+    }
+}
+
+#[starlark_module]
+fn regex_methods(builder: &mut GlobalsBuilder) {
+    /// See `re.match`.
+    fn r#match(this: ARef<RegexValue>, ref s: &str) -> Value<'v> {
+        Ok(re_match(&this.0, s, heap))
+    }
+
+    /// See `re.search`.
+    fn search(this: ARef<RegexValue>, ref s: &str) -> Value<'v> {
+        Ok(re_search(&this.0, s, heap))
+    }
+
+    /// See `re.findall`.
+    fn findall(this: ARef<RegexValue>, ref s: &str) -> Value<'v> {
+        Ok(re_findall(&this.0, s, heap))
+    }
+
+    /// See `re.sub`.
+    fn sub(this: ARef<RegexValue>, ref repl: &str, ref s: &str, count @ 0: i32) -> String {
+        Ok(re_sub(&this.0, repl, s, count))
+    }
+
+    /// See `re.split`.
+    fn split(this: ARef<RegexValue>, ref s: &str, maxsplit @ 0: i32) -> Value<'v> {
+        Ok(re_split(&this.0, s, maxsplit, heap))
+    }
+
+    /// The source of the pattern.
+    #[attribute]
+    fn pattern(this: ARef<RegexValue>) -> String {
+        Ok(this.0.as_str().to_owned())
+    }
+}
+
+pub fn re(builder: &mut GlobalsBuilder) {
+    builder.struct_("re", re_members)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::assert::{self, Assert};
+
+    #[test]
+    fn test_match_search() {
+        assert::all_true(
+            r#"
+re.match("a(b)?(c)?", "abx") == ["ab", "b", None]
+re.match("b", "abc") == None
+re.search("b", "abc") == ["b"]
+re.search("x", "abc") == None
+re.compile("[0-9]+").search("ab12cd") == ["12"]
+re.compile("[0-9]+").match("ab12cd") == None
+"#,
+        );
+    }
+
+    #[test]
+    fn test_findall_sub_split() {
+        assert::all_true(
+            r##"
+re.findall("[0-9]+", "a1b22c333") == ["1", "22", "333"]
+re.findall("([a-z])[0-9]", "a1b22") == ["a", "b"]
+re.findall("([a-z])([0-9])", "a1b22") == [("a", "1"), ("b", "2")]
+re.sub("[0-9]", "#", "a1b22") == "a#b##"
+re.sub("[0-9]", "#", "a1b22", 2) == "a#b#2"
+re.sub("([a-z])([0-9])", "$2$1", "a1b2") == "1a2b"
+re.split(", *", "a,b,  c") == ["a", "b", "c"]
+re.split(",", "a,b,c", maxsplit = 1) == ["a", "b,c"]
+re.compile(",").split("a,b") == ["a", "b"]
+re.compile(",").sub(";", "a,b") == "a;b"
+re.compile("([a-z])").findall("a1b") == ["a", "b"]
+"##,
+        );
+    }
+
+    #[test]
+    fn test_regex_value() {
+        assert::all_true(
+            r#"
+type(re.compile("a+")) == "regex"
+repr(re.compile("a\"+")) == 're.compile("a\\"+")'
+re.compile("a+").pattern == "a+"
+re.compile("a+") == re.compile("a+")
+re.compile("a+") != re.compile("b+")
+"#,
+        );
+        assert::fail("re.compile('(')", "unclosed group");
+        assert::fail(
+            "re.search(1, 'a')",
+            "Type of parameter `pattern` doesn't match",
+        );
+    }
+
+    #[test]
+    fn test_regex_frozen() {
+        let mut a = Assert::new();
+        a.module("patterns", "digits = re.compile('[0-9]+')");
+        a.is_true(
+            r#"
+load("patterns", "digits")
+digits.findall("1 2 3") == ["1", "2", "3"]
+"#,
+        );
+    }
+}
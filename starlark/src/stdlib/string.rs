@@ -23,8 +23,8 @@ use crate::{
     environment::GlobalsBuilder,
     stdlib::util::convert_indices,
     values::{
-        fast_string, interpolation, list::List, none::NoneOr, string, tuple::Tuple, UnpackValue,
-        Value, ValueError,
+        fast_string, interpolation, list::List, none::NoneOr, string, tuple::Tuple, StringIndexing,
+        UnpackValue, Value, ValueError,
     },
 };
 use anyhow::anyhow;
@@ -105,20 +105,27 @@ impl<'v> UnpackValue<'v> for StringOrTuple<'v> {
     }
 }
 
+/// Convert the optional `start` and `end` arguments, which count characters,
+/// into bounds for [`fast_string::substring_stored`].
+fn convert_str_indices(s: &str, start: NoneOr<i32>, end: NoneOr<i32>) -> (usize, usize) {
+    convert_indices(fast_string::len_stored(s) as i32, start, end)
+}
+
 #[starlark_module]
 pub(crate) fn string_methods(builder: &mut GlobalsBuilder) {
     /// [string.elems](
     /// https://github.com/google/skylark/blob/3705afa472e466b8b061cce44b47c9ddc6db696d/doc/spec.md#string·elems
-    /// ): returns an iterable of the bytes values of a string.
+    /// ): returns an iterable of the elements of a string.
     ///
-    /// `S.elems()` returns an iterable value containing the
-    /// sequence of numeric bytes values in the string S.
+    /// `S.elems()` returns an iterable value containing successive
+    /// 1-element substrings of S. Elements are Unicode code points, so
+    /// this is the same as `S.codepoints()`. When strings are indexed by byte
+    /// (see [`StringIndexing`](crate::values::StringIndexing)) the elements are
+    /// bytes, so S must be ASCII.
     ///
-    /// To materialize the entire sequence of bytes, apply `list(...)` to the
+    /// To materialize the entire sequence of elements, apply `list(...)` to the
     /// result.
     ///
-    /// Example:
-    ///
     /// Examples:
     ///
     /// ```
@@ -128,9 +135,35 @@ pub(crate) fn string_methods(builder: &mut GlobalsBuilder) {
     /// # "#);
     /// ```
     fn elems(this: Value<'v>) -> Value<'v> {
+        if fast_string::string_indexing() == StringIndexing::Bytes {
+            fast_string::check_ascii(this.unpack_str().unwrap())?;
+        }
         Ok(string::iterate_chars(this, heap))
     }
 
+    /// [string.elem_ords](
+    /// https://github.com/bazelbuild/starlark/blob/master/spec.md#string·elem_ords
+    /// ): returns an iterable of the numeric values of the elements of a string.
+    ///
+    /// `S.elem_ords()` returns an iterable value containing the sequence
+    /// of integer values of the elements of S, which are Unicode code points,
+    /// so this is the same as `S.codepoint_ords()`. When strings are indexed by
+    /// byte they are the bytes of the UTF-8 encoding of S.
+    ///
+    /// Examples:
+    ///
+    /// ```
+    /// # starlark::assert::all_true(r#"
+    /// list("Hello, 世界".elem_ords()) == [72, 101, 108, 108, 111, 44, 32, 19990, 30028]
+    /// # "#);
+    /// ```
+    fn elem_ords(this: Value<'v>) -> Value<'v> {
+        match fast_string::string_indexing() {
+            StringIndexing::CodePoints => Ok(string::iterate_codepoints(this, heap)),
+            StringIndexing::Bytes => Ok(string::iterate_bytes(this, heap)),
+        }
+    }
+
     /// [string.capitalize](
     /// https://github.com/google/skylark/blob/3705afa472e466b8b061cce44b47c9ddc6db696d/doc/spec.md#string·capitalize
     /// ): returns a copy of string, with each first letter of a word in upper
@@ -167,30 +200,45 @@ pub(crate) fn string_methods(builder: &mut GlobalsBuilder) {
         Ok(result)
     }
 
-    /// [string.codepoints](
-    /// https://github.com/google/skylark/blob/3705afa472e466b8b061cce44b47c9ddc6db696d/doc/spec.md#string·codepoints
-    /// ): returns an iterable of the unicode codepoint of a string.
+    /// [string.codepoint_ords](
+    /// https://github.com/bazelbuild/starlark/blob/master/spec.md#string·codepoint_ords
+    /// ): returns an iterable of the unicode codepoints of a string.
     ///
-    /// `S.codepoints()` returns an iterable value containing the
+    /// `S.codepoint_ords()` returns an iterable value containing the
     /// sequence of integer Unicode code points encoded by the string S.
-    /// Each invalid code within the string is treated as if it encodes the
-    /// Unicode replacement character, U+FFFD.
     ///
     /// By returning an iterable, not a list, the cost of decoding the string
     /// is deferred until actually needed; apply `list(...)` to the result to
     /// materialize the entire sequence.
     ///
-    /// Example:
+    /// Examples:
+    ///
+    /// ```
+    /// # starlark::assert::all_true(r#"
+    /// list("Hello, 世界".codepoint_ords()) == [72, 101, 108, 108, 111, 44, 32, 19990, 30028]
+    /// # "#);
+    /// ```
+    fn codepoint_ords(this: Value<'v>) -> Value<'v> {
+        Ok(string::iterate_codepoints(this, heap))
+    }
+
+    /// [string.codepoints](
+    /// https://github.com/bazelbuild/starlark/blob/master/spec.md#string·codepoints
+    /// ): returns an iterable of the unicode codepoints of a string.
+    ///
+    /// `S.codepoints()` returns an iterable value containing the
+    /// sequence of substrings of S that each encode a single Unicode code point.
+    /// Use `S.codepoint_ords()` to get the code points as integers.
     ///
     /// Examples:
     ///
     /// ```
     /// # starlark::assert::all_true(r#"
-    /// list("Hello, 世界".codepoints()) == [72, 101, 108, 108, 111, 44, 32, 19990, 30028]
+    /// list("Hello, 世界".codepoints()) == ["H", "e", "l", "l", "o", ",", " ", "世", "界"]
     /// # "#);
     /// ```
     fn codepoints(this: Value<'v>) -> Value<'v> {
-        Ok(string::iterate_codepoints(this, heap))
+        Ok(string::iterate_chars(this, heap))
     }
 
     /// [string.count](
@@ -225,9 +273,8 @@ pub(crate) fn string_methods(builder: &mut GlobalsBuilder) {
         ref end @ NoneOr::None: NoneOr<i32>,
     ) -> i32 {
         if !start.is_none() || !end.is_none() {
-            let (start, end) = convert_indices(this.len() as i32, start, end);
-            // FIXME: This unwrap can be triggered by users, should bubble up an error
-            this = this.get(start..end).unwrap();
+            let (start, end) = convert_str_indices(this, start, end);
+            this = fast_string::substring_stored(this, start, end)?;
         }
         Ok(fast_string::count_matches(this, needle) as i32)
     }
@@ -282,11 +329,10 @@ pub(crate) fn string_methods(builder: &mut GlobalsBuilder) {
         ref start @ NoneOr::None: NoneOr<i32>,
         ref end @ NoneOr::None: NoneOr<i32>,
     ) -> i32 {
-        let (start, end) = convert_indices(this.len() as i32, start, end);
-        if let Some(substring) = this.get(start..end) {
-            if let Some(offset) = substring.find(needle) {
-                return Ok((offset + start) as i32);
-            }
+        let (start, end) = convert_str_indices(this, start, end);
+        let substring = fast_string::substring_stored(this, start, end)?;
+        if let Some(offset) = substring.find(needle) {
+            return Ok((fast_string::len(&substring[..offset]) + start) as i32);
         }
         Ok(-1)
     }
@@ -321,6 +367,9 @@ pub(crate) fn string_methods(builder: &mut GlobalsBuilder) {
     /// If all the numeric field names form the sequence 0, 1, 2, and so on,
     /// they may be omitted and those values will be implied; however,
     /// the explicit and implicit forms may not be mixed.
+    /// The field name may be followed by any number of `.attr` attribute
+    /// accesses and `[key]` index operations, e.g. `{0.name}` or `{x[key]}`.
+    /// A key made of digits is an integer, otherwise it is a string.
     ///
     /// The *conversion* specifies how to convert an argument value `x` to a
     /// string. It may be either `!r`, which converts the value using
@@ -328,8 +377,17 @@ pub(crate) fn string_methods(builder: &mut GlobalsBuilder) {
     /// the default.
     ///
     /// The *format specifier*, after a colon, specifies field width,
-    /// alignment, padding, and numeric precision.
-    /// Currently it must be empty, but it is reserved for future use.
+    /// alignment, padding, and numeric precision, following
+    /// [Python](https://docs.python.org/3/library/string.html#format-specification-mini-language):
+    ///
+    /// ```text
+    /// [[fill]align][sign][#][0][width][,][.precision][type]
+    /// ```
+    ///
+    /// Where `align` is one of `<`, `>`, `^` or `=`, `sign` is one of `+`, `-` or
+    /// space, and `type` is `s` for strings, or one of `b`, `c`, `d`, `n`, `o`, `x`
+    /// or `X` for integers. The width and precision may be given by nested fields,
+    /// e.g. `{:{width}}`.
     ///
     /// Examples:
     ///
@@ -340,10 +398,13 @@ pub(crate) fn string_methods(builder: &mut GlobalsBuilder) {
     /// "a{}b{}c".format(1, 2) == "a1b2c"
     /// "({1}, {0})".format("zero", "one") == "(one, zero)"
     /// "Is {0!r} {0!s}?".format("heterological") == "Is \"heterological\" heterological?"
+    /// "{:>6}|{:<4}|".format("right", "l") == " right|l   |"
+    /// "{:8,d}".format(12345) == "  12,345"
+    /// "{0.x}{d[k]}".format(struct(x = 1), d = {"k": 2}) == "12"
     /// # "#);
     /// ```
     fn format(this: &str, args: Vec<Value>, kwargs: SmallMap<&str, Value>) -> String {
-        interpolation::format(this, args, kwargs)
+        interpolation::format(this, args, kwargs, eval)
     }
 
     /// [string.index](
@@ -371,11 +432,10 @@ pub(crate) fn string_methods(builder: &mut GlobalsBuilder) {
         ref start @ NoneOr::None: NoneOr<i32>,
         ref end @ NoneOr::None: NoneOr<i32>,
     ) -> i32 {
-        let (start, end) = convert_indices(this.len() as i32, start, end);
-        if let Some(substring) = this.get(start..end) {
-            if let Some(offset) = substring.find(needle) {
-                return Ok((offset + start) as i32);
-            }
+        let (start, end) = convert_str_indices(this, start, end);
+        let substring = fast_string::substring_stored(this, start, end)?;
+        if let Some(offset) = substring.find(needle) {
+            return Ok((fast_string::len(&substring[..offset]) + start) as i32);
         }
         Err(anyhow!("Substring '{}' not found in '{}'", needle, this))
     }
@@ -750,11 +810,10 @@ pub(crate) fn string_methods(builder: &mut GlobalsBuilder) {
         ref start @ NoneOr::None: NoneOr<i32>,
         ref end @ NoneOr::None: NoneOr<i32>,
     ) -> i32 {
-        let (start, end) = convert_indices(this.len() as i32, start, end);
-        if let Some(substring) = this.get(start..end) {
-            if let Some(offset) = substring.rfind(needle) {
-                return Ok((offset + start) as i32);
-            }
+        let (start, end) = convert_str_indices(this, start, end);
+        let substring = fast_string::substring_stored(this, start, end)?;
+        if let Some(offset) = substring.rfind(needle) {
+            return Ok((fast_string::len(&substring[..offset]) + start) as i32);
         }
         Ok(-1)
     }
@@ -784,11 +843,10 @@ pub(crate) fn string_methods(builder: &mut GlobalsBuilder) {
         ref start @ NoneOr::None: NoneOr<i32>,
         ref end @ NoneOr::None: NoneOr<i32>,
     ) -> i32 {
-        let (start, end) = convert_indices(this.len() as i32, start, end);
-        if let Some(substring) = this.get(start..end) {
-            if let Some(offset) = substring.rfind(needle) {
-                return Ok((offset + start) as i32);
-            }
+        let (start, end) = convert_str_indices(this, start, end);
+        let substring = fast_string::substring_stored(this, start, end)?;
+        if let Some(offset) = substring.rfind(needle) {
+            return Ok((fast_string::len(&substring[..offset]) + start) as i32);
         }
         Err(anyhow!("Substring '{}' not found in '{}'", needle, this))
     }
@@ -17,7 +17,10 @@
 
 //! AST for parsed starlark files.
 
-use crate::codemap::{CodeMap, Pos, Span, Spanned};
+use crate::{
+    codemap::{CodeMap, Pos, Span, Spanned},
+    values::bytes::collect_bytes_repr,
+};
 use derivative::Derivative;
 use gazebo::prelude::*;
 use static_assertions::assert_eq_size;
@@ -34,6 +37,7 @@ pub type AstArgument = Spanned<Argument>;
 pub type AstString = Spanned<String>;
 pub type AstParameter = Spanned<Parameter>;
 pub type AstInt = Spanned<i32>;
+pub type AstBytes = Spanned<Vec<u8>>;
 pub type AstStmt = Spanned<Stmt>;
 
 // We don't care _that_ much about the size of these structures,
@@ -81,15 +85,19 @@ pub enum Argument {
 pub enum Parameter {
     Normal(AstString, Option<Box<AstExpr>>),
     WithDefaultValue(AstString, Option<Box<AstExpr>>, Box<AstExpr>),
+    /// The `/` marker, all the parameters before it are positional-only.
+    Slash,
     NoArgs,
     Args(AstString, Option<Box<AstExpr>>),
     KwArgs(AstString, Option<Box<AstExpr>>),
 }
 
 #[derive(Debug, Clone)]
+#[allow(clippy::enum_variant_names)]
 pub enum AstLiteral {
     IntLiteral(AstInt),
     StringLiteral(AstString),
+    BytesLiteral(AstBytes),
 }
 
 #[derive(Debug)]
@@ -107,6 +115,7 @@ pub enum Expr {
     Identifier(AstString),
     Lambda(Vec<AstParameter>, Box<AstExpr>),
     Literal(AstLiteral),
+    FString(Vec<FStringPart>),
     Not(Box<AstExpr>),
     Minus(Box<AstExpr>),
     Plus(Box<AstExpr>),
@@ -119,6 +128,15 @@ pub enum Expr {
     DictComprehension(Box<(AstExpr, AstExpr)>, Box<ForClause>, Vec<Clause>),
 }
 
+/// A piece of an f-string, e.g. `f"x = {x!r:>5}"` is a literal `x = ` followed by an expression
+/// with conversion `r` and format spec `>5`. The spec is made of parts too, since it may contain
+/// fields, e.g. `f"{x:>{width}}"`.
+#[derive(Debug)]
+pub enum FStringPart {
+    Literal(String),
+    Expr(AstExpr, Option<char>, Option<Vec<FStringPart>>),
+}
+
 /// In some places e.g. AssignModify, the Tuple case is not allowed.
 #[derive(Debug)]
 pub enum Assign {
@@ -285,6 +303,11 @@ where
 
 fn fmt_string_literal(f: &mut Formatter<'_>, s: &str) -> fmt::Result {
     f.write_str("\"")?;
+    fmt_string_chars(f, s)?;
+    f.write_str("\"")
+}
+
+fn fmt_string_chars(f: &mut Formatter<'_>, s: &str) -> fmt::Result {
     for c in s.chars() {
         match c {
             '\n' => f.write_str("\\n")?,
@@ -296,7 +319,34 @@ fn fmt_string_literal(f: &mut Formatter<'_>, s: &str) -> fmt::Result {
             x => f.write_str(&x.to_string())?,
         }
     }
-    f.write_str("\"")
+    Ok(())
+}
+
+impl Display for FStringPart {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            FStringPart::Literal(s) => {
+                fmt_string_chars(f, &s.replace('{', "{{").replace('}', "}}"))
+            }
+            FStringPart::Expr(e, conversion, spec) => {
+                write!(f, "{{{}", e.node)?;
+                if let Some(c) = conversion {
+                    write!(f, "!{}", c)?;
+                }
+                if let Some(spec) = spec {
+                    f.write_str(":")?;
+                    for x in spec {
+                        match x {
+                            // A spec can't contain braces, so is written as it is
+                            FStringPart::Literal(s) => f.write_str(s)?,
+                            x => x.fmt(f)?,
+                        }
+                    }
+                }
+                f.write_str("}")
+            }
+        }
+    }
 }
 
 impl Display for AstLiteral {
@@ -304,6 +354,11 @@ impl Display for AstLiteral {
         match self {
             AstLiteral::IntLiteral(i) => i.node.fmt(f),
             AstLiteral::StringLiteral(s) => fmt_string_literal(f, &s.node),
+            AstLiteral::BytesLiteral(b) => {
+                let mut s = String::new();
+                collect_bytes_repr(&b.node, &mut s);
+                f.write_str(&s)
+            }
         }
     }
 }
@@ -386,6 +441,13 @@ impl Display for Expr {
                 f.write_str("}}")
             }
             Expr::Literal(x) => x.fmt(f),
+            Expr::FString(parts) => {
+                f.write_str("f\"")?;
+                for x in parts {
+                    x.fmt(f)?;
+                }
+                f.write_str("\"")
+            }
         }
     }
 }
@@ -421,6 +483,7 @@ impl Display for Parameter {
         let (prefix, name, typ, default) = match self {
             Parameter::Normal(s, t) => ("", s, t, None),
             Parameter::WithDefaultValue(s, t, e) => ("", s, t, Some(e)),
+            Parameter::Slash => return write!(f, "/"),
             Parameter::NoArgs => return write!(f, "*"),
             Parameter::Args(s, t) => ("*", s, t, None),
             Parameter::KwArgs(s, t) => ("**", s, t, None),
@@ -24,7 +24,7 @@ use gazebo::prelude::*;
 use thiserror::Error;
 
 #[derive(Error, Debug)]
-enum DialectError {
+pub(crate) enum DialectError {
     #[error("`def` is not allowed in this dialect")]
     Def,
     #[error("`lambda` is not allowed in this dialect")]
@@ -33,8 +33,12 @@ enum DialectError {
     Load,
     #[error("* keyword-only-arguments is not allowed in this dialect")]
     KeywordOnlyArguments,
+    #[error("/ positional-only-arguments is not allowed in this dialect")]
+    PositionalOnlyArguments,
     #[error("type annotations are not allowed in this dialect")]
     Types,
+    #[error("f-strings are not allowed in this dialect")]
+    FStrings,
 }
 
 /// Starlark language features to enable, e.g. [`Standard`](Dialect::Standard) to follow the Starlark standard.
@@ -52,6 +56,9 @@ pub struct Dialect {
     /// Are `*` keyword-only arguments allowed as per [PEP 3102](https://www.python.org/dev/peps/pep-3102/).
     /// Only enabled in [`Extended`](Dialect::Extended).
     pub enable_keyword_only_arguments: bool,
+    /// Are `/` positional-only arguments allowed as per [PEP 570](https://www.python.org/dev/peps/pep-0570/).
+    /// Only enabled in [`Extended`](Dialect::Extended).
+    pub enable_positional_only_arguments: bool,
     /// Are expressions allowed in type positions as per [PEP 484](https://www.python.org/dev/peps/pep-0484/).
     /// Only enabled in [`Extended`](Dialect::Extended).
     pub enable_types: bool,
@@ -65,6 +72,10 @@ pub struct Dialect {
     /// Are `for`, `if` and other statements allowed at the top level.
     /// Only enabled in [`Extended`](Dialect::Extended).
     pub enable_top_level_stmt: bool,
+    /// Are f-strings such as `f"{name}-{version}"` permitted, as per [PEP 498](https://www.python.org/dev/peps/pep-0498/).
+    /// As in Python, a format spec may contain fields, e.g. `f"{x:>{width}}"`, but only one level deep.
+    /// Only enabled in [`Extended`](Dialect::Extended).
+    pub enable_f_strings: bool,
 }
 
 // These are morally enumerations, so give them enumeration-like names
@@ -77,10 +88,12 @@ impl Dialect {
         enable_lambda: true,
         enable_load: true,
         enable_keyword_only_arguments: false,
+        enable_positional_only_arguments: false,
         enable_types: false,
         enable_tabs: true,
         enable_load_reexport: true, // But they plan to change it
         enable_top_level_stmt: false,
+        enable_f_strings: false,
     };
 
     /// A superset of [`Standard`](Dialect::Standard), including extra features (types, top-level statements etc).
@@ -89,10 +102,12 @@ impl Dialect {
         enable_lambda: true,
         enable_load: true,
         enable_keyword_only_arguments: true,
+        enable_positional_only_arguments: true,
         enable_types: true,
         enable_tabs: true,
         enable_load_reexport: true,
         enable_top_level_stmt: true,
+        enable_f_strings: true,
     };
 }
 
@@ -152,6 +167,21 @@ impl Dialect {
         }
     }
 
+    pub(crate) fn check_positional_only_arguments<T>(
+        &self,
+        codemap: &CodeMap,
+        begin: usize,
+        end: usize,
+        x: T,
+    ) -> anyhow::Result<T> {
+        let span = Span::new(Pos::new(begin as u32), Pos::new(end as u32));
+        if self.enable_positional_only_arguments {
+            Ok(x)
+        } else {
+            err(codemap, span, DialectError::PositionalOnlyArguments)
+        }
+    }
+
     pub(crate) fn check_type<T>(
         &self,
         codemap: &CodeMap,
@@ -164,6 +194,21 @@ impl Dialect {
         }
     }
 
+    pub(crate) fn check_f_string<T>(
+        &self,
+        codemap: &CodeMap,
+        begin: usize,
+        end: usize,
+        x: T,
+    ) -> anyhow::Result<T> {
+        let span = Span::new(Pos::new(begin as u32), Pos::new(end as u32));
+        if self.enable_f_strings {
+            Ok(x)
+        } else {
+            err(codemap, span, DialectError::FStrings)
+        }
+    }
+
     pub(crate) fn load_visibility(&self) -> Visibility {
         if self.enable_load_reexport {
             Visibility::Public
@@ -15,7 +15,11 @@
  * limitations under the License.
  */
 
-use crate::{assert, assert::Assert, syntax::ast::Stmt};
+use crate::{
+    assert,
+    assert::Assert,
+    syntax::{ast::Stmt, Dialect},
+};
 use gazebo::prelude::*;
 
 #[test]
@@ -298,3 +302,20 @@ fn test_bad_assignment() {
     assert::parse_fail("[!x or y!] = 1");
     assert::parse_fail("![x]! += 1");
 }
+
+#[test]
+fn test_f_string() {
+    assert_eq!(
+        assert::parse("f'{x} and {y!r:>5}'"),
+        "f\"{x} and {y!r:>5}\"\n"
+    );
+    assert_eq!(
+        assert::parse("f'{x:>{w + 1}.{p}f}'"),
+        "f\"{x:>{(w + 1)}.{p}f}\"\n"
+    );
+    assert::parse_fail("f'{!x = 1!}'");
+    assert::parse_fail("f'{1 !)! 2}'");
+    let mut a = Assert::new();
+    a.dialect(&Dialect::Standard);
+    a.parse_fail("!f'{x}'!");
+}
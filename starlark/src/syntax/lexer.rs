@@ -22,6 +22,7 @@ use crate::{
         cursors::{CursorBytes, CursorChars},
         dialect::Dialect,
     },
+    values::bytes::collect_bytes_repr,
 };
 use gazebo::dupe::Dupe;
 use logos::Logos;
@@ -46,6 +47,8 @@ pub(crate) enum LexemeError {
     StartsZero(String),
     #[error("Parse error: integer overflow, must fit in 32 bits, got `{0}`")]
     IntOverflow(String),
+    #[error("Parse error: invalid f-string, {0}")]
+    InvalidFString(&'static str),
 }
 
 type Lexeme = anyhow::Result<(usize, Token, usize)>;
@@ -82,6 +85,23 @@ impl<'a> Lexer<'a> {
         lexer2
     }
 
+    /// Lex the expression inside an f-string field, which runs from byte `start` to the end of `input`.
+    /// The input is the start of the whole file, so that the positions of tokens are correct.
+    /// The expression is treated as though it were in brackets, so may span multiple lines.
+    pub fn new_fragment(input: &'a str, start: usize, dialect: &Dialect, codemap: CodeMap) -> Self {
+        let mut lexer = Token::lexer(input);
+        lexer.bump(start);
+        Self {
+            codemap,
+            indent_levels: Vec::new(),
+            buffer: VecDeque::new(),
+            lexer,
+            parens: 1,
+            done: false,
+            dialect_allow_tabs: dialect.enable_tabs,
+        }
+    }
+
     fn err_pos<T>(&self, msg: LexemeError, pos: usize) -> anyhow::Result<T> {
         self.err_span(msg, pos, pos)
     }
@@ -378,6 +398,235 @@ impl<'a> Lexer<'a> {
         )
     }
 
+    // Turn the raw string produced by `string` into bytes, decoding any escapes.
+    // Unlike strings, `\x` and octal escapes denote a single byte, not a code point.
+    fn bytes(&self, res: Lexeme, raw: bool) -> Lexeme {
+        let (start, s, end) = match res {
+            Ok((start, Token::StringLiteral(s), end)) => (start, s, end),
+            res => return res,
+        };
+        if raw {
+            return Ok((start, Token::BytesLiteral(s.into_bytes()), end));
+        }
+        let mut res = Vec::with_capacity(s.len());
+        let mut buf = String::new();
+        let mut it = CursorChars::new_offset(&s, 0);
+        while let Some(c) = it.next() {
+            if c != '\\' {
+                res.extend(c.encode_utf8(&mut [0; 4]).as_bytes());
+                continue;
+            }
+            let pos = it.pos();
+            let byte = match it.next() {
+                Some('x') => Self::escape_char(&mut it, 2, 2, 16).map(Some),
+                Some(c @ '0'..='7') => {
+                    it.unnext(c);
+                    Self::escape_char(&mut it, 1, 3, 8).map(Some)
+                }
+                Some(c) => {
+                    it.unnext(c);
+                    buf.clear();
+                    Self::escape(&mut it, &mut buf).map(|_| None)
+                }
+                None => Err(()),
+            };
+            match byte {
+                Ok(Some(b)) if (b as u32) < 256 => res.push(b as u32 as u8),
+                Ok(None) => res.extend(buf.as_bytes()),
+                _ => {
+                    return self.err_span(
+                        LexemeError::InvalidEscapeSequence(s[pos - 1..it.pos()].to_owned()),
+                        start,
+                        end,
+                    );
+                }
+            }
+        }
+        Ok((start, Token::BytesLiteral(res), end))
+    }
+
+    // Split an f-string into literal text and fields. We use the original source between the quotes,
+    // since the raw string produced by `string` has already dropped the backslash from `\'` and `\"`.
+    fn f_string(&self, res: Lexeme, prefix_len: usize, triple: bool, raw: bool) -> Lexeme {
+        let (start, end) = match res {
+            Ok((start, _, end)) => (start, end),
+            res => return res,
+        };
+        let quotes = if triple { 3 } else { 1 };
+        // The prefix includes the first quote character
+        let offset = start + prefix_len + quotes - 1;
+        let content = &self.lexer.source()[offset..end - quotes];
+        let b = content.as_bytes();
+
+        let mut chunks = Vec::new();
+        let mut literal = String::new();
+        let mut literal_start = 0;
+        let mut i = 0;
+        while i < b.len() {
+            match b[i] {
+                b'{' | b'}' if b.get(i + 1) == Some(&b[i]) => {
+                    literal.push_str(&content[literal_start..=i]);
+                    i += 2;
+                    literal_start = i;
+                }
+                b'}' => {
+                    return self.err_span(
+                        LexemeError::InvalidFString("single '}' is not allowed"),
+                        offset + i,
+                        offset + i + 1,
+                    );
+                }
+                b'{' => {
+                    literal.push_str(&content[literal_start..i]);
+                    if !literal.is_empty() {
+                        let x = self.f_string_literal(&literal, raw, start, end)?;
+                        chunks.push(FStringChunk::Literal(x));
+                        literal.clear();
+                    }
+                    let (field, next) = self.f_string_field(content, offset, i + 1, false)?;
+                    chunks.push(field);
+                    i = next;
+                    literal_start = i;
+                }
+                _ => i += 1,
+            }
+        }
+        literal.push_str(&content[literal_start..]);
+        if !literal.is_empty() {
+            let x = self.f_string_literal(&literal, raw, start, end)?;
+            chunks.push(FStringChunk::Literal(x));
+        }
+        Ok((start, Token::FStringLiteral(chunks), end))
+    }
+
+    // Decode the escapes in the literal text of an f-string, in the same way as `string`.
+    fn f_string_literal(
+        &self,
+        s: &str,
+        raw: bool,
+        start: usize,
+        end: usize,
+    ) -> anyhow::Result<String> {
+        let mut res = String::with_capacity(s.len());
+        let mut it = CursorChars::new_offset(s, 0);
+        while let Some(c) = it.next() {
+            match c {
+                '\r' => {}
+                '\\' if raw => match it.next() {
+                    Some(c @ ('\'' | '"')) => res.push(c),
+                    Some(c) => {
+                        res.push('\\');
+                        res.push(c);
+                    }
+                    None => res.push('\\'),
+                },
+                '\\' => {
+                    let pos = it.pos();
+                    if Self::escape(&mut it, &mut res).is_err() {
+                        return self.err_span(
+                            LexemeError::InvalidEscapeSequence(s[pos..it.pos()].to_owned()),
+                            start,
+                            end,
+                        );
+                    }
+                }
+                c => res.push(c),
+            }
+        }
+        Ok(res)
+    }
+
+    // Parse the f-string field which starts at byte `start` of `content`, just after the `{`.
+    // The `content` starts at byte `offset` of the file. As in Python, the format spec may
+    // contain fields itself, e.g. `{x:>{width}}`, but those can't be nested any further.
+    // Returns the field, and the position just after its closing `}`.
+    fn f_string_field(
+        &self,
+        content: &str,
+        offset: usize,
+        start: usize,
+        nested: bool,
+    ) -> anyhow::Result<(FStringChunk, usize)> {
+        let err = |msg, pos: usize| {
+            self.err_span(
+                LexemeError::InvalidFString(msg),
+                offset + pos,
+                offset + pos + 1,
+            )
+        };
+        let b = content.as_bytes();
+
+        // Find the end of the expression, skipping over brackets and strings
+        let mut depth = 0;
+        let mut quote = None;
+        let mut i = start;
+        let expr_end = loop {
+            let c = match b.get(i) {
+                None => return err("expecting '}'", i),
+                Some(c) => *c,
+            };
+            match quote {
+                Some(_) if c == b'\\' => i += 1,
+                Some(q) if c == q => quote = None,
+                Some(_) => {}
+                None => match c {
+                    b'\'' | b'"' => quote = Some(c),
+                    b'(' | b'[' | b'{' => depth += 1,
+                    b')' | b']' | b'}' if depth > 0 => depth -= 1,
+                    b'}' | b':' if depth == 0 => break i,
+                    b'!' if depth == 0 && b.get(i + 1) != Some(&b'=') => break i,
+                    _ => {}
+                },
+            }
+            i += 1;
+        };
+        if content[start..expr_end].trim().is_empty() {
+            return err("empty expression not allowed", start);
+        }
+
+        let mut conversion = None;
+        if b[i] == b'!' {
+            conversion = match b.get(i + 1) {
+                Some(b'r') => Some('r'),
+                Some(b's') => Some('s'),
+                _ => return err("conversion must be `!r` or `!s`", i),
+            };
+            i += 2;
+            if !matches!(b.get(i), Some(b':' | b'}')) {
+                return err("expecting ':' or '}' after the conversion", i);
+            }
+        }
+
+        let mut spec = None;
+        if b[i] == b':' {
+            let mut chunks = Vec::new();
+            i += 1;
+            loop {
+                let literal_start = i;
+                i = match content[i..].find(|c| c == '{' || c == '}') {
+                    Some(x) => i + x,
+                    None => return err("expecting '}'", content.len()),
+                };
+                if i > literal_start {
+                    chunks.push(FStringChunk::Literal(content[literal_start..i].to_owned()));
+                }
+                if b[i] == b'}' {
+                    break;
+                }
+                if nested {
+                    return err("fields in a format spec can't be nested any further", i);
+                }
+                let (field, next) = self.f_string_field(content, offset, i + 1, true)?;
+                chunks.push(field);
+                i = next;
+            }
+            spec = Some(chunks);
+        }
+
+        let field = FStringChunk::Field(offset + start, offset + expr_end, conversion, spec);
+        Ok((field, i + 1))
+    }
+
     pub fn next(&mut self) -> Option<Lexeme> {
         loop {
             // Note that this function doesn't always return - a few branches use `continue`
@@ -441,10 +690,16 @@ impl<'a> Lexer<'a> {
                             }
                         }
                         Token::RawDoubleQuote => {
-                            let raw = self.lexer.span().len() == 2;
-                            if self.lexer.remainder().starts_with("\"\"") {
+                            let prefix = self.lexer.slice();
+                            let prefix_len = prefix.len();
+                            let raw = prefix.contains('r');
+                            let bytes = prefix.contains('b');
+                            let f_string = prefix.contains('f');
+                            // Bytes and f-strings are lexed as raw strings, with the escapes decoded afterwards
+                            let triple = self.lexer.remainder().starts_with("\"\"");
+                            let res = if triple {
                                 let mut qs = 0;
-                                Some(self.string(true, raw, |c| {
+                                self.string(true, raw || bytes || f_string, |c| {
                                     if c == '\"' {
                                         qs += 1;
                                         qs == 3
@@ -452,16 +707,29 @@ impl<'a> Lexer<'a> {
                                         qs = 0;
                                         false
                                     }
-                                }))
+                                })
                             } else {
-                                Some(self.string(false, raw, |c| c == '\"'))
-                            }
+                                self.string(false, raw || bytes || f_string, |c| c == '\"')
+                            };
+                            Some(if bytes {
+                                self.bytes(res, raw)
+                            } else if f_string {
+                                self.f_string(res, prefix_len, triple, raw)
+                            } else {
+                                res
+                            })
                         }
                         Token::RawSingleQuote => {
-                            let raw = self.lexer.span().len() == 2;
-                            if self.lexer.remainder().starts_with("''") {
+                            let prefix = self.lexer.slice();
+                            let prefix_len = prefix.len();
+                            let raw = prefix.contains('r');
+                            let bytes = prefix.contains('b');
+                            let f_string = prefix.contains('f');
+                            // Bytes and f-strings are lexed as raw strings, with the escapes decoded afterwards
+                            let triple = self.lexer.remainder().starts_with("''");
+                            let res = if triple {
                                 let mut qs = 0;
-                                Some(self.string(true, raw, |c| {
+                                self.string(true, raw || bytes || f_string, |c| {
                                     if c == '\'' {
                                         qs += 1;
                                         qs == 3
@@ -469,10 +737,17 @@ impl<'a> Lexer<'a> {
                                         qs = 0;
                                         false
                                     }
-                                }))
+                                })
                             } else {
-                                Some(self.string(false, raw, |c| c == '\''))
-                            }
+                                self.string(false, raw || bytes || f_string, |c| c == '\'')
+                            };
+                            Some(if bytes {
+                                self.bytes(res, raw)
+                            } else if f_string {
+                                self.f_string(res, prefix_len, triple, raw)
+                            } else {
+                                res
+                            })
                         }
                         Token::OpeningCurly | Token::OpeningRound | Token::OpeningSquare => {
                             self.parens += 1;
@@ -513,9 +788,21 @@ pub enum Token {
     // things ourselves
     #[token("'")]
     #[token("r'")]
+    #[token("b'")]
+    #[token("rb'")]
+    #[token("br'")]
+    #[token("f'")]
+    #[token("rf'")]
+    #[token("fr'")]
     RawSingleQuote,
     #[token("\"")]
     #[token("r\"")]
+    #[token("b\"")]
+    #[token("rb\"")]
+    #[token("br\"")]
+    #[token("f\"")]
+    #[token("rf\"")]
+    #[token("fr\"")]
     RawDoubleQuote,
 
     #[regex(
@@ -534,7 +821,9 @@ pub enum Token {
     #[regex("0[oO][0-7]+", |_| 8)]
     IntegerLiteral(i32), // An integer literal (123, 0x1, 0b1011, 0o755, ...)
 
-    StringLiteral(String), // A string literal
+    StringLiteral(String),             // A string literal
+    BytesLiteral(Vec<u8>),             // A bytes literal
+    FStringLiteral(Vec<FStringChunk>), // An f-string literal, split into its literals and fields
 
     // Keywords
     #[token("and")]
@@ -656,6 +945,37 @@ pub enum Token {
     ClosingRound,
 }
 
+/// Part of an f-string, as split up by the lexer.
+#[derive(Debug, Clone, PartialEq)]
+pub enum FStringChunk {
+    /// Literal text, with any escapes decoded.
+    Literal(String),
+    /// A field `{expr!conversion:spec}`, where the expression is given by its byte range in the file,
+    /// and the spec is literal text, possibly with fields of its own.
+    Field(usize, usize, Option<char>, Option<Vec<FStringChunk>>),
+}
+
+impl FStringChunk {
+    /// Used for testing
+    fn unlex(&self, s: &mut String) {
+        match self {
+            FStringChunk::Literal(x) => s.push_str(x),
+            FStringChunk::Field(begin, end, conversion, spec) => {
+                s.push_str(&format!("{{{}..{}", begin, end));
+                if let Some(c) = conversion {
+                    s.push('!');
+                    s.push(*c);
+                }
+                if let Some(spec) = spec {
+                    s.push(':');
+                    spec.iter().for_each(|x| x.unlex(s));
+                }
+                s.push('}');
+            }
+        }
+    }
+}
+
 impl Token {
     /// Used for testing
     pub(crate) fn unlex(&self) -> String {
@@ -663,6 +983,24 @@ impl Token {
             Token::Indent => "\t".to_owned(),
             Token::Newline => "\n".to_owned(),
             Token::Dedent => "#dedent".to_owned(),
+            Token::BytesLiteral(x) => {
+                let mut s = String::new();
+                collect_bytes_repr(x, &mut s);
+                s
+            }
+            Token::FStringLiteral(xs) => {
+                let mut s = "f\"".to_owned();
+                for x in xs {
+                    match x {
+                        FStringChunk::Literal(x) => {
+                            s.push_str(&x.replace('{', "{{").replace('}', "}}"))
+                        }
+                        field => field.unlex(&mut s),
+                    }
+                }
+                s.push('"');
+                s
+            }
             Token::StringLiteral(x) => {
                 // The Rust {:?} is unstable, so changes between versions,
                 // instead use the JSON standard for string escapes.
@@ -753,6 +1091,12 @@ impl Display for Token {
             Token::Identifier(s) => write!(f, "identifier '{}'", s),
             Token::IntegerLiteral(i) => write!(f, "integer literal '{}'", i),
             Token::StringLiteral(s) => write!(f, "string literal '{}'", s),
+            Token::BytesLiteral(b) => {
+                let mut s = String::new();
+                collect_bytes_repr(b, &mut s);
+                write!(f, "bytes literal '{}'", s)
+            }
+            Token::FStringLiteral(_) => write!(f, "f-string literal"),
             Token::RawSingleQuote => write!(f, "starting '"),
             Token::RawDoubleQuote => write!(f, "starting \""),
             Token::Tabs => Ok(()),
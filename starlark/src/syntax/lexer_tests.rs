@@ -128,6 +128,35 @@ fn test_string_lit() {
     );
 }
 
+#[test]
+fn test_bytes_lit() {
+    assert_eq!(
+        assert::lex(r#"b'' b"a" b'\x00\xff' b'\377' b'é' br'\x00' rb"\n" b'''x'''"#),
+        r#"b"" b"a" b"\x00\xff" b"\xff" b"\xc3\xa9" b"\\x00" b"\\n" b"x" "#.to_owned() + "\n"
+    );
+    // Escapes that don't fit in a byte
+    assert::parse_fail(r#"!b'\400'!"#);
+    assert::parse_fail(r#"!b'\x4'!"#);
+}
+
+#[test]
+fn test_f_string_lit() {
+    assert_eq!(
+        assert::lex(r#"f'a{x}b' rf"\n{y!r:>5}" f'{{}}\t'"#),
+        r#"f"a{4..5}b" f"\n{15..16!r:>5}" f"{{}}	" "#.to_owned() + "\n"
+    );
+    assert::parse_fail("f'a !}! b'");
+    assert::parse_fail("f'a {!}!'");
+    assert_eq!(
+        assert::lex("f'{x:>{w}.{p!r}}'"),
+        "f\"{3..4:>{7..8}.{11..12!r}}\" \n"
+    );
+    assert::parse_fail("f'{x:{y:!{!z}}}'");
+    assert::fail("f'{x!a}'", "conversion must be `!r` or `!s`");
+    assert::fail("f'{x!rs}'", "expecting ':' or '}' after the conversion");
+    assert::fail("f'{x'", "expecting '}'");
+}
+
 #[test]
 fn test_string_escape() {
     assert_eq!(assert::lex("'\\0\\0\\1n'"), "\"\u{0}\u{0}\u{1}n\" \n");
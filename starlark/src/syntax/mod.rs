@@ -19,6 +19,7 @@
 
 pub use ast::AstModule;
 pub use dialect::Dialect;
+pub(crate) use dialect::DialectError;
 
 #[cfg(test)]
 mod grammar_tests;
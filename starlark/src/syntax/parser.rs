@@ -19,16 +19,21 @@ use crate::{
     codemap::{CodeMap, FileSpan, Pos, Span},
     errors::Diagnostic,
     syntax::{
-        ast::{AstModule, AstStmt, Stmt},
+        ast::{AstExpr, AstModule, AstStmt, Expr, FStringPart, Stmt},
         dialect::Dialect,
         grammar::StarlarkParser,
-        lexer::{Lexer, Token},
+        lexer::{FStringChunk, Lexer, Token},
     },
 };
-use anyhow::anyhow;
 use gazebo::prelude::*;
 use lalrpop_util as lu;
 use std::{fs, path::Path};
+use thiserror::Error;
+
+/// An error raised by the parser, as opposed to the lexer (see `LexemeError`).
+#[derive(Debug, Error)]
+#[error("{0}")]
+pub(crate) struct ParseError(String);
 
 fn one_of(expected: &[String]) -> String {
     let mut result = String::new();
@@ -89,7 +94,60 @@ pub(crate) fn parse_error_add_span(
         lu::ParseError::User { .. } => unreachable!(),
     };
 
-    Diagnostic::new(anyhow!(message), span, codemap)
+    Diagnostic::new(ParseError(message), span, codemap)
+}
+
+impl Expr {
+    /// Parse the expressions in the fields of an f-string, as split up by the lexer.
+    pub(crate) fn parse_f_string(
+        chunks: Vec<FStringChunk>,
+        codemap: &CodeMap,
+        dialect: &Dialect,
+    ) -> anyhow::Result<Expr> {
+        Ok(Expr::FString(Self::parse_f_string_parts(
+            chunks, codemap, dialect,
+        )?))
+    }
+
+    fn parse_f_string_parts(
+        chunks: Vec<FStringChunk>,
+        codemap: &CodeMap,
+        dialect: &Dialect,
+    ) -> anyhow::Result<Vec<FStringPart>> {
+        chunks.into_try_map(|x| match x {
+            FStringChunk::Literal(x) => Ok(FStringPart::Literal(x)),
+            FStringChunk::Field(begin, end, conversion, spec) => {
+                let lexer =
+                    Lexer::new_fragment(&codemap.source()[..end], begin, dialect, codemap.dupe());
+                let stmt = StarlarkParser::new()
+                    .parse(codemap, dialect, lexer)
+                    .map_err(|e| parse_error_add_span(e, end, codemap.dupe()))?;
+                let spec = match spec {
+                    None => None,
+                    Some(spec) => Some(Self::parse_f_string_parts(spec, codemap, dialect)?),
+                };
+                match single_expression(stmt) {
+                    Some(x) => Ok(FStringPart::Expr(x, conversion, spec)),
+                    None => Err(Diagnostic::new(
+                        ParseError(
+                            "Parse error: f-string fields must contain a single expression"
+                                .to_owned(),
+                        ),
+                        Span::new(Pos::new(begin as u32), Pos::new(end as u32)),
+                        codemap.dupe(),
+                    )),
+                }
+            }
+        })
+    }
+}
+
+fn single_expression(x: AstStmt) -> Option<AstExpr> {
+    match x.node {
+        Stmt::Expression(x) => Some(x),
+        Stmt::Statements(mut xs) if xs.len() == 1 => single_expression(xs.pop().unwrap()),
+        _ => None,
+    }
 }
 
 impl AstModule {
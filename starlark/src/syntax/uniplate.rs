@@ -19,7 +19,7 @@
 #![allow(clippy::many_single_char_names)]
 
 use crate::syntax::ast::{
-    Assign, AstExpr, AstStmt, AstString, Clause, Expr, ForClause, Parameter, Stmt,
+    Assign, AstExpr, AstStmt, AstString, Clause, Expr, FStringPart, ForClause, Parameter, Stmt,
 };
 
 enum Visit<'a> {
@@ -114,7 +114,7 @@ impl Parameter {
                 (Some(a), b.as_ref().map(|x| &**x), None)
             }
             Parameter::WithDefaultValue(a, b, c) => (Some(a), b.as_ref().map(|x| &**x), Some(&**c)),
-            Parameter::NoArgs => (None, None, None),
+            Parameter::Slash | Parameter::NoArgs => (None, None, None),
         }
     }
 
@@ -150,6 +150,17 @@ impl Expr {
                 f(body);
             }
             Expr::Literal(_) => {}
+            Expr::FString(parts) => {
+                fn visit<'a>(parts: &'a [FStringPart], f: &mut impl FnMut(&'a AstExpr)) {
+                    for x in parts {
+                        if let FStringPart::Expr(x, _, spec) = x {
+                            f(x);
+                            visit(spec.as_deref().unwrap_or_default(), f);
+                        }
+                    }
+                }
+                visit(parts, &mut f)
+            }
             Expr::Not(x) => f(x),
             Expr::Minus(x) => f(x),
             Expr::Plus(x) => f(x),
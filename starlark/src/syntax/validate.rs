@@ -33,7 +33,7 @@ use std::collections::HashSet;
 use thiserror::Error;
 
 #[derive(Error, Debug)]
-enum ValidateError {
+pub(crate) enum ValidateError {
     #[error("`break` cannot be used outside of a `for` loop")]
     BreakOutsideLoop,
     #[error("`continue` cannot be used outside of a `for` loop")]
@@ -61,7 +61,7 @@ enum ArgsStage {
 }
 
 #[derive(Error, Debug)]
-enum ArgumentDefinitionOrderError {
+pub(crate) enum ArgumentDefinitionOrderError {
     #[error("positional argument after non positional")]
     PositionalThenNonPositional,
     #[error("named argument after *args or **kwargs")]
@@ -75,6 +75,16 @@ enum ArgumentDefinitionOrderError {
 }
 
 impl Expr {
+    /// Lambda parameters follow the same rules as `def` parameters, see [`Stmt::check_def`].
+    pub fn check_lambda(
+        parameters: Vec<AstParameter>,
+        body: AstExpr,
+        codemap: &CodeMap,
+    ) -> anyhow::Result<Expr> {
+        check_parameters(&parameters, codemap)?;
+        Ok(Expr::Lambda(parameters, box body))
+    }
+
     /// We want to check a function call is well-formed.
     /// Our eventual plan is to follow the Python invariants, but for now, we are closer
     /// to the Starlark invariants.
@@ -159,8 +169,70 @@ fn test_param_name<'a, T>(
     Ok(())
 }
 
+fn check_parameters(parameters: &[AstParameter], codemap: &CodeMap) -> anyhow::Result<()> {
+    let err = |span, msg| Err(Diagnostic::new(msg, span, codemap.dupe()));
+
+    // you can't repeat argument names
+    let mut argset = HashSet::new();
+    // You can't have more than one *args/*, **kwargs
+    // **kwargs must be last
+    // You can't have a required `x` after an optional `y=1`
+    let mut seen_args = false;
+    let mut seen_kwargs = false;
+    let mut seen_optional = false;
+    let mut seen_positional_only = false;
+
+    for arg in parameters.iter() {
+        match &arg.node {
+            Parameter::Normal(n, ..) => {
+                if seen_kwargs || seen_optional {
+                    return err(arg.span, ArgumentUseOrderError::PositionalThenNonPositional);
+                }
+                test_param_name(&mut argset, n, arg, codemap)?;
+            }
+            Parameter::WithDefaultValue(n, ..) => {
+                if seen_kwargs {
+                    return err(arg.span, ArgumentUseOrderError::DefaultParameterAfterStars);
+                }
+                seen_optional = true;
+                test_param_name(&mut argset, n, arg, codemap)?;
+            }
+            Parameter::Slash => {
+                if argset.is_empty() || seen_positional_only || seen_args || seen_kwargs {
+                    return err(
+                        arg.span,
+                        ArgumentUseOrderError::PositionalOnlyMarkerPosition,
+                    );
+                }
+                seen_positional_only = true;
+            }
+            Parameter::NoArgs => {
+                if seen_args || seen_kwargs {
+                    return err(arg.span, ArgumentUseOrderError::ArgsParameterAfterStars);
+                }
+                seen_args = true;
+            }
+            Parameter::Args(n, ..) => {
+                if seen_args || seen_kwargs {
+                    return err(arg.span, ArgumentUseOrderError::ArgsParameterAfterStars);
+                }
+                seen_args = true;
+                test_param_name(&mut argset, n, arg, codemap)?;
+            }
+            Parameter::KwArgs(n, ..) => {
+                if seen_kwargs {
+                    return err(arg.span, ArgumentUseOrderError::MultipleKwargs);
+                }
+                seen_kwargs = true;
+                test_param_name(&mut argset, n, arg, codemap)?;
+            }
+        }
+    }
+    Ok(())
+}
+
 #[derive(Error, Debug)]
-enum ArgumentUseOrderError {
+pub(crate) enum ArgumentUseOrderError {
     #[error("duplicated parameter name")]
     DuplicateParameterName,
     #[error("positional parameter after non positional")]
@@ -171,6 +243,10 @@ enum ArgumentUseOrderError {
     ArgsParameterAfterStars,
     #[error("Multiple kwargs dictionary in parameters")]
     MultipleKwargs,
+    #[error(
+        "Positional-only marker `/` must follow a parameter, and come before any args or kwargs"
+    )]
+    PositionalOnlyMarkerPosition,
 }
 
 impl Stmt {
@@ -181,54 +257,7 @@ impl Stmt {
         stmts: AstStmt,
         codemap: &CodeMap,
     ) -> anyhow::Result<Stmt> {
-        let err = |span, msg| Err(Diagnostic::new(msg, span, codemap.dupe()));
-
-        // you can't repeat argument names
-        let mut argset = HashSet::new();
-        // You can't have more than one *args/*, **kwargs
-        // **kwargs must be last
-        // You can't have a required `x` after an optional `y=1`
-        let mut seen_args = false;
-        let mut seen_kwargs = false;
-        let mut seen_optional = false;
-
-        for arg in parameters.iter() {
-            match &arg.node {
-                Parameter::Normal(n, ..) => {
-                    if seen_kwargs || seen_optional {
-                        return err(arg.span, ArgumentUseOrderError::PositionalThenNonPositional);
-                    }
-                    test_param_name(&mut argset, n, arg, codemap)?;
-                }
-                Parameter::WithDefaultValue(n, ..) => {
-                    if seen_kwargs {
-                        return err(arg.span, ArgumentUseOrderError::DefaultParameterAfterStars);
-                    }
-                    seen_optional = true;
-                    test_param_name(&mut argset, n, arg, codemap)?;
-                }
-                Parameter::NoArgs => {
-                    if seen_args || seen_kwargs {
-                        return err(arg.span, ArgumentUseOrderError::ArgsParameterAfterStars);
-                    }
-                    seen_args = true;
-                }
-                Parameter::Args(n, ..) => {
-                    if seen_args || seen_kwargs {
-                        return err(arg.span, ArgumentUseOrderError::ArgsParameterAfterStars);
-                    }
-                    seen_args = true;
-                    test_param_name(&mut argset, n, arg, codemap)?;
-                }
-                Parameter::KwArgs(n, ..) => {
-                    if seen_kwargs {
-                        return err(arg.span, ArgumentUseOrderError::MultipleKwargs);
-                    }
-                    seen_kwargs = true;
-                    test_param_name(&mut argset, n, arg, codemap)?;
-                }
-            }
-        }
+        check_parameters(&parameters, codemap)?;
         Ok(Stmt::Def(name, parameters, return_type, box stmts))
     }
 
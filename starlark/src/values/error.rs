@@ -25,6 +25,8 @@ use thiserror::Error;
 pub enum ValueError {
     #[error("Operation `{op}` not supported on type `{typ}`")]
     OperationNotSupported { op: String, typ: String },
+    #[error("Operation `.{attr}` not supported on type `{typ}`")]
+    MissingAttribute { attr: String, typ: String },
     #[error("Operation `{op}` not supported for types `{left}` and `{right}`")]
     OperationNotSupportedBinary {
         op: String,
@@ -39,6 +41,8 @@ pub enum ValueError {
     IncorrectParameterType,
     #[error("Type of parameter `{0}` doesn't match")]
     IncorrectParameterTypeNamed(String),
+    #[error("Type of parameter `{0}` doesn't match, expected `{1}`")]
+    IncorrectParameterTypeNamedExpected(String, String),
     #[error("Index `{0}` is out of bound")]
     IndexOutOfBound(i32),
     #[error("Key `{0}` was not found")]
@@ -53,6 +57,8 @@ pub(crate) enum ControlError {
     NotHashableValue(String),
     #[error("Too many recursion levels")]
     TooManyRecursionLevel,
+    #[error("Function called recursively, which is not allowed: {0}")]
+    RecursiveCall(String),
     #[error("This operation mutate an iterable for an iterator while iterating.")]
     MutationDuringIteration,
 }
@@ -78,6 +84,14 @@ impl ValueError {
         }
     }
 
+    pub(crate) fn missing_attribute<T>(typ: &str, attr: &str) -> anyhow::Result<T> {
+        Err(ValueError::MissingAttribute {
+            attr: attr.to_owned(),
+            typ: typ.to_owned(),
+        }
+        .into())
+    }
+
     /// Helper to create an [`OperationNotSupported`](ValueError::OperationNotSupported) error.
     pub fn unsupported<'v, T, V: StarlarkValue<'v> + ?Sized>(
         left: &V,
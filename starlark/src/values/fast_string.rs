@@ -15,11 +15,170 @@
  * limitations under the License.
  */
 
-//! Our string operations (indexing) are O(n) because of our current representation.
-//! There are plans afoot to change that, but in the meantime let's use fast algorithms
-//! to make up some of the difference.
+//! Strings are indexed by Unicode code point, but stored as UTF-8, so indexing is not
+//! naturally O(1). Strings are scanned with fast algorithms. For longer strings stored on a
+//! heap we keep a small cache recording whether the string is ASCII (so indexing is O(1)), or
+//! otherwise the byte offset of every [`OFFSET_STRIDE`]th character (so indexing is bounded by
+//! the stride). The cache is keyed by address, so is only used by the `_stored` functions,
+//! whose strings can't be freed without the cache being invalidated.
+//!
+//! Alternatively, strings can be indexed by byte, as in the Go implementation, see
+//! [`StringIndexing`].
 
-use std::{cmp::min, str};
+use gazebo::prelude::*;
+use std::{
+    cell::{Cell, RefCell},
+    cmp::min,
+    str,
+    sync::atomic::{self, AtomicUsize},
+};
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+enum StringIndexError {
+    #[error("Indexing by byte would split a character, which a string can't hold")]
+    SplitCharacter,
+}
+
+/// What the indices of a string count, which is used by `len`, indexing, slicing and the
+/// positions taken and returned by methods such as `find`. Set with [`set_string_indexing`].
+#[derive(Debug, Clone, Copy, Dupe, PartialEq, Eq)]
+pub enum StringIndexing {
+    /// Unicode code points, the default.
+    CodePoints,
+    /// Bytes of the UTF-8 encoding, as in the Go implementation. Strings must always hold
+    /// valid UTF-8, so anything which would split a character, e.g. `"é"[0]`, is an error.
+    Bytes,
+}
+
+thread_local!(static INDEXING: Cell<StringIndexing> = Cell::new(StringIndexing::CodePoints));
+
+/// Set what string indices count on the current thread, see [`StringIndexing`]. Constant
+/// expressions are worked out when code is compiled, so this should be set before a module is
+/// parsed and kept the same until it has finished being evaluated.
+pub fn set_string_indexing(indexing: StringIndexing) {
+    INDEXING.with(|c| c.set(indexing));
+}
+
+/// What string indices count on the current thread.
+pub(crate) fn string_indexing() -> StringIndexing {
+    INDEXING.with(Cell::get)
+}
+
+/// In [`StringIndexing::Bytes`], check every byte of a string is a whole character, as
+/// needed to split it into elements.
+pub(crate) fn check_ascii(x: &str) -> anyhow::Result<()> {
+    if x.is_ascii() {
+        Ok(())
+    } else {
+        Err(StringIndexError::SplitCharacter.into())
+    }
+}
+
+/// In [`StringIndexing::Bytes`], turn bytes of a string back into a string, failing if they
+/// don't hold whole characters.
+pub(crate) fn from_bytes(x: Vec<u8>) -> anyhow::Result<String> {
+    String::from_utf8(x).map_err(|_| StringIndexError::SplitCharacter.into())
+}
+
+/// Strings shorter than this many bytes are scanned directly, as that's cheaper than the cache.
+const CACHE_MIN_LEN: usize = 64;
+
+/// For non-ASCII strings, record the byte offset of every nth character.
+const OFFSET_STRIDE: usize = 32;
+
+/// The number of strings we remember offsets for.
+const CACHE_SIZE: usize = 8;
+
+/// Incremented whenever string storage might be freed, which invalidates the cache,
+/// since a new string might then be allocated at the same address.
+static GENERATION: AtomicUsize = AtomicUsize::new(0);
+
+/// Must be called before freeing any memory which may contain a string.
+pub(crate) fn invalidate_cache() {
+    GENERATION.fetch_add(1, atomic::Ordering::SeqCst);
+}
+
+struct Index {
+    generation: usize,
+    // The address and byte length of the string, used as the key
+    ptr: usize,
+    len: usize,
+    // The length in characters
+    char_len: usize,
+    // Empty if the string is ASCII, otherwise the offset of every `OFFSET_STRIDE` character
+    offsets: Vec<usize>,
+}
+
+impl Index {
+    fn new(x: &str, generation: usize) -> Self {
+        let mut offsets = Vec::new();
+        let char_len = if skip_at_most_1byte(x, x.len()) == x.len() {
+            x.len()
+        } else {
+            let mut n = 0;
+            for (i, (offset, _)) in x.char_indices().enumerate() {
+                if i % OFFSET_STRIDE == 0 {
+                    offsets.push(offset);
+                }
+                n += 1;
+            }
+            n
+        };
+        Self {
+            generation,
+            ptr: x.as_ptr() as usize,
+            len: x.len(),
+            char_len,
+            offsets,
+        }
+    }
+
+    /// The byte offset of character `i`, which must be at most `char_len`.
+    fn byte_offset(&self, x: &str, i: usize) -> usize {
+        if self.offsets.is_empty() {
+            i
+        } else if i == self.char_len {
+            x.len()
+        } else {
+            let start = self.offsets[i / OFFSET_STRIDE];
+            match x[start..].char_indices().nth(i % OFFSET_STRIDE) {
+                Some((offset, _)) => start + offset,
+                None => x.len(),
+            }
+        }
+    }
+}
+
+thread_local!(static CACHE: RefCell<Vec<Index>> = RefCell::new(Vec::new()));
+
+/// Run a function with the index of a (long) string, computing it if necessary.
+/// The string must be stored on a [`Heap`](crate::values::Heap) or
+/// [`FrozenHeap`](crate::values::FrozenHeap), since any other string might be freed, and
+/// another allocated at the same address, without the cache knowing.
+fn with_index<R>(x: &str, f: impl FnOnce(&Index) -> R) -> R {
+    let generation = GENERATION.load(atomic::Ordering::SeqCst);
+    CACHE.with(|cache| {
+        let mut cache = cache.borrow_mut();
+        if cache.first().map_or(false, |c| c.generation != generation) {
+            cache.clear();
+        }
+        let ptr = x.as_ptr() as usize;
+        let pos = match cache.iter().position(|c| c.ptr == ptr && c.len == x.len()) {
+            Some(pos) => pos,
+            None => {
+                if cache.len() >= CACHE_SIZE {
+                    cache.pop();
+                }
+                cache.push(Index::new(x, generation));
+                cache.len() - 1
+            }
+        };
+        // Move to the front, so the least recently used is last
+        cache[..=pos].rotate_right(1);
+        f(&cache[0])
+    })
+}
 
 #[inline(always)]
 fn is_1byte(x: u8) -> bool {
@@ -86,14 +245,92 @@ fn skip_at_most_1byte(x: &str, n: usize) -> usize {
 
 /// Find the character at position `i`.
 pub fn at(x: &str, i: usize) -> Option<char> {
-    let n = skip_at_most_1byte(x, i);
+    let n = skip_at_most_1byte(x, min(i, x.len()));
     let s = unsafe { str::from_utf8_unchecked(&x.as_bytes()[n..]) };
     s.chars().nth(i - n)
 }
 
-/// Find the length of the string in characters.
+/// Like [`at`], but for a string stored on a heap, so long strings can use the cache.
+pub(crate) fn at_stored(x: &str, i: usize) -> Option<char> {
+    if x.len() >= CACHE_MIN_LEN {
+        return with_index(x, |index| {
+            if i >= index.char_len {
+                None
+            } else {
+                x[index.byte_offset(x, i)..].chars().next()
+            }
+        });
+    }
+    at(x, i)
+}
+
+/// Find the length of a string stored on a heap in characters, using the cache for long strings,
+/// or in bytes in [`StringIndexing::Bytes`].
 /// If the length matches the length in bytes, the string must be 7bit ASCII.
-pub fn len(x: &str) -> usize {
+pub(crate) fn len_stored(x: &str) -> usize {
+    if string_indexing() == StringIndexing::Bytes {
+        return x.len();
+    }
+    if x.len() >= CACHE_MIN_LEN {
+        return with_index(x, |index| index.char_len);
+    }
+    count_chars(x)
+}
+
+/// Find the byte offset of the character at position `i`, or the length of the
+/// string in bytes if `i` is at least the length in characters.
+pub fn byte_offset(x: &str, i: usize) -> usize {
+    let n = skip_at_most_1byte(x, min(i, x.len()));
+    match x[n..].char_indices().nth(i - n) {
+        Some((offset, _)) => n + offset,
+        None => x.len(),
+    }
+}
+
+/// Like [`byte_offset`], but for a string stored on a heap, so long strings can use the cache.
+fn byte_offset_stored(x: &str, i: usize) -> usize {
+    if x.len() >= CACHE_MIN_LEN {
+        return with_index(x, |index| index.byte_offset(x, min(i, index.char_len)));
+    }
+    byte_offset(x, i)
+}
+
+/// Find the substring between the characters at positions `start` and `end`,
+/// clamping both to the length of the string.
+pub fn substring(x: &str, start: usize, end: usize) -> &str {
+    if start >= end {
+        return "";
+    }
+    &x[byte_offset(x, start)..byte_offset(x, end)]
+}
+
+/// Like [`substring`], but for a string stored on a heap, so long strings can use the cache.
+/// In [`StringIndexing::Bytes`], `start` and `end` count bytes, and it is an error if either
+/// is in the middle of a character.
+pub(crate) fn substring_stored(x: &str, start: usize, end: usize) -> anyhow::Result<&str> {
+    if start >= end {
+        return Ok("");
+    }
+    if string_indexing() == StringIndexing::Bytes {
+        return x
+            .get(min(start, x.len())..min(end, x.len()))
+            .ok_or_else(|| StringIndexError::SplitCharacter.into());
+    }
+    Ok(&x[byte_offset_stored(x, start)..byte_offset_stored(x, end)])
+}
+
+/// The length of a string in characters, or in bytes in [`StringIndexing::Bytes`], without
+/// consulting the cache. Used to turn the byte offset of a match into an index.
+pub(crate) fn len(x: &str) -> usize {
+    match string_indexing() {
+        StringIndexing::CodePoints => count_chars(x),
+        StringIndexing::Bytes => x.len(),
+    }
+}
+
+/// Find the length of the string in characters, without consulting the cache.
+/// Used for temporary substrings, which may not be stored on a heap.
+pub fn count_chars(x: &str) -> usize {
     let n = skip_at_most_1byte(x, x.len());
     if n == x.len() {
         n // All 1 byte
@@ -133,3 +370,74 @@ pub fn append(x: &str, y: &str) -> String {
     s.push_str(y);
     s
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_index() {
+        for x in &[
+            "hello".to_owned(),
+            "héllo wörld".to_owned(),
+            "x".repeat(100),
+            "é".repeat(100),
+            format!("{}世界{}", "a".repeat(70), "b".repeat(70)),
+        ] {
+            let chars: Vec<char> = x.chars().collect();
+            assert_eq!(count_chars(x), chars.len());
+            assert_eq!(len_stored(x), chars.len());
+            for i in 0..=chars.len() + 1 {
+                assert_eq!(at(x, i), chars.get(i).copied());
+                assert_eq!(at_stored(x, i), chars.get(i).copied());
+                let expect: String = chars.iter().take(i).collect();
+                assert_eq!(byte_offset(x, i), expect.len());
+                assert_eq!(byte_offset_stored(x, i), expect.len());
+                let expect: String = chars.iter().take(i).skip(1).collect();
+                assert_eq!(substring(x, 1, i), expect);
+                assert_eq!(substring_stored(x, 1, i).unwrap(), expect);
+            }
+        }
+    }
+
+    #[test]
+    fn test_bytes() {
+        set_string_indexing(StringIndexing::Bytes);
+        let x = "héllo";
+        assert_eq!(len_stored(x), 6);
+        assert_eq!(len("é"), 2);
+        assert_eq!(substring_stored(x, 1, 3).unwrap(), "é");
+        assert_eq!(substring_stored(x, 3, 100).unwrap(), "llo");
+        assert!(substring_stored(x, 2, 4).is_err());
+        assert_eq!(from_bytes(vec![b'a', 0xc3, 0xa9]).unwrap(), "aé");
+        assert!(from_bytes(vec![0xc3]).is_err());
+        set_string_indexing(StringIndexing::CodePoints);
+        assert_eq!(len_stored(x), 5);
+    }
+
+    #[test]
+    fn test_invalidate() {
+        let x = "é".repeat(100);
+        assert_eq!(len_stored(&x), 100);
+        invalidate_cache();
+        // Still correct after the cache is discarded
+        assert_eq!(len_stored(&x), 100);
+        assert_eq!(at_stored(&x, 99), Some('é'));
+    }
+
+    #[test]
+    fn test_temporaries() {
+        // Temporaries of the same byte length are often allocated at the same address,
+        // which must not be mistaken for the previous one
+        for _ in 0..10 {
+            let x = format!("{}{}", "é".repeat(40), "a".repeat(11));
+            assert_eq!(substring(&x, 0, 40), "é".repeat(40));
+            drop(x);
+            let y = format!("{}{}", "a".repeat(11), "é".repeat(40));
+            assert_eq!(
+                substring(&y, 0, 40),
+                format!("{}{}", "a".repeat(11), "é".repeat(29))
+            );
+        }
+    }
+}
@@ -17,35 +17,317 @@
 
 //! String interpolation-related code.
 //! Based on <https://docs.python.org/3/library/stdtypes.html#printf-style-string-formatting>
+//! and <https://docs.python.org/3/library/string.html#format-string-syntax>.
+//!
+//! Both `%` and `format` share the same [`FormatSpec`], which supports the Python format
+//! specification mini-language, except for floating point types (which we don't have).
 
 use crate::{
     collections::SmallMap,
-    values::{tuple::Tuple, StarlarkValue, Value, ValueError, ValueLike},
+    eval::Evaluator,
+    values::{
+        fast_string,
+        function::{BoundMethod, NativeAttribute},
+        tuple::Tuple,
+        AttrType, Heap, Value,
+    },
 };
-use anyhow::anyhow;
-use gazebo::{cast, prelude::*};
-use std::{fmt::Write, str::FromStr};
+use gazebo::prelude::*;
 use thiserror::Error;
 
 /// Operator `%` format or evaluation errors
-#[derive(Clone, Dupe, Debug, Error)]
-enum StringInterpolationError {
+#[derive(Clone, Debug, Error)]
+pub(crate) enum StringInterpolationError {
     /// Interpolation parameter is too big for the format string.
     #[error("Too many arguments for format string")]
     TooManyParameters,
     /// Interpolation parameter is too small for the format string.
     #[error("Not enough arguments for format string")]
     NotEnoughParameters,
+    /// A positional argument is used after a `%(name)` one, which takes the whole value.
+    #[error("Positional arguments can't follow `%(name)` arguments in a format string")]
+    PositionalAfterMapping,
+    /// The format string is malformed, or doesn't suit the value it is applied to.
+    /// The position is in characters from the start of the format string.
+    #[error("{message}, at position {position} of format string `{format}`")]
+    InvalidFormat {
+        message: String,
+        position: usize,
+        format: String,
+    },
 }
 
-pub(crate) fn percent(format: &str, value: Value) -> anyhow::Result<String> {
-    // For performance reasons, we treat format as a list of bytes
-    // (which is fine, the only thing we care about are '%' and ASCII digits).
-    // As a result, we accumulate into a Vec<u8>, which we know at any point
-    // we are at the end or at a '%' must be a valid UTF8 buffer.
+impl StringInterpolationError {
+    fn invalid_format(format: &str, byte_offset: usize, message: String) -> Self {
+        StringInterpolationError::InvalidFormat {
+            message,
+            position: fast_string::count_chars(&format[..byte_offset]),
+            format: format.to_owned(),
+        }
+    }
+
+    fn invalid(format: &str, byte_offset: usize, message: String) -> anyhow::Error {
+        Self::invalid_format(format, byte_offset, message).into()
+    }
+}
+
+#[derive(Clone, Copy, Dupe, Debug, PartialEq, Eq)]
+enum Align {
+    Left,
+    Right,
+    Center,
+    /// Padding goes between the sign (and prefix) and the digits, written `=`.
+    AfterSign,
+}
+
+#[derive(Clone, Copy, Dupe, Debug, PartialEq, Eq)]
+enum Sign {
+    Minus,
+    Plus,
+    Space,
+}
+
+/// How to format a single value, as written after the `:` in a `format` field,
+/// or between the `%` and the conversion character.
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct FormatSpec {
+    fill: char,
+    align: Option<Align>,
+    sign: Sign,
+    /// Use the alternate form, written `#`, which adds a `0x` style prefix.
+    alternate: bool,
+    width: usize,
+    /// Thousands separator, either `,` or `_`.
+    grouping: Option<char>,
+    /// For strings the maximum length, for integers the minimum number of digits.
+    precision: Option<usize>,
+    ty: Option<char>,
+}
+
+impl Default for FormatSpec {
+    fn default() -> Self {
+        Self {
+            fill: ' ',
+            align: None,
+            sign: Sign::Minus,
+            alternate: false,
+            width: 0,
+            grouping: None,
+            precision: None,
+            ty: None,
+        }
+    }
+}
+
+/// Parse a decimal number starting at `i`, returning the number and the index after it.
+fn parse_number(x: &[char], mut i: usize) -> Result<(Option<usize>, usize), String> {
+    let start = i;
+    let mut res: usize = 0;
+    while let Some(d) = x.get(i).and_then(|c| c.to_digit(10)) {
+        res = res
+            .checked_mul(10)
+            .and_then(|r| r.checked_add(d as usize))
+            .ok_or_else(|| "Too many decimal digits in format string".to_owned())?;
+        i += 1;
+    }
+    Ok((if i == start { None } else { Some(res) }, i))
+}
+
+impl FormatSpec {
+    /// Parse the format spec mini-language: `[[fill]align][sign][#][0][width][,][.precision][type]`.
+    /// On error, returns the character offset into `spec` of the problem.
+    fn parse(spec: &str) -> Result<Self, (usize, String)> {
+        fn align(c: char) -> Option<Align> {
+            match c {
+                '<' => Some(Align::Left),
+                '>' => Some(Align::Right),
+                '^' => Some(Align::Center),
+                '=' => Some(Align::AfterSign),
+                _ => None,
+            }
+        }
+
+        let x: Vec<char> = spec.chars().collect();
+        let mut res = Self::default();
+        let mut i = 0;
+        if let Some(a) = x.get(1).and_then(|c| align(*c)) {
+            res.fill = x[0];
+            res.align = Some(a);
+            i = 2;
+        } else if let Some(a) = x.get(0).and_then(|c| align(*c)) {
+            res.align = Some(a);
+            i = 1;
+        }
+        match x.get(i) {
+            Some('+') => res.sign = Sign::Plus,
+            Some(' ') => res.sign = Sign::Space,
+            _ => {}
+        }
+        if matches!(x.get(i), Some('+' | ' ' | '-')) {
+            i += 1;
+        }
+        if x.get(i) == Some(&'#') {
+            res.alternate = true;
+            i += 1;
+        }
+        if x.get(i) == Some(&'0') {
+            if res.align.is_none() {
+                res.fill = '0';
+                res.align = Some(Align::AfterSign);
+            }
+            i += 1;
+        }
+        let (width, j) = parse_number(&x, i).map_err(|e| (i, e))?;
+        res.width = width.unwrap_or(0);
+        i = j;
+        if let Some(c @ (',' | '_')) = x.get(i) {
+            res.grouping = Some(*c);
+            i += 1;
+        }
+        if x.get(i) == Some(&'.') {
+            let (precision, j) = parse_number(&x, i + 1).map_err(|e| (i, e))?;
+            match precision {
+                None => return Err((i, "Format specifier missing precision".to_owned())),
+                Some(p) => res.precision = Some(p),
+            }
+            i = j;
+        }
+        if let Some(c) = x.get(i) {
+            res.ty = Some(*c);
+            i += 1;
+        }
+        if i < x.len() {
+            return Err((i, "Invalid format specifier".to_owned()));
+        }
+        Ok(res)
+    }
+
+    /// Pad `prefix` (the sign and any `0x`) followed by `body` to the width.
+    fn pad(&self, prefix: &str, body: &str, default: Align, out: &mut String) {
+        let len = fast_string::count_chars(prefix) + fast_string::count_chars(body);
+        let n = self.width.saturating_sub(len);
+        let fill = |out: &mut String, n: usize| out.extend(std::iter::repeat(self.fill).take(n));
+        match self.align.unwrap_or(default) {
+            Align::Left => {
+                out.push_str(prefix);
+                out.push_str(body);
+                fill(out, n);
+            }
+            Align::Right => {
+                fill(out, n);
+                out.push_str(prefix);
+                out.push_str(body);
+            }
+            Align::Center => {
+                fill(out, n / 2);
+                out.push_str(prefix);
+                out.push_str(body);
+                fill(out, n - n / 2);
+            }
+            Align::AfterSign => {
+                out.push_str(prefix);
+                fill(out, n);
+                out.push_str(body);
+            }
+        }
+    }
+
+    fn format_str(&self, s: &str, out: &mut String) -> Result<(), String> {
+        if let Some(ty) = self.ty {
+            if ty != 's' {
+                return Err(format!(
+                    "Unknown format code '{}' for value of type 'string'",
+                    ty
+                ));
+            }
+        }
+        if self.sign != Sign::Minus {
+            return Err("Sign not allowed in string format specifier".to_owned());
+        }
+        if self.alternate {
+            return Err("Alternate form (#) not allowed in string format specifier".to_owned());
+        }
+        if let Some(c) = self.grouping {
+            return Err(format!("Cannot specify '{}' with 's'", c));
+        }
+        if self.align == Some(Align::AfterSign) {
+            return Err("'=' alignment not allowed in string format specifier".to_owned());
+        }
+        let s = match self.precision {
+            Some(p) => fast_string::substring(s, 0, p),
+            None => s,
+        };
+        self.pad("", s, Align::Left, out);
+        Ok(())
+    }
 
+    fn format_int(&self, v: i32, out: &mut String) -> Result<(), String> {
+        let ty = self.ty.unwrap_or('d');
+        let abs = v.unsigned_abs();
+        let (digits, prefix, group_size) = match ty {
+            'd' | 'n' => (abs.to_string(), "", 3),
+            'b' => (format!("{:b}", abs), "0b", 4),
+            'o' => (format!("{:o}", abs), "0o", 4),
+            'x' => (format!("{:x}", abs), "0x", 4),
+            'X' => (format!("{:X}", abs), "0X", 4),
+            'c' => {
+                if self.sign != Sign::Minus || self.alternate || self.grouping.is_some() {
+                    return Err("Sign, alternate form and grouping not allowed with 'c'".to_owned());
+                }
+                return match std::char::from_u32(v as u32) {
+                    Some(c) => {
+                        self.pad("", &c.to_string(), Align::Right, out);
+                        Ok(())
+                    }
+                    None => Err(format!("Value {} is not a valid Unicode code point", v)),
+                };
+            }
+            _ => {
+                return Err(format!(
+                    "Unknown format code '{}' for value of type 'int'",
+                    ty
+                ));
+            }
+        };
+        let mut digits = match self.precision {
+            Some(p) if p > digits.len() => "0".repeat(p - digits.len()) + &digits,
+            _ => digits,
+        };
+        match self.grouping {
+            Some(',') if group_size != 3 => {
+                return Err(format!("Cannot specify ',' with '{}'", ty));
+            }
+            Some(sep) => {
+                let mut res = String::with_capacity(digits.len() * 2);
+                for (i, c) in digits.chars().enumerate() {
+                    if i != 0 && (digits.len() - i) % group_size == 0 {
+                        res.push(sep);
+                    }
+                    res.push(c);
+                }
+                digits = res;
+            }
+            None => {}
+        }
+        let sign = match (v < 0, self.sign) {
+            (true, _) => "-",
+            (false, Sign::Plus) => "+",
+            (false, Sign::Space) => " ",
+            (false, Sign::Minus) => "",
+        };
+        let prefix = if self.alternate { prefix } else { "" };
+        self.pad(&format!("{}{}", sign, prefix), &digits, Align::Right, out);
+        Ok(())
+    }
+}
+
+pub(crate) fn percent<'v>(
+    format: &str,
+    value: Value<'v>,
+    heap: &'v Heap,
+) -> anyhow::Result<String> {
     // random guess as a baseline capacity
-    let mut res: Vec<u8> = Vec::with_capacity(format.len() + 20);
+    let mut res = String::with_capacity(format.len() + 20);
 
     let tuple = Tuple::from_value(value);
     let one = &[value];
@@ -54,313 +336,661 @@ pub(crate) fn percent(format: &str, value: Value) -> anyhow::Result<String> {
         None => one,
     };
     let mut values = values.iter().copied();
-    let mut next_value = || -> anyhow::Result<Value> {
+    let mut used_mapping = false;
+    // As in Python, once a `%(name)` argument has taken the value as a mapping,
+    // there are no positional arguments left
+    let mut next_value = |used_mapping: bool| -> anyhow::Result<Value> {
+        if used_mapping {
+            return Err(StringInterpolationError::PositionalAfterMapping.into());
+        }
         values
             .next()
             .ok_or_else(|| StringInterpolationError::NotEnoughParameters.into())
     };
 
-    // because of the way format is defined, we can deal with it as bytes
-    let mut format = format.as_bytes().iter().copied();
-    while let Some(c) = format.next() {
-        if c == b'%' {
-            if let Some(c) = format.next() {
-                let out: &mut String = unsafe { cast::ptr_mut(&mut res) };
-                match c {
-                    b'%' => res.push(b'%'),
-                    b's' => {
-                        let arg = next_value()?;
-                        match arg.unpack_str() {
-                            None => arg.collect_repr(out),
-                            Some(s) => out.push_str(s),
-                        }
-                    }
-                    b'r' => next_value()?.collect_repr(out),
-                    b'd' => write!(out, "{}", next_value()?.to_int()?).unwrap(),
-                    b'o' => {
-                        let v = next_value()?.to_int()?;
-                        write!(
-                            out,
-                            "{}{:o}",
-                            if v < 0 { "-" } else { "" },
-                            v.wrapping_abs() as u64
-                        )
-                        .unwrap();
-                    }
-                    b'x' => {
-                        let v = next_value()?.to_int()?;
-                        write!(
-                            out,
-                            "{}{:x}",
-                            if v < 0 { "-" } else { "" },
-                            v.wrapping_abs() as u64
-                        )
-                        .unwrap();
-                    }
-                    b'X' => {
-                        let v = next_value()?.to_int()?;
-                        write!(
-                            out,
-                            "{}{:X}",
-                            if v < 0 { "-" } else { "" },
-                            v.wrapping_abs() as u64
-                        )
-                        .unwrap()
-                    }
-                    c => {
-                        res.push(b'%');
-                        res.push(c);
-                    }
-                }
+    // Everything we care about is ASCII, so we can deal with the format as bytes,
+    // and only ever slice at ASCII characters
+    let bytes = format.as_bytes();
+    let invalid = |i: usize, msg: String| StringInterpolationError::invalid(format, i, msg);
+    let mut i = 0;
+    while let Some(n) = format[i..].find('%') {
+        res.push_str(&format[i..i + n]);
+        let start = i + n;
+        i = start + 1;
+        if bytes.get(i) == Some(&b'%') {
+            res.push('%');
+            i += 1;
+            continue;
+        }
+
+        // The mapping key, e.g. `%(name)s`
+        let mut arg = None;
+        if bytes.get(i) == Some(&b'(') {
+            let close = match format[i..].find(')') {
+                None => return Err(invalid(start, "Incomplete format key".to_owned())),
+                Some(close) => i + close,
+            };
+            let key = heap.alloc(&format[i + 1..close]);
+            arg = Some(value.at(key, heap)?);
+            used_mapping = true;
+            i = close + 1;
+        }
+
+        let mut spec = FormatSpec::default();
+        let mut zero = false;
+        while let Some(c) = bytes.get(i) {
+            match c {
+                b'-' => spec.align = Some(Align::Left),
+                b'+' => spec.sign = Sign::Plus,
+                b' ' if spec.sign != Sign::Plus => spec.sign = Sign::Space,
+                b' ' => {}
+                b'#' => spec.alternate = true,
+                b'0' => zero = true,
+                _ => break,
+            }
+            i += 1;
+        }
+        let mut number = |i: &mut usize| -> anyhow::Result<Option<usize>> {
+            if bytes.get(*i) == Some(&b'*') {
+                *i += 1;
+                Ok(Some(next_value(used_mapping)?.to_int()?.max(0) as usize))
             } else {
-                res.push(b'%');
+                let chars: Vec<char> = format[*i..]
+                    .bytes()
+                    .take_while(|c| c.is_ascii_digit())
+                    .map(char::from)
+                    .collect();
+                let (res, len) = parse_number(&chars, 0).map_err(|e| invalid(*i, e))?;
+                *i += len;
+                Ok(res)
             }
-        } else {
-            res.push(c);
+        };
+        spec.width = number(&mut i)?.unwrap_or(0);
+        if bytes.get(i) == Some(&b'.') {
+            i += 1;
+            spec.precision = Some(number(&mut i)?.unwrap_or(0));
         }
+
+        let conv = match format[i..].chars().next() {
+            None => return Err(invalid(start, "Incomplete format".to_owned())),
+            Some(c) => c,
+        };
+        i += conv.len_utf8();
+        let mut arg = || match arg {
+            Some(x) => Ok(x),
+            None => next_value(used_mapping),
+        };
+        let r = match conv {
+            's' | 'r' => {
+                let arg = arg()?;
+                let s = if conv == 'r' {
+                    arg.to_repr()
+                } else {
+                    arg.to_str()
+                };
+                // Sign and zero padding are ignored for strings, which are right aligned
+                spec.sign = Sign::Minus;
+                spec.alternate = false;
+                spec.align.get_or_insert(Align::Right);
+                spec.format_str(&s, &mut res)
+            }
+            'c' => {
+                let arg = arg()?;
+                let c = match arg.unpack_str() {
+                    Some(s) if fast_string::count_chars(s) == 1 => Ok(s.to_owned()),
+                    Some(_) => Err("%c requires an int or a single character".to_owned()),
+                    None => match std::char::from_u32(arg.to_int()? as u32) {
+                        Some(c) => Ok(c.to_string()),
+                        None => Err(format!("Value {} is not a valid Unicode code point", arg)),
+                    },
+                };
+                c.map(|c| {
+                    spec.align.get_or_insert(Align::Right);
+                    spec.pad("", &c, Align::Right, &mut res)
+                })
+            }
+            'd' | 'i' | 'u' | 'o' | 'x' | 'X' => {
+                let arg = arg()?;
+                if zero && spec.align.is_none() {
+                    spec.fill = '0';
+                    spec.align = Some(Align::AfterSign);
+                }
+                spec.ty = Some(match conv {
+                    'i' | 'u' => 'd',
+                    c => c,
+                });
+                spec.format_int(arg.to_int()?, &mut res)
+            }
+            c => Err(format!("Unsupported format character '{}'", c)),
+        };
+        r.map_err(|e| invalid(start, e))?;
     }
-    if next_value().is_ok() {
+    res.push_str(&format[i..]);
+    if !used_mapping && next_value(false).is_ok() {
         Err(StringInterpolationError::TooManyParameters.into())
     } else {
-        Ok(unsafe { String::from_utf8_unchecked(res) })
+        Ok(res)
     }
 }
 
-pub(crate) fn format(
+/// The arguments passed to `format`, and how they have been used so far.
+struct FormatArgs<'a, 'v> {
+    args: &'a [Value<'v>],
+    kwargs: &'a SmallMap<&'a str, Value<'v>>,
+    next: usize,
+    captured_by_index: bool,
+    captured_by_order: bool,
+}
+
+/// Find the first of `chars` in `x` that isn't inside `[...]`.
+fn find_unbracketed(x: &str, chars: &[char]) -> Option<usize> {
+    let mut depth = 0;
+    for (i, c) in x.char_indices() {
+        match c {
+            '[' => depth += 1,
+            ']' if depth > 0 => depth -= 1,
+            c if depth == 0 && chars.contains(&c) => return Some(i),
+            _ => {}
+        }
+    }
+    None
+}
+
+pub(crate) fn format<'v>(
     this: &str,
-    args: Vec<Value>,
-    kwargs: SmallMap<&str, Value>,
+    args: Vec<Value<'v>>,
+    kwargs: SmallMap<&str, Value<'v>>,
+    eval: &mut Evaluator<'v, '_>,
 ) -> anyhow::Result<String> {
-    let mut it = args.iter().copied();
-    let mut captured_by_index = false;
-    let mut captured_by_order = false;
-    let mut result = String::new();
-    let mut capture = String::new();
-    for c in this.chars() {
-        match (c, capture.as_str()) {
-            ('{', "") | ('}', "") => capture.push(c),
-            (.., "") => result.push(c),
-            ('{', "{") => {
-                result.push('{');
-                capture.clear();
+    let mut state = FormatArgs {
+        args: &args,
+        kwargs: &kwargs,
+        next: 0,
+        captured_by_index: false,
+        captured_by_order: false,
+    };
+    let mut result = String::with_capacity(this.len());
+    let bytes = this.as_bytes();
+    let mut literal = 0;
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'{' if bytes.get(i + 1) == Some(&b'{') => {
+                result.push_str(&this[literal..=i]);
+                i += 2;
+                literal = i;
             }
-            ('{', "}") => return Err(anyhow!("Standalone '}}' in format string `{}`", this)),
-            ('{', ..) => return Err(anyhow!("Unmatched '{' in format string")),
-            ('}', "}") => {
-                result.push('}');
-                capture.clear();
+            b'{' => {
+                result.push_str(&this[literal..i]);
+                // Find the matching close, the spec may contain nested fields
+                let mut depth = 0;
+                let mut end = None;
+                for (j, c) in bytes.iter().enumerate().skip(i) {
+                    match c {
+                        b'{' => depth += 1,
+                        b'}' => {
+                            depth -= 1;
+                            if depth == 0 {
+                                end = Some(j);
+                                break;
+                            }
+                        }
+                        _ => {}
+                    }
+                }
+                let end = end.ok_or_else(|| {
+                    StringInterpolationError::invalid(this, i, "Unmatched '{'".to_owned())
+                })?;
+                format_field(this, i + 1, end, &mut state, eval, &mut result)?;
+                i = end + 1;
+                literal = i;
+            }
+            b'}' if bytes.get(i + 1) == Some(&b'}') => {
+                result.push_str(&this[literal..=i]);
+                i += 2;
+                literal = i;
             }
-            ('}', ..) => {
-                result += &format_capture(
-                    &capture,
-                    &mut it,
-                    &mut captured_by_index,
-                    &mut captured_by_order,
-                    &args,
-                    &kwargs,
-                )?;
-                capture.clear();
+            b'}' => {
+                return Err(StringInterpolationError::invalid(
+                    this,
+                    i,
+                    "Standalone '}'".to_owned(),
+                ));
             }
-            (.., "}") => return Err(anyhow!("Standalone '}}' in format string `{}`", this)),
-            _ => capture.push(c),
+            _ => i += 1,
         }
     }
-    match capture.as_str() {
-        "}" => Err(anyhow!("Standalone '}}' in format string `{}`", this)),
-        "" => Ok(result),
-        _ => Err(anyhow!("Unmatched '{' in format string")),
-    }
+    result.push_str(&this[literal..]);
+    Ok(result)
 }
 
-fn format_capture<'v, T: Iterator<Item = Value<'v>>>(
-    capture: &str,
-    it: &mut T,
-    captured_by_index: &mut bool,
-    captured_by_order: &mut bool,
-    args: &[Value],
-    kwargs: &SmallMap<&str, Value>,
-) -> anyhow::Result<String> {
-    let (n, conv) = {
-        if let Some(x) = capture.find('!') {
-            (capture.get(1..x).unwrap(), capture.get(x + 1..).unwrap())
-        } else {
-            (capture.get(1..).unwrap(), "s")
-        }
+/// Format the replacement field `this[start..end]`, which excludes the braces.
+fn format_field<'v>(
+    this: &str,
+    start: usize,
+    end: usize,
+    state: &mut FormatArgs<'_, 'v>,
+    eval: &mut Evaluator<'v, '_>,
+    out: &mut String,
+) -> anyhow::Result<()> {
+    let invalid = |i: usize, msg: String| StringInterpolationError::invalid(this, i, msg);
+    let field = &this[start..end];
+    let (name_conv, spec) = match find_unbracketed(field, &[':']) {
+        Some(x) => (&field[..x], Some((start + x + 1, &field[x + 1..]))),
+        None => (field, None),
     };
-    let conv_s = |x: Value| x.to_str();
-    let conv_r = |x: Value| x.to_repr();
-    let conv: &dyn Fn(Value) -> String = match conv {
-        "s" => &conv_s,
-        "r" => &conv_r,
-        c => {
-            return Err(anyhow!(
-                concat!(
-                    "'{}' is not a valid format string specifier, only ",
-                    "'s' and 'r' are valid specifiers",
+    let (name, conv) = match find_unbracketed(name_conv, &['!']) {
+        Some(x) => (&name_conv[..x], Some((start + x + 1, &name_conv[x + 1..]))),
+        None => (name_conv, None),
+    };
+    if name.contains('{') {
+        return Err(invalid(start, "Unexpected '{' in field name".to_owned()));
+    }
+    let value = format_field_value(this, start, name, state, eval)?;
+    let conv = match conv {
+        None => None,
+        Some((_, "r")) => Some('r'),
+        Some((_, "s")) => Some('s'),
+        Some((pos, c)) => {
+            return Err(invalid(
+                pos,
+                format!(
+                    "'{}' is not a valid conversion, only 'r' and 's' are valid",
+                    c
                 ),
-                c
             ));
         }
     };
-    if n.is_empty() {
-        if *captured_by_index {
-            return Err(anyhow!(
-                "Cannot mix manual field specification and automatic field numbering in format string",
-            ));
-        } else {
-            *captured_by_order = true;
-            if let Some(x) = it.next() {
-                return Ok(conv(x));
+
+    let spec = match spec {
+        None => FormatSpec::default(),
+        Some((pos, spec)) => {
+            let (spec, pos) = if spec.contains('{') {
+                // Fill in any nested fields, e.g. `{:{width}}`, before parsing
+                let expanded = format_nested(this, pos, spec, state, eval)?;
+                (expanded, None)
             } else {
-                return Err(anyhow!("Not enough parameters in format string"));
+                (spec.to_owned(), Some(pos))
+            };
+            FormatSpec::parse(&spec).map_err(|(offset, e)| match pos {
+                Some(pos) => {
+                    let offset = spec.char_indices().nth(offset).map_or(spec.len(), |x| x.0);
+                    invalid(pos + offset, e)
+                }
+                None => invalid(start, e),
+            })?
+        }
+    };
+
+    format_value(value, conv, &spec, out).map_err(|e| invalid(start, e))
+}
+
+/// Format a value, after applying any conversion (`r` or `s`), according to the spec.
+fn format_value(
+    value: Value,
+    conversion: Option<char>,
+    spec: &FormatSpec,
+    out: &mut String,
+) -> Result<(), String> {
+    match conversion {
+        // The common case, for which we can avoid any extra work
+        None if *spec == FormatSpec::default() => {
+            match value.unpack_str() {
+                Some(s) => out.push_str(s),
+                None => out.push_str(&value.to_str()),
             }
+            Ok(())
         }
-    } else if n.chars().all(|c| c.is_ascii_digit()) {
-        if *captured_by_order {
-            return Err(anyhow!(
-                "Cannot mix manual field specification and automatic field numbering in format string",
+        Some('r') => spec.format_str(&value.to_repr(), out),
+        Some(_) => spec.format_str(&value.to_str(), out),
+        None => match spec.ty {
+            Some('b' | 'c' | 'd' | 'n' | 'o' | 'x' | 'X') => match value.to_int() {
+                Ok(_) if spec.precision.is_some() => {
+                    Err("Precision not allowed in integer format specifier".to_owned())
+                }
+                Ok(i) => spec.format_int(i, out),
+                Err(_) => Err(format!(
+                    "Unknown format code '{}' for value of type '{}'",
+                    spec.ty.unwrap(),
+                    value.get_type()
+                )),
+            },
+            None if value.unpack_int().is_some() => {
+                if spec.precision.is_some() {
+                    Err("Precision not allowed in integer format specifier".to_owned())
+                } else {
+                    spec.format_int(value.unpack_int().unwrap(), out)
+                }
+            }
+            Some('s') | None => match value.unpack_str() {
+                Some(s) => spec.format_str(s, out),
+                None => spec.format_str(&value.to_str(), out),
+            },
+            Some(c) => Err(format!(
+                "Unknown format code '{}' for value of type '{}'",
+                c,
+                value.get_type()
+            )),
+        },
+    }
+}
+
+/// The conversion and format spec of an f-string field, e.g. `!r:>10`, parsed ahead of time.
+pub(crate) struct FStringFormat {
+    conversion: Option<char>,
+    spec: FormatSpec,
+    format: String,
+}
+
+impl FStringFormat {
+    pub(crate) fn new(
+        conversion: Option<char>,
+        spec: Option<&str>,
+    ) -> Result<Self, StringInterpolationError> {
+        let format = spec.unwrap_or_default();
+        let spec = match spec {
+            None => FormatSpec::default(),
+            Some(spec) => FormatSpec::parse(spec).map_err(|(offset, e)| {
+                let offset = spec.char_indices().nth(offset).map_or(spec.len(), |x| x.0);
+                StringInterpolationError::invalid_format(spec, offset, e)
+            })?,
+        };
+        Ok(Self {
+            conversion,
+            spec,
+            format: format.to_owned(),
+        })
+    }
+
+    pub(crate) fn conversion(&self) -> Option<char> {
+        self.conversion
+    }
+
+    /// The format spec as written, e.g. `>10`.
+    pub(crate) fn spec(&self) -> &str {
+        &self.format
+    }
+
+    /// Append the formatted value to `out`.
+    pub(crate) fn format(&self, value: Value, out: &mut String) -> anyhow::Result<()> {
+        format_value(value, self.conversion, &self.spec, out)
+            .map_err(|e| StringInterpolationError::invalid(&self.format, 0, e))
+    }
+}
+
+/// Expand the fields nested in a format spec, which may only be field names.
+fn format_nested<'v>(
+    this: &str,
+    start: usize,
+    spec: &str,
+    state: &mut FormatArgs<'_, 'v>,
+    eval: &mut Evaluator<'v, '_>,
+) -> anyhow::Result<String> {
+    let mut res = String::new();
+    let mut rest = spec;
+    while let Some(open) = rest.find('{') {
+        let offset = start + (spec.len() - rest.len()) + open;
+        res.push_str(&rest[..open]);
+        let close = match rest[open..].find('}') {
+            Some(close) => open + close,
+            None => {
+                return Err(StringInterpolationError::invalid(
+                    this,
+                    offset,
+                    "Unmatched '{'".to_owned(),
+                ));
+            }
+        };
+        let name = &rest[open + 1..close];
+        if name.contains(|c| matches!(c, '{' | '!' | ':')) {
+            return Err(StringInterpolationError::invalid(
+                this,
+                offset,
+                "Nested fields in a format spec can't have a conversion or format spec".to_owned(),
             ));
-        } else {
-            *captured_by_index = true;
-            let i = i32::from_str(n).unwrap();
-            if i < 0 || i >= (args.len() as i32) {
-                return Err(ValueError::IndexOutOfBound(i).into());
+        }
+        let v = format_field_value(this, offset + 1, name, state, eval)?;
+        res.push_str(&v.to_str());
+        rest = &rest[close + 1..];
+    }
+    res.push_str(rest);
+    Ok(res)
+}
+
+/// Find the value named by a field, e.g. `0`, `name`, `x.attr` or `x[key]`.
+/// The field starts at byte `start` of `this`.
+fn format_field_value<'v>(
+    this: &str,
+    start: usize,
+    name: &str,
+    state: &mut FormatArgs<'_, 'v>,
+    eval: &mut Evaluator<'v, '_>,
+) -> anyhow::Result<Value<'v>> {
+    let invalid = |i: usize, msg: String| StringInterpolationError::invalid(this, i, msg);
+    let mixed = || {
+        invalid(
+            start,
+            "Cannot mix manual field specification and automatic field numbering".to_owned(),
+        )
+    };
+
+    let arg_end = name.find(|c| c == '.' || c == '[').unwrap_or(name.len());
+    let arg = &name[..arg_end];
+    let mut value = if arg.is_empty() {
+        if state.captured_by_index {
+            return Err(mixed());
+        }
+        state.captured_by_order = true;
+        match state.args.get(state.next) {
+            Some(x) => {
+                state.next += 1;
+                *x
+            }
+            None => {
+                return Err(invalid(start, "Not enough positional arguments".to_owned()));
+            }
+        }
+    } else if arg.bytes().all(|c| c.is_ascii_digit()) {
+        if state.captured_by_order {
+            return Err(mixed());
+        }
+        state.captured_by_index = true;
+        match arg.parse::<usize>().ok().and_then(|i| state.args.get(i)) {
+            Some(x) => *x,
+            None => {
+                return Err(invalid(
+                    start,
+                    format!(
+                        "Index {} out of range, with {} positional arguments",
+                        arg,
+                        state.args.len()
+                    ),
+                ));
             }
-            Ok(conv(args[i as usize]))
         }
     } else {
-        if let Some(x) = n.chars().find(|c| match c {
-            '.' | ',' | '[' | ']' => true,
-            _ => false,
-        }) {
-            return Err(anyhow!(
-                "Invalid character '{}' inside replacement field",
-                x
-            ));
+        match state.kwargs.get(arg) {
+            Some(x) => *x,
+            None => {
+                return Err(invalid(
+                    start,
+                    format!("Keyword argument `{}` not found", arg),
+                ));
+            }
         }
-        match kwargs.get(n) {
-            None => Err(ValueError::KeyNotFound(Box::<str>::from(n).to_repr()).into()),
-            Some(v) => Ok(conv(*v)),
+    };
+
+    let heap = eval.heap();
+    let mut i = arg_end;
+    while i < name.len() {
+        let pos = start + i;
+        if let Some(rest) = name[i..].strip_prefix('.') {
+            let len = rest.find(|c| c == '.' || c == '[').unwrap_or(rest.len());
+            let attr = &rest[..len];
+            if attr.is_empty() {
+                return Err(invalid(pos, "Empty attribute in format field".to_owned()));
+            }
+            value = match value.get_attr_error(attr, heap)? {
+                (AttrType::Field, x) => x,
+                (AttrType::Method, x) => match x.downcast_ref::<NativeAttribute>() {
+                    Some(x) => x.call(value, eval)?,
+                    None => heap.alloc(BoundMethod::new(value, x)),
+                },
+            };
+            i += 1 + len;
+        } else if let Some(rest) = name[i..].strip_prefix('[') {
+            let len = rest
+                .find(']')
+                .ok_or_else(|| invalid(pos, "Missing ']' in format field".to_owned()))?;
+            let key = &rest[..len];
+            if key.is_empty() {
+                return Err(invalid(pos, "Empty index in format field".to_owned()));
+            }
+            let key = match key.parse::<i32>() {
+                Ok(k) if key.bytes().all(|c| c.is_ascii_digit()) => Value::new_int(k),
+                _ => heap.alloc(key),
+            };
+            value = value.at(key, heap)?;
+            i += len + 2;
+        } else {
+            return Err(invalid(
+                pos,
+                "Only '.' or '[' may follow ']' in a format field".to_owned(),
+            ));
         }
     }
+    Ok(value)
 }
 
 #[cfg(test)]
 mod tests {
-    use super::*;
-    use crate::values::Heap;
+    use crate::assert;
 
     #[test]
     fn test_format_capture() {
-        let heap = Heap::new();
-        let args = vec![heap.alloc("1"), heap.alloc("2"), heap.alloc("3")];
-        let mut kwargs = SmallMap::new();
-        let it = heap.alloc(args.clone());
-        let it = it.iterate(&heap).unwrap();
-        let mut it = it.iter();
-        let mut captured_by_index = false;
-        let mut captured_by_order = false;
-
-        kwargs.insert("a", heap.alloc("x"));
-        kwargs.insert("b", heap.alloc("y"));
-        kwargs.insert("c", heap.alloc("z"));
-        assert_eq!(
-            format_capture(
-                "{",
-                &mut it,
-                &mut captured_by_index,
-                &mut captured_by_order,
-                &args,
-                &kwargs,
-            )
-            .unwrap(),
-            "1"
+        assert::all_true(
+            r#"
+"{} {!s} {!r}".format("1", "2", "3") == '1 2 "3"'
+"{a!r} {a!s}".format(a = "x") == '"x" x'
+"{1}".format("1", "2", "3") == "2"
+"#,
+        );
+        assert::fail("'{} {1}'.format(1, 2)", "Cannot mix");
+        assert::fail("'{1} {}'.format(1, 2)", "Cannot mix");
+        assert::fail("'{} {}'.format(1)", "Not enough positional arguments");
+    }
+
+    #[test]
+    fn test_format_spec() {
+        assert::all_true(
+            r#"
+"{:>6}|{:<6}|{:^6}".format("ab", "cd", "ef") == "    ab|cd    |  ef  "
+"{:*^7}".format("mid") == "**mid**"
+"{:05d}".format(-42) == "-0042"
+"{:+d} {: d}".format(3, 3) == "+3  3"
+"{:,}".format(1234567) == "1,234,567"
+"{:_x}".format(0x12345678) == "1234_5678"
+"{:#x} {:#o} {:#b} {:X}".format(255, 8, 5, 255) == "0xff 0o10 0b101 FF"
+"{:=+6}".format(12) == "+   12"
+"{:.3}".format("abcdef") == "abc"
+"{:c}".format(65) == "A"
+"{:>5}".format(True) == " True"
+"{:>8}".format([1]) == "     [1]"
+"{0!r:>5}".format("a") == '  "a"'
+"{:{}}|".format("x", 3) == "x  |"
+"{:>{w}}".format(1, w = 4) == "   1"
+"{0.x} {0.y[1]}".format(struct(x = 1, y = [2, 3])) == "1 3"
+"{d[k]} {d[0]}".format(d = {"k": "v", 0: "zero"}) == "v zero"
+"{{}} {{{}}}".format(1) == "{} {1}"
+"#,
+        );
+    }
+
+    #[test]
+    fn test_format_errors() {
+        assert::fail(
+            "'{:q}'.format(1)",
+            "Unknown format code 'q' for value of type 'int', at position 1 of format string `{:q}`",
+        );
+        assert::fail(
+            "'ab {:.2d}'.format(1)",
+            "Precision not allowed in integer format specifier, at position 4",
+        );
+        assert::fail(
+            "'{:+}'.format('s')",
+            "Sign not allowed in string format specifier",
         );
-        assert_eq!(
-            format_capture(
-                "{!s",
-                &mut it,
-                &mut captured_by_index,
-                &mut captured_by_order,
-                &args,
-                &kwargs,
-            )
-            .unwrap(),
-            "2"
+        assert::fail(
+            "'{:d}'.format('s')",
+            "Unknown format code 'd' for value of type 'string'",
         );
-        assert_eq!(
-            format_capture(
-                "{!r",
-                &mut it,
-                &mut captured_by_index,
-                &mut captured_by_order,
-                &args,
-                &kwargs,
-            )
-            .unwrap(),
-            "\"3\""
+        assert::fail(
+            "'{:5x5}'.format(1)",
+            "Invalid format specifier, at position 4",
         );
-        assert_eq!(
-            format_capture(
-                "{a!r",
-                &mut it,
-                &mut captured_by_index,
-                &mut captured_by_order,
-                &args,
-                &kwargs,
-            )
-            .unwrap(),
-            "\"x\""
+        assert::fail(
+            "'{0!x}'.format(1)",
+            "'x' is not a valid conversion, only 'r' and 's' are valid, at position 3",
+        );
+        assert::fail("'ab }'.format(1)", "Standalone '}', at position 3");
+        assert::fail("'é {'.format(1)", "Unmatched '{', at position 2");
+        assert::fail("'{x}'.format(y = 1)", "Keyword argument `x` not found");
+        assert::fail("'{3}'.format(1)", "Index 3 out of range");
+        assert::fail("'{0.nope}'.format(1)", "nope");
+    }
+
+    #[test]
+    fn test_percent() {
+        assert::all_true(
+            r#"
+"%5d|%-5d|%05d" % (42, 42, -42) == "   42|42   |-0042"
+"%+d % d" % (1, 1) == "+1  1"
+"%.3d" % 7 == "007"
+"%#x %#o %X %i %u" % (255, 8, 255, 1, 2) == "0xff 0o10 FF 1 2"
+"%-4s|%4s|%.2s" % ("a", "b", "xyz") == "a   |   b|xy"
+"%*d|%-*s|" % (4, 1, 3, "x") == "   1|x  |"
+"%c%c" % (72, "i") == "Hi"
+"%(a)s-%(b)03d" % {"a": "x", "b": 7} == "x-007"
+"100%%" % () == "100%"
+"%r" % ("x",) == '"x"'
+"#,
         );
-        assert_eq!(
-            format_capture(
-                "{a!s",
-                &mut it,
-                &mut captured_by_index,
-                &mut captured_by_order,
-                &args,
-                &kwargs,
-            )
-            .unwrap(),
-            "x"
+        assert::fail(
+            "'%q' % 1",
+            "Unsupported format character 'q', at position 0",
         );
-        assert!(
-            format_capture(
-                "{1",
-                &mut it,
-                &mut captured_by_index,
-                &mut captured_by_order,
-                &args,
-                &kwargs,
-            )
-            .is_err()
+        assert::fail("'abc %' % 1", "Incomplete format, at position 4");
+        assert::fail("'%s %s' % 1", "Not enough arguments");
+        assert::fail("'%s' % (1, 2)", "Too many arguments");
+        assert::fail(
+            "'%(a)s %s' % {'a': 1}",
+            "Positional arguments can't follow `%(name)` arguments",
         );
-        captured_by_order = false;
-        let it = heap.alloc(args.clone());
-        let it = it.iterate(&heap).unwrap();
-        let mut it = it.iter();
-        assert_eq!(
-            format_capture(
-                "{1",
-                &mut it,
-                &mut captured_by_index,
-                &mut captured_by_order,
-                &args,
-                &kwargs,
-            )
-            .unwrap(),
-            "2"
+        assert::fail(
+            "'%(a)s %*d' % {'a': 1}",
+            "Positional arguments can't follow `%(name)` arguments",
         );
-        assert!(
-            format_capture(
-                "{",
-                &mut it,
-                &mut captured_by_index,
-                &mut captured_by_order,
-                &args,
-                &kwargs,
-            )
-            .is_err()
+        // Python allows the mapping to be used positionally before any names
+        assert::eq("'%s %(a)s' % {'a': 1}", "'{\"a\": 1} 1'");
+    }
+
+    #[test]
+    fn test_precision_temporaries() {
+        // The strings formatted with a precision are temporaries of the same length, so
+        // many of them end up at the same address, which must not confuse the counting of
+        // code points
+        assert::pass(
+            r#"
+def f():
+    strings = ["é" * 40 + "a" * 11, "a" * 11 + "é" * 40, "a" + "界" * 30, "é" * 45 + "a"]
+    expect_s = [s[:30] for s in strings]
+    expect_r = [repr([s])[:40] for s in strings]
+    for _ in range(20):
+        assert_eq(["%.30s" % s for s in strings], expect_s)
+        assert_eq(["%.40r" % [s] for s in strings], expect_r)
+        assert_eq(["{:.30}".format(s) for s in strings], expect_s)
+f()
+"#,
         );
     }
 }
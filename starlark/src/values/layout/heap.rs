@@ -20,6 +20,7 @@
 // Encoding none, bool etc in the pointer of frozen value
 
 use crate::values::{
+    fast_string,
     layout::{
         arena::Arena,
         pointer::Pointer,
@@ -35,6 +36,7 @@ use std::{
     fmt,
     fmt::{Debug, Formatter},
     hash::{Hash, Hasher},
+    mem,
     ops::Deref,
     ptr,
     sync::Arc,
@@ -67,6 +69,13 @@ pub struct FrozenHeap {
     refs: RefCell<HashSet<FrozenHeapRef>>, // Memory I depend on
 }
 
+impl Drop for Heap {
+    fn drop(&mut self) {
+        // Our strings are about to be freed
+        fast_string::invalidate_cache();
+    }
+}
+
 impl Debug for FrozenHeap {
     fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
         let mut x = f.debug_struct("FrozenHeap");
@@ -103,6 +112,13 @@ impl PartialEq<FrozenHeapRef> for FrozenHeapRef {
 
 impl Eq for FrozenHeapRef {}
 
+impl Drop for FrozenHeap {
+    fn drop(&mut self) {
+        // Our strings are about to be freed
+        fast_string::invalidate_cache();
+    }
+}
+
 impl FrozenHeap {
     /// Create a new [`FrozenHeap`].
     pub fn new() -> Self {
@@ -312,7 +328,10 @@ impl Heap {
             arena: Arena::new(),
         };
         f(&traceer);
-        *arena = traceer.arena;
+        let old = mem::replace(&mut *arena, traceer.arena);
+        // Any strings that weren't copied are about to be freed
+        fast_string::invalidate_cache();
+        drop(old);
     }
 }
 
@@ -28,17 +28,22 @@
 //!   trait.
 //! * All the nested modules represent the built-in Starlark values. These are all defined using [`StarlarkValue`],
 //!   so may serve as interesting inspiration for writing your own values, in addition to occuring in Starlark programs.
-pub use crate::values::{error::*, iter::*, layout::*, owned::*, traits::*, types::*, unpack::*};
+pub use crate::values::fast_string::{set_string_indexing, StringIndexing};
+pub use crate::values::{
+    error::*, iter::*, layout::*, owned::*, serialize::from_value,
+    stack_guard::set_max_comparison_depth, traits::*, types::*, unpack::*,
+};
 use crate::{
     codemap::Span,
     collections::{Hashed, SmallHashResult},
+    docs::FunctionDocs,
     eval::{Evaluator, Parameters},
     values::function::FUNCTION_TYPE,
 };
 use gazebo::coerce::{Coerce, CoerceKey};
 pub use gazebo::{any::AnyLifetime, cell::ARef, prelude::*};
 use indexmap::Equivalent;
-pub use starlark_module::Trace;
+pub use starlark_module::{Freeze, StarlarkAttrs, Trace};
 use std::{
     cell::RefMut,
     cmp::Ordering,
@@ -57,6 +62,7 @@ pub(crate) mod interpolation;
 mod iter;
 mod layout;
 mod owned;
+mod serialize;
 mod stack_guard;
 mod traits;
 mod types;
@@ -172,7 +178,7 @@ impl Heap {
 /// allowing implementations of [`ComplexValue`] to be agnostic of their contained type.
 /// For details about each function, see the documentation for [`Value`],
 /// which provides the same functions (and more).
-pub trait ValueLike<'v>: Eq + Copy + Debug + Default {
+pub trait ValueLike<'v>: Eq + Copy + Debug + Default + AllocValue<'v> {
     /// Produce a [`Value`] regardless of the type you are starting with.
     fn to_value(self) -> Value<'v>;
 
@@ -203,6 +209,7 @@ pub trait ValueLike<'v>: Eq + Copy + Debug + Default {
     }
 
     fn to_json(self) -> anyhow::Result<String> {
+        let _guard = stack_guard::stack_guard()?;
         self.get_aref().to_json()
     }
 
@@ -312,10 +319,14 @@ impl<'v> Value<'v> {
     }
 
     /// Implement the `str()` function - converts a string value to itself,
-    /// otherwise uses `repr()`.
+    /// decodes bytes as UTF-8, otherwise uses `repr()`.
     pub fn to_str(self) -> String {
         match self.unpack_str() {
-            None => self.to_repr(),
+            None => match bytes::Bytes::from_value(self) {
+                // Invalid sequences are replaced with U+FFFD, as in the Go implementation
+                Some(b) => String::from_utf8_lossy(b.as_bytes()).into_owned(),
+                None => self.to_repr(),
+            },
             Some(s) => s.to_owned(),
         }
     }
@@ -442,9 +453,7 @@ impl<'v> Value<'v> {
         heap: &'v Heap,
     ) -> anyhow::Result<(AttrType, Value<'v>)> {
         match self.get_attr(attribute, heap) {
-            None => {
-                ValueError::unsupported_owned(self.get_type(), &format!(".{}", attribute), None)
-            }
+            None => ValueError::missing_attribute(self.get_type(), attribute),
             Some(x) => Ok(x),
         }
     }
@@ -502,6 +511,11 @@ impl<'v> Value<'v> {
         self.get_aref().at(index, heap)
     }
 
+    /// The documentation for this value, if it is a function which has some.
+    pub fn documentation(self) -> Option<FunctionDocs> {
+        self.get_aref().documentation()
+    }
+
     pub fn slice(
         self,
         start: Option<Value<'v>>,
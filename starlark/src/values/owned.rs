@@ -113,3 +113,10 @@ impl OwnedFrozenValue {
         self.value
     }
 }
+
+impl AllocFrozenValue for OwnedFrozenValue {
+    fn alloc_frozen_value(self, heap: &FrozenHeap) -> FrozenValue {
+        // Safe because the heap we are allocating on now keeps the owner alive
+        unsafe { self.owned_frozen_value(heap) }
+    }
+}
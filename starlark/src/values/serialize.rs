@@ -0,0 +1,1140 @@
+/*
+ * Copyright 2019 The Starlark in Rust Authors.
+ * Copyright (c) Facebook, Inc. and its affiliates.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Converting between Starlark values and Rust types with [`serde`].
+//!
+//! A [`Value`] implements [`Serialize`], so can be written out with e.g. `serde_json`,
+//! and [`from_value`] turns a [`Value`] into any Rust type which implements
+//! [`Deserialize`], e.g. a config struct. Going the other way,
+//! [`alloc_serialize`](Heap::alloc_serialize) turns any Rust value which implements
+//! [`Serialize`] into a [`Value`].
+//!
+//! `None`, bools, ints, strings, lists, tuples, dicts, structs, records and enums are
+//! supported. Structs and records become maps from their field names, and enum values
+//! become their value. Errors say where in the value the problem was, e.g.
+//! `x.deps[1]`, the same as `json.encode`.
+
+use crate::{
+    collections::SmallMap,
+    values::{
+        bytes::Bytes, dict::Dict, enumeration::EnumValue, list::List, record::Record,
+        structs::Struct, tuple::Tuple, view::ListView, FrozenValue, Heap, Value, ValueLike,
+    },
+};
+use serde::{
+    de::{
+        self, value::BorrowedStrDeserializer, DeserializeSeed, Deserializer, EnumAccess,
+        IntoDeserializer, MapAccess, SeqAccess, VariantAccess, Visitor,
+    },
+    forward_to_deserialize_any,
+    ser::{self, SerializeMap, SerializeSeq, Serializer},
+    Deserialize, Serialize,
+};
+use std::{
+    convert::TryFrom,
+    fmt::{self, Display},
+    vec,
+};
+
+/// An error converting between a [`Value`] and a Rust type.
+#[derive(Debug)]
+struct SerdeError {
+    message: String,
+    /// Where in the value the error was, innermost first, e.g. `[1]` then `.deps`.
+    path: Vec<String>,
+}
+
+impl SerdeError {
+    fn at(mut self, step: String) -> Self {
+        self.path.push(step);
+        self
+    }
+}
+
+impl Display for SerdeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.message)?;
+        if !self.path.is_empty() {
+            f.write_str(", at x")?;
+            for x in self.path.iter().rev() {
+                f.write_str(x)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for SerdeError {}
+
+impl de::Error for SerdeError {
+    fn custom<T: Display>(msg: T) -> Self {
+        Self {
+            message: msg.to_string(),
+            path: Vec::new(),
+        }
+    }
+}
+
+impl ser::Error for SerdeError {
+    fn custom<T: Display>(msg: T) -> Self {
+        de::Error::custom(msg)
+    }
+}
+
+/// How we got to a value from the one containing it.
+#[derive(Clone, Copy)]
+enum Step<'a, 'v> {
+    Root,
+    Index(usize),
+    Key(Value<'v>),
+    Field(&'a str),
+}
+
+/// A value being serialized, with the values containing it, so we can spot cycles and
+/// say where errors are.
+struct Node<'a, 'v> {
+    value: Value<'v>,
+    step: Step<'a, 'v>,
+    parent: Option<&'a Node<'a, 'v>>,
+}
+
+impl<'a, 'v> Node<'a, 'v> {
+    fn child(&'a self, step: Step<'a, 'v>, value: Value<'v>) -> Self {
+        Self {
+            value,
+            step,
+            parent: Some(self),
+        }
+    }
+
+    fn path(&self) -> String {
+        let mut steps = Vec::new();
+        let mut node = Some(self);
+        while let Some(x) = node {
+            steps.push(match x.step {
+                Step::Root => String::new(),
+                Step::Index(i) => format!("[{}]", i),
+                Step::Key(k) => format!("[{}]", k.to_repr()),
+                Step::Field(name) => format!(".{}", name),
+            });
+            node = x.parent;
+        }
+        steps.reverse();
+        format!("x{}", steps.concat())
+    }
+
+    fn is_cycle(&self) -> bool {
+        let mut node = self.parent;
+        while let Some(x) = node {
+            if x.value.ptr_eq(self.value) {
+                return true;
+            }
+            node = x.parent;
+        }
+        false
+    }
+
+    fn seq<S: Serializer>(&self, xs: &[Value<'v>], s: S) -> Result<S::Ok, S::Error> {
+        let mut seq = s.serialize_seq(Some(xs.len()))?;
+        for (i, x) in xs.iter().enumerate() {
+            seq.serialize_element(&self.child(Step::Index(i), *x))?;
+        }
+        seq.end()
+    }
+
+    fn fields<'x, S: Serializer>(
+        &self,
+        fields: impl ExactSizeIterator<Item = (&'x str, Value<'v>)>,
+        s: S,
+    ) -> Result<S::Ok, S::Error> {
+        let mut map = s.serialize_map(Some(fields.len()))?;
+        for (k, v) in fields {
+            map.serialize_entry(k, &self.child(Step::Field(k), v))?;
+        }
+        map.end()
+    }
+}
+
+impl Serialize for Node<'_, '_> {
+    fn serialize<S: Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        let x = self.value;
+        if x.is_none() {
+            return s.serialize_none();
+        } else if let Some(x) = x.unpack_bool() {
+            return s.serialize_bool(x);
+        } else if let Some(x) = x.unpack_int() {
+            return s.serialize_i32(x);
+        } else if let Some(x) = x.unpack_str() {
+            return s.serialize_str(x);
+        } else if let Some(e) = EnumValue::from_value(x) {
+            // Stands for its value, in the same place
+            let value = Node {
+                value: e.value,
+                step: self.step,
+                parent: self.parent,
+            };
+            return value.serialize(s);
+        }
+        if self.is_cycle() {
+            return Err(ser::Error::custom(format!(
+                "cannot serialize cyclic data structure, at {}",
+                self.path()
+            )));
+        }
+        if let Some(xs) = List::from_value(x) {
+            self.seq(&xs.content, s)
+        } else if let Some(xs) = Tuple::from_value(x) {
+            self.seq(&xs.content, s)
+        } else if let Some(d) = Dict::from_value(x) {
+            let mut map = s.serialize_map(Some(d.content.len()))?;
+            for (k, v) in d.iter() {
+                map.serialize_entry(&self.child(Step::Key(k), k), &self.child(Step::Key(k), v))?;
+            }
+            map.end()
+        } else if let Some(x) = Struct::from_value(x) {
+            self.fields(x.fields.iter().map(|(k, v)| (k.as_str(), *v)), s)
+        } else if let Some(x) = Record::from_value(x) {
+            let typ = x.get_record_type();
+            let names = typ.fields.keys().map(|k| k.as_str());
+            self.fields(names.zip(x.values.iter().copied()), s)
+        } else {
+            // Other types may know how to produce JSON
+            match x.to_json() {
+                Ok(json) => match serde_json::from_str::<serde_json::Value>(&json) {
+                    Ok(json) => json.serialize(s),
+                    Err(e) => Err(ser::Error::custom(format!("{}, at {}", e, self.path()))),
+                },
+                Err(_) => Err(ser::Error::custom(format!(
+                    "cannot serialize value of type `{}`, at {}",
+                    x.get_type(),
+                    self.path()
+                ))),
+            }
+        }
+    }
+}
+
+impl Serialize for Value<'_> {
+    fn serialize<S: Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        let node = Node {
+            value: *self,
+            step: Step::Root,
+            parent: None,
+        };
+        node.serialize(s)
+    }
+}
+
+impl Serialize for FrozenValue {
+    fn serialize<S: Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        self.to_value().serialize(s)
+    }
+}
+
+/// Convert a [`Value`] to any Rust type which implements [`Deserialize`]. Strings can
+/// be borrowed from the value, as `&'v str`.
+///
+/// ```
+/// # use serde::Deserialize;
+/// #[derive(Deserialize)]
+/// struct Config {
+///     name: String,
+///     deps: Vec<String>,
+///     jobs: Option<u32>,
+/// }
+///
+/// let x = starlark::assert::pass(r#"struct(name = "app", deps = ["lib"], jobs = None)"#);
+/// let config: Config = starlark::values::from_value(x.value()).unwrap();
+/// assert_eq!(config.deps, ["lib"]);
+/// ```
+pub fn from_value<'v, T: Deserialize<'v>>(x: Value<'v>) -> anyhow::Result<T> {
+    Ok(T::deserialize(ValueDeserializer(x))?)
+}
+
+struct ValueDeserializer<'v>(Value<'v>);
+
+/// The name of a field, or the key of a dict.
+enum Key<'v> {
+    Field(String),
+    Value(Value<'v>),
+}
+
+impl<'v> ValueDeserializer<'v> {
+    /// Enum values stand for their value.
+    fn value(&self) -> Value<'v> {
+        match EnumValue::from_value(self.0) {
+            Some(x) => x.value,
+            None => self.0,
+        }
+    }
+
+    fn fields<'x>(fields: impl Iterator<Item = (&'x String, Value<'v>)>) -> Map<'v> {
+        Map::new(fields.map(|(k, v)| (Key::Field(k.clone()), v)).collect())
+    }
+}
+
+impl<'de> Deserializer<'de> for ValueDeserializer<'de> {
+    type Error = SerdeError;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, SerdeError> {
+        let x = self.value();
+        if x.is_none() {
+            visitor.visit_unit()
+        } else if let Some(x) = x.unpack_bool() {
+            visitor.visit_bool(x)
+        } else if let Some(x) = x.unpack_int() {
+            visitor.visit_i32(x)
+        } else if let Some(x) = x.unpack_str() {
+            visitor.visit_borrowed_str(x)
+        } else if let Some(xs) = List::from_value(x) {
+            Seq::new(xs.content.clone()).visit(visitor)
+        } else if let Some(xs) = Tuple::from_value(x) {
+            Seq::new(xs.content.clone()).visit(visitor)
+        } else if let Some(xs) = ListView::from_value(x) {
+            match xs.values() {
+                Some(xs) => Seq::new(xs.collect()).visit(visitor),
+                // Without a heap we can't make the tuples, so go through their JSON
+                None => {
+                    let json = x.to_json().map_err(de::Error::custom)?;
+                    let json: serde_json::Value =
+                        serde_json::from_str(&json).map_err(de::Error::custom)?;
+                    json.deserialize_any(visitor).map_err(de::Error::custom)
+                }
+            }
+        } else if let Some(d) = Dict::from_value(x) {
+            let items = d.iter().map(|(k, v)| (Key::Value(k), v)).collect();
+            visitor.visit_map(Map::new(items))
+        } else if let Some(x) = Struct::from_value(x) {
+            visitor.visit_map(Self::fields(x.fields.iter().map(|(k, v)| (k, *v))))
+        } else if let Some(x) = Record::from_value(x) {
+            let typ = x.get_record_type();
+            let fields = typ.fields.keys().zip(x.values.iter().copied());
+            visitor.visit_map(Self::fields(fields))
+        } else {
+            Err(de::Error::custom(format!(
+                "cannot deserialize value of type `{}`",
+                x.get_type()
+            )))
+        }
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, SerdeError> {
+        if self.0.is_none() {
+            visitor.visit_none()
+        } else {
+            visitor.visit_some(self)
+        }
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, SerdeError> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    /// A unit variant is written as its name, and any other as a dict from its name
+    /// to its contents.
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, SerdeError> {
+        let x = self.value();
+        if let Some(x) = x.unpack_str() {
+            return visitor.visit_enum(BorrowedStrDeserializer::new(x));
+        }
+        if let Some(d) = Dict::from_value(x) {
+            if d.content.len() == 1 {
+                let (name, value) = d.iter().next().unwrap();
+                return visitor.visit_enum(Variant { name, value });
+            }
+        }
+        Err(de::Error::custom(format!(
+            "expected a string or a dict with one key for an enum, got `{}`",
+            x.get_type()
+        )))
+    }
+
+    forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 u8 u16 u32 u64 f32 f64 char str string bytes byte_buf unit
+        unit_struct seq tuple tuple_struct map struct identifier ignored_any
+    }
+}
+
+struct Seq<'v> {
+    items: vec::IntoIter<Value<'v>>,
+    index: usize,
+}
+
+impl<'v> Seq<'v> {
+    fn new(items: Vec<Value<'v>>) -> Self {
+        Self {
+            items: items.into_iter(),
+            index: 0,
+        }
+    }
+
+    /// Visit the items, failing if the visitor wanted fewer, e.g. a pair given three items.
+    fn visit<V: Visitor<'v>>(mut self, visitor: V) -> Result<V::Value, SerdeError> {
+        let res = visitor.visit_seq(&mut self)?;
+        let rest = self.items.len();
+        if rest == 0 {
+            Ok(res)
+        } else {
+            Err(de::Error::invalid_length(
+                self.index + rest,
+                &format!("{} elements", self.index).as_str(),
+            ))
+        }
+    }
+}
+
+impl<'de> SeqAccess<'de> for Seq<'de> {
+    type Error = SerdeError;
+
+    fn next_element_seed<T: DeserializeSeed<'de>>(
+        &mut self,
+        seed: T,
+    ) -> Result<Option<T::Value>, SerdeError> {
+        match self.items.next() {
+            None => Ok(None),
+            Some(x) => {
+                let i = self.index;
+                self.index += 1;
+                match seed.deserialize(ValueDeserializer(x)) {
+                    Ok(x) => Ok(Some(x)),
+                    Err(e) => Err(e.at(format!("[{}]", i))),
+                }
+            }
+        }
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.items.len())
+    }
+}
+
+struct Map<'v> {
+    items: vec::IntoIter<(Key<'v>, Value<'v>)>,
+    /// The value for the last key, and where it is.
+    value: Option<(String, Value<'v>)>,
+}
+
+impl<'v> Map<'v> {
+    fn new(items: Vec<(Key<'v>, Value<'v>)>) -> Self {
+        Self {
+            items: items.into_iter(),
+            value: None,
+        }
+    }
+}
+
+impl<'de> MapAccess<'de> for Map<'de> {
+    type Error = SerdeError;
+
+    fn next_key_seed<K: DeserializeSeed<'de>>(
+        &mut self,
+        seed: K,
+    ) -> Result<Option<K::Value>, SerdeError> {
+        let (k, v) = match self.items.next() {
+            None => return Ok(None),
+            Some(x) => x,
+        };
+        let (path, res) = match k {
+            Key::Field(k) => (format!(".{}", k), seed.deserialize(k.into_deserializer())),
+            Key::Value(k) => (
+                format!("[{}]", k.to_repr()),
+                seed.deserialize(ValueDeserializer(k)),
+            ),
+        };
+        let res = res.map_err(|e| e.at(path.clone()))?;
+        self.value = Some((path, v));
+        Ok(Some(res))
+    }
+
+    fn next_value_seed<V: DeserializeSeed<'de>>(
+        &mut self,
+        seed: V,
+    ) -> Result<V::Value, SerdeError> {
+        // Serde always asks for a key before its value
+        let (path, v) = self.value.take().unwrap();
+        seed.deserialize(ValueDeserializer(v))
+            .map_err(|e| e.at(path))
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.items.len())
+    }
+}
+
+/// An enum variant written as a dict from its name to its contents.
+struct Variant<'v> {
+    name: Value<'v>,
+    value: Value<'v>,
+}
+
+impl<'de> EnumAccess<'de> for Variant<'de> {
+    type Error = SerdeError;
+    type Variant = VariantContents<'de>;
+
+    fn variant_seed<V: DeserializeSeed<'de>>(
+        self,
+        seed: V,
+    ) -> Result<(V::Value, VariantContents<'de>), SerdeError> {
+        let path = format!("[{}]", self.name.to_repr());
+        let res = seed.deserialize(ValueDeserializer(self.name))?;
+        Ok((
+            res,
+            VariantContents {
+                path,
+                value: self.value,
+            },
+        ))
+    }
+}
+
+struct VariantContents<'v> {
+    path: String,
+    value: Value<'v>,
+}
+
+impl<'de> VariantAccess<'de> for VariantContents<'de> {
+    type Error = SerdeError;
+
+    fn unit_variant(self) -> Result<(), SerdeError> {
+        <()>::deserialize(ValueDeserializer(self.value)).map_err(|e| e.at(self.path))
+    }
+
+    fn newtype_variant_seed<T: DeserializeSeed<'de>>(
+        self,
+        seed: T,
+    ) -> Result<T::Value, SerdeError> {
+        seed.deserialize(ValueDeserializer(self.value))
+            .map_err(|e| e.at(self.path))
+    }
+
+    fn tuple_variant<V: Visitor<'de>>(
+        self,
+        _len: usize,
+        visitor: V,
+    ) -> Result<V::Value, SerdeError> {
+        ValueDeserializer(self.value)
+            .deserialize_any(visitor)
+            .map_err(|e| e.at(self.path))
+    }
+
+    fn struct_variant<V: Visitor<'de>>(
+        self,
+        _fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, SerdeError> {
+        ValueDeserializer(self.value)
+            .deserialize_any(visitor)
+            .map_err(|e| e.at(self.path))
+    }
+}
+
+impl Heap {
+    /// Allocate any Rust value which implements [`Serialize`]. Sequences become lists,
+    /// tuples become tuples, maps become dicts and structs become Starlark structs.
+    /// Unit variants of enums become their name, and other variants a dict from their
+    /// name to their contents.
+    ///
+    /// ```
+    /// # use serde::Serialize;
+    /// #[derive(Serialize)]
+    /// struct Config {
+    ///     name: String,
+    ///     deps: Vec<String>,
+    /// }
+    ///
+    /// let heap = starlark::values::Heap::new();
+    /// let config = Config { name: "app".to_owned(), deps: vec!["lib".to_owned()] };
+    /// let x = heap.alloc_serialize(&config).unwrap();
+    /// assert_eq!(x.to_repr(), r#"struct(name="app", deps=["lib"])"#);
+    /// ```
+    pub fn alloc_serialize<'v, T: Serialize + ?Sized>(
+        &'v self,
+        x: &T,
+    ) -> anyhow::Result<Value<'v>> {
+        Ok(x.serialize(ValueSerializer(self))?)
+    }
+}
+
+#[derive(Clone, Copy)]
+struct ValueSerializer<'v>(&'v Heap);
+
+impl<'v> ValueSerializer<'v> {
+    fn int<T: Copy + Display>(self, x: T) -> Result<Value<'v>, SerdeError>
+    where
+        i32: TryFrom<T>,
+    {
+        match i32::try_from(x) {
+            Ok(x) => Ok(Value::new_int(x)),
+            Err(_) => Err(ser::Error::custom(format!("integer `{}` is too large", x))),
+        }
+    }
+
+    /// A variant with contents, as a dict from its name to the contents.
+    fn variant(self, name: &'static str, x: Value<'v>) -> Value<'v> {
+        let mut res = SmallMap::with_capacity(1);
+        // Hashing a string can't fail
+        res.insert_hashed(self.0.alloc(name).get_hashed().unwrap(), x);
+        self.0.alloc(Dict::new(res))
+    }
+
+    fn float(self, x: f64) -> Result<Value<'v>, SerdeError> {
+        Err(ser::Error::custom(format!(
+            "number `{}` is not supported, only integers are",
+            x
+        )))
+    }
+}
+
+impl<'v> Serializer for ValueSerializer<'v> {
+    type Ok = Value<'v>;
+    type Error = SerdeError;
+    type SerializeSeq = SerializeItems<'v>;
+    type SerializeTuple = SerializeItems<'v>;
+    type SerializeTupleStruct = SerializeItems<'v>;
+    type SerializeTupleVariant = SerializeItems<'v>;
+    type SerializeMap = SerializeDict<'v>;
+    type SerializeStruct = SerializeFields<'v>;
+    type SerializeStructVariant = SerializeFields<'v>;
+
+    fn serialize_bool(self, x: bool) -> Result<Value<'v>, SerdeError> {
+        Ok(Value::new_bool(x))
+    }
+
+    fn serialize_i8(self, x: i8) -> Result<Value<'v>, SerdeError> {
+        self.int(x)
+    }
+
+    fn serialize_i16(self, x: i16) -> Result<Value<'v>, SerdeError> {
+        self.int(x)
+    }
+
+    fn serialize_i32(self, x: i32) -> Result<Value<'v>, SerdeError> {
+        Ok(Value::new_int(x))
+    }
+
+    fn serialize_i64(self, x: i64) -> Result<Value<'v>, SerdeError> {
+        self.int(x)
+    }
+
+    fn serialize_u8(self, x: u8) -> Result<Value<'v>, SerdeError> {
+        self.int(x)
+    }
+
+    fn serialize_u16(self, x: u16) -> Result<Value<'v>, SerdeError> {
+        self.int(x)
+    }
+
+    fn serialize_u32(self, x: u32) -> Result<Value<'v>, SerdeError> {
+        self.int(x)
+    }
+
+    fn serialize_u64(self, x: u64) -> Result<Value<'v>, SerdeError> {
+        self.int(x)
+    }
+
+    fn serialize_f32(self, x: f32) -> Result<Value<'v>, SerdeError> {
+        self.float(x.into())
+    }
+
+    fn serialize_f64(self, x: f64) -> Result<Value<'v>, SerdeError> {
+        self.float(x)
+    }
+
+    fn serialize_char(self, x: char) -> Result<Value<'v>, SerdeError> {
+        Ok(self.0.alloc(x.to_string().as_str()))
+    }
+
+    fn serialize_str(self, x: &str) -> Result<Value<'v>, SerdeError> {
+        Ok(self.0.alloc(x))
+    }
+
+    fn serialize_bytes(self, x: &[u8]) -> Result<Value<'v>, SerdeError> {
+        Ok(self.0.alloc_simple(Bytes::new(x.to_vec())))
+    }
+
+    fn serialize_none(self) -> Result<Value<'v>, SerdeError> {
+        Ok(Value::new_none())
+    }
+
+    fn serialize_some<T: Serialize + ?Sized>(self, x: &T) -> Result<Value<'v>, SerdeError> {
+        x.serialize(self)
+    }
+
+    fn serialize_unit(self) -> Result<Value<'v>, SerdeError> {
+        Ok(Value::new_none())
+    }
+
+    fn serialize_unit_struct(self, _name: &'static str) -> Result<Value<'v>, SerdeError> {
+        Ok(Value::new_none())
+    }
+
+    fn serialize_unit_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+    ) -> Result<Value<'v>, SerdeError> {
+        Ok(self.0.alloc(variant))
+    }
+
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        x: &T,
+    ) -> Result<Value<'v>, SerdeError> {
+        x.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+        x: &T,
+    ) -> Result<Value<'v>, SerdeError> {
+        let x = x
+            .serialize(self)
+            .map_err(|e| e.at(format!("[{:?}]", variant)))?;
+        Ok(self.variant(variant, x))
+    }
+
+    fn serialize_seq(self, len: Option<usize>) -> Result<SerializeItems<'v>, SerdeError> {
+        Ok(SerializeItems::new(
+            self,
+            len.unwrap_or_default(),
+            false,
+            None,
+        ))
+    }
+
+    fn serialize_tuple(self, len: usize) -> Result<SerializeItems<'v>, SerdeError> {
+        Ok(SerializeItems::new(self, len, true, None))
+    }
+
+    fn serialize_tuple_struct(
+        self,
+        _name: &'static str,
+        len: usize,
+    ) -> Result<SerializeItems<'v>, SerdeError> {
+        Ok(SerializeItems::new(self, len, true, None))
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+        len: usize,
+    ) -> Result<SerializeItems<'v>, SerdeError> {
+        Ok(SerializeItems::new(self, len, true, Some(variant)))
+    }
+
+    fn serialize_map(self, len: Option<usize>) -> Result<SerializeDict<'v>, SerdeError> {
+        Ok(SerializeDict {
+            serializer: self,
+            content: SmallMap::with_capacity(len.unwrap_or_default()),
+            key: None,
+        })
+    }
+
+    fn serialize_struct(
+        self,
+        _name: &'static str,
+        len: usize,
+    ) -> Result<SerializeFields<'v>, SerdeError> {
+        Ok(SerializeFields::new(self, len, None))
+    }
+
+    fn serialize_struct_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+        len: usize,
+    ) -> Result<SerializeFields<'v>, SerdeError> {
+        Ok(SerializeFields::new(self, len, Some(variant)))
+    }
+}
+
+/// Builds a list, or a tuple, possibly as the contents of an enum variant.
+struct SerializeItems<'v> {
+    serializer: ValueSerializer<'v>,
+    items: Vec<Value<'v>>,
+    tuple: bool,
+    variant: Option<&'static str>,
+}
+
+impl<'v> SerializeItems<'v> {
+    fn new(
+        serializer: ValueSerializer<'v>,
+        len: usize,
+        tuple: bool,
+        variant: Option<&'static str>,
+    ) -> Self {
+        Self {
+            serializer,
+            items: Vec::with_capacity(len),
+            tuple,
+            variant,
+        }
+    }
+
+    fn item<T: Serialize + ?Sized>(&mut self, x: &T) -> Result<(), SerdeError> {
+        let i = self.items.len();
+        let mut x = x.serialize(self.serializer);
+        if let Some(variant) = self.variant {
+            x = x.map_err(|e| e.at(format!("[{:?}]", variant)));
+        }
+        self.items.push(x.map_err(|e| e.at(format!("[{}]", i)))?);
+        Ok(())
+    }
+
+    fn finish(self) -> Result<Value<'v>, SerdeError> {
+        let heap = self.serializer.0;
+        let res = if self.tuple {
+            heap.alloc(Tuple::new(self.items))
+        } else {
+            heap.alloc(self.items)
+        };
+        Ok(match self.variant {
+            None => res,
+            Some(variant) => self.serializer.variant(variant, res),
+        })
+    }
+}
+
+impl<'v> ser::SerializeSeq for SerializeItems<'v> {
+    type Ok = Value<'v>;
+    type Error = SerdeError;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, x: &T) -> Result<(), SerdeError> {
+        self.item(x)
+    }
+
+    fn end(self) -> Result<Value<'v>, SerdeError> {
+        self.finish()
+    }
+}
+
+impl<'v> ser::SerializeTuple for SerializeItems<'v> {
+    type Ok = Value<'v>;
+    type Error = SerdeError;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, x: &T) -> Result<(), SerdeError> {
+        self.item(x)
+    }
+
+    fn end(self) -> Result<Value<'v>, SerdeError> {
+        self.finish()
+    }
+}
+
+impl<'v> ser::SerializeTupleStruct for SerializeItems<'v> {
+    type Ok = Value<'v>;
+    type Error = SerdeError;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, x: &T) -> Result<(), SerdeError> {
+        self.item(x)
+    }
+
+    fn end(self) -> Result<Value<'v>, SerdeError> {
+        self.finish()
+    }
+}
+
+impl<'v> ser::SerializeTupleVariant for SerializeItems<'v> {
+    type Ok = Value<'v>;
+    type Error = SerdeError;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, x: &T) -> Result<(), SerdeError> {
+        self.item(x)
+    }
+
+    fn end(self) -> Result<Value<'v>, SerdeError> {
+        self.finish()
+    }
+}
+
+/// Builds a dict.
+struct SerializeDict<'v> {
+    serializer: ValueSerializer<'v>,
+    content: SmallMap<Value<'v>, Value<'v>>,
+    /// The key whose value comes next.
+    key: Option<Value<'v>>,
+}
+
+impl<'v> SerializeMap for SerializeDict<'v> {
+    type Ok = Value<'v>;
+    type Error = SerdeError;
+
+    fn serialize_key<T: Serialize + ?Sized>(&mut self, x: &T) -> Result<(), SerdeError> {
+        self.key = Some(x.serialize(self.serializer)?);
+        Ok(())
+    }
+
+    fn serialize_value<T: Serialize + ?Sized>(&mut self, x: &T) -> Result<(), SerdeError> {
+        // Serde always gives a key before its value
+        let key = self.key.take().unwrap();
+        let path = || format!("[{}]", key.to_repr());
+        let value = x.serialize(self.serializer).map_err(|e| e.at(path()))?;
+        let key = key
+            .get_hashed()
+            .map_err(|e| <SerdeError as ser::Error>::custom(e).at(path()))?;
+        self.content.insert_hashed(key, value);
+        Ok(())
+    }
+
+    fn end(self) -> Result<Value<'v>, SerdeError> {
+        Ok(self.serializer.0.alloc(Dict::new(self.content)))
+    }
+}
+
+/// Builds a struct, possibly as the contents of an enum variant.
+struct SerializeFields<'v> {
+    serializer: ValueSerializer<'v>,
+    fields: SmallMap<String, Value<'v>>,
+    variant: Option<&'static str>,
+}
+
+impl<'v> SerializeFields<'v> {
+    fn new(serializer: ValueSerializer<'v>, len: usize, variant: Option<&'static str>) -> Self {
+        Self {
+            serializer,
+            fields: SmallMap::with_capacity(len),
+            variant,
+        }
+    }
+
+    fn field<T: Serialize + ?Sized>(
+        &mut self,
+        name: &'static str,
+        x: &T,
+    ) -> Result<(), SerdeError> {
+        let mut x = x.serialize(self.serializer);
+        if let Some(variant) = self.variant {
+            x = x.map_err(|e| e.at(format!("[{:?}]", variant)));
+        }
+        let x = x.map_err(|e| e.at(format!(".{}", name)))?;
+        self.fields.insert(name.to_owned(), x);
+        Ok(())
+    }
+
+    fn finish(self) -> Result<Value<'v>, SerdeError> {
+        let res = self.serializer.0.alloc(Struct::new(self.fields));
+        Ok(match self.variant {
+            None => res,
+            Some(variant) => self.serializer.variant(variant, res),
+        })
+    }
+}
+
+impl<'v> ser::SerializeStruct for SerializeFields<'v> {
+    type Ok = Value<'v>;
+    type Error = SerdeError;
+
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        name: &'static str,
+        x: &T,
+    ) -> Result<(), SerdeError> {
+        self.field(name, x)
+    }
+
+    fn end(self) -> Result<Value<'v>, SerdeError> {
+        self.finish()
+    }
+}
+
+impl<'v> ser::SerializeStructVariant for SerializeFields<'v> {
+    type Ok = Value<'v>;
+    type Error = SerdeError;
+
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        name: &'static str,
+        x: &T,
+    ) -> Result<(), SerdeError> {
+        self.field(name, x)
+    }
+
+    fn end(self) -> Result<Value<'v>, SerdeError> {
+        self.finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        assert,
+        values::{from_value, Heap},
+    };
+    use serde::{Deserialize, Serialize};
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    enum Mode {
+        Fast,
+        Slow { level: u8 },
+        Custom(String),
+    }
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Config<'a> {
+        name: &'a str,
+        deps: Vec<String>,
+        jobs: Option<u32>,
+        modes: Vec<Mode>,
+        pair: (i32, bool),
+    }
+
+    #[test]
+    fn test_from_value() {
+        let x = assert::pass(
+            r#"
+Mode = enum("Fast", "Slow")
+Opts = record(level = int.type)
+struct(
+    name = "app",
+    deps = ["a", "b"],
+    jobs = None,
+    modes = [Mode("Fast"), {"Slow": Opts(level = 3)}, {"Custom": "x"}],
+    pair = [1, True],
+)
+"#,
+        );
+        let config: Config = from_value(x.value()).unwrap();
+        assert_eq!(
+            config,
+            Config {
+                name: "app",
+                deps: vec!["a".to_owned(), "b".to_owned()],
+                jobs: None,
+                modes: vec![
+                    Mode::Fast,
+                    Mode::Slow { level: 3 },
+                    Mode::Custom("x".to_owned())
+                ],
+                pair: (1, true),
+            }
+        );
+    }
+
+    #[test]
+    fn test_from_value_view() {
+        let x = assert::pass(r#"{"a": 1}.items()"#);
+        let items: Vec<(String, i32)> = from_value(x.value()).unwrap();
+        assert_eq!(items, [("a".to_owned(), 1)]);
+        let x = assert::pass(r#"{"a": 1}.keys()"#);
+        let keys: Vec<&str> = from_value(x.value()).unwrap();
+        assert_eq!(keys, ["a"]);
+    }
+
+    #[test]
+    fn test_from_value_fail() {
+        let fail = |program: &str, msg: &str| {
+            let x = assert::pass(program);
+            let e = from_value::<Config>(x.value()).unwrap_err().to_string();
+            assert!(e.contains(msg), "Expected `{}` in `{}`", msg, e);
+        };
+        fail(
+            "struct(name = 'x', deps = ['a', 1], jobs = 1, modes = [], pair = (1, True))",
+            "invalid type: integer `1`, expected a string, at x.deps[1]",
+        );
+        fail(
+            "struct(name = 'x', deps = [], jobs = -1, modes = [], pair = (1, True))",
+            "expected u32, at x.jobs",
+        );
+        fail(
+            "struct(name = 'x', deps = [], jobs = 1, modes = [{'Slow': {'level': 'x'}}], pair = (1, True))",
+            "at x.modes[0][\"Slow\"][\"level\"]",
+        );
+        fail(
+            "struct(name = 'x', deps = [], jobs = 1, modes = [], pair = (1, True, 2))",
+            "invalid length 3, expected 2 elements, at x.pair",
+        );
+        fail("struct(name = 'x')", "missing field `deps`");
+    }
+
+    #[test]
+    fn test_serialize() {
+        let json = |program: &str| serde_json::to_string(&assert::pass(program).value());
+        assert_eq!(
+            json(
+                r#"
+Rec = record(a = int.type, b = str.type)
+Color = enum("red", "green")
+[None, True, 1, "x", (2,), {"k": [Color("green")]}, struct(r = Rec(a = 1, b = "y"))]
+"#
+            )
+            .unwrap(),
+            r#"[null,true,1,"x",[2],{"k":["green"]},{"r":{"a":1,"b":"y"}}]"#
+        );
+        assert_eq!(
+            json("def f(): pass\n{'a': [1, struct(b = f)]}")
+                .unwrap_err()
+                .to_string(),
+            "cannot serialize value of type `function`, at x[\"a\"][1].b"
+        );
+        assert_eq!(
+            json("xs = [1]\nxs.append({'k': xs})\nxs")
+                .unwrap_err()
+                .to_string(),
+            "cannot serialize cyclic data structure, at x[1][\"k\"]"
+        );
+    }
+
+    #[test]
+    fn test_alloc_serialize() {
+        let heap = Heap::new();
+        let config = Config {
+            name: "app",
+            deps: vec!["a".to_owned()],
+            jobs: Some(4),
+            modes: vec![
+                Mode::Fast,
+                Mode::Slow { level: 1 },
+                Mode::Custom("c".to_owned()),
+            ],
+            pair: (-1, false),
+        };
+        let x = heap.alloc_serialize(&config).unwrap();
+        assert_eq!(
+            x.to_repr(),
+            r#"struct(name="app", deps=["a"], jobs=4, modes=["Fast", {"Slow": struct(level=1)}, {"Custom": "c"}], pair=(-1, False))"#
+        );
+        assert_eq!(from_value::<Config>(x).unwrap(), config);
+
+        let e = heap.alloc_serialize(&vec![(1, 1.5)]).unwrap_err();
+        assert_eq!(
+            e.to_string(),
+            "number `1.5` is not supported, only integers are, at x[0][1]"
+        );
+        let e = heap.alloc_serialize(&[u64::MAX]).unwrap_err();
+        assert_eq!(
+            e.to_string(),
+            "integer `18446744073709551615` is too large, at x[0]"
+        );
+    }
+}
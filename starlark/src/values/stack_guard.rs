@@ -20,13 +20,12 @@
 use crate::values::ControlError;
 use std::cell::Cell;
 
-// Maximum recursion level for comparison
-// TODO(dmarting): those are rather short, maybe make it configurable?
+// Default maximum recursion level for comparison, see `set_max_comparison_depth`
 #[cfg(debug_assertions)]
-const MAX_RECURSION: u32 = 200;
+const DEFAULT_MAX_RECURSION: u32 = 200;
 
 #[cfg(not(debug_assertions))]
-const MAX_RECURSION: u32 = 3000;
+const DEFAULT_MAX_RECURSION: u32 = 3000;
 
 // A thread-local counter is used to detect too deep recursion.
 //
@@ -40,6 +39,15 @@ const MAX_RECURSION: u32 = 3000;
 //   signatures to accept some "context" parameters, but passing it as
 //   thread-local is easier.
 thread_local!(static STACK_DEPTH: Cell<u32> = Cell::new(0));
+thread_local!(static MAX_RECURSION: Cell<u32> = Cell::new(DEFAULT_MAX_RECURSION));
+
+/// Set how deeply nested values can be compared with `==`, `<` etc. on the current thread,
+/// after which the comparison fails with "Too many recursion levels". The default is 3000,
+/// or 200 in debug builds. Large values may cause the native stack to overflow, in which
+/// case compare on a thread with a larger stack.
+pub fn set_max_comparison_depth(max_depth: u32) {
+    MAX_RECURSION.with(|c| c.set(max_depth));
+}
 
 /// Stored previous stack depth before calling `try_inc`.
 ///
@@ -69,7 +77,7 @@ fn inc() -> StackGuard {
 
 /// Check stack depth does not exceed configured max stack depth.
 fn check() -> anyhow::Result<()> {
-    if STACK_DEPTH.with(Cell::get) >= MAX_RECURSION {
+    if STACK_DEPTH.with(Cell::get) >= MAX_RECURSION.with(Cell::get) {
         return Err(ControlError::TooManyRecursionLevel.into());
     }
     Ok(())
@@ -30,15 +30,16 @@
 //! hold several values.
 use crate::{
     codemap::Span,
-    collections::SmallMap,
+    collections::{Hashed, SmallMap},
+    docs::FunctionDocs,
     environment::Globals,
     eval::{Evaluator, Parameters},
     values::{
-        function::FUNCTION_TYPE, ConstFrozenValue, ControlError, Freezer, Heap, StarlarkIterable,
-        Tracer, Value, ValueError,
+        function::FUNCTION_TYPE, ConstFrozenValue, ControlError, Freezer, FrozenValue, Heap,
+        StarlarkIterable, Tracer, Value, ValueError,
     },
 };
-use gazebo::any::AnyLifetime;
+use gazebo::{any::AnyLifetime, prelude::*};
 use std::{
     cell::RefCell,
     cmp::Ordering,
@@ -149,10 +150,120 @@ unsafe impl<'v> Trace<'v> for u32 {
     fn trace(&mut self, _tracer: &Tracer<'v>) {}
 }
 
+unsafe impl<'v> Trace<'v> for usize {
+    fn trace(&mut self, _tracer: &Tracer<'v>) {}
+}
+
 unsafe impl<'v> Trace<'v> for bool {
     fn trace(&mut self, _tracer: &Tracer<'v>) {}
 }
 
+/// Turn a type containing [`Value`]s into its counterpart containing [`FrozenValue`]s,
+/// e.g. `Vec<Value<'v>>` into `Vec<FrozenValue>`. Usually derived with
+/// [`#[derive(Freeze)]`](macro@crate::values::Freeze) on a type `FooGen<V>`, which
+/// freezes `FooGen<Value<'v>>` into `FooGen<FrozenValue>` field by field, so that
+/// [`ComplexValue::freeze`] can be written as `Ok(box Freeze::freeze(*self, freezer)?)`.
+pub trait Freeze {
+    /// The frozen counterpart of this type.
+    type Frozen;
+
+    fn freeze(self, freezer: &Freezer) -> anyhow::Result<Self::Frozen>;
+}
+
+impl<'v> Freeze for Value<'v> {
+    type Frozen = FrozenValue;
+
+    fn freeze(self, freezer: &Freezer) -> anyhow::Result<FrozenValue> {
+        Value::freeze(self, freezer)
+    }
+}
+
+impl<'v> Freeze for Hashed<Value<'v>> {
+    type Frozen = Hashed<FrozenValue>;
+
+    fn freeze(self, freezer: &Freezer) -> anyhow::Result<Hashed<FrozenValue>> {
+        Hashed::<Value<'v>>::freeze(&self, freezer)
+    }
+}
+
+impl<T: Freeze> Freeze for Vec<T> {
+    type Frozen = Vec<T::Frozen>;
+
+    fn freeze(self, freezer: &Freezer) -> anyhow::Result<Self::Frozen> {
+        self.into_try_map(|x| x.freeze(freezer))
+    }
+}
+
+impl<T: Freeze> Freeze for Option<T> {
+    type Frozen = Option<T::Frozen>;
+
+    fn freeze(self, freezer: &Freezer) -> anyhow::Result<Self::Frozen> {
+        self.map(|x| x.freeze(freezer)).transpose()
+    }
+}
+
+impl<T: Freeze> Freeze for Box<T> {
+    type Frozen = Box<T::Frozen>;
+
+    fn freeze(self, freezer: &Freezer) -> anyhow::Result<Self::Frozen> {
+        Ok(box (*self).freeze(freezer)?)
+    }
+}
+
+impl<T1: Freeze, T2: Freeze> Freeze for (T1, T2) {
+    type Frozen = (T1::Frozen, T2::Frozen);
+
+    fn freeze(self, freezer: &Freezer) -> anyhow::Result<Self::Frozen> {
+        Ok((self.0.freeze(freezer)?, self.1.freeze(freezer)?))
+    }
+}
+
+impl<K: Freeze, V: Freeze> Freeze for SmallMap<K, V>
+where
+    K::Frozen: Eq,
+{
+    type Frozen = SmallMap<K::Frozen, V::Frozen>;
+
+    fn freeze(self, freezer: &Freezer) -> anyhow::Result<Self::Frozen> {
+        let mut res = SmallMap::with_capacity(self.len());
+        for (k, v) in self.into_iter_hashed() {
+            // Frozen keys have the same hash as the originals
+            let k = Hashed::new_unchecked(k.hash(), k.into_key().freeze(freezer)?);
+            res.insert_hashed(k, v.freeze(freezer)?);
+        }
+        Ok(res)
+    }
+}
+
+macro_rules! freeze_identity {
+    ($($t:ty),*) => {
+        $(
+            impl Freeze for $t {
+                type Frozen = Self;
+
+                fn freeze(self, _freezer: &Freezer) -> anyhow::Result<Self> {
+                    Ok(self)
+                }
+            }
+        )*
+    };
+}
+
+freeze_identity!(FrozenValue, String, i32, u32, usize, bool, ());
+
+/// Attribute access for a type, usually derived with
+/// [`#[derive(StarlarkAttrs)]`](macro@crate::values::StarlarkAttrs), which makes each
+/// field an attribute. Use the [`starlark_attrs!`](crate::starlark_attrs) macro in the
+/// [`StarlarkValue`] implementation to provide `get_attr`, `has_attr` and `dir_attr`
+/// from it.
+pub trait StarlarkAttrs<'v> {
+    /// The names of the attributes, in the order `dir` returns them.
+    const ATTRS: &'static [&'static str];
+
+    /// Get an attribute, [`None`] if there is no such attribute.
+    fn attr(&self, attribute: &str, heap: &'v Heap) -> Option<Value<'v>>;
+}
+
 /// A trait for values which are more complex - because they are either mutable,
 /// or contain references to other values.
 ///
@@ -497,6 +608,12 @@ pub trait StarlarkValue<'v>: 'v + AnyLifetime<'v> + AsStarlarkValue<'v> + Debug
         ValueError::unsupported(self, "call()")
     }
 
+    /// The documentation for this value, if it is a function which has some, e.g. a
+    /// function defined with [`#[starlark_module]`](macro@crate::starlark_module).
+    fn documentation(&self) -> Option<FunctionDocs> {
+        None
+    }
+
     /// Return the result of `a[index]` if `a` is indexable.
     fn at(&self, index: Value<'v>, _heap: &'v Heap) -> anyhow::Result<Value<'v>> {
         ValueError::unsupported_with(self, "[]", index)
@@ -684,6 +801,8 @@ pub trait StarlarkValue<'v>: 'v + AnyLifetime<'v> + AsStarlarkValue<'v> + Debug
     /// "%s" % ((1,),) == "(1,)"
     /// "%s" % [1] == "[1]"
     /// "test" % () == "test"
+    /// "%5d|%-5s|" % (42, "ab") == "   42|ab   |"
+    /// "%(name)s is %(age)03d" % {"name": "Bob", "age": 7} == "Bob is 007"
     /// # "#);
     /// ```
     fn percent(&self, other: Value<'v>, _heap: &'v Heap) -> anyhow::Result<Value<'v>> {
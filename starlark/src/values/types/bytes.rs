@@ -0,0 +1,199 @@
+/*
+ * Copyright 2019 The Starlark in Rust Authors.
+ * Copyright (c) Facebook, Inc. and its affiliates.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! The bytes type, an immutable sequence of bytes, written `b"..."`.
+
+use crate::{
+    environment::{Globals, GlobalsStatic},
+    values::{
+        index::{convert_index, convert_slice_indices},
+        Heap, StarlarkValue, Value, ValueError,
+    },
+};
+use std::{cmp::Ordering, collections::hash_map::DefaultHasher, hash::Hasher};
+
+/// The `bytes` type, an immutable sequence of bytes.
+///
+/// Indexing produces an `int` in the range 0-255, while slicing produces `bytes`.
+/// Unlike a string, a `bytes` value is not iterable, use `elems()` to get the bytes as a list.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Bytes(Box<[u8]>);
+
+starlark_simple_value!(Bytes);
+
+impl Bytes {
+    /// The result of calling `type()` on bytes.
+    pub const TYPE: &'static str = "bytes";
+
+    /// Create a new bytes value.
+    pub fn new(x: Vec<u8>) -> Self {
+        Self(x.into_boxed_slice())
+    }
+
+    /// The underlying bytes.
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+/// Write the bytes as a `b"..."` literal, using escapes for anything that isn't printable ASCII.
+pub(crate) fn collect_bytes_repr(x: &[u8], s: &mut String) {
+    s.push_str("b\"");
+    for b in x {
+        match b {
+            b'\n' => s.push_str("\\n"),
+            b'\t' => s.push_str("\\t"),
+            b'\r' => s.push_str("\\r"),
+            b'"' => s.push_str("\\\""),
+            b'\\' => s.push_str("\\\\"),
+            0x20..=0x7E => s.push(*b as char),
+            _ => s.push_str(&format!("\\x{:02x}", b)),
+        }
+    }
+    s.push('"');
+}
+
+impl<'v> StarlarkValue<'v> for Bytes {
+    starlark_type!(Bytes::TYPE);
+
+    fn get_methods(&self) -> Option<&'static Globals> {
+        static RES: GlobalsStatic = GlobalsStatic::new();
+        RES.methods(crate::stdlib::bytes::bytes_methods)
+    }
+
+    fn collect_repr(&self, s: &mut String) {
+        collect_bytes_repr(&self.0, s)
+    }
+
+    fn to_bool(&self) -> bool {
+        !self.0.is_empty()
+    }
+
+    fn get_hash(&self) -> anyhow::Result<u64> {
+        let mut s = DefaultHasher::new();
+        s.write(&self.0);
+        Ok(s.finish())
+    }
+
+    fn equals(&self, other: Value<'v>) -> anyhow::Result<bool> {
+        match Bytes::from_value(other) {
+            None => Ok(false),
+            Some(other) => Ok(self.0 == other.0),
+        }
+    }
+
+    fn compare(&self, other: Value<'v>) -> anyhow::Result<Ordering> {
+        match Bytes::from_value(other) {
+            None => ValueError::unsupported_with(self, "cmp()", other),
+            Some(other) => Ok(self.0.cmp(&other.0)),
+        }
+    }
+
+    fn at(&self, index: Value, _heap: &'v Heap) -> anyhow::Result<Value<'v>> {
+        let i = convert_index(index, self.0.len() as i32)? as usize;
+        Ok(Value::new_int(self.0[i] as i32))
+    }
+
+    fn length(&self) -> anyhow::Result<i32> {
+        Ok(self.0.len() as i32)
+    }
+
+    fn is_in(&self, other: Value<'v>) -> anyhow::Result<bool> {
+        if let Some(x) = other.unpack_int() {
+            if (0..=255).contains(&x) {
+                return Ok(self.0.contains(&(x as u8)));
+            }
+            return Err(ValueError::IncorrectParameterType.into());
+        }
+        match Bytes::from_value(other) {
+            Some(needle) if needle.0.is_empty() => Ok(true),
+            Some(needle) => Ok(self.0.windows(needle.0.len()).any(|x| x == &*needle.0)),
+            None => ValueError::unsupported_with(self, "in", other),
+        }
+    }
+
+    fn slice(
+        &self,
+        start: Option<Value>,
+        stop: Option<Value>,
+        stride: Option<Value>,
+        heap: &'v Heap,
+    ) -> anyhow::Result<Value<'v>> {
+        let (start, stop, stride) =
+            convert_slice_indices(self.0.len() as i32, start, stop, stride)?;
+        let mut res = Vec::new();
+        let mut i = start;
+        if stride > 0 {
+            while i < stop {
+                res.push(self.0[i as usize]);
+                i += stride;
+            }
+        } else {
+            while i > stop {
+                res.push(self.0[i as usize]);
+                i += stride;
+            }
+        }
+        Ok(heap.alloc(Bytes::new(res)))
+    }
+
+    fn add(&self, other: Value<'v>, heap: &'v Heap) -> anyhow::Result<Value<'v>> {
+        match Bytes::from_value(other) {
+            Some(other) => Ok(heap.alloc(Bytes::new([&*self.0, &*other.0].concat()))),
+            None => ValueError::unsupported_with(self, "+", other),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::assert;
+
+    #[test]
+    fn test_bytes() {
+        assert::all_true(
+            r#"
+type(b"abc") == "bytes"
+len(b"abc") == 3
+b"abc"[0] == 97
+b"abc"[-1] == 99
+b"abcde"[1:3] == b"bc"
+b"abcde"[::-2] == b"eca"
+b"ab" + b"cd" == b"abcd"
+b"a" < b"b"
+b"bc" in b"abcd"
+98 in b"abc"
+not b""
+b"\x00\xff".elems() == [0, 255]
+b"\377" == b"\xff"
+b"é" == b"\xc3\xa9"
+rb"\x00" == b"\\x00"
+repr(b"a\x00\"\n") == 'b"a\\x00\\"\\n"'
+str(b"caf\xc3\xa9") == "café"
+str(b"\xff") == "�"
+bytes("café") == b"caf\xc3\xa9"
+bytes([104, 105]) == b"hi"
+bytes(b"x") == b"x"
+{b"a": 1}[b"a"] == 1
+b"a" != "a"
+"#,
+        );
+        assert::fail("b'abc'[3]", "out of bound");
+        assert::fails("bytes([256])", &[]);
+        assert::fails("b'a' + 'a'", &[]);
+    }
+}
@@ -42,8 +42,8 @@ use crate::{
     values::{
         function::{NativeFunction, FUNCTION_TYPE},
         index::convert_index,
-        ComplexValue, Freezer, Heap, SimpleValue, StarlarkIterable, StarlarkValue, Trace, Value,
-        ValueLike,
+        ComplexValue, Freeze, Freezer, Heap, SimpleValue, StarlarkAttrs, StarlarkIterable,
+        StarlarkValue, Trace, Value, ValueLike,
     },
 };
 use derivative::Derivative;
@@ -51,7 +51,7 @@ use gazebo::{any::AnyLifetime, cell::ARef};
 use thiserror::Error;
 
 #[derive(Error, Debug)]
-enum EnumError {
+pub(crate) enum EnumError {
     #[error("enum values must all be distinct, but repeated `{0}`")]
     DuplicateEnumValue(String),
     #[error("Unknown enum element `{0}`, given to `{1}`")]
@@ -59,27 +59,28 @@ enum EnumError {
 }
 
 /// The type of an enumeration, created by `enum()`.
-#[derive(Clone, Debug, Trace)]
+#[derive(Clone, Debug, Trace, Freeze)]
 // Deliberately store fully populated values
 // for each entry, so we can produce enum values with zero allocation.
 pub struct EnumTypeGen<V> {
-    typ: Option<String>,
+    pub(crate) typ: Option<String>,
     // The key is the value of the enumeration
     // The value is a value of type EnumValue
-    elements: SmallMap<V, V>,
+    pub(crate) elements: SmallMap<V, V>,
     // Function to construct an enumeration, cached, so we don't recreate it on each invoke.
     constructor: V,
 }
 
 /// A value from an enumeration.
-#[derive(Clone, Derivative, Trace)]
+#[derive(Clone, Derivative, Trace, Freeze, StarlarkAttrs)]
 #[derivative(Debug)]
 pub struct EnumValueGen<V> {
     // Must ignore value.typ or type.elements, since they are circular
     #[derivative(Debug = "ignore")]
-    typ: V, // Must be EnumType it points back to (so it can get the type)
-    value: V,   // The value of this enumeration
-    index: i32, // The index in the enumeration
+    #[starlark(skip)]
+    pub(crate) typ: V, // Must be EnumType it points back to (so it can get the type)
+    pub(crate) value: V,   // The value of this enumeration
+    pub(crate) index: i32, // The index in the enumeration
 }
 
 starlark_complex_value!(pub EnumType);
@@ -92,15 +93,7 @@ impl<'v> ComplexValue<'v> for EnumType<'v> {
     }
 
     fn freeze(self: Box<Self>, freezer: &Freezer) -> anyhow::Result<Box<dyn SimpleValue>> {
-        let mut elements = SmallMap::with_capacity(self.elements.len());
-        for (k, t) in self.elements.into_iter_hashed() {
-            elements.insert_hashed(k.freeze(freezer)?, t.freeze(freezer)?);
-        }
-        Ok(box FrozenEnumType {
-            typ: self.typ,
-            elements,
-            constructor: self.constructor.freeze(freezer)?,
-        })
+        Ok(box Freeze::freeze(*self, freezer)?)
     }
 
     fn export_as(&mut self, variable_name: &str, _eval: &mut Evaluator<'v, '_>) {
@@ -112,11 +105,7 @@ impl<'v> ComplexValue<'v> for EnumType<'v> {
 
 impl<'v> ComplexValue<'v> for EnumValue<'v> {
     fn freeze(self: Box<Self>, freezer: &Freezer) -> anyhow::Result<Box<dyn SimpleValue>> {
-        Ok(box FrozenEnumValue {
-            typ: self.typ.freeze(freezer)?,
-            value: self.value.freeze(freezer)?,
-            index: self.index,
-        })
+        Ok(box Freeze::freeze(*self, freezer)?)
     }
 }
 
@@ -296,19 +285,5 @@ where
         self.value.get_hash()
     }
 
-    fn get_attr(&self, attribute: &str, _heap: &'v Heap) -> Option<Value<'v>> {
-        match attribute {
-            "index" => Some(Value::new_int(self.index)),
-            "value" => Some(self.value.to_value()),
-            _ => None,
-        }
-    }
-
-    fn has_attr(&self, attribute: &str) -> bool {
-        attribute == "index" || attribute == "value"
-    }
-
-    fn dir_attr(&self) -> Vec<String> {
-        vec!["index".to_owned(), "value".to_owned()]
-    }
+    starlark_attrs!();
 }
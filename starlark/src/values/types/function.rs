@@ -20,10 +20,11 @@
 use crate as starlark;
 use crate::{
     codemap::Span,
+    docs::FunctionDocs,
     eval::{Evaluator, Parameters, ParametersParser, ParametersSpec},
     values::{
         AllocFrozenValue, AllocValue, ComplexValue, ConstFrozenValue, Freezer, FrozenHeap,
-        FrozenValue, Heap, SimpleValue, StarlarkValue, Trace, Value, ValueLike,
+        FrozenValue, Heap, SimpleValue, StarlarkValue, Trace, Value, ValueError, ValueLike,
     },
 };
 use derivative::Derivative;
@@ -81,6 +82,7 @@ pub struct NativeFunction {
     name: String,
     parameters: ParametersSpec<FrozenValue>,
     typ: Option<FrozenValue>,
+    docs: Option<FunctionDocs>,
 }
 
 impl AllocFrozenValue for NativeFunction {
@@ -108,6 +110,7 @@ impl NativeFunction {
             name,
             parameters,
             typ: None,
+            docs: None,
         }
     }
 
@@ -115,6 +118,27 @@ impl NativeFunction {
     pub fn set_type(&mut self, typ: &'static ConstFrozenValue) {
         self.typ = Some(typ.unpack())
     }
+
+    /// The documentation, usually generated by [`#[starlark_module]`](macro@starlark_module)
+    /// from the doc comment and signature.
+    pub fn set_docs(&mut self, docs: FunctionDocs) {
+        self.docs = Some(docs)
+    }
+
+    /// If a parameter was the wrong type, say which type we expected.
+    fn improve_error(&self, e: anyhow::Error) -> anyhow::Error {
+        if let Some(ValueError::IncorrectParameterTypeNamed(name)) = e.downcast_ref() {
+            let name = name.trim_matches('_');
+            if let Some(typ) = self.docs.as_ref().and_then(|x| x.param_type(name)) {
+                return ValueError::IncorrectParameterTypeNamedExpected(
+                    name.to_owned(),
+                    typ.to_owned(),
+                )
+                .into();
+            }
+        }
+        e
+    }
 }
 
 impl SimpleValue for NativeFunction {}
@@ -147,11 +171,15 @@ impl<'v> StarlarkValue<'v> for NativeFunction {
                 let parser = ParametersParser::new(slots);
                 let res = (self.function)(eval, this, parser);
                 eval.local_variables.release_after(slots);
-                res
+                res.map_err(|e| self.improve_error(e))
             })
         })
     }
 
+    fn documentation(&self) -> Option<FunctionDocs> {
+        self.docs.clone()
+    }
+
     fn get_attr(&self, attribute: &str, _heap: &'v Heap) -> Option<Value<'v>> {
         if let Some(s) = &self.typ {
             if attribute == "type" {
@@ -267,4 +295,8 @@ where
         params.this = Some(self.this.to_value());
         self.method.invoke(location, params, eval)
     }
+
+    fn documentation(&self) -> Option<FunctionDocs> {
+        self.method.to_value().documentation()
+    }
 }
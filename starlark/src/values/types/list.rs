@@ -25,7 +25,9 @@ use crate::{
         error::ValueError,
         index::{convert_index, convert_slice_indices},
         iter::StarlarkIterable,
-        tuple, AllocFrozenValue, AllocValue, ComplexValue, Freezer, FrozenHeap, FrozenValue, Heap,
+        tuple,
+        view::ListView,
+        AllocFrozenValue, AllocValue, ComplexValue, Freezer, FrozenHeap, FrozenValue, Heap,
         SimpleValue, StarlarkValue, UnpackValue, Value, ValueLike,
     },
 };
@@ -183,16 +185,22 @@ where
     }
 
     fn equals(&self, other: Value<'v>) -> anyhow::Result<bool> {
-        match List::from_value(other) {
-            None => Ok(false),
-            Some(other) => equals_slice(&self.content, &other.content, |x, y| x.equals(*y)),
+        if let Some(other) = List::from_value(other) {
+            equals_slice(&self.content, &other.content, |x, y| x.equals(*y))
+        } else if let Some(other) = ListView::from_value(other) {
+            other.equals_list(&self.content)
+        } else {
+            Ok(false)
         }
     }
 
     fn compare(&self, other: Value<'v>) -> anyhow::Result<Ordering> {
-        match List::from_value(other) {
-            None => ValueError::unsupported_with(self, "cmp()", other),
-            Some(other) => compare_slice(&self.content, &other.content, |x, y| x.compare(*y)),
+        if let Some(other) = List::from_value(other) {
+            compare_slice(&self.content, &other.content, |x, y| x.compare(*y))
+        } else if let Some(other) = ListView::from_value(other) {
+            Ok(other.compare_list(&self.content)?.reverse())
+        } else {
+            ValueError::unsupported_with(self, "cmp()", other)
         }
     }
 
@@ -232,7 +240,13 @@ where
     }
 
     fn add(&self, other: Value<'v>, heap: &'v Heap) -> anyhow::Result<Value<'v>> {
-        if let Some(other) = List::from_value(other) {
+        if let Some(other) = ListView::from_value(other) {
+            let mut result = List {
+                content: self.iter().collect(),
+            };
+            result.content.extend(other.to_vec(heap));
+            Ok(heap.alloc(result))
+        } else if let Some(other) = List::from_value(other) {
             let mut result = List {
                 content: Vec::with_capacity(self.len() + other.len()),
             };
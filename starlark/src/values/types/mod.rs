@@ -17,6 +17,7 @@
 
 pub mod any;
 pub mod bool;
+pub mod bytes;
 pub mod dict;
 pub mod enumeration;
 pub mod function;
@@ -28,3 +29,4 @@ pub mod record;
 pub mod string;
 pub mod structs;
 pub mod tuple;
+pub mod view;
@@ -28,9 +28,9 @@ use std::{marker::PhantomData, num::NonZeroI32};
 /// Representation of `range()` type.
 #[derive(Clone, Copy, Dupe, Debug)]
 pub struct Range {
-    start: i32,
-    stop: i32,
-    step: NonZeroI32,
+    pub(crate) start: i32,
+    pub(crate) stop: i32,
+    pub(crate) step: NonZeroI32,
 }
 
 starlark_simple_value!(Range);
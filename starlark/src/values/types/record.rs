@@ -64,16 +64,16 @@ use std::{
 #[derive(Clone, Debug, Dupe, Trace)]
 pub struct FieldGen<V> {
     pub(crate) typ: V,
-    default: Option<V>,
+    pub(crate) default: Option<V>,
 }
 
 /// The result of `record()`, being the type of records.
 #[derive(Debug, Trace)]
 pub struct RecordTypeGen<V> {
     /// The name of this type, e.g. MyRecord
-    typ: Option<String>,
+    pub(crate) typ: Option<String>,
     /// The V is the type the field must satisfy (e.g. `"string"`)
-    fields: SmallMap<String, (FieldGen<V>, TypeCompiled)>,
+    pub(crate) fields: SmallMap<String, (FieldGen<V>, TypeCompiled)>,
     /// The construction function, which takes a hidden parameter (this type)
     /// followed by the arguments of the field.
     /// Creating these on every invoke is pretty expensive (profiling shows)
@@ -84,8 +84,8 @@ pub struct RecordTypeGen<V> {
 /// An actual record.
 #[derive(Clone, Debug, Trace)]
 pub struct RecordGen<V> {
-    typ: V, // Must be RecordType
-    values: Vec<V>,
+    pub(crate) typ: V, // Must be RecordType
+    pub(crate) values: Vec<V>,
 }
 
 starlark_complex_value!(pub(crate) Field);
@@ -189,7 +189,7 @@ impl<'v> RecordType<'v> {
 impl<'v, V: ValueLike<'v>> RecordGen<V> {
     pub const TYPE: &'static str = "record";
 
-    fn get_record_type(&self) -> ARef<'v, RecordType<'v>> {
+    pub(crate) fn get_record_type(&self) -> ARef<'v, RecordType<'v>> {
         // Safe to unwrap because we always ensure typ is RecordType
         RecordType::from_value(self.typ.to_value()).unwrap()
     }
@@ -21,9 +21,11 @@ use crate as starlark;
 use crate::{
     environment::{Globals, GlobalsStatic},
     values::{
-        fast_string, index::convert_slice_indices, interpolation, AllocFrozenValue, AllocValue,
-        ComplexValue, Freezer, FrozenHeap, FrozenValue, Heap, SimpleValue, StarlarkIterable,
-        StarlarkValue, Trace, UnpackValue, Value, ValueError, ValueLike,
+        fast_string,
+        index::{convert_index, convert_slice_indices},
+        interpolation, AllocFrozenValue, AllocValue, ComplexValue, Freezer, FrozenHeap,
+        FrozenValue, Heap, SimpleValue, StarlarkIterable, StarlarkValue, StringIndexing, Trace,
+        UnpackValue, Value, ValueError, ValueLike,
     },
 };
 use gazebo::any::AnyLifetime;
@@ -174,18 +176,23 @@ impl<'v> StarlarkValue<'v> for Box<str> {
     }
 
     fn at(&self, index: Value, heap: &'v Heap) -> anyhow::Result<Value<'v>> {
+        if fast_string::string_indexing() == StringIndexing::Bytes {
+            let i = convert_index(index, self.len() as i32)?;
+            let c = fast_string::from_bytes(vec![self.as_bytes()[i as usize]])?;
+            return Ok(heap.alloc(c));
+        }
         // This method is disturbingly hot. Use the logic from `convert_index`,
         // but modified to be UTF8 string friendly.
         match index.to_int() {
             Err(_) => Err(ValueError::IncorrectParameterType.into()),
             Ok(i) => {
                 if i >= 0 {
-                    match fast_string::at(self, i as usize) {
+                    match fast_string::at_stored(self, i as usize) {
                         None => Err(ValueError::IndexOutOfBound(i).into()),
                         Some(c) => Ok(heap.alloc(c.to_string())),
                     }
                 } else {
-                    let len = fast_string::len(self);
+                    let len = fast_string::len_stored(self);
                     let ind = (-i) as usize; // Index from the end, minimum of 1
                     if ind > len {
                         Err(ValueError::IndexOutOfBound(i).into())
@@ -193,7 +200,7 @@ impl<'v> StarlarkValue<'v> for Box<str> {
                         // We are a 7bit ASCII string, so take the fast-path
                         Ok(heap.alloc(self.as_bytes()[len - ind] as char))
                     } else {
-                        Ok(heap.alloc(fast_string::at(self, len - ind).unwrap()))
+                        Ok(heap.alloc(fast_string::at_stored(self, len - ind).unwrap()))
                     }
                 }
             }
@@ -201,7 +208,7 @@ impl<'v> StarlarkValue<'v> for Box<str> {
     }
 
     fn length(&self) -> anyhow::Result<i32> {
-        Ok(fast_string::len(self) as i32)
+        Ok(fast_string::len_stored(self) as i32)
     }
 
     fn is_in(&self, other: Value) -> anyhow::Result<bool> {
@@ -218,7 +225,16 @@ impl<'v> StarlarkValue<'v> for Box<str> {
         stride: Option<Value>,
         heap: &'v Heap,
     ) -> anyhow::Result<Value<'v>> {
-        let (start, stop, stride) = convert_slice_indices(self.len() as i32, start, stop, stride)?;
+        // Indices count characters, not bytes, unless we are indexing by byte
+        let len = fast_string::len_stored(self);
+        let (start, stop, stride) = convert_slice_indices(len as i32, start, stop, stride)?;
+        if stride == 1 {
+            return Ok(heap.alloc(fast_string::substring_stored(
+                self,
+                start as usize,
+                stop as usize,
+            )?));
+        }
         let (low, take, astride) = if stride < 0 {
             (stop + 1, start - stop, -stride)
         } else {
@@ -228,11 +244,16 @@ impl<'v> StarlarkValue<'v> for Box<str> {
             return Ok(heap.alloc(""));
         };
 
-        let v: String = self
-            .chars()
-            .skip(low as usize)
-            .take(take as usize)
-            .collect();
+        if fast_string::string_indexing() == StringIndexing::Bytes {
+            let v = &self.as_bytes()[low as usize..(low + take) as usize];
+            let v: Vec<u8> = if stride > 0 {
+                v.iter().copied().step_by(astride as usize).collect()
+            } else {
+                v.iter().copied().rev().step_by(astride as usize).collect()
+            };
+            return Ok(heap.alloc(fast_string::from_bytes(v)?));
+        }
+        let v = fast_string::substring_stored(self, low as usize, (low + take) as usize)?;
         let v: String = if stride > 0 {
             v.chars()
                 .enumerate()
@@ -286,7 +307,7 @@ impl<'v> StarlarkValue<'v> for Box<str> {
     }
 
     fn percent(&self, other: Value<'v>, heap: &'v Heap) -> anyhow::Result<Value<'v>> {
-        Ok(heap.alloc(interpolation::percent(self, other)?))
+        Ok(heap.alloc(interpolation::percent(self, other, heap)?))
     }
 }
 
@@ -302,17 +323,19 @@ impl<'v, 'a> AllocFrozenValue for &'a str {
     }
 }
 
-/// An opaque iterator over a string, produced by elems/codepoints
+/// An opaque iterator over a string, produced by elems/codepoints and their `_ords` variants
 #[derive(Debug, Trace)]
 struct StringIteratorGen<V> {
     string: V,
     produce_char: bool, // if not char, then int
+    bytes: bool,        // produce the bytes as ints, rather than the characters
 }
 
 pub(crate) fn iterate_chars<'v>(string: Value<'v>, heap: &'v Heap) -> Value<'v> {
     heap.alloc(StringIterator {
         string,
         produce_char: true,
+        bytes: false,
     })
 }
 
@@ -320,6 +343,15 @@ pub(crate) fn iterate_codepoints<'v>(string: Value<'v>, heap: &'v Heap) -> Value
     heap.alloc(StringIterator {
         string,
         produce_char: false,
+        bytes: false,
+    })
+}
+
+pub(crate) fn iterate_bytes<'v>(string: Value<'v>, heap: &'v Heap) -> Value<'v> {
+    heap.alloc(StringIterator {
+        string,
+        produce_char: false,
+        bytes: true,
     })
 }
 
@@ -341,7 +373,11 @@ impl<'v, T: ValueLike<'v>> StarlarkIterable<'v> for StringIteratorGen<T> {
     where
         'v: 'a,
     {
-        let s = self.string.to_value().unpack_str().unwrap().chars();
+        let s = self.string.to_value().unpack_str().unwrap();
+        if self.bytes {
+            return box s.bytes().map(|x| Value::new_int(x as i32));
+        }
+        let s = s.chars();
         if self.produce_char {
             box s.map(move |x| heap.alloc(x))
         } else {
@@ -355,6 +391,7 @@ impl<'v> ComplexValue<'v> for StringIterator<'v> {
         Ok(box FrozenStringIterator {
             string: freezer.freeze(self.string)?,
             produce_char: self.produce_char,
+            bytes: self.bytes,
         })
     }
 }
@@ -385,6 +422,35 @@ len("😿") == 1
         );
     }
 
+    #[test]
+    fn test_string_codepoints() {
+        // Strings are indexed by code point, check both short and long (cached) strings
+        assert::pass(
+            r#"
+s = "Hello, 世界!"
+assert_eq(len(s), 10)
+assert_eq((s[7], s[-2]), ("世", "界"))
+assert_eq(s[5:9], ", 世界")
+assert_eq(s[-3:], "世界!")
+assert_eq(s[::-1], "!界世 ,olleH")
+assert_eq(s[1::3], "eo世")
+assert_eq((s.find("界"), s.rfind("l"), s.index("!", 3, 10)), (8, 3, 9))
+assert_eq(s.count("l", 3), 1)
+assert_eq(list(s.elems())[7], "世")
+assert_eq(list(s.codepoints())[8], "界")
+assert_eq(list(s.codepoint_ords())[7], 19990)
+assert_eq(list(s.elem_ords())[0], 72)
+assert_eq(chr(ord(s[8])), "界")
+long = "é" * 100 + "x" + "ü" * 100
+assert_eq(len(long), 201)
+assert_eq((long[100], long[-1], long[99:102]), ("x", "ü", "éxü"))
+assert_eq(long.find("x"), 100)
+assert_eq([long[i] for i in range(100)], ["é"] * 100)
+"#,
+        );
+        assert::fail("'世界'[2]", "out of bound");
+    }
+
     #[test]
     fn test_arithmetic_on_string() {
         assert::all_true(
@@ -0,0 +1,468 @@
+/*
+ * Copyright 2019 The Starlark in Rust Authors.
+ * Copyright (c) Facebook, Inc. and its affiliates.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! The list view type, returned by `enumerate`, `zip`, `reversed` and the `items`,
+//! `keys` and `values` methods of a dict.
+//!
+//! The spec says these return lists, so a view behaves like an immutable list, with
+//! `len`, indexing, slicing, `in`, `+` and comparison, and equals a list with the same
+//! items. But where a list of `d.items()` holds a tuple for each item, a view only
+//! holds the values that make up the tuples, and allocates a tuple when an item is
+//! asked for. So a loop over a view which unpacks each tuple never needs one.
+//!
+//! A view takes a copy of the values when it is made, rather than looking at the dict
+//! or list it came from, so it doesn't change when they do, as a list wouldn't, and
+//! the program may mutate them while looping over the view.
+
+use crate as starlark;
+use crate::values::{
+    comparison::{compare_slice, equals_slice},
+    index::{convert_index, convert_slice_indices},
+    list::List,
+    tuple::Tuple,
+    ComplexValue, Freezer, Heap, SimpleValue, StarlarkIterable, StarlarkValue, Trace, Value,
+    ValueError, ValueLike,
+};
+use gazebo::{any::AnyLifetime, prelude::*};
+use std::cmp::Ordering;
+
+/// Define the list view type. See [`ListView`] and [`FrozenListView`] as the two aliases.
+#[derive(Clone, Debug, Trace)]
+pub struct ListViewGen<V> {
+    /// How many values make up each item.
+    width: usize,
+    /// Whether each item is a tuple of its values, rather than its only value.
+    tuples: bool,
+    /// The values of all the items, one item after another.
+    content: Vec<V>,
+}
+
+starlark_complex_value!(pub ListView);
+
+impl<V> ListViewGen<V> {
+    /// The result of calling `type()` on list views.
+    pub const TYPE: &'static str = "list_view";
+}
+
+/// One item of a view, or of a list.
+#[derive(Clone, Copy)]
+enum Item<'a, V> {
+    Value(V),
+    Tuple(&'a [V]),
+}
+
+impl<'v, V: ValueLike<'v>> Item<'_, V> {
+    fn to_value(self, heap: &'v Heap) -> Value<'v> {
+        match self {
+            Self::Value(x) => x.to_value(),
+            Self::Tuple(xs) => heap.alloc(Tuple::new(xs.map(|x| x.to_value()))),
+        }
+    }
+
+    fn collect_repr(self, s: &mut String) {
+        match self {
+            Self::Value(x) => x.collect_repr(s),
+            Self::Tuple(xs) => {
+                s.push('(');
+                for (i, x) in xs.iter().enumerate() {
+                    if i != 0 {
+                        s.push_str(", ");
+                    }
+                    x.collect_repr(s);
+                }
+                if xs.len() == 1 {
+                    s.push(',');
+                }
+                s.push(')');
+            }
+        }
+    }
+
+    fn to_json(self) -> anyhow::Result<String> {
+        match self {
+            Self::Value(x) => x.to_value().to_json(),
+            Self::Tuple(xs) => {
+                let xs: Vec<String> = xs.try_map(|x| x.to_value().to_json())?;
+                Ok(format!("[{}]", xs.join(", ")))
+            }
+        }
+    }
+
+    fn equals<V2: ValueLike<'v>>(self, other: Item<V2>) -> anyhow::Result<bool> {
+        match (self, other) {
+            (Self::Value(x), Item::Value(y)) => x.to_value().equals(y.to_value()),
+            (Self::Tuple(xs), Item::Tuple(ys)) => {
+                equals_slice(xs, ys, |x, y| x.to_value().equals(y.to_value()))
+            }
+            (Self::Value(x), Item::Tuple(ys)) => match Tuple::from_value(x.to_value()) {
+                None => Ok(false),
+                Some(xs) => equals_slice(&xs.content, ys, |x, y| x.equals(y.to_value())),
+            },
+            (Self::Tuple(_), Item::Value(_)) => other.equals(self),
+        }
+    }
+
+    fn compare<V2: ValueLike<'v>>(self, other: Item<V2>) -> anyhow::Result<Ordering> {
+        match (self, other) {
+            (Self::Value(x), Item::Value(y)) => x.to_value().compare(y.to_value()),
+            (Self::Tuple(xs), Item::Tuple(ys)) => {
+                compare_slice(xs, ys, |x, y| x.to_value().compare(y.to_value()))
+            }
+            (Self::Value(x), Item::Tuple(ys)) => match Tuple::from_value(x.to_value()) {
+                None => ValueError::unsupported_owned(
+                    x.to_value().get_type(),
+                    "cmp()",
+                    Some(Tuple::TYPE),
+                ),
+                Some(xs) => compare_slice(&xs.content, ys, |x, y| x.compare(y.to_value())),
+            },
+            (Self::Tuple(_), Item::Value(_)) => Ok(other.compare(self)?.reverse()),
+        }
+    }
+}
+
+impl<'v> ComplexValue<'v> for ListView<'v> {
+    fn freeze(self: Box<Self>, freezer: &Freezer) -> anyhow::Result<Box<dyn SimpleValue>> {
+        Ok(box FrozenListView {
+            width: self.width,
+            tuples: self.tuples,
+            content: self.content.into_try_map(|v| v.freeze(freezer))?,
+        })
+    }
+}
+
+impl<'v, V: ValueLike<'v>> ListViewGen<V> {
+    /// A view whose items are the values.
+    pub fn new(content: Vec<V>) -> Self {
+        Self {
+            width: 1,
+            tuples: false,
+            content,
+        }
+    }
+
+    /// A view whose items are tuples, each made of the next `width` values.
+    pub fn new_tuples(width: usize, content: Vec<V>) -> Self {
+        assert!(width > 0 && content.len() % width == 0);
+        Self {
+            width,
+            tuples: true,
+            content,
+        }
+    }
+
+    /// How many values make up each item, and whether the items are tuples of them.
+    pub fn shape(&self) -> (usize, bool) {
+        (self.width, self.tuples)
+    }
+
+    /// The values which make up the items, one item after another.
+    pub fn content(&self) -> &[V] {
+        &self.content
+    }
+
+    /// Obtain the number of items in the view.
+    pub fn len(&self) -> usize {
+        self.content.len() / self.width
+    }
+
+    /// The items, if the view isn't of tuples. Tuples can't be made without a heap.
+    pub fn values<'a>(&'a self) -> Option<impl Iterator<Item = Value<'v>> + 'a>
+    where
+        'v: 'a,
+    {
+        if self.tuples {
+            None
+        } else {
+            Some(self.content.iter().map(|x| x.to_value()))
+        }
+    }
+
+    fn items(&self) -> impl ExactSizeIterator<Item = Item<V>> {
+        let tuples = self.tuples;
+        self.content.chunks(self.width).map(move |xs| {
+            if tuples {
+                Item::Tuple(xs)
+            } else {
+                Item::Value(xs[0])
+            }
+        })
+    }
+
+    /// Whether the view has the same items as a list with these values.
+    pub(crate) fn equals_list<V2: ValueLike<'v>>(&self, other: &[V2]) -> anyhow::Result<bool> {
+        equals_items(self.items(), other.iter().map(|x| Item::Value(*x)))
+    }
+
+    /// How the view compares to a list with these values.
+    pub(crate) fn compare_list<V2: ValueLike<'v>>(&self, other: &[V2]) -> anyhow::Result<Ordering> {
+        compare_items(self.items(), other.iter().map(|x| Item::Value(*x)))
+    }
+
+    /// Allocate all the items, to make a list.
+    pub(crate) fn to_vec(&self, heap: &'v Heap) -> Vec<Value<'v>> {
+        self.items().map(|x| x.to_value(heap)).collect()
+    }
+}
+
+fn equals_items<'a, 'b, 'v, V1: ValueLike<'v> + 'a, V2: ValueLike<'v> + 'b>(
+    xs: impl ExactSizeIterator<Item = Item<'a, V1>>,
+    ys: impl ExactSizeIterator<Item = Item<'b, V2>>,
+) -> anyhow::Result<bool> {
+    if xs.len() != ys.len() {
+        return Ok(false);
+    }
+    for (x, y) in xs.zip(ys) {
+        if !x.equals(y)? {
+            return Ok(false);
+        }
+    }
+    Ok(true)
+}
+
+/// Compares like [`compare_slice`], so views compare in the same way as lists.
+fn compare_items<'a, 'b, 'v, V1: ValueLike<'v> + 'a, V2: ValueLike<'v> + 'b>(
+    xs: impl ExactSizeIterator<Item = Item<'a, V1>>,
+    ys: impl ExactSizeIterator<Item = Item<'b, V2>>,
+) -> anyhow::Result<Ordering> {
+    if xs.len() != ys.len() {
+        return Ok(xs.len().cmp(&ys.len()));
+    }
+    for (x, y) in xs.zip(ys) {
+        match x.compare(y)? {
+            Ordering::Equal => {}
+            res => return Ok(res),
+        }
+    }
+    Ok(Ordering::Equal)
+}
+
+impl<'v, V: ValueLike<'v>> StarlarkValue<'v> for ListViewGen<V>
+where
+    Self: AnyLifetime<'v>,
+{
+    starlark_type!(ListView::TYPE);
+
+    fn matches_type(&self, ty: &str) -> bool {
+        ty == ListView::TYPE || ty == List::TYPE
+    }
+
+    fn collect_repr(&self, s: &mut String) {
+        s.push('[');
+        for (i, x) in self.items().enumerate() {
+            if i != 0 {
+                s.push_str(", ");
+            }
+            x.collect_repr(s);
+        }
+        s.push(']');
+    }
+
+    fn to_json(&self) -> anyhow::Result<String> {
+        let xs: Vec<String> = self
+            .items()
+            .map(Item::to_json)
+            .collect::<anyhow::Result<_>>()?;
+        Ok(format!("[{}]", xs.join(", ")))
+    }
+
+    fn to_bool(&self) -> bool {
+        !self.content.is_empty()
+    }
+
+    fn equals(&self, other: Value<'v>) -> anyhow::Result<bool> {
+        if let Some(other) = List::from_value(other) {
+            self.equals_list(&other.content)
+        } else if let Some(other) = ListView::from_value(other) {
+            equals_items(self.items(), other.items())
+        } else {
+            Ok(false)
+        }
+    }
+
+    fn compare(&self, other: Value<'v>) -> anyhow::Result<Ordering> {
+        if let Some(other) = List::from_value(other) {
+            self.compare_list(&other.content)
+        } else if let Some(other) = ListView::from_value(other) {
+            compare_items(self.items(), other.items())
+        } else {
+            ValueError::unsupported_with(self, "cmp()", other)
+        }
+    }
+
+    fn at(&self, index: Value, heap: &'v Heap) -> anyhow::Result<Value<'v>> {
+        let i = convert_index(index, self.len() as i32)? as usize;
+        Ok(self.items().nth(i).unwrap().to_value(heap))
+    }
+
+    fn length(&self) -> anyhow::Result<i32> {
+        Ok(self.len() as i32)
+    }
+
+    fn is_in(&self, other: Value<'v>) -> anyhow::Result<bool> {
+        for x in self.items() {
+            if x.equals(Item::Value(other))? {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    fn slice(
+        &self,
+        start: Option<Value>,
+        stop: Option<Value>,
+        stride: Option<Value>,
+        heap: &'v Heap,
+    ) -> anyhow::Result<Value<'v>> {
+        let (start, stop, stride) = convert_slice_indices(self.len() as i32, start, stop, stride)?;
+        let mut content = Vec::new();
+        let mut i = start;
+        while (stride > 0 && i < stop) || (stride < 0 && i > stop) {
+            let at = i as usize * self.width;
+            content.extend(
+                self.content[at..at + self.width]
+                    .iter()
+                    .map(|x| x.to_value()),
+            );
+            i += stride;
+        }
+        Ok(heap.alloc(ListView {
+            width: self.width,
+            tuples: self.tuples,
+            content,
+        }))
+    }
+
+    fn iterate(&self) -> anyhow::Result<&(dyn StarlarkIterable<'v> + 'v)> {
+        Ok(self)
+    }
+
+    fn add(&self, other: Value<'v>, heap: &'v Heap) -> anyhow::Result<Value<'v>> {
+        let mut res = self.to_vec(heap);
+        if let Some(other) = List::from_value(other) {
+            res.extend(other.iter());
+        } else if let Some(other) = ListView::from_value(other) {
+            res.extend(other.to_vec(heap));
+        } else {
+            return ValueError::unsupported_with(self, "+", other);
+        }
+        Ok(heap.alloc(List::new(res)))
+    }
+
+    fn mul(&self, other: Value, heap: &'v Heap) -> anyhow::Result<Value<'v>> {
+        match other.unpack_int() {
+            Some(n) => {
+                let xs = self.to_vec(heap);
+                let mut res = Vec::new();
+                for _ in 0..n {
+                    res.extend(xs.iter().copied());
+                }
+                Ok(heap.alloc(List::new(res)))
+            }
+            None => Err(ValueError::IncorrectParameterType.into()),
+        }
+    }
+}
+
+impl<'v, V: ValueLike<'v>> StarlarkIterable<'v> for ListViewGen<V> {
+    fn to_iter<'a>(&'a self, heap: &'v Heap) -> Box<dyn Iterator<Item = Value<'v>> + 'a>
+    where
+        'v: 'a,
+    {
+        box self.items().map(move |x| x.to_value(heap))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::assert::Assert;
+
+    #[test]
+    fn test_list_view() {
+        Assert::new().pass(
+            r#"
+d = {"a": 1, "b": 2}
+assert_eq(d.items(), [("a", 1), ("b", 2)])
+assert_eq([("a", 1), ("b", 2)], d.items())
+assert_eq(["a", "b"], d.keys())
+assert_ne(d.items(), [("a", 1), ("b", 3)])
+assert_ne(d.items(), [("a", 1), ["b", 2]])
+assert_eq(enumerate(["a", "b"]), zip([0, 1], ["a", "b"]))
+assert_eq(reversed(d.items()), [("b", 2), ("a", 1)])
+assert_eq(zip(), [])
+assert_eq(zip([1]), [(1,)])
+assert_eq(len(zip([1, 2], [3])), 1)
+assert_eq(d.items()[1], ("b", 2))
+assert_eq(d.keys()[-1], "b")
+assert_eq(d.items()[::-1], [("b", 2), ("a", 1)])
+assert_eq(enumerate("abc".elems())[1:], [(1, "b"), (2, "c")])
+assert_eq([("a", 1) in d.items(), ("a", 2) in d.items(), "b" in d.keys()], [True, False, True])
+assert_eq(d.items() + [1], [("a", 1), ("b", 2), 1])
+assert_eq([1] + d.keys(), [1, "a", "b"])
+assert_eq(d.values() * 2, [1, 2, 1, 2])
+assert_eq(dict(d.items()), d)
+assert_eq(list(enumerate("ab".elems(), 1)), [(1, "a"), (2, "b")])
+assert_eq(d.items() < [("a", 1), ("c", 0)], True)
+assert_eq([("a", 1), ("b", 1)] < d.items(), True)
+assert_eq(sorted([d.keys(), ["a", "a"]]), [["a", "a"], ["a", "b"]])
+assert_eq(str(d.items()), '[("a", 1), ("b", 2)]')
+assert_eq(str(zip([1])), "[(1,)]")
+assert_eq(type(d.items()), "list_view")
+assert_eq([bool({}.items()), bool(d.items())], [False, True])
+"#,
+        );
+    }
+
+    #[test]
+    fn test_list_view_copy() {
+        // A view is a copy, so is unaffected by later changes
+        let a = Assert::new();
+        a.pass(
+            r#"
+d = {"a": 1}
+xs = [1]
+ks = d.keys()
+e = enumerate(xs)
+d["b"] = 2
+xs.append(2)
+assert_eq(ks, ["a"])
+assert_eq(e, [(0, 1)])
+"#,
+        );
+        a.fail(
+            "enumerate([1]).append(2)",
+            "not supported on type `list_view`",
+        );
+    }
+
+    #[test]
+    fn test_list_view_frozen() {
+        let mut a = Assert::new();
+        a.module("views", "items = {'a': 1}.items()\nkeys = {'a': 1}.keys()");
+        a.pass(
+            r#"
+load("views", "items", "keys")
+assert_eq(items, [("a", 1)])
+assert_eq([("a", 1)], items)
+assert_eq(keys, ["a"])
+assert_eq(items[0], ("a", 1))
+assert_eq(type(items), "list_view")
+"#,
+        );
+    }
+}
@@ -21,6 +21,7 @@ use crate::{
         dict::{Dict, ValueStr},
         list::List,
         tuple::Tuple,
+        view::ListView,
         Trace, Tracer, Value,
     },
 };
@@ -29,7 +30,7 @@ use std::fmt::{self, Debug};
 use thiserror::Error;
 
 #[derive(Debug, Error)]
-enum TypingError {
+pub(crate) enum TypingError {
     /// The value does not have the specified type
     #[error("Value `{0}` of type `{1}` does not match the type annotation `{2}` for {3}")]
     TypeAnnotationMismatch(String, String, String, String),
@@ -61,7 +62,11 @@ impl TypeCompiled {
 
         // Dictionary with a single element
         fn unpack_singleton_dictionary<'v>(x: &Dict<'v>) -> Option<(Value<'v>, Value<'v>)> {
-            if x.len() == 1 { x.iter().next() } else { None }
+            if x.len() == 1 {
+                x.iter().next()
+            } else {
+                None
+            }
         }
 
         fn f(ty: Value) -> anyhow::Result<Box<dyn for<'v> Fn(Value<'v>) -> bool + Send + Sync>> {
@@ -106,11 +111,16 @@ impl TypeCompiled {
                         let wildcard = t.unpack_str().map(is_wildcard) == Some(true);
                         if wildcard {
                             // Any type - so avoid the inner iteration
-                            Ok(box |v| List::from_value(v).is_some())
+                            Ok(box |v| {
+                                List::from_value(v).is_some() || ListView::from_value(v).is_some()
+                            })
                         } else {
                             let t = f(t)?;
                             Ok(box move |v| match List::from_value(v) {
-                                None => false,
+                                // Views of tuples don't match, as we can't make the tuples to check
+                                None => ListView::from_value(v).map_or(false, |v| {
+                                    v.values().map_or(false, |mut xs| xs.all(|x| t(x)))
+                                }),
                                 Some(v) => v.iter().all(|v| t(v)),
                             })
                         }
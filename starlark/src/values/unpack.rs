@@ -17,7 +17,7 @@
 
 //! Parameter conversion utilities for `starlark_module` macros.
 
-use crate::values::{list::List, tuple::Tuple, ComplexValue, Value};
+use crate::values::{list::List, tuple::Tuple, view::ListView, ComplexValue, Value};
 use gazebo::cell::ARef;
 use std::{cell::RefMut, marker::PhantomData, ops::Deref};
 
@@ -141,6 +141,9 @@ impl<'v, T: UnpackValue<'v>> UnpackValue<'v> for Vec<T> {
             o.iter().map(T::unpack_value).collect::<Option<Vec<_>>>()
         } else if let Some(o) = Tuple::from_value(value) {
             o.iter().map(T::unpack_value).collect::<Option<Vec<_>>>()
+        } else if let Some(o) = ListView::from_value(value) {
+            // Views of tuples can't be unpacked, as we can't make the tuples here
+            o.values()?.map(T::unpack_value).collect::<Option<Vec<_>>>()
         } else {
             None
         }
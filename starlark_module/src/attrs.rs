@@ -0,0 +1,141 @@
+/*
+ * Copyright 2019 The Starlark in Rust Authors.
+ * Copyright (c) Facebook, Inc. and its affiliates.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use crate::util::ident_string;
+use proc_macro2::{Span, TokenStream};
+use quote::{quote, quote_spanned};
+use syn::{
+    parse_macro_input, parse_quote, spanned::Spanned, Attribute, Data, DeriveInput, Error, Fields,
+    GenericParam, Generics, Ident, Lifetime, LifetimeDef, Type, TypePath, WherePredicate,
+};
+
+pub fn derive_attrs(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    match attrs_impl(&input) {
+        Ok(x) => x.into(),
+        Err(e) => e.to_compile_error().into(),
+    }
+}
+
+/// Fields marked `#[starlark(skip)]` are not attributes.
+fn is_skipped(attrs: &[Attribute]) -> syn::Result<bool> {
+    let mut res = false;
+    for attr in attrs {
+        if attr.path.is_ident("starlark") {
+            let arg: Ident = attr.parse_args()?;
+            if arg != "skip" {
+                return Err(Error::new(arg.span(), "Unknown attribute, expected `skip`"));
+            }
+            res = true;
+        }
+    }
+    Ok(res)
+}
+
+/// Is the field a Starlark value, i.e. a type parameter like the `V` in `FooGen<V>`,
+/// or `Value<'v>` or `FrozenValue`, rather than Rust data which must be allocated.
+fn is_value(generics: &Generics, ty: &Type) -> bool {
+    let path = match ty {
+        Type::Path(TypePath { qself: None, path }) => path,
+        _ => return false,
+    };
+    let last = match path.segments.last() {
+        Some(x) => &x.ident,
+        None => return false,
+    };
+    if path.segments.len() == 1 && generics.type_params().any(|x| &x.ident == last) {
+        return true;
+    }
+    last == "Value" || last == "FrozenValue"
+}
+
+fn attrs_impl(input: &DeriveInput) -> syn::Result<TokenStream> {
+    let fields = match &input.data {
+        Data::Struct(data) => match &data.fields {
+            Fields::Named(fields) => &fields.named,
+            _ => {
+                return Err(Error::new(
+                    input.ident.span(),
+                    "Can't derive StarlarkAttrs for a struct without named fields",
+                ));
+            }
+        },
+        _ => {
+            return Err(Error::new(
+                input.ident.span(),
+                "Can't derive StarlarkAttrs for anything but a struct",
+            ));
+        }
+    };
+
+    let mut names = Vec::new();
+    let mut arms = Vec::new();
+    let mut bounds: Vec<WherePredicate> = Vec::new();
+    for f in fields {
+        if is_skipped(&f.attrs)? {
+            continue;
+        }
+        let ident = f.ident.as_ref().unwrap();
+        let name = ident_string(ident);
+        let ty = &f.ty;
+        if is_value(&input.generics, ty) {
+            // Return the stored value itself, so mutating it, e.g. appending to a list, is seen
+            arms.push(quote_spanned! {f.span() =>
+                #name => Some(starlark::values::ValueLike::to_value(self.#ident)),
+            });
+            bounds.push(parse_quote!(#ty: starlark::values::ValueLike<'v>));
+        } else {
+            arms.push(quote_spanned! {f.span() =>
+                #name => Some(starlark::values::AllocValue::alloc_value(
+                    std::clone::Clone::clone(&self.#ident),
+                    heap,
+                )),
+            });
+            bounds.push(parse_quote!(#ty: std::clone::Clone + starlark::values::AllocValue<'v>));
+        }
+        names.push(name);
+    }
+
+    let mut generics = input.generics.clone();
+    let has_tick_v = generics.lifetimes().any(|t| t.lifetime.ident == "v");
+    generics.make_where_clause().predicates.extend(bounds);
+    let (_, ty_generics, where_clause) = generics.split_for_impl();
+    let mut generics2 = generics.clone();
+    if !has_tick_v {
+        let tick_v = LifetimeDef::new(Lifetime::new("'v", Span::call_site()));
+        generics2.params.insert(0, GenericParam::Lifetime(tick_v));
+    }
+    let (impl_generics, _, _) = generics2.split_for_impl();
+
+    let name = &input.ident;
+    Ok(quote! {
+        impl #impl_generics starlark::values::StarlarkAttrs<'v> for #name #ty_generics #where_clause {
+            const ATTRS: &'static [&'static str] = &[#(#names),*];
+
+            fn attr(
+                &self,
+                attribute: &str,
+                heap: &'v starlark::values::Heap,
+            ) -> Option<starlark::values::Value<'v>> {
+                match attribute {
+                    #(#arms)*
+                    _ => None,
+                }
+            }
+        }
+    })
+}
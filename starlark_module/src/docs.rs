@@ -0,0 +1,190 @@
+/*
+ * Copyright 2019 The Starlark in Rust Authors.
+ * Copyright (c) Facebook, Inc. and its affiliates.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use crate::{typ::*, util::*};
+use proc_macro2::TokenStream;
+use quote::quote;
+use syn::*;
+
+// Create an expression producing the `FunctionDocs` of a function.
+pub(crate) fn render_docs(x: &StarFun) -> TokenStream {
+    let docs = render_option(doc_comment(&x.attrs));
+    let params = x
+        .args
+        .iter()
+        .filter(|x| !x.is_this())
+        .map(render_param_docs);
+    let return_type = render_option(type_hint(&x.return_type));
+    quote! {
+        starlark::docs::FunctionDocs {
+            docs: #docs,
+            params: vec![#( #params ),*],
+            return_type: #return_type,
+        }
+    }
+}
+
+fn render_param_docs(arg: &StarArg) -> TokenStream {
+    let name = ident_string(&arg.name);
+    let name = name.trim_matches('_');
+    let kind = if arg.is_args() {
+        quote! { starlark::docs::ParamKind::Args }
+    } else if arg.is_kwargs() {
+        quote! { starlark::docs::ParamKind::Kwargs }
+    } else if arg.is_option() {
+        quote! { starlark::docs::ParamKind::Optional }
+    } else if let Some(default) = &arg.default {
+        let default = default_repr(default);
+        quote! { starlark::docs::ParamKind::Defaulted(#default.to_owned()) }
+    } else {
+        quote! { starlark::docs::ParamKind::Required }
+    };
+    // The `*args` and `**kwargs` are always a list and a dict, whatever Rust unpacks them as
+    let typ = if arg.is_args() {
+        Some("list".to_owned())
+    } else if arg.is_kwargs() {
+        Some("dict".to_owned())
+    } else {
+        type_hint(&arg.ty)
+    };
+    let typ = render_option(typ);
+    let positional_only = arg.by_ref;
+    quote! {
+        starlark::docs::ParamDocs {
+            name: #name.to_owned(),
+            kind: #kind,
+            positional_only: #positional_only,
+            keyword_only: false,
+            typ: #typ,
+        }
+    }
+}
+
+fn render_option(x: Option<String>) -> TokenStream {
+    match x {
+        None => quote! { None },
+        Some(x) => quote! { Some(#x.to_owned()) },
+    }
+}
+
+// The text of the `///` comments, without the leading space on each line.
+fn doc_comment(attrs: &[Attribute]) -> Option<String> {
+    let lines = attrs
+        .iter()
+        .filter(|x| x.path.is_ident("doc"))
+        .filter_map(|x| match x.parse_meta() {
+            Ok(Meta::NameValue(MetaNameValue {
+                lit: Lit::Str(s), ..
+            })) => Some(s.value()),
+            _ => None,
+        })
+        .map(|x| x.strip_prefix(' ').map_or(x.clone(), str::to_owned))
+        .collect::<Vec<_>>();
+    let res = starlark_examples(&lines).join("\n").trim().to_owned();
+    if res.is_empty() {
+        None
+    } else {
+        Some(res)
+    }
+}
+
+// The examples in doc comments are Starlark wrapped in hidden Rust lines, e.g.
+// `# starlark::assert::all_true(r#"`, so drop those and mark the code as Python,
+// which is the closest language most Markdown renderers can highlight.
+fn starlark_examples(lines: &[String]) -> Vec<&str> {
+    let mut res = Vec::new();
+    let mut in_code = false;
+    for line in lines {
+        if line.starts_with("```") {
+            res.push(if in_code { "```" } else { "```python" });
+            in_code = !in_code;
+        } else if !(in_code && (line == "#" || line.starts_with("# "))) {
+            res.push(line);
+        }
+    }
+    res
+}
+
+// A default written as a Rust pattern, e.g. `2` or `""`, written as Starlark.
+fn default_repr(x: &Pat) -> String {
+    match x {
+        Pat::Lit(PatLit {
+            expr: box Expr::Lit(ExprLit { lit, .. }),
+            ..
+        }) => match lit {
+            Lit::Str(x) => format!("{:?}", x.value()),
+            Lit::Bool(x) if x.value => "True".to_owned(),
+            Lit::Bool(_) => "False".to_owned(),
+            lit => quote!(#lit).to_string(),
+        },
+        // Both `NoneType` and `NoneOr::None` are a Starlark `None`
+        Pat::Ident(PatIdent { ident, .. }) if ident == "NoneType" => "None".to_owned(),
+        Pat::Path(PatPath { path, .. })
+            if path.segments.last().map_or(false, |x| x.ident == "None") =>
+        {
+            "None".to_owned()
+        }
+        x => quote!(#x).to_string().replace(" :: ", "::"),
+    }
+}
+
+// The Starlark type of values of a Rust type, if we can tell, written as the name
+// `type()` gives at runtime, e.g. `string` for `&str` and `list` for `Vec<i32>`.
+fn type_hint(x: &Type) -> Option<String> {
+    match x {
+        Type::Reference(TypeReference { elem: box x, .. })
+        | Type::Group(TypeGroup { elem: box x, .. })
+        | Type::Paren(TypeParen { elem: box x, .. }) => type_hint(x),
+        Type::Tuple(TypeTuple { elems, .. }) if elems.is_empty() => Some("NoneType".to_owned()),
+        Type::Tuple(_) => Some("tuple".to_owned()),
+        Type::Path(TypePath { path, .. }) => {
+            let seg = path.segments.last()?;
+            let args = match &seg.arguments {
+                PathArguments::AngleBracketed(x) => x
+                    .args
+                    .iter()
+                    .filter_map(|x| match x {
+                        GenericArgument::Type(x) => Some(x),
+                        _ => None,
+                    })
+                    .collect(),
+                _ => Vec::new(),
+            };
+            let name = seg.ident.to_string();
+            match name.as_str() {
+                "Value" | "FrozenValue" => None,
+                "i8" | "i16" | "i32" | "i64" | "u8" | "u16" | "u32" | "u64" | "usize" => {
+                    Some("int".to_owned())
+                }
+                "bool" => Some("bool".to_owned()),
+                "str" | "String" => Some("string".to_owned()),
+                "NoneType" => Some("NoneType".to_owned()),
+                "Option" | "ARef" | "Box" => type_hint(args.first()?),
+                "NoneOr" => Some(format!("{} | NoneType", type_hint(args.first()?)?)),
+                "Vec" | "List" => Some("list".to_owned()),
+                "ListView" => Some("list_view".to_owned()),
+                "SmallMap" | "Dict" => Some("dict".to_owned()),
+                "Tuple" => Some("tuple".to_owned()),
+                "Struct" => Some("struct".to_owned()),
+                "Range" => Some("range".to_owned()),
+                "Bytes" => Some("bytes".to_owned()),
+                _ => Some(name),
+            }
+        }
+        _ => None,
+    }
+}
@@ -0,0 +1,106 @@
+/*
+ * Copyright 2019 The Starlark in Rust Authors.
+ * Copyright (c) Facebook, Inc. and its affiliates.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use proc_macro2::TokenStream;
+use quote::{quote, quote_spanned};
+use syn::{
+    parse_macro_input, spanned::Spanned, Attribute, Data, DataStruct, DeriveInput, Error, Fields,
+    GenericParam, Ident,
+};
+
+pub fn derive_freeze(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    match freeze_impl(&input) {
+        Ok(x) => x.into(),
+        Err(e) => e.to_compile_error().into(),
+    }
+}
+
+fn freeze_impl(input: &DeriveInput) -> syn::Result<TokenStream> {
+    // We freeze `FooGen<Value<'v>>` into `FooGen<FrozenValue>`, so need exactly one type parameter
+    let params = &input.generics.params;
+    if params.len() != 1 || !matches!(params.first(), Some(GenericParam::Type(_))) {
+        return Err(Error::new(
+            input.generics.span(),
+            "Can't derive Freeze for a type without exactly one type parameter, e.g. `FooGen<V>`",
+        ));
+    }
+    let name = &input.ident;
+    let body = match &input.data {
+        Data::Struct(data) => freeze_struct(name, data)?,
+        _ => {
+            return Err(Error::new(
+                input.ident.span(),
+                "Can't derive Freeze for anything but a struct",
+            ));
+        }
+    };
+    Ok(quote! {
+        impl<'v> starlark::values::Freeze for #name<starlark::values::Value<'v>> {
+            type Frozen = #name<starlark::values::FrozenValue>;
+            fn freeze(self, freezer: &starlark::values::Freezer) -> anyhow::Result<Self::Frozen> {
+                Ok(#body)
+            }
+        }
+    })
+}
+
+/// Fields marked `#[freeze(identity)]` are moved over as they are, without needing `Freeze`.
+fn is_identity(attrs: &[Attribute]) -> syn::Result<bool> {
+    let mut res = false;
+    for attr in attrs {
+        if attr.path.is_ident("freeze") {
+            let arg: Ident = attr.parse_args()?;
+            if arg != "identity" {
+                return Err(Error::new(
+                    arg.span(),
+                    "Unknown attribute, expected `identity`",
+                ));
+            }
+            res = true;
+        }
+    }
+    Ok(res)
+}
+
+fn freeze_struct(name: &Ident, data: &DataStruct) -> syn::Result<TokenStream> {
+    let mut xs = Vec::new();
+    for (i, f) in data.fields.iter().enumerate() {
+        let field = match &f.ident {
+            Some(ident) => quote! {#ident},
+            None => {
+                let i = syn::Index::from(i);
+                quote! {#i}
+            }
+        };
+        xs.push(if is_identity(&f.attrs)? {
+            quote_spanned! {f.span() => self.#field}
+        } else {
+            quote_spanned! {f.span() =>
+                starlark::values::Freeze::freeze(self.#field, freezer)?
+            }
+        });
+    }
+    Ok(match &data.fields {
+        Fields::Named(fields) => {
+            let names = fields.named.iter().map(|f| &f.ident);
+            quote! { #name { #(#names: #xs,)* } }
+        }
+        Fields::Unnamed(_) => quote! { #name ( #(#xs,)* ) },
+        Fields::Unit => quote! { #name },
+    })
+}
@@ -25,6 +25,9 @@ extern crate proc_macro;
 use proc_macro::TokenStream;
 use syn::*;
 
+mod attrs;
+mod docs;
+mod freeze;
 mod parse;
 mod render;
 mod trace;
@@ -74,6 +77,9 @@ mod util;
 ///
 /// All these functions interoperate properly with `dir()`, `getattr()` and `hasattr()`.
 ///
+/// The doc comment of each function, and its signature with types worked out from the Rust
+/// types, are available from the function value using `documentation()`.
+///
 /// If a desired function name is also a Rust keyword, use the `r#` prefix, e.g. `r#type`.
 #[proc_macro_attribute]
 pub fn starlark_module(attr: TokenStream, input: TokenStream) -> TokenStream {
@@ -87,3 +93,20 @@ pub fn starlark_module(attr: TokenStream, input: TokenStream) -> TokenStream {
 pub fn derive_trace(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
     trace::derive_trace(input)
 }
+
+/// Derive the `Freeze` trait for a type `FooGen<V>`, freezing `FooGen<Value<'v>>` into
+/// `FooGen<FrozenValue>` by freezing each field. Fields marked `#[freeze(identity)]`
+/// are moved across unchanged.
+#[proc_macro_derive(Freeze, attributes(freeze))]
+pub fn derive_freeze(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
+    freeze::derive_freeze(input)
+}
+
+/// Derive the `StarlarkAttrs` trait, making each field an attribute. Fields which are
+/// values, such as the `V` of `FooGen<V>`, are returned as they are, while other fields
+/// are cloned and allocated with `AllocValue`. Fields marked `#[starlark(skip)]` are not
+/// attributes.
+#[proc_macro_derive(StarlarkAttrs, attributes(starlark))]
+pub fn derive_starlark_attrs(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
+    attrs::derive_attrs(input)
+}
@@ -15,7 +15,7 @@
  * limitations under the License.
  */
 
-use crate::{typ::*, util::*};
+use crate::{docs::render_docs, typ::*, util::*};
 use gazebo::prelude::*;
 use proc_macro2::TokenStream;
 use quote::quote;
@@ -97,6 +97,7 @@ fn render_attr(x: StarAttr) -> TokenStream {
 
 fn render_fun(x: StarFun) -> TokenStream {
     let signature = render_signature(&x);
+    let docs = render_docs(&x);
 
     let StarFun {
         name,
@@ -110,23 +111,19 @@ fn render_fun(x: StarFun) -> TokenStream {
     let native_name_str = format!("native_{}", name_str);
     let bind_args = args.map(bind_argument);
 
-    let setter = if let Some(typ) = type_attribute {
+    let set_type = type_attribute.map(|typ| {
         quote! {
             static TYPE: starlark::values::ConstFrozenValue =
                 starlark::values::ConstFrozenValue::new(#typ);
-            let signature_str = signature.signature();
-            let mut func = starlark::values::function::NativeFunction::new(#name, signature_str, signature);
             func.set_type(&TYPE);
-            globals_builder.set(#name_str, func);
-        }
-    } else {
-        quote! {
-            let signature_str = signature.signature();
-            globals_builder.set(
-                #name_str,
-                starlark::values::function::NativeFunction::new(#name, signature_str, signature),
-            );
         }
+    });
+    let setter = quote! {
+        let signature_str = signature.signature();
+        let mut func = starlark::values::function::NativeFunction::new(#name, signature_str, signature);
+        func.set_docs(#docs);
+        #set_type
+        globals_builder.set(#name_str, func);
     };
     quote! {
         #( #attrs )*